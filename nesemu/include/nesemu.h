@@ -0,0 +1,113 @@
+#ifndef NESEMU_H
+#define NESEMU_H
+
+/* Generated by cbindgen from src/capi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/*
+ Status returned by every function that can fail.
+ */
+typedef enum NesStatus {
+  NesOk = 0,
+  NesErrNull = -1,
+  NesErrUnsupported = -2,
+  NesErrPanic = -3,
+  NesErrInvalidArgument = -4,
+} NesStatus;
+
+/*
+ Opaque emulator instance.
+ */
+typedef struct NesHandle NesHandle;
+
+/*
+ Create an emulator from an iNES image. Returns null if the image is
+ invalid. The data is copied; the caller keeps ownership of `data`.
+
+ # Safety
+ `data` must point to `len` readable bytes.
+ */
+struct NesHandle *nes_create_from_memory(const uint8_t *data, uintptr_t len);
+
+/*
+ Free an emulator. Null is ignored.
+
+ # Safety
+ `handle` must be null or a live handle from nes_create_from_memory, and
+ must not be used afterwards.
+ */
+void nes_destroy(struct NesHandle *handle);
+
+/*
+ Press the console's Reset button; RAM is kept.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_reset(struct NesHandle *handle);
+
+/*
+ Power the console off and on again; RAM is cleared.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_power_cycle(struct NesHandle *handle);
+
+/*
+ Run until the start of the next frame.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_run_frame(struct NesHandle *handle);
+
+/*
+ Copy the current frame into `out`. Not available yet: the PPU is not
+ emulated.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_frame_buffer(struct NesHandle *handle, uint8_t *out, uintptr_t out_len);
+
+/*
+ Set the buttons held on controller `port` (0 or 1). Bit 0 is A, then B,
+ Select, Start, Up, Down, Left, Right.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_set_buttons(struct NesHandle *handle, uint8_t port, uint8_t mask);
+
+/*
+ Read `len` bytes of CPU address space starting at `addr` into `out`,
+ wrapping at $FFFF.
+
+ # Safety
+ `handle` must be null or a live handle, and `out` must point to `len`
+ writable bytes.
+ */
+enum NesStatus nes_read_mem(struct NesHandle *handle, uint16_t addr, uint8_t *out, uintptr_t len);
+
+/*
+ Not available yet: save states are not supported.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_save_state(struct NesHandle *handle, uint8_t *out, uintptr_t *out_len);
+
+/*
+ Not available yet: save states are not supported.
+
+ # Safety
+ `handle` must be null or a live handle.
+ */
+enum NesStatus nes_load_state(struct NesHandle *handle, const uint8_t *data, uintptr_t len);
+
+#endif  /* NESEMU_H */
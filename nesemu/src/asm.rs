@@ -0,0 +1,449 @@
+use std::collections::HashMap;
+
+use crate::opcodes::{self, AddrMode};
+
+// 6502 assembler: single instructions for the monitor, or whole programs
+// for tests and patches. Program syntax, one statement per line:
+//
+//   ; comment
+//   * = $8000            set the origin (also `*=$8000`)
+//   lives = $75          constant (must be defined before use)
+//   reset: sei           label, optionally followed by a statement
+//          lda #<table   `<` / `>` take the low / high byte
+//          bne reset     labels may be used before they are defined
+//   table: .byte 1, 2, $03
+//          .word reset, table+2
+//
+// Operands referring to a label defined later are always assembled with
+// absolute addressing, since their zero-page-ness isn't known in pass one.
+
+// Parse a number in assembler syntax: `$` hex, `%` binary, otherwise decimal
+pub fn parse_number(text: &str) -> Result<u16, String> {
+    let text = text.trim();
+    let parsed = if let Some(hex) = text.strip_prefix('$') {
+        u16::from_str_radix(hex, 16)
+    } else if let Some(bin) = text.strip_prefix('%') {
+        u16::from_str_radix(bin, 2)
+    } else {
+        text.parse::<u16>()
+    };
+    parsed.map_err(|_| format!("invalid number '{}'", text))
+}
+
+// Find the opcode byte for a mnemonic/addressing mode pair, preferring official encodings
+pub fn find_opcode(mnemonic: &str, mode: AddrMode) -> Option<u8> {
+    let mut unofficial = None;
+    for (code, entry) in opcodes::OPCODES.iter().enumerate() {
+        if let Some(op) = entry
+            && op.mode == mode
+            && op.mnemonic.eq_ignore_ascii_case(mnemonic)
+        {
+            if op.official {
+                return Some(code as u8);
+            }
+            unofficial.get_or_insert(code as u8);
+        }
+    }
+    unofficial
+}
+
+fn has_mode(mnemonic: &str, mode: AddrMode) -> bool {
+    find_opcode(mnemonic, mode).is_some()
+}
+
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+// Resolves an expression to a value; Ok(None) means it names a label that
+// isn't defined yet
+type Resolver<'a> = dyn Fn(&str) -> Result<Option<u16>, String> + 'a;
+
+// `[<|>] (number | label) [(+|-) number]`
+fn evaluate(text: &str, labels: &HashMap<String, u16>) -> Result<Option<u16>, String> {
+    let text = text.trim();
+    if let Some(rest) = text.strip_prefix('<') {
+        return Ok(evaluate(rest, labels)?.map(|v| v & 0xFF));
+    }
+    if let Some(rest) = text.strip_prefix('>') {
+        return Ok(evaluate(rest, labels)?.map(|v| v >> 8));
+    }
+
+    if let Some(split) = text.rfind(['+', '-']).filter(|&i| i > 0) {
+        let (base, offset) = (&text[..split], parse_number(&text[split + 1..])?);
+        return Ok(evaluate(base, labels)?.map(|v| match &text[split..split + 1] {
+            "+" => v.wrapping_add(offset),
+            _ => v.wrapping_sub(offset),
+        }));
+    }
+
+    if is_identifier(text) {
+        Ok(labels.get(&text.to_ascii_lowercase()).copied())
+    } else {
+        parse_number(text).map(Some)
+    }
+}
+
+// Encode one instruction at `addr`. With `wide`, zero-page forms are
+// promoted to absolute. Unresolved labels encode as placeholders, which is
+// fine for sizing in pass one.
+fn encode(addr: u16, line: &str, resolve: &Resolver, wide: bool) -> Result<Vec<u8>, String> {
+    let line = line.trim();
+    let (mnemonic, operand) = match line.split_once(char::is_whitespace) {
+        Some((m, rest)) => (m, rest.trim()),
+        None => (line, ""),
+    };
+    let mnemonic = mnemonic.to_ascii_uppercase();
+    let operand = operand.replace(' ', "");
+    let upper = operand.to_ascii_uppercase();
+
+    if !opcodes::OPCODES.iter().flatten().any(|op| op.mnemonic == mnemonic) {
+        return Err(format!("unknown mnemonic '{}'", mnemonic));
+    }
+
+    let mut unresolved = false;
+    let mut value_of = |text: &str| -> Result<u16, String> {
+        match resolve(text)? {
+            Some(value) => Ok(value),
+            None => {
+                unresolved = true;
+                Ok(0xFFFF)
+            }
+        }
+    };
+    let zero_page = |value: u16, unresolved: bool| value <= 0xFF && !unresolved && !wide;
+
+    let (mode, value) = if operand.is_empty() {
+        if has_mode(&mnemonic, AddrMode::Accumulator) {
+            (AddrMode::Accumulator, 0)
+        } else {
+            (AddrMode::Implied, 0)
+        }
+    } else if upper == "A" {
+        (AddrMode::Accumulator, 0)
+    } else if let Some(imm) = operand.strip_prefix('#') {
+        let value = value_of(imm)?;
+        (AddrMode::Immediate, if unresolved { 0 } else { value })
+    } else if let Some(inner) = operand.strip_prefix('(') {
+        let inner_upper = inner.to_ascii_uppercase();
+        if inner_upper.ends_with(",X)") {
+            (AddrMode::IndirectX, value_of(&inner[..inner.len() - 3])?)
+        } else if inner_upper.ends_with("),Y") {
+            (AddrMode::IndirectY, value_of(&inner[..inner.len() - 3])?)
+        } else if let Some(ptr) = inner.strip_suffix(')') {
+            (AddrMode::Indirect, value_of(ptr)?)
+        } else {
+            return Err(format!("malformed indirect operand '{}'", operand));
+        }
+    } else if upper.ends_with(",X") {
+        let value = value_of(&operand[..operand.len() - 2])?;
+        if zero_page(value, unresolved) && has_mode(&mnemonic, AddrMode::ZeroPageX) {
+            (AddrMode::ZeroPageX, value)
+        } else {
+            (AddrMode::AbsoluteX, value)
+        }
+    } else if upper.ends_with(",Y") {
+        let value = value_of(&operand[..operand.len() - 2])?;
+        if zero_page(value, unresolved) && has_mode(&mnemonic, AddrMode::ZeroPageY) {
+            (AddrMode::ZeroPageY, value)
+        } else {
+            (AddrMode::AbsoluteY, value)
+        }
+    } else {
+        let value = value_of(&operand)?;
+        if has_mode(&mnemonic, AddrMode::Relative) {
+            (AddrMode::Relative, value)
+        } else if zero_page(value, unresolved) && has_mode(&mnemonic, AddrMode::ZeroPage) {
+            (AddrMode::ZeroPage, value)
+        } else {
+            (AddrMode::Absolute, value)
+        }
+    };
+
+    let opcode = find_opcode(&mnemonic, mode)
+        .ok_or_else(|| format!("{} does not support {:?} addressing", mnemonic, mode))?;
+
+    let mut bytes = vec![opcode];
+    match mode.operand_len() {
+        0 => {}
+        1 if mode == AddrMode::Relative => {
+            // Offset is relative to the address after the 2-byte branch
+            let offset = value as i32 - addr.wrapping_add(2) as i32;
+            if !unresolved && !(-128..=127).contains(&offset) {
+                return Err(format!("branch target ${:04X} out of range", value));
+            }
+            bytes.push(offset as i8 as u8);
+        }
+        1 => {
+            if value > 0xFF && !unresolved {
+                return Err(format!("operand ${:X} does not fit in a byte", value));
+            }
+            bytes.push(value as u8);
+        }
+        _ => {
+            bytes.push((value & 0xFF) as u8);
+            bytes.push((value >> 8) as u8);
+        }
+    }
+    Ok(bytes)
+}
+
+// Assemble a single instruction located at `addr` (needed for branch offsets)
+pub fn assemble_line(addr: u16, line: &str) -> Result<Vec<u8>, String> {
+    encode(addr, line, &|text| parse_number(text).map(Some), false)
+}
+
+enum Statement<'a> {
+    Origin(&'a str),
+    Constant(String, &'a str),
+    Bytes(Vec<&'a str>),
+    Words(Vec<&'a str>),
+    Instruction(&'a str),
+}
+
+struct SourceLine<'a> {
+    number: usize,
+    label: Option<String>,
+    statement: Option<Statement<'a>>,
+}
+
+fn parse_source_line<'a>(number: usize, line: &'a str) -> Result<SourceLine<'a>, String> {
+    let mut text = line.split(';').next().unwrap_or("").trim();
+    let mut label = None;
+
+    if let Some((name, rest)) = text.split_once(':')
+        && is_identifier(name.trim())
+    {
+        label = Some(name.trim().to_ascii_lowercase());
+        text = rest.trim();
+    }
+
+    let args = |rest: &'a str| -> Vec<&'a str> { rest.split(',').map(str::trim).collect() };
+    let statement = if text.is_empty() {
+        None
+    } else if let Some(rest) = text.strip_prefix('*') {
+        let rest = rest.trim_start();
+        let origin = rest.strip_prefix('=').ok_or("expected '* = <address>'")?;
+        Some(Statement::Origin(origin.trim()))
+    } else if let Some(rest) = text.strip_prefix(".byte") {
+        Some(Statement::Bytes(args(rest)))
+    } else if let Some(rest) = text.strip_prefix(".word") {
+        Some(Statement::Words(args(rest)))
+    } else if text.starts_with('.') {
+        return Err(format!("unknown directive '{}'", text.split_whitespace().next().unwrap_or(text)));
+    } else if let Some((name, value)) = text.split_once('=')
+        && is_identifier(name.trim())
+    {
+        Some(Statement::Constant(name.trim().to_ascii_lowercase(), value.trim()))
+    } else {
+        Some(Statement::Instruction(text))
+    };
+
+    Ok(SourceLine {
+        number,
+        label,
+        statement,
+    })
+}
+
+pub struct Program {
+    // Contiguous runs of output as (start address, bytes), in source order
+    pub segments: Vec<(u16, Vec<u8>)>,
+    pub labels: HashMap<String, u16>,
+}
+
+impl Program {
+    // All output concatenated, for single-origin programs
+    pub fn bytes(&self) -> Vec<u8> {
+        self.segments.iter().flat_map(|(_, bytes)| bytes.iter().copied()).collect()
+    }
+
+    pub fn label(&self, name: &str) -> Option<u16> {
+        self.labels.get(&name.to_ascii_lowercase()).copied()
+    }
+}
+
+// Assemble a whole program starting at `origin` (until a `*` directive)
+pub fn assemble(origin: u16, source: &str) -> Result<Program, String> {
+    let lines = source
+        .lines()
+        .enumerate()
+        .map(|(i, line)| parse_source_line(i + 1, line).map_err(|e| format!("line {}: {}", i + 1, e)))
+        .collect::<Result<Vec<_>, String>>()?;
+
+    // Pass one: sizes and label addresses. Remember which instructions
+    // used labels that were still undefined, so pass two encodes them the
+    // same width.
+    let mut labels: HashMap<String, u16> = HashMap::new();
+    let mut forward = vec![false; lines.len()];
+    let mut addr = origin;
+    for (index, line) in lines.iter().enumerate() {
+        let fail = |e: String| format!("line {}: {}", line.number, e);
+
+        if let Some(label) = &line.label
+            && labels.insert(label.clone(), addr).is_some()
+        {
+            return Err(fail(format!("label '{}' defined twice", label)));
+        }
+        match &line.statement {
+            None => {}
+            Some(Statement::Origin(expr)) => {
+                addr = evaluate(expr, &labels)
+                    .map_err(fail)?
+                    .ok_or_else(|| fail("origin must not use undefined labels".to_string()))?;
+            }
+            Some(Statement::Constant(name, expr)) => {
+                let value = evaluate(expr, &labels)
+                    .map_err(fail)?
+                    .ok_or_else(|| fail(format!("'{}' uses an undefined label", name)))?;
+                if labels.insert(name.clone(), value).is_some() {
+                    return Err(fail(format!("label '{}' defined twice", name)));
+                }
+            }
+            Some(Statement::Bytes(values)) => addr = addr.wrapping_add(values.len() as u16),
+            Some(Statement::Words(values)) => addr = addr.wrapping_add(2 * values.len() as u16),
+            Some(Statement::Instruction(text)) => {
+                let seen_unresolved = std::cell::Cell::new(false);
+                let resolve = |expr: &str| {
+                    let value = evaluate(expr, &labels)?;
+                    seen_unresolved.set(seen_unresolved.get() || value.is_none());
+                    Ok(value)
+                };
+                let size = encode(addr, text, &resolve, false).map_err(fail)?.len();
+                forward[index] = seen_unresolved.get();
+                addr = addr.wrapping_add(size as u16);
+            }
+        }
+    }
+
+    // Pass two: emit bytes with every label known
+    let resolve_all = |expr: &str| -> Result<u16, String> {
+        evaluate(expr, &labels)?.ok_or_else(|| format!("undefined label in '{}'", expr.trim()))
+    };
+    let mut segments: Vec<(u16, Vec<u8>)> = vec![(origin, Vec::new())];
+    let mut addr = origin;
+    for (index, line) in lines.iter().enumerate() {
+        let fail = |e: String| format!("line {}: {}", line.number, e);
+        let bytes = match &line.statement {
+            None | Some(Statement::Constant(..)) => continue,
+            Some(Statement::Origin(expr)) => {
+                addr = resolve_all(expr).map_err(fail)?;
+                segments.push((addr, Vec::new()));
+                continue;
+            }
+            Some(Statement::Bytes(values)) => values
+                .iter()
+                .map(|v| {
+                    let value = resolve_all(v)?;
+                    if value > 0xFF {
+                        return Err(format!("${:X} does not fit in a byte", value));
+                    }
+                    Ok(value as u8)
+                })
+                .collect::<Result<Vec<u8>, String>>()
+                .map_err(fail)?,
+            Some(Statement::Words(values)) => values
+                .iter()
+                .map(|v| resolve_all(v).map(u16::to_le_bytes))
+                .collect::<Result<Vec<[u8; 2]>, String>>()
+                .map_err(fail)?
+                .concat(),
+            Some(Statement::Instruction(text)) => {
+                let resolve = |expr: &str| resolve_all(expr).map(Some);
+                encode(addr, text, &resolve, forward[index]).map_err(fail)?
+            }
+        };
+        addr = addr.wrapping_add(bytes.len() as u16);
+        if let Some((_, segment)) = segments.last_mut() {
+            segment.extend(bytes);
+        }
+    }
+
+    segments.retain(|(_, bytes)| !bytes.is_empty());
+    Ok(Program { segments, labels })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm;
+    use crate::mem::Memory;
+
+    #[test]
+    fn assembles_every_addressing_mode() {
+        let cases: [(&str, &[u8]); 13] = [
+            ("nop", &[0xEA]),
+            ("asl a", &[0x0A]),
+            ("asl", &[0x0A]),
+            ("lda #$12", &[0xA9, 0x12]),
+            ("lda $12", &[0xA5, 0x12]),
+            ("lda $12,x", &[0xB5, 0x12]),
+            ("ldx $12,y", &[0xB6, 0x12]),
+            ("lda $1234", &[0xAD, 0x34, 0x12]),
+            ("lda $1234,x", &[0xBD, 0x34, 0x12]),
+            ("lda $12,y", &[0xB9, 0x12, 0x00]), // No zero-page,Y form for LDA
+            ("jmp ($1234)", &[0x6C, 0x34, 0x12]),
+            ("lda ($12,x)", &[0xA1, 0x12]),
+            ("lda ($12),y", &[0xB1, 0x12]),
+        ];
+        for (line, bytes) in cases {
+            assert_eq!(assemble_line(0x0600, line).as_deref(), Ok(bytes), "{line}");
+        }
+        assert_eq!(assemble_line(0x0600, "bne $05F0"), Ok(vec![0xD0, 0xEE]));
+        assert_eq!(assemble_line(0x0600, "beq $0681"), Ok(vec![0xF0, 0x7F]));
+    }
+
+    // Disassemble each opcode, assemble the text back and get the same
+    // bytes. Unofficial opcodes that duplicate an official encoding come
+    // back as the official one, so for those it's the text that must match.
+    #[test]
+    fn round_trips_through_the_disassembler() {
+        let mut memory = Memory::with_program(&[], 0x8000);
+        for (code, op) in opcodes::OPCODES.iter().enumerate() {
+            let Some(op) = op else { continue };
+            let bytes = [code as u8, 0x34, 0x12];
+            for (i, &byte) in bytes.iter().enumerate() {
+                memory.poke(0x0600 + i as u16, byte);
+            }
+            let line = disasm::disassemble_one(&memory, 0x0600);
+            let text = format!("{} {}", line.mnemonic, line.operand);
+
+            let assembled = assemble_line(0x0600, &text).unwrap_or_else(|e| panic!("{text}: {e}"));
+            if op.official {
+                assert_eq!(assembled, line.bytes, "{text}");
+            } else {
+                for (i, &byte) in assembled.iter().enumerate() {
+                    memory.poke(0x0600 + i as u16, byte);
+                }
+                let again = disasm::disassemble_one(&memory, 0x0600);
+                assert_eq!(format!("{} {}", again.mnemonic, again.operand), text);
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_unknown_mnemonics() {
+        assert_eq!(assemble_line(0x0600, "xyz #$01"), Err("unknown mnemonic 'XYZ'".to_string()));
+        assert_eq!(assemble(0x8000, "nop\n foo").err(), Some("line 2: unknown mnemonic 'FOO'".to_string()));
+        assert_eq!(
+            assemble_line(0x0600, "jmp #$01"),
+            Err("JMP does not support Immediate addressing".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_branches_out_of_range() {
+        assert_eq!(
+            assemble_line(0x0600, "bne $0682"),
+            Err("branch target $0682 out of range".to_string())
+        );
+        assert_eq!(
+            assemble_line(0x0600, "bne $0581"),
+            Err("branch target $0581 out of range".to_string())
+        );
+        let far = format!("bne far\n .byte {}\n far: rts", vec!["0"; 128].join(","));
+        assert_eq!(assemble(0x8000, &far).err(), Some("line 1: branch target $8082 out of range".to_string()));
+    }
+}
@@ -0,0 +1,168 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::nes::Nes;
+use crate::profile::Profiler;
+use crate::stats::{Stats, StatsSummary};
+
+// Headless speed measurement for `nesemu bench`: run whole frames without
+// pacing for a fixed wall-clock time and report how fast that was. A baseline
+// is simply the --json output of an earlier run.
+
+// 1789773 Hz CPU clock / 29780.5 cycles per frame
+pub const NTSC_FPS: f64 = 60.0988;
+
+// Frame times kept for the percentiles, about 18 minutes at 60 fps
+const STATS_WINDOW: usize = 1 << 16;
+
+pub struct BenchResult {
+    pub frames: u64,
+    pub wall: Duration,
+    pub profiler: Profiler, // Per-frame timings
+    pub frame_times: Option<StatsSummary>,
+}
+
+impl BenchResult {
+    pub fn fps(&self) -> f64 {
+        if self.wall.is_zero() {
+            return 0.0;
+        }
+        self.frames as f64 / self.wall.as_secs_f64()
+    }
+
+    // How many times faster than a real console
+    pub fn realtime_ratio(&self) -> f64 {
+        self.fps() / NTSC_FPS
+    }
+
+    pub fn to_json(&self) -> String {
+        let frame_times = self.frame_times.map_or("null".to_string(), |summary| summary.to_json());
+        format!(
+            "{{\"frames\":{},\"seconds\":{:.3},\"fps\":{:.2},\"realtime\":{:.3},\"frame_times\":{}}}",
+            self.frames,
+            self.wall.as_secs_f64(),
+            self.fps(),
+            self.realtime_ratio(),
+            frame_times
+        )
+    }
+}
+
+impl fmt::Display for BenchResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "frames:   {}", self.frames)?;
+        writeln!(f, "time:     {:.3}s", self.wall.as_secs_f64())?;
+        writeln!(f, "fps:      {:.2}", self.fps())?;
+        writeln!(f, "realtime: {:.2}x", self.realtime_ratio())?;
+        if let Some(summary) = self.frame_times {
+            writeln!(f, "window:   {}", summary)?;
+        }
+        write!(f, "{}", self.profiler)
+    }
+}
+
+// Run frames until `duration` of wall time has passed
+pub fn run(nes: &mut Nes, duration: Duration) -> BenchResult {
+    let mut profiler = Profiler::new();
+    let mut stats = Stats::new(STATS_WINDOW);
+    let start = Instant::now();
+    while start.elapsed() < duration {
+        profiler.begin_frame();
+        let frame_start = Instant::now();
+        profiler.time("cpu", || nes.run_frame());
+        stats.record_frame(frame_start.elapsed());
+        profiler.end_frame();
+    }
+    BenchResult {
+        frames: profiler.frames(),
+        wall: start.elapsed(),
+        profiler,
+        frame_times: stats.summary(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Baseline {
+    pub fps: f64,
+}
+
+impl Baseline {
+    // Reads the "fps" field of a saved --json result
+    pub fn parse(text: &str) -> Result<Baseline, String> {
+        let (_, rest) = text.split_once("\"fps\":").ok_or("baseline has no \"fps\" field")?;
+        let number: String = rest
+            .trim_start()
+            .chars()
+            .take_while(|c| c.is_ascii_digit() || *c == '.')
+            .collect();
+        let fps = number
+            .parse()
+            .map_err(|_| format!("invalid fps '{}' in baseline", number))?;
+        Ok(Baseline { fps })
+    }
+
+    // Fails when `fps` is more than `threshold` percent below the baseline
+    pub fn check(&self, fps: f64, threshold: f64) -> Result<(), String> {
+        let floor = self.fps * (1.0 - threshold / 100.0);
+        if fps < floor {
+            return Err(format!(
+                "{:.2} fps is {:.1}% below the baseline of {:.2} fps (threshold {}%)",
+                fps,
+                (1.0 - fps / self.fps) * 100.0,
+                self.fps,
+                threshold
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Made-up timings; nothing is actually run
+    fn result(frames: u64, wall: Duration) -> BenchResult {
+        BenchResult {
+            frames,
+            wall,
+            profiler: Profiler::new(),
+            frame_times: None,
+        }
+    }
+
+    #[test]
+    fn baseline_round_trips_through_json() {
+        let result = result(600, Duration::from_secs(2));
+        assert_eq!(result.fps(), 300.0);
+        let json = result.to_json();
+        assert_eq!(json, "{\"frames\":600,\"seconds\":2.000,\"fps\":300.00,\"realtime\":4.992,\"frame_times\":null}");
+        assert_eq!(Baseline::parse(&json), Ok(Baseline { fps: 300.0 }));
+        assert_eq!(Baseline::parse("{\"fps\": 61.5}"), Ok(Baseline { fps: 61.5 }));
+    }
+
+    #[test]
+    fn check_fails_only_past_the_threshold() {
+        let baseline = Baseline { fps: 300.0 };
+        assert_eq!(baseline.check(320.0, 10.0), Ok(()));
+        assert_eq!(baseline.check(280.0, 10.0), Ok(()));
+        assert_eq!(baseline.check(270.0, 10.0), Ok(()), "exactly at the floor");
+        assert_eq!(
+            baseline.check(240.0, 10.0),
+            Err("240.00 fps is 20.0% below the baseline of 300.00 fps (threshold 10%)".to_string())
+        );
+        assert!(baseline.check(299.0, 0.0).is_err());
+
+        // A fake run against it, end to end
+        let slow = result(450, Duration::from_secs(2));
+        assert!(baseline.check(slow.fps(), 20.0).is_err());
+        assert!(baseline.check(slow.fps(), 30.0).is_ok());
+    }
+
+    #[test]
+    fn bad_baselines_are_rejected() {
+        assert_eq!(Baseline::parse("{\"frames\":600}"), Err("baseline has no \"fps\" field".to_string()));
+        assert_eq!(Baseline::parse("{\"fps\":null}"), Err("invalid fps '' in baseline".to_string()));
+        assert_eq!(result(0, Duration::ZERO).fps(), 0.0);
+    }
+}
@@ -0,0 +1,74 @@
+use crate::mem::Memory;
+
+// Everything the CPU can reach, as seen from its side. Reads take &mut self
+// because real registers change when read ($2002, $2007, the controller
+// ports); peek is the read for tools, which must leave the machine as it was.
+// A bus owns its devices rather than borrowing them, so the CPU's per-bus
+// dispatch table can be a static.
+pub trait Bus: 'static {
+    fn read(&mut self, addr: u16) -> u8;
+    fn write(&mut self, addr: u16, value: u8);
+    fn peek(&self, addr: u16) -> u8;
+
+    // Level of /IRQ as the CPU polls it. Nothing drives it by default.
+    fn irq_asserted(&self) -> bool {
+        false
+    }
+
+    // The page of an OAM DMA that a write just started, cleared on taking it
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        None
+    }
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr) as u16;
+        let hi = self.read(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    // Pointer fetch for (zp,X) and (zp),Y, wrapping within the zero page
+    fn read_zp_u16(&mut self, ptr: u8) -> u16 {
+        let lo = self.read(ptr as u16) as u16;
+        let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    fn peek_u16(&self, addr: u16) -> u16 {
+        let lo = self.peek(addr) as u16;
+        let hi = self.peek(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    fn peek_zp_u16(&self, ptr: u8) -> u16 {
+        let lo = self.peek(ptr as u16) as u16;
+        let hi = self.peek(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+}
+
+impl Bus for Memory {
+    #[inline]
+    fn read(&mut self, addr: u16) -> u8 {
+        Memory::read(self, addr)
+    }
+
+    #[inline]
+    fn write(&mut self, addr: u16, value: u8) {
+        Memory::write(self, addr, value)
+    }
+
+    #[inline]
+    fn peek(&self, addr: u16) -> u8 {
+        Memory::peek(self, addr)
+    }
+
+    #[inline]
+    fn irq_asserted(&self) -> bool {
+        self.irq_line().is_asserted()
+    }
+
+    #[inline]
+    fn take_oam_dma(&mut self) -> Option<u8> {
+        Memory::take_oam_dma(self)
+    }
+}
@@ -0,0 +1,199 @@
+use std::fmt;
+
+// Shadow call stack built by watching JSR/RTS/RTI and interrupt entry
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameKind {
+    Subroutine,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub kind: FrameKind,
+    pub return_addr: u16, // Where execution resumes after RTS/RTI
+    pub target: u16,      // Entry point of the subroutine or handler
+    pub sp: u8,           // Stack pointer before the return address was pushed
+}
+
+impl fmt::Display for Frame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self.kind {
+            FrameKind::Subroutine => "JSR",
+            FrameKind::Interrupt => "INT",
+        };
+        write!(
+            f,
+            "{} ${:04X}, returns to ${:04X} (SP ${:02X})",
+            kind, self.target, self.return_addr, self.sp
+        )
+    }
+}
+
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> Self {
+        Self { frames: Vec::new() }
+    }
+
+    // Innermost frame last
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn depth(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // Record the effect of an executed instruction. `pc`/`sp` are the values
+    // before it ran, `new_pc`/`new_sp` the values after.
+    pub fn on_instruction(&mut self, opcode: u8, pc: u16, sp: u8, new_pc: u16, new_sp: u8) {
+        // A frame's return address lives just below its entry SP, so once SP
+        // has climbed back to (or above) that point the frame is gone. This
+        // covers RTS/RTI as well as games that pull the return address
+        // manually and JMP away, or reset SP with TXS.
+        self.frames.retain(|frame| frame.sp > new_sp);
+
+        match opcode {
+            0x20 => self.frames.push(Frame {
+                kind: FrameKind::Subroutine,
+                return_addr: pc.wrapping_add(3),
+                target: new_pc,
+                sp,
+            }),
+            0x00 => self.on_interrupt(pc.wrapping_add(2), new_pc, sp),
+            _ => {}
+        }
+    }
+
+    // Interrupt entry: `return_addr` is the PC that was pushed
+    pub fn on_interrupt(&mut self, return_addr: u16, handler: u16, sp: u8) {
+        self.frames.push(Frame {
+            kind: FrameKind::Interrupt,
+            return_addr,
+            target: handler,
+            sp,
+        });
+    }
+}
+
+impl Default for CallStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::debugger::Debugger;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    const JSR: u8 = 0x20;
+    const RTS: u8 = 0x60;
+    const RTI: u8 = 0x40;
+    const TXS: u8 = 0x9A;
+
+    fn console(source: &str) -> (Nes, Debugger) {
+        let nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        (nes, Debugger::new())
+    }
+
+    #[test]
+    fn nested_jsrs_push_and_pop_in_order() {
+        let (mut nes, mut debugger) = console(
+            "
+            jsr outer
+     done:  jmp done
+     outer: jsr inner
+            rts
+     inner: nop
+            rts",
+        );
+        let sp = nes.cpu.sp;
+        debugger.step(&mut nes); // JSR outer
+        debugger.step(&mut nes); // JSR inner
+        let frames = debugger.call_stack();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].target, frames[0].return_addr, frames[0].sp), (0xC006, 0xC003, sp));
+        assert_eq!((frames[1].target, frames[1].return_addr, frames[1].sp), (0xC00A, 0xC009, sp - 2));
+        assert!(frames.iter().all(|f| f.kind == FrameKind::Subroutine));
+
+        debugger.step(&mut nes); // NOP
+        debugger.step(&mut nes); // RTS from inner
+        assert_eq!(nes.cpu.pc, 0xC009);
+        assert_eq!(debugger.call_stack(), &frames[..1]);
+        debugger.step(&mut nes); // RTS from outer
+        assert_eq!(nes.cpu.pc, 0xC003);
+        assert!(debugger.call_stack().is_empty());
+    }
+
+    // The NMI is taken in place of an instruction, so the frame has to come
+    // from the interrupt, not the opcode at PC
+    #[test]
+    fn rti_closes_the_nmi_frame() {
+        let (mut nes, mut debugger) = console(
+            "
+            lda #$80
+            sta $2000
+     main:  jmp main
+     nmi:   jsr handler
+            rti
+     handler: rts
+            * = $FFFA
+            .word nmi",
+        );
+        let mut steps = 0;
+        while debugger.call_stack().is_empty() {
+            debugger.step(&mut nes);
+            steps += 1;
+            assert!(steps < 100_000, "no NMI within {steps} instructions");
+        }
+        let frame = debugger.call_stack()[0];
+        assert_eq!(frame.kind, FrameKind::Interrupt);
+        assert_eq!((frame.target, frame.return_addr), (0xC008, 0xC005));
+        assert_eq!(nes.cpu.pc, 0xC008);
+
+        debugger.step(&mut nes); // JSR handler
+        assert_eq!(debugger.call_stack().len(), 2);
+        debugger.step(&mut nes); // RTS
+        assert_eq!(debugger.call_stack(), vec![frame]);
+        debugger.step(&mut nes); // RTI
+        assert_eq!(nes.cpu.pc, 0xC005);
+        assert!(debugger.call_stack().is_empty());
+    }
+
+    #[test]
+    fn unbalanced_rts_leaves_an_empty_stack_alone() {
+        let mut stack = CallStack::new();
+        stack.on_instruction(RTS, 0xC000, 0xFD, 0x1235, 0xFF);
+        assert_eq!(stack.depth(), 0);
+        stack.on_instruction(RTI, 0xC001, 0xFF, 0x0000, 0x02);
+        assert_eq!(stack.depth(), 0);
+    }
+
+    // Returning past a frame without its RTS (stack reset, or a return
+    // address pulled by hand) drops it along with everything inside it
+    #[test]
+    fn frames_below_the_stack_pointer_are_dropped() {
+        let mut stack = CallStack::new();
+        stack.on_instruction(JSR, 0xC000, 0xFD, 0xC100, 0xFB);
+        stack.on_instruction(JSR, 0xC100, 0xFB, 0xC200, 0xF9);
+        stack.on_interrupt(0xC200, 0xC300, 0xF9);
+        assert_eq!(stack.depth(), 3);
+
+        stack.on_instruction(RTS, 0xC300, 0xF6, 0xC103, 0xFB);
+        assert_eq!(stack.depth(), 1);
+        assert_eq!(stack.frames()[0].target, 0xC100);
+        stack.on_instruction(TXS, 0xC103, 0xFB, 0xC104, 0xFF);
+        assert_eq!(stack.depth(), 0);
+    }
+}
@@ -0,0 +1,237 @@
+// C interface for embedding. Every entry point catches panics and reports
+// failures as a negative NesStatus; regenerate include/nesemu.h with
+// `cbindgen --config cbindgen.toml --output include/nesemu.h` after changes.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use crate::input::ButtonState;
+use crate::nes::Nes;
+
+/// Status returned by every function that can fail.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NesStatus {
+    NesOk = 0,
+    NesErrNull = -1,
+    NesErrUnsupported = -2,
+    NesErrPanic = -3,
+    NesErrInvalidArgument = -4,
+}
+
+/// Opaque emulator instance.
+pub struct NesHandle {
+    nes: Nes,
+}
+
+// Run `f` on the handle, mapping a null handle or a panic to an error code
+fn with_handle(handle: *mut NesHandle, f: impl FnOnce(&mut Nes) -> NesStatus) -> NesStatus {
+    // SAFETY: callers promise `handle` is null or came from nes_create_from_memory
+    let Some(handle) = (unsafe { handle.as_mut() }) else {
+        return NesStatus::NesErrNull;
+    };
+    panic::catch_unwind(AssertUnwindSafe(|| f(&mut handle.nes))).unwrap_or(NesStatus::NesErrPanic)
+}
+
+/// Create an emulator from an iNES image. Returns null if the image is
+/// invalid. The data is copied; the caller keeps ownership of `data`.
+///
+/// # Safety
+/// `data` must point to `len` readable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_create_from_memory(data: *const u8, len: usize) -> *mut NesHandle {
+    if data.is_null() {
+        return ptr::null_mut();
+    }
+    // SAFETY: guaranteed by the caller
+    let bytes = unsafe { slice::from_raw_parts(data, len) };
+    let created = panic::catch_unwind(|| {
+        Nes::from_bytes(bytes).ok().map(|nes| NesHandle { nes })
+    });
+    match created {
+        Ok(Some(handle)) => Box::into_raw(Box::new(handle)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// Free an emulator. Null is ignored.
+///
+/// # Safety
+/// `handle` must be null or a live handle from nes_create_from_memory, and
+/// must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_destroy(handle: *mut NesHandle) {
+    if !handle.is_null() {
+        // SAFETY: guaranteed by the caller
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(unsafe { Box::from_raw(handle) })));
+    }
+}
+
+/// Press the console's Reset button; RAM is kept.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_reset(handle: *mut NesHandle) -> NesStatus {
+    with_handle(handle, |nes| {
+        nes.soft_reset();
+        NesStatus::NesOk
+    })
+}
+
+/// Power the console off and on again; RAM is cleared.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_power_cycle(handle: *mut NesHandle) -> NesStatus {
+    with_handle(handle, |nes| {
+        nes.power_cycle();
+        NesStatus::NesOk
+    })
+}
+
+/// Run until the start of the next frame.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_run_frame(handle: *mut NesHandle) -> NesStatus {
+    with_handle(handle, |nes| {
+        nes.run_frame();
+        NesStatus::NesOk
+    })
+}
+
+/// Copy the current frame into `out`. Not available yet: the PPU is not
+/// emulated.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_frame_buffer(handle: *mut NesHandle, out: *mut u8, out_len: usize) -> NesStatus {
+    let _ = (out, out_len);
+    with_handle(handle, |_| NesStatus::NesErrUnsupported)
+}
+
+/// Set the buttons held on controller `port` (0 or 1). Bit 0 is A, then B,
+/// Select, Start, Up, Down, Left, Right.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_set_buttons(handle: *mut NesHandle, port: u8, mask: u8) -> NesStatus {
+    with_handle(handle, |nes| {
+        if port > 1 {
+            return NesStatus::NesErrInvalidArgument;
+        }
+        nes.set_controller(port as usize, ButtonState::from_bits(mask));
+        NesStatus::NesOk
+    })
+}
+
+/// Read `len` bytes of CPU address space starting at `addr` into `out`,
+/// wrapping at $FFFF.
+///
+/// # Safety
+/// `handle` must be null or a live handle, and `out` must point to `len`
+/// writable bytes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_read_mem(handle: *mut NesHandle, addr: u16, out: *mut u8, len: usize) -> NesStatus {
+    if out.is_null() {
+        return NesStatus::NesErrNull;
+    }
+    with_handle(handle, |nes| {
+        // SAFETY: guaranteed by the caller
+        let out = unsafe { slice::from_raw_parts_mut(out, len) };
+        for (i, byte) in out.iter_mut().enumerate() {
+            *byte = nes.memory.peek(addr.wrapping_add(i as u16));
+        }
+        NesStatus::NesOk
+    })
+}
+
+/// Not available yet: save states are not supported.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_save_state(handle: *mut NesHandle, out: *mut u8, out_len: *mut usize) -> NesStatus {
+    let _ = (out, out_len);
+    with_handle(handle, |_| NesStatus::NesErrUnsupported)
+}
+
+/// Not available yet: save states are not supported.
+///
+/// # Safety
+/// `handle` must be null or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn nes_load_state(handle: *mut NesHandle, data: *const u8, len: usize) -> NesStatus {
+    let _ = (data, len);
+    with_handle(handle, |_| NesStatus::NesErrUnsupported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+
+    // The NMI handler counts frames at $10
+    const FRAME_COUNTER: &str = "
+            lda #$80
+            sta $2000
+     spin:  jmp spin
+     nmi:   inc $10
+            rti
+            * = $FFFA
+            .word nmi";
+
+    fn read_byte(handle: *mut NesHandle, addr: u16) -> u8 {
+        let mut byte = 0xFF;
+        assert_eq!(unsafe { nes_read_mem(handle, addr, &mut byte, 1) }, NesStatus::NesOk);
+        byte
+    }
+
+    #[test]
+    fn create_run_and_destroy() {
+        let image = testbus::ines_image(FRAME_COUNTER, 0).unwrap();
+        let handle = unsafe { nes_create_from_memory(image.as_ptr(), image.len()) };
+        assert!(!handle.is_null());
+
+        let mut reset_vector = [0; 2];
+        assert_eq!(unsafe { nes_read_mem(handle, 0xFFFC, reset_vector.as_mut_ptr(), 2) }, NesStatus::NesOk);
+        assert_eq!(reset_vector, [0x00, 0xC0]);
+
+        for frames in 1..=3 {
+            assert_eq!(unsafe { nes_run_frame(handle) }, NesStatus::NesOk);
+            assert_eq!(read_byte(handle, 0x0010), frames);
+        }
+        assert_eq!(unsafe { nes_set_buttons(handle, 1, 0x81) }, NesStatus::NesOk);
+        assert_eq!(unsafe { nes_set_buttons(handle, 2, 0x81) }, NesStatus::NesErrInvalidArgument);
+        assert_eq!(unsafe { nes_reset(handle) }, NesStatus::NesOk);
+        assert_eq!(read_byte(handle, 0x0010), 3, "reset keeps RAM");
+        assert_eq!(unsafe { nes_power_cycle(handle) }, NesStatus::NesOk);
+        assert_eq!(read_byte(handle, 0x0010), 0, "power cycling clears it");
+        assert_eq!(unsafe { nes_frame_buffer(handle, ptr::null_mut(), 0) }, NesStatus::NesErrUnsupported);
+
+        unsafe { nes_destroy(handle) };
+    }
+
+    #[test]
+    fn bad_arguments_are_reported() {
+        let garbage = [0u8; 16];
+        assert!(unsafe { nes_create_from_memory(garbage.as_ptr(), garbage.len()) }.is_null());
+        assert!(unsafe { nes_create_from_memory(ptr::null(), 16) }.is_null());
+
+        let mut byte = 0;
+        assert_eq!(unsafe { nes_run_frame(ptr::null_mut()) }, NesStatus::NesErrNull);
+        assert_eq!(unsafe { nes_read_mem(ptr::null_mut(), 0, &mut byte, 1) }, NesStatus::NesErrNull);
+        unsafe { nes_destroy(ptr::null_mut()) };
+
+        let image = testbus::ines_image(FRAME_COUNTER, 0).unwrap();
+        let handle = unsafe { nes_create_from_memory(image.as_ptr(), image.len()) };
+        assert_eq!(unsafe { nes_read_mem(handle, 0, ptr::null_mut(), 1) }, NesStatus::NesErrNull);
+        unsafe { nes_destroy(handle) };
+    }
+}
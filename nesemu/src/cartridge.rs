@@ -0,0 +1,281 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+
+// What the cartridge connects to: $4020-$FFFF on the CPU side, the pattern
+// tables at $0000-$1FFF on the PPU side, and the nametable wiring. Only
+// NROM is mapped so far; the mapper id is kept for when the rest land.
+//
+//   $6000-$7FFF  8 KiB PRG-RAM (save RAM on boards that have a battery)
+//   $8000-$FFFF  PRG-ROM, a 16 KiB image mirrored at $C000
+
+const CHR_RAM_SIZE: usize = 0x2000;
+
+// A PRG-ROM image the cartridge can't map. Without mappers only the two
+// NROM sizes work; anything else would need bank switching to be right.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PrgRomError {
+    Empty,
+    UnsupportedSize(usize),
+}
+
+impl fmt::Display for PrgRomError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrgRomError::Empty => write!(f, "PRG-ROM is empty"),
+            PrgRomError::UnsupportedSize(len) => write!(
+                f,
+                "PRG-ROM of {} bytes is not supported; only 16 KiB and 32 KiB images map without a mapper",
+                len
+            ),
+        }
+    }
+}
+
+impl core::error::Error for PrgRomError {}
+
+// How the board wires the PPU's two nametables into its four slots
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mirroring {
+    Horizontal, // $2000 = $2400, $2800 = $2C00 (vertical scrolling)
+    Vertical,   // $2000 = $2800, $2400 = $2C00 (horizontal scrolling)
+    FourScreen, // Extra VRAM on the cartridge gives four distinct tables
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cartridge {
+    prg_rom: Vec<u8>,
+    prg_mask: usize,         // CPU address bits that index prg_rom
+    prg_ram: [u8; 0x2000],   // $6000-$7FFF
+    chr: Vec<u8>,            // CHR-ROM, or CHR-RAM when the image has none
+    chr_is_ram: bool,
+    mirroring: Mirroring,
+    mapper: u8,
+    battery: bool,           // prg_ram survives power-off (a save file)
+}
+
+impl Cartridge {
+    // An image without CHR-ROM gets 8 KiB of CHR-RAM instead
+    pub fn new(prg_rom: Vec<u8>, chr_rom: Vec<u8>, mirroring: Mirroring, mapper: u8) -> Result<Self, PrgRomError> {
+        let chr_is_ram = chr_rom.is_empty();
+        Ok(Self {
+            prg_mask: prg_mask(prg_rom.len())?,
+            prg_rom,
+            prg_ram: [0; 0x2000],
+            chr: if chr_is_ram { vec![0; CHR_RAM_SIZE] } else { chr_rom },
+            chr_is_ram,
+            mirroring,
+            mapper,
+            battery: false,
+        })
+    }
+
+    // Just a PRG image on an NROM board, for tests and tools
+    pub fn from_prg(prg_rom: Vec<u8>) -> Result<Self, PrgRomError> {
+        Cartridge::new(prg_rom, Vec::new(), Mirroring::Horizontal, 0)
+    }
+
+    // $4020-$FFFF as the CPU reads it. Nothing here has read side effects,
+    // so this doubles as the peek.
+    pub fn cpu_read(&self, addr: u16) -> u8 {
+        match addr {
+            0x6000..=0x7FFF => self.prg_ram[(addr - 0x6000) as usize],
+            0x8000..=0xFFFF => self.prg_rom[addr as usize & self.prg_mask],
+            _ => 0, // Unmapped areas return 0
+        }
+    }
+
+    // PRG-ROM ignores writes
+    pub fn cpu_write(&mut self, addr: u16, value: u8) {
+        if let 0x6000..=0x7FFF = addr {
+            self.prg_ram[(addr - 0x6000) as usize] = value;
+        }
+    }
+
+    // Debugger write. On $8000-$FFFF it patches the PRG-ROM image; every
+    // mirror sees the patch and reloading the ROM undoes it.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match self.prg_offset(addr) {
+            Some(offset) => self.prg_rom[offset] = value,
+            None => self.cpu_write(addr, value),
+        }
+    }
+
+    // The pattern tables, $0000-$1FFF
+    pub fn ppu_read(&self, addr: u16) -> u8 {
+        self.chr[addr as usize % self.chr.len()]
+    }
+
+    // Only CHR-RAM takes writes
+    pub fn ppu_write(&mut self, addr: u16, value: u8) {
+        if self.chr_is_ram {
+            let len = self.chr.len();
+            self.chr[addr as usize % len] = value;
+        }
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+
+    pub fn mapper(&self) -> u8 {
+        self.mapper
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.battery
+    }
+
+    pub fn set_battery(&mut self, battery: bool) {
+        self.battery = battery;
+    }
+
+    pub fn prg_ram(&self) -> &[u8; 0x2000] {
+        &self.prg_ram
+    }
+
+    // Restore PRG-RAM from a save. A short image leaves the rest zeroed and
+    // a long one is cut off; returns whether the size was exactly right.
+    pub fn load_prg_ram(&mut self, data: &[u8]) -> bool {
+        let len = data.len().min(self.prg_ram.len());
+        self.prg_ram = [0; 0x2000];
+        self.prg_ram[..len].copy_from_slice(&data[..len]);
+        data.len() == self.prg_ram.len()
+    }
+
+    pub fn prg_rom_len(&self) -> usize {
+        self.prg_rom.len()
+    }
+
+    pub fn chr_is_ram(&self) -> bool {
+        self.chr_is_ram
+    }
+
+    // Translate a CPU address to an offset into PRG-ROM, for tools that
+    // track ROM bytes rather than addresses
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        match addr {
+            0x8000..=0xFFFF => Some(addr as usize & self.prg_mask),
+            _ => None,
+        }
+    }
+
+    // Inverse of prg_offset: the first CPU address a PRG-ROM offset is visible at
+    pub fn prg_addr(&self, offset: usize) -> Option<u16> {
+        if offset < self.prg_rom.len() && offset < 0x8000 {
+            Some(0x8000 + offset as u16)
+        } else {
+            None
+        }
+    }
+
+    // Swap in a new PRG-ROM image; on error the old one stays
+    pub fn load_prg_rom(&mut self, new_prg: Vec<u8>) -> Result<(), PrgRomError> {
+        self.prg_mask = prg_mask(new_prg.len())?;
+        self.prg_rom = new_prg;
+        Ok(())
+    }
+
+    // Power-on state: the RAMs cleared, the ROMs kept. Battery-backed
+    // PRG-RAM holds its contents through a power cycle.
+    pub fn reset(&mut self) {
+        if !self.battery {
+            self.prg_ram = [0; 0x2000];
+        }
+        if self.chr_is_ram {
+            self.chr.fill(0);
+        }
+    }
+
+    // First byte that differs from `other`, like Memory::diff
+    pub fn diff(&self, other: &Cartridge) -> Option<String> {
+        let regions: [(&str, &[u8], &[u8]); 3] = [
+            ("prg_ram", &self.prg_ram, &other.prg_ram),
+            ("prg_rom", &self.prg_rom, &other.prg_rom),
+            ("chr", &self.chr, &other.chr),
+        ];
+        for (name, ours, theirs) in regions {
+            if ours.len() != theirs.len() {
+                return Some(format!("{}: {} bytes vs {}", name, ours.len(), theirs.len()));
+            }
+            if let Some(i) = (0..ours.len()).find(|&i| ours[i] != theirs[i]) {
+                return Some(format!("{}[${:X}]: ${:02X} vs ${:02X}", name, i, ours[i], theirs[i]));
+            }
+        }
+        if self.mirroring != other.mirroring {
+            return Some(format!("mirroring: {:?} vs {:?}", self.mirroring, other.mirroring));
+        }
+        if self.battery != other.battery {
+            return Some(format!("battery: {} vs {}", self.battery, other.battery));
+        }
+        (self.mapper != other.mapper).then(|| format!("mapper: {} vs {}", self.mapper, other.mapper))
+    }
+}
+
+// $8000-$FFFF maps to PRG-ROM by masking the address: 32 KiB maps linearly
+// and 16 KiB appears twice. Bigger images need a mapper to pick the banks,
+// and a mask would silently show the wrong ones.
+fn prg_mask(len: usize) -> Result<usize, PrgRomError> {
+    match len {
+        0 => Err(PrgRomError::Empty),
+        0x4000 => Ok(0x3FFF),
+        0x8000 => Ok(0x7FFF),
+        _ => Err(PrgRomError::UnsupportedSize(len)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Each byte holds the high bits of its own offset, so reads show where
+    // in the image they landed
+    fn numbered_prg(len: usize) -> Vec<u8> {
+        (0..len).map(|offset| (offset >> 8) as u8).collect()
+    }
+
+    #[test]
+    fn empty_prg_rom_is_rejected() {
+        assert_eq!(Cartridge::from_prg(Vec::new()), Err(PrgRomError::Empty));
+    }
+
+    #[test]
+    fn sixteen_kib_is_mirrored_at_c000() {
+        let cartridge = Cartridge::from_prg(numbered_prg(0x4000)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000), 0x00);
+        assert_eq!(cartridge.cpu_read(0xBFFF), 0x3F);
+        assert_eq!(cartridge.cpu_read(0xFFFF), 0x3F);
+        assert_eq!(cartridge.prg_offset(0xC123), Some(0x0123));
+    }
+
+    #[test]
+    fn thirty_two_kib_maps_linearly() {
+        let cartridge = Cartridge::from_prg(numbered_prg(0x8000)).unwrap();
+        assert_eq!(cartridge.cpu_read(0x8000), 0x00);
+        assert_eq!(cartridge.cpu_read(0xC000), 0x40);
+        assert_eq!(cartridge.cpu_read(0xFFFF), 0x7F);
+        assert_eq!(cartridge.prg_offset(0xC123), Some(0x4123));
+    }
+
+    #[test]
+    fn other_sizes_need_a_mapper() {
+        for len in [0x2000, 0x6000, 0xC000] {
+            assert_eq!(Cartridge::from_prg(vec![0; len]), Err(PrgRomError::UnsupportedSize(len)));
+        }
+    }
+
+    #[test]
+    fn load_prg_rom_keeps_the_old_image_on_error() {
+        let mut cartridge = Cartridge::from_prg(numbered_prg(0x8000)).unwrap();
+        assert_eq!(cartridge.load_prg_rom(vec![0; 0xC000]), Err(PrgRomError::UnsupportedSize(0xC000)));
+        assert_eq!(cartridge.load_prg_rom(Vec::new()), Err(PrgRomError::Empty));
+        assert_eq!(cartridge.prg_rom_len(), 0x8000);
+        assert_eq!(cartridge.cpu_read(0xC000), 0x40);
+
+        cartridge.load_prg_rom(numbered_prg(0x4000)).unwrap();
+        assert_eq!(cartridge.cpu_read(0xC000), 0x00);
+    }
+}
@@ -0,0 +1,189 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::cpu::Cpu;
+use crate::mem;
+use crate::opcodes::{self, AddrMode};
+
+// Code/Data Logger producing FCEUX-compatible .cdl files. One byte per
+// PRG-ROM byte:
+//   bit 0   accessed as code (opcode or operand)
+//   bit 1   read as data
+//   bit 2-3 8 KiB CPU window it was mapped in when last accessed ($8000/$A000/$C000/$E000)
+//   bit 4   indirectly accessed as code (JMP ($xxxx) target)
+//   bit 5   indirectly accessed as data (($xx,X) / ($xx),Y target)
+pub const CDL_CODE: u8 = 0x01;
+pub const CDL_DATA: u8 = 0x02;
+pub const CDL_BANK_MASK: u8 = 0x0C;
+pub const CDL_INDIRECT_CODE: u8 = 0x10;
+pub const CDL_INDIRECT_DATA: u8 = 0x20;
+
+pub struct CdlSummary {
+    pub total: usize,
+    pub code: usize,
+    pub data: usize,
+}
+
+impl fmt::Display for CdlSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let percent = |n: usize| {
+            if self.total == 0 { 0.0 } else { n as f64 * 100.0 / self.total as f64 }
+        };
+        write!(
+            f,
+            "{} bytes: {:.2}% executed, {:.2}% read as data",
+            self.total,
+            percent(self.code),
+            percent(self.data)
+        )
+    }
+}
+
+pub struct CodeDataLogger {
+    log: Vec<u8>,
+    pub logging: bool,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_rom_len: usize) -> Self {
+        Self {
+            log: vec![0; prg_rom_len],
+            logging: true,
+        }
+    }
+
+    // Called before the instruction at PC executes
+    pub fn log_instruction(&mut self, cpu: &Cpu, memory: &mem::Memory) {
+        if !self.logging {
+            return;
+        }
+
+        let opcode = memory.peek(cpu.pc);
+        let Some(op) = opcodes::lookup(opcode) else {
+            self.mark(memory, cpu.pc, CDL_CODE);
+            return;
+        };
+
+        for i in 0..op.size() {
+            self.mark(memory, cpu.pc.wrapping_add(i), CDL_CODE);
+        }
+
+        match (op.mnemonic, op.mode) {
+            // Control flow and stores don't read their operand address
+            ("JMP", AddrMode::Absolute) | ("JSR", _) | ("STA", _) | ("STX", _) | ("STY", _) => {}
+            ("JMP", AddrMode::Indirect) => {
+                let pointer = memory.peek_u16(cpu.pc.wrapping_add(1));
+                self.mark(memory, pointer, CDL_DATA);
+                self.mark(memory, pointer.wrapping_add(1), CDL_DATA);
+                if let Some(target) = cpu.effective_address(memory, op.mode) {
+                    self.mark(memory, target, CDL_INDIRECT_CODE);
+                }
+            }
+            (_, AddrMode::Relative) => {}
+            (_, AddrMode::IndirectX | AddrMode::IndirectY) => {
+                if let Some(addr) = cpu.effective_address(memory, op.mode) {
+                    self.mark(memory, addr, CDL_DATA | CDL_INDIRECT_DATA);
+                }
+            }
+            (_, mode) => {
+                if let Some(addr) = cpu.effective_address(memory, mode) {
+                    self.mark(memory, addr, CDL_DATA);
+                }
+            }
+        }
+    }
+
+    fn mark(&mut self, memory: &mem::Memory, addr: u16, flags: u8) {
+        let Some(offset) = memory.prg_offset(addr) else {
+            return;
+        };
+        let Some(entry) = self.log.get_mut(offset) else {
+            return;
+        };
+        let bank = (((addr >> 13) & 0x03) as u8) << 2;
+        *entry = (*entry & !CDL_BANK_MASK) | bank | flags;
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.log
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, &self.log)
+    }
+
+    pub fn summary(&self) -> CdlSummary {
+        CdlSummary {
+            total: self.log.len(),
+            code: self.log.iter().filter(|&&b| b & CDL_CODE != 0).count(),
+            data: self.log.iter().filter(|&&b| b & CDL_DATA != 0).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    // Everything runs from $C000, the third 8 KiB window
+    const BANK: u8 = 2 << 2;
+
+    #[test]
+    fn marks_code_and_data_as_a_program_runs() {
+        let source = "
+            lda table
+            lda #<table+1
+            sta $20
+            lda #>table
+            sta $21
+            ldy #0
+            lda ($20),y
+            jmp (vector)
+            * = $C080
+     table: .byte 1, 2, 3
+            * = $C090
+    vector: .word target
+            * = $C0A0
+    target: jmp target";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.start_cdl();
+        for _ in 0..10 {
+            nes.step();
+        }
+
+        let cdl = nes.cdl().unwrap();
+        let log = cdl.data();
+        assert_eq!(log.len(), 0x4000);
+        assert!(log[..0x12].iter().all(|&b| b == CDL_CODE | BANK), "{:02X?}", &log[..0x12]);
+        assert_eq!(log[0x12], 0);
+        assert_eq!(log[0x80], CDL_DATA | BANK);
+        assert_eq!(log[0x81], CDL_DATA | CDL_INDIRECT_DATA | BANK);
+        assert_eq!(log[0x82], 0, "never read");
+        assert_eq!(&log[0x90..0x92], [CDL_DATA | BANK; 2]);
+        assert_eq!(log[0xA0], CDL_CODE | CDL_INDIRECT_CODE | BANK);
+        assert_eq!(&log[0xA1..0xA3], [CDL_CODE | BANK; 2]);
+
+        let summary = cdl.summary();
+        assert_eq!((summary.total, summary.code, summary.data), (0x4000, 0x15, 4));
+        assert_eq!(summary.to_string(), "16384 bytes: 0.13% executed, 0.02% read as data");
+    }
+
+    #[test]
+    fn stopping_pauses_the_log() {
+        let source = "lda #1\n lda #2\n spin: jmp spin";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.start_cdl();
+        nes.step();
+        nes.stop_cdl();
+        nes.step();
+        nes.start_cdl();
+        nes.step();
+        let log = nes.cdl().unwrap().data();
+        let code = CDL_CODE | BANK;
+        assert_eq!(&log[..7], [code, code, 0, 0, code, code, code]);
+    }
+}
@@ -0,0 +1,136 @@
+// A standard pad as the CPU sees it through $4016/$4017. Writing 1 to bit 0
+// of $4016 holds the shift register loading the buttons; once it drops
+// back to 0 each read shifts one out, A first:
+//
+//   A, B, Select, Start, Up, Down, Left, Right, then 1 forever
+//
+// While the strobe is held every read returns A.
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Controller {
+    buttons: u8, // Held now, bit 0 = A through bit 7 = Right
+    shift: u8,   // Latched buttons still to be read out, next in bit 0
+    strobe: bool,
+}
+
+impl Controller {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn buttons(&self) -> u8 {
+        self.buttons
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.buttons = buttons;
+        if self.strobe {
+            self.shift = buttons;
+        }
+    }
+
+    // Bit 0 of a $4016 write; both ports see it
+    pub fn write_strobe(&mut self, value: u8) {
+        self.strobe = value & 1 != 0;
+        if self.strobe {
+            self.shift = self.buttons;
+        }
+    }
+
+    // The serial bit a read returns, shifting to the next one. Ones come
+    // in behind the last button, as on an official pad.
+    pub fn read(&mut self) -> u8 {
+        if self.strobe {
+            return self.buttons & 1;
+        }
+        let bit = self.shift & 1;
+        self.shift = (self.shift >> 1) | 0x80;
+        bit
+    }
+
+    // What read would return, without shifting
+    pub fn peek(&self) -> u8 {
+        if self.strobe { self.buttons & 1 } else { self.shift & 1 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mem::Memory;
+
+    const A: u8 = 1 << 0;
+    const START: u8 = 1 << 3;
+    const LEFT: u8 = 1 << 6;
+
+    fn latched(buttons: u8) -> Controller {
+        let mut pad = Controller::new();
+        pad.set_buttons(buttons);
+        pad.write_strobe(1);
+        pad.write_strobe(0);
+        pad
+    }
+
+    #[test]
+    fn strobe_held_reads_a_every_time() {
+        let mut pad = Controller::new();
+        pad.set_buttons(A | LEFT);
+        pad.write_strobe(1);
+        for _ in 0..10 {
+            assert_eq!(pad.read(), 1);
+        }
+        pad.set_buttons(LEFT);
+        assert_eq!(pad.read(), 0);
+    }
+
+    #[test]
+    fn reads_come_out_a_first_right_last() {
+        // One button at a time, so each shows up in exactly its own slot
+        for button in 0..8 {
+            let mut pad = latched(1 << button);
+            let bits: Vec<u8> = (0..8).map(|_| pad.read()).collect();
+            let expected: Vec<u8> = (0..8).map(|i| (i == button) as u8).collect();
+            assert_eq!(bits, expected, "button bit {button}");
+        }
+    }
+
+    #[test]
+    fn reads_after_the_eighth_return_1() {
+        let mut pad = latched(START);
+        for _ in 0..8 {
+            pad.read();
+        }
+        for _ in 0..20 {
+            assert_eq!(pad.read(), 1);
+        }
+    }
+
+    // Changing the buttons after the latch doesn't change what's shifted out
+    #[test]
+    fn latch_holds_until_the_next_strobe() {
+        let mut pad = latched(A);
+        pad.set_buttons(0);
+        assert_eq!(pad.read(), 1);
+        pad.write_strobe(1);
+        pad.write_strobe(0);
+        assert_eq!(pad.read(), 0);
+    }
+
+    // The pad drives bit 0 only; the rest is the $40 left on the bus
+    #[test]
+    fn ports_read_back_open_bus_in_the_upper_bits() {
+        let mut memory = Memory::with_program(&[], 0x8000);
+        memory.set_controller_state(0, A);
+        memory.set_controller_state(1, A);
+        memory.write(0x4016, 1);
+        memory.write(0x4016, 0);
+        assert_eq!(memory.read(0x4016), 0x41);
+        assert_eq!(memory.read(0x4017), 0x41);
+        assert_eq!(memory.read(0x4017), 0x40);
+        for _ in 0..6 {
+            memory.read(0x4017);
+        }
+        assert_eq!(memory.read(0x4017), 0x41);
+        assert_eq!(memory.read(0x4016), 0x40); // Port 1 shifts on its own
+    }
+}
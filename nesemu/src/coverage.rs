@@ -0,0 +1,169 @@
+use crate::disasm;
+use crate::mem;
+use crate::opcodes;
+
+const BANK_SIZE: usize = 0x1000;
+const REPORT_GAPS: usize = 10;
+const GAP_CONTEXT_LINES: usize = 4;
+
+// Bitset of executed PRG-ROM offsets (opcode and operand bytes)
+pub struct Coverage {
+    bits: Vec<u64>,
+    len: usize,
+}
+
+impl Coverage {
+    pub fn new(prg_rom_len: usize) -> Self {
+        Self {
+            bits: vec![0; prg_rom_len.div_ceil(64)],
+            len: prg_rom_len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn contains(&self, offset: usize) -> bool {
+        offset < self.len && self.bits[offset / 64] & (1 << (offset % 64)) != 0
+    }
+
+    pub fn insert(&mut self, offset: usize) {
+        if offset < self.len {
+            self.bits[offset / 64] |= 1 << (offset % 64);
+        }
+    }
+
+    pub fn count(&self) -> usize {
+        self.bits.iter().map(|word| word.count_ones() as usize).sum()
+    }
+
+    fn count_range(&self, start: usize, end: usize) -> usize {
+        (start..end).filter(|&offset| self.contains(offset)).count()
+    }
+
+    // Called before the instruction at `pc` executes
+    pub fn log_instruction(&mut self, memory: &mem::Memory, pc: u16) {
+        let size = opcodes::lookup(memory.peek(pc)).map_or(1, |op| op.size());
+        for i in 0..size {
+            if let Some(offset) = memory.prg_offset(pc.wrapping_add(i)) {
+                self.insert(offset);
+            }
+        }
+    }
+
+    // Runs of never-executed offsets as (start, length), largest first
+    pub fn gaps(&self) -> Vec<(usize, usize)> {
+        let mut gaps = Vec::new();
+        let mut start = None;
+        for offset in 0..=self.len {
+            let executed = offset == self.len || self.contains(offset);
+            match (start, executed) {
+                (None, false) => start = Some(offset),
+                (Some(s), true) => {
+                    gaps.push((s, offset - s));
+                    start = None;
+                }
+                _ => {}
+            }
+        }
+        gaps.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        gaps
+    }
+
+    pub fn report(&self, memory: &mem::Memory) -> String {
+        let percent = |n: usize, total: usize| {
+            if total == 0 { 0.0 } else { n as f64 * 100.0 / total as f64 }
+        };
+
+        let mut out = format!(
+            "PRG-ROM coverage: {} / {} bytes executed ({:.2}%)\n\n",
+            self.count(),
+            self.len,
+            percent(self.count(), self.len)
+        );
+
+        out.push_str("Bank  Offsets      Executed\n");
+        for (bank, start) in (0..self.len).step_by(BANK_SIZE).enumerate() {
+            let end = (start + BANK_SIZE).min(self.len);
+            let executed = self.count_range(start, end);
+            out.push_str(&format!(
+                "{:<4}  {:05X}-{:05X}  {:6.2}%\n",
+                bank,
+                start,
+                end - 1,
+                percent(executed, end - start)
+            ));
+        }
+
+        out.push_str("\nLargest never-executed gaps:\n");
+        for (start, len) in self.gaps().into_iter().take(REPORT_GAPS) {
+            out.push_str(&format!("  offset {:05X}-{:05X} ({} bytes)", start, start + len - 1, len));
+            match memory.prg_addr(start) {
+                Some(addr) => {
+                    out.push_str(&format!(" at ${:04X}\n", addr));
+                    for line in disasm::disassemble(memory, addr, GAP_CONTEXT_LINES) {
+                        out.push_str(&format!("    {}\n", line));
+                    }
+                }
+                None => out.push('\n'),
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    #[test]
+    fn gaps_come_largest_first() {
+        let mut coverage = Coverage::new(100);
+        for offset in (0..10).chain(20..30).chain(35..40).chain([99, 100]) {
+            coverage.insert(offset);
+        }
+        assert_eq!(coverage.count(), 26, "offset 100 is past the end");
+        assert!(coverage.contains(99) && !coverage.contains(10));
+        assert_eq!(coverage.gaps(), [(40, 59), (10, 10), (30, 5)]);
+    }
+
+    // 8 of 16 KiB run, in two pieces with 11 bytes skipped between them
+    #[test]
+    fn report_gives_the_percentages_and_gaps() {
+        let source = "
+            lda #1
+            jmp skip
+            * = $C010
+     skip:  jmp skip";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.enable_coverage();
+        for _ in 0..4 {
+            nes.step();
+        }
+
+        let coverage = nes.coverage().unwrap();
+        assert_eq!(coverage.gaps()[..2], [(0x13, 0x3FED), (0x05, 0x0B)]);
+        let report = coverage.report(&nes.memory);
+        let expected = "\
+PRG-ROM coverage: 8 / 16384 bytes executed (0.05%)
+
+Bank  Offsets      Executed
+0     00000-00FFF    0.20%
+1     01000-01FFF    0.00%
+2     02000-02FFF    0.00%
+3     03000-03FFF    0.00%
+
+Largest never-executed gaps:
+  offset 00013-03FFF (16365 bytes) at $8013
+";
+        assert!(report.starts_with(expected), "{report}");
+        assert!(report.contains("\n  offset 00005-0000F (11 bytes) at $8005\n    8005  00"), "{report}");
+    }
+}
@@ -1,7 +1,15 @@
-use std::{fs::{File, OpenOptions}, io::Write};
-
-use crate::mem;
-
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::bus::Bus;
+use crate::opcodes::{self, AddrMode};
+use crate::verify;
+
+#[derive(Debug, Clone)]
 pub struct Cpu {
     pub pc: u16,     // Program Counter
     pub sp: u8,      // Stack Pointer
@@ -9,6 +17,42 @@ pub struct Cpu {
     pub x: u8,       // X Register
     pub y: u8,       // Y Register
     pub status: u8,  // Processor Status
+    pub cycles: u64, // Total CPU cycles executed
+    nmi_line: bool,    // Level of the /NMI input, as last set
+    nmi_pending: bool, // Latched on the line's rising edge, cleared when taken
+    halted: bool,      // Jammed by a JAM opcode; only reset recovers
+    delayed_i: Option<bool>, // I as the IRQ poll sees it right after CLI, SEI or PLP
+    nmi_line_after_poll: Option<bool>, // /NMI level to apply once the next step has polled
+    busy_cycles: u64, // Cycles still to come of the instruction tick started
+    verify: bool,     // Cross-check ALU results against verify.rs
+    exec: ExecInfo,          // Filled in by the handlers as the current step runs
+}
+
+// What one step did, so tooling doesn't have to decode the instruction again
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ExecInfo {
+    pub opcode: u8, // $00 when an interrupt was taken (the CPU runs it as a BRK) or when jammed
+    pub len: u8,    // Instruction bytes; 0 when no instruction ran
+    pub addr: Option<u16>, // Effective address, for modes that have one (JMP's target included)
+    pub value: Option<u8>, // The operand read, or the value written (an RMW's result)
+    pub branch_taken: bool,
+    pub interrupt: Option<Interrupt>,
+    pub oam_dma: Option<u8>, // Page the instruction started an OAM DMA from; the stall is in `cycles`
+    pub cycles: u16,
+}
+
+// The programmer-visible registers and the cycle count, for save states and
+// lockstep comparisons. Interrupt latches and the jam state aren't included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CpuState {
+    pub pc: u16,
+    pub sp: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub status: u8,
+    pub cycles: u64,
 }
 
 // 6502 Status Flag Constants
@@ -21,139 +65,423 @@ const UNUSED_FLAG: u8 = 0b0010_0000;    // Bit 5
 const OVERFLOW_FLAG: u8 = 0b0100_0000;  // Bit 6
 const NEGATIVE_FLAG: u8 = 0b1000_0000;  // Bit 7
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Carry,
+    Zero,
+    InterruptDisable,
+    Decimal,
+    Break,
+    Unused,
+    Overflow,
+    Negative,
+}
+
+impl Flag {
+    pub fn mask(self) -> u8 {
+        match self {
+            Flag::Carry => CARRY_FLAG,
+            Flag::Zero => ZERO_FLAG,
+            Flag::InterruptDisable => INTERRUPT_FLAG,
+            Flag::Decimal => DECIMAL_FLAG,
+            Flag::Break => BREAK_FLAG,
+            Flag::Unused => UNUSED_FLAG,
+            Flag::Overflow => OVERFLOW_FLAG,
+            Flag::Negative => NEGATIVE_FLAG,
+        }
+    }
+}
+
+// Their change to I lands after the IRQ poll, so it takes effect one
+// instruction late
+const PLP: u8 = 0x28;
+const CLI: u8 = 0x58;
+const SEI: u8 = 0x78;
+
+// The CPU is suspended while OAM DMA copies 256 bytes, plus one cycle to
+// line up with reads when the DMA starts on an odd cycle
+const OAM_DMA_CYCLES: u64 = 513;
+
+const NMI_VECTOR: u16 = 0xFFFA;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    Nmi,
+    Irq,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpuError {
+    UnknownOpcode { opcode: u8, pc: u16 },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CpuError::UnknownOpcode { opcode, pc } => write!(f, "unknown opcode ${:02X} at ${:04X}", opcode, pc),
+        }
+    }
+}
+
+impl core::error::Error for CpuError {}
+
 
 impl Cpu {
     pub fn new() -> Self {
         Self {
             pc: 0,
-            sp: 0xFD,
+            sp: 0x00, // The reset sequence leaves it at $FD
             a: 0,
             x: 0,
             y: 0,
             status: 0x24, // unused & interrupt disable flags set
+            cycles: 0,
+            nmi_line: false,
+            nmi_pending: false,
+            halted: false,
+            delayed_i: None,
+            nmi_line_after_poll: None,
+            busy_cycles: 0,
+            verify: false,
+            exec: ExecInfo::default(),
+        }
+    }
+
+    pub fn snapshot(&self) -> CpuState {
+        CpuState {
+            pc: self.pc,
+            sp: self.sp,
+            a: self.a,
+            x: self.x,
+            y: self.y,
+            status: self.status,
+            cycles: self.cycles,
+        }
+    }
+
+    pub fn restore(&mut self, state: &CpuState) {
+        self.pc = state.pc;
+        self.sp = state.sp;
+        self.a = state.a;
+        self.x = state.x;
+        self.y = state.y;
+        self.status = state.status;
+        self.cycles = state.cycles;
+    }
+
+    // First register that differs from `other`, e.g. "pc: $8003 vs $8000"
+    pub fn diff(&self, other: &Cpu) -> Option<String> {
+        let registers = [
+            ("pc", self.pc, other.pc),
+            ("sp", self.sp as u16, other.sp as u16),
+            ("a", self.a as u16, other.a as u16),
+            ("x", self.x as u16, other.x as u16),
+            ("y", self.y as u16, other.y as u16),
+            ("status", self.status as u16, other.status as u16),
+        ];
+        if let Some((name, ours, theirs)) = registers.iter().find(|(_, ours, theirs)| ours != theirs) {
+            return Some(format!("{}: ${:02X} vs ${:02X}", name, ours, theirs));
+        }
+        if self.cycles != other.cycles {
+            return Some(format!("cycles: {} vs {}", self.cycles, other.cycles));
+        }
+        if self.nmi_line != other.nmi_line {
+            return Some(format!("nmi_line: {} vs {}", self.nmi_line, other.nmi_line));
+        }
+        if self.nmi_pending != other.nmi_pending {
+            return Some(format!("nmi_pending: {} vs {}", self.nmi_pending, other.nmi_pending));
+        }
+        if self.halted != other.halted {
+            return Some(format!("halted: {} vs {}", self.halted, other.halted));
+        }
+        if self.delayed_i != other.delayed_i {
+            return Some(format!("delayed_i: {:?} vs {:?}", self.delayed_i, other.delayed_i));
+        }
+        if self.nmi_line_after_poll != other.nmi_line_after_poll {
+            return Some(format!(
+                "nmi_line_after_poll: {:?} vs {:?}",
+                self.nmi_line_after_poll, other.nmi_line_after_poll
+            ));
+        }
+        (self.busy_cycles != other.busy_cycles)
+            .then(|| format!("busy_cycles: {} vs {}", self.busy_cycles, other.busy_cycles))
+    }
+
+    // Registers as given, everything else as after new(); for tests that
+    // want to start mid-program without a reset
+    pub fn with_state(pc: u16, sp: u8, a: u8, x: u8, y: u8, status: u8) -> Self {
+        Self {
+            pc,
+            sp,
+            a,
+            x,
+            y,
+            status,
+            ..Cpu::new()
+        }
+    }
+
+    // The state after switching the console on: the reset sequence run
+    // from the power-up registers
+    pub fn power_on<B: Bus>(bus: &mut B) -> Self {
+        let mut cpu = Cpu::new();
+        cpu.reset(bus);
+        cpu
+    }
+
+    // The reset sequence, at power-on or from the Reset button. It runs like
+    // an interrupt with the stack writes suppressed: SP still drops by 3, I
+    // is set and it takes 7 cycles. A, X and Y are left alone. An
+    // instruction tick was partway through is abandoned.
+    pub fn reset<B: Bus>(&mut self, bus: &mut B) {
+        self.halted = false;
+        self.delayed_i = None;
+        self.busy_cycles = 0;
+        self.sp = self.sp.wrapping_sub(3);
+        self.set_flag(Flag::InterruptDisable, true);
+        self.pc = bus.read_u16(0xFFFC);
+        self.cycles += 7;
+    }
+
+    // NMI is edge-triggered: raising the line latches one NMI, holding it
+    // high does nothing more, and it has to drop before it can fire again
+    pub fn set_nmi_line(&mut self, asserted: bool) {
+        if asserted && !self.nmi_line {
+            self.nmi_pending = true;
+        }
+        self.nmi_line = asserted;
+    }
+
+    // Change /NMI early in the next instruction, after the interrupt poll
+    // that came before it, so an NMI raised then is taken after the
+    // instruction. Raised early in a BRK it hijacks the vector fetch.
+    pub fn set_nmi_line_after_poll(&mut self, asserted: bool) {
+        self.nmi_line_after_poll = Some(asserted);
+    }
+
+    // Check ALU results against verify.rs, panicking on a mismatch (slow)
+    pub fn enable_verify(&mut self) {
+        self.verify = true;
+    }
+
+    pub fn disable_verify(&mut self) {
+        self.verify = false;
+    }
+
+    // A JAM opcode stopped the CPU; PC is left on it
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // An NMI has been latched and the next exec_next_instr will take it
+    pub fn nmi_pending(&self) -> bool {
+        self.nmi_pending
+    }
+
+    // What the next exec_next_instr takes instead of an instruction. /IRQ is
+    // level-triggered: while I is set it simply waits, and fires once I is
+    // clear with the line still asserted. That is straight after RTI, but
+    // only after one more instruction following CLI or PLP. Likewise an IRQ
+    // can still get in right after SEI.
+    pub fn pending_interrupt<B: Bus>(&self, bus: &B) -> Option<Interrupt> {
+        let irq_disabled = self.delayed_i.unwrap_or(self.get_flag(Flag::InterruptDisable));
+        if self.halted {
+            None
+        } else if self.nmi_pending {
+            Some(Interrupt::Nmi)
+        } else if bus.irq_asserted() && !irq_disabled {
+            Some(Interrupt::Irq)
+        } else {
+            None
+        }
+    }
+
+    // Take a non-maskable interrupt now
+    pub fn nmi<B: Bus>(&mut self, bus: &mut B) {
+        self.nmi_pending = false;
+        self.interrupt(bus, NMI_VECTOR);
+    }
+
+    // Take a maskable interrupt now, whatever the I flag says
+    pub fn irq<B: Bus>(&mut self, bus: &mut B) {
+        self.interrupt(bus, IRQ_VECTOR);
+    }
+
+    // Hardware interrupt sequence: like BRK, but the pushed status has B
+    // clear and PC is pushed as is
+    fn interrupt<B: Bus>(&mut self, bus: &mut B, vector: u16) {
+        self.push_u16(bus, self.pc);
+        self.push_u8(bus, (self.status & !BREAK_FLAG) | UNUSED_FLAG);
+        self.set_flag(Flag::InterruptDisable, true);
+        self.delayed_i = None;
+        self.pc = bus.read_u16(vector);
+        self.cycles += 7;
+    }
+
+    // Address the operand of the instruction at PC refers to, resolved with
+    // the current registers. Reads memory without side effects and does not
+    // change any state, so tools can call it before the instruction executes.
+    pub fn effective_address<B: Bus>(&self, bus: &B, mode: AddrMode) -> Option<u16> {
+        let operand = self.pc.wrapping_add(1);
+        let byte = bus.peek(operand);
+        let word = bus.peek_u16(operand);
+        match mode {
+            AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate => None,
+            AddrMode::ZeroPage => Some(byte as u16),
+            AddrMode::ZeroPageX => Some(byte.wrapping_add(self.x) as u16),
+            AddrMode::ZeroPageY => Some(byte.wrapping_add(self.y) as u16),
+            AddrMode::Absolute => Some(word),
+            AddrMode::AbsoluteX => Some(word.wrapping_add(self.x as u16)),
+            AddrMode::AbsoluteY => Some(word.wrapping_add(self.y as u16)),
+            AddrMode::Indirect => {
+                // Same page-wrap bug as JMP ($xxFF)
+                let hi_addr = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+                Some(((bus.peek(hi_addr) as u16) << 8) | bus.peek(word) as u16)
+            }
+            AddrMode::IndirectX => Some(bus.peek_zp_u16(byte.wrapping_add(self.x))),
+            AddrMode::IndirectY => Some(bus.peek_zp_u16(byte).wrapping_add(self.y as u16)),
+            AddrMode::Relative => {
+                Some(operand.wrapping_add(1).wrapping_add(byte as i8 as u16))
+            }
+        }
+    }
+
+    // The instruction at PC as a nestest.log line, without the PPU column:
+    //
+    //   C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7
+    //
+    // Memory operands show the value there now (for stores, the value about
+    // to be overwritten), and unofficial opcodes get a '*'. Only peeks at
+    // the bus, so tracing never disturbs the console.
+    pub fn trace<B: Bus>(&self, bus: &B) -> String {
+        let opcode = bus.peek(self.pc);
+        let (bytes, instruction) = match opcodes::lookup(opcode) {
+            Some(op) => {
+                let bytes: Vec<u8> = (0..op.size()).map(|i| bus.peek(self.pc.wrapping_add(i))).collect();
+                let prefix = if op.official { ' ' } else { '*' };
+                let operand = self.trace_operand(bus, op, &bytes);
+                (bytes, format!("{}{} {}", prefix, op.mnemonic, operand))
+            }
+            None => (vec![opcode], format!(" .byte ${:02X}", opcode)),
+        };
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        format!(
+            "{:04X}  {:<8} {:<33}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            self.pc,
+            hex.join(" "),
+            instruction.trim_end(),
+            self.a,
+            self.x,
+            self.y,
+            self.status,
+            self.sp,
+            self.cycles
+        )
+    }
+
+    // nestest's operand annotations: "@ addr" for the effective address of
+    // indexed modes, "= value" for what is there
+    fn trace_operand<B: Bus>(&self, bus: &B, op: &opcodes::Opcode, bytes: &[u8]) -> String {
+        let text = opcodes::format_operand(op.mode, self.pc, bytes);
+        let Some(addr) = self.effective_address(bus, op.mode) else {
+            return text;
+        };
+        let value = bus.peek(addr);
+        match op.mode {
+            AddrMode::ZeroPage | AddrMode::Absolute if matches!(op.mnemonic, "JMP" | "JSR") => text,
+            AddrMode::ZeroPage | AddrMode::Absolute => format!("{} = {:02X}", text, value),
+            AddrMode::ZeroPageX | AddrMode::ZeroPageY => format!("{} @ {:02X} = {:02X}", text, addr, value),
+            AddrMode::AbsoluteX | AddrMode::AbsoluteY => format!("{} @ {:04X} = {:02X}", text, addr, value),
+            AddrMode::Indirect => format!("{} = {:04X}", text, addr),
+            AddrMode::IndirectX => {
+                let pointer = bytes[1].wrapping_add(self.x);
+                format!("{} @ {:02X} = {:04X} = {:02X}", text, pointer, addr, value)
+            }
+            AddrMode::IndirectY => {
+                let base = bus.peek_zp_u16(bytes[1]);
+                format!("{} = {:04X} @ {:04X} = {:02X}", text, base, addr, value)
+            }
+            _ => text,
         }
     }
 
-    pub fn reset(&mut self, memory: &mem::Memory) {
-        self.pc = memory.read_u16(0xFFFC);
-        println!("CPU PC: ${:04X}",self.pc);
+    pub fn get_flag(&self, flag: Flag) -> bool {
+        self.status & flag.mask() != 0
+    }
 
+    pub fn set_flag(&mut self, flag: Flag, value: bool) {
+        if value {
+            self.status |= flag.mask();
+        } else {
+            self.status &= !flag.mask();
+        }
     }
 
     fn update_zero_and_negative_flags(&mut self, result: u8) {
-        self.status = (self.status & !(0b10 | 0b1000_0000))
-            | if result == 0 { 0b10 } else { 0 }
-            | if result & 0x80 != 0 { 0b1000_0000 } else { 0 };
+        self.set_flag(Flag::Zero, result == 0);
+        self.set_flag(Flag::Negative, result & 0x80 != 0);
     }
 
-    fn push_u8(&mut self, memory: &mut mem::Memory, val: u8) {
+    fn push_u8<B: Bus>(&mut self, bus: &mut B, val: u8) {
         let addr = 0x0100 | self.sp as u16;
-        memory.write(addr, val);
+        bus.write(addr, val);
         self.sp = self.sp.wrapping_sub(1);
     }
 
-    fn push_u16(&mut self, memory: &mut mem::Memory, val: u16) {
-        self.push_u8(memory, (val >> 8) as u8);
-        self.push_u8(memory, (val & 0xFF) as u8);
+    fn push_u16<B: Bus>(&mut self, bus: &mut B, val: u16) {
+        self.push_u8(bus, (val >> 8) as u8);
+        self.push_u8(bus, (val & 0xFF) as u8);
     }
 
-    fn pull_u16(&mut self, memory: &mut mem::Memory) -> u16 {
+    fn pull_u16<B: Bus>(&mut self, bus: &mut B) -> u16 {
         self.sp = self.sp.wrapping_add(1);
-        let lo = memory.read(0x0100 | self.sp as u16) as u16;
+        let lo = bus.read(0x0100 | self.sp as u16) as u16;
         self.sp = self.sp.wrapping_add(1);
-        let hi = memory.read(0x0100 | self.sp as u16) as u16;
+        let hi = bus.read(0x0100 | self.sp as u16) as u16;
         (hi << 8) | lo
     }
 
     // Helper method to pull processor status from stack
-    fn pull_status(&mut self, memory: &mut mem::Memory) {
+    fn pull_status<B: Bus>(&mut self, bus: &mut B) {
         self.sp = self.sp.wrapping_add(1);
-        let status = memory.read(0x0100 | self.sp as u16);
+        let status = bus.read(0x0100 | self.sp as u16);
         // Note: Bits 4 and 5 are ignored when pulled (except for PHP)
         self.status = (status & 0b11001111) | (self.status & 0b00110000);
     }
 
     // ADC implementation
-    fn adc(&mut self, memory: &mem::Memory, operand: u8) {
-        let carry = (self.status & 0b0000_0001) as u16; // Get carry flag
+    fn adc(&mut self, operand: u8) {
+        let carry = self.get_flag(Flag::Carry) as u16;
         let a = self.a as u16;
         let m = operand as u16;
         let result = a + m + carry;
-
-        // Update Carry flag (bit 0)
-        self.status = if result > 0xFF { 
-            self.status | 0b0000_0001 
-        } else { 
-            self.status & 0b1111_1110 
-        };
-
-        // Update Zero flag (bit 1)
         let result_u8 = result as u8;
-        self.status = if result_u8 == 0 { 
-            self.status | 0b0000_0010 
-        } else { 
-            self.status & 0b1111_1101 
-        };
-
-        // Update Negative flag (bit 7)
-        self.status = if result_u8 & 0x80 != 0 { 
-            self.status | 0b1000_0000 
-        } else { 
-            self.status & 0b0111_1111 
-        };
 
-        // Update Overflow flag (bit 6)
+        self.set_flag(Flag::Carry, result > 0xFF);
+        self.update_zero_and_negative_flags(result_u8);
         // Overflow occurs when the sign of both inputs is the same,
         // and different from the result's sign
-        let overflow = ((a ^ result) & (m ^ result) & 0x80) != 0;
-        self.status = if overflow { 
-            self.status | 0b0100_0000 
-        } else { 
-            self.status & 0b1011_1111 
-        };
+        self.set_flag(Flag::Overflow, (a ^ result) & (m ^ result) & 0x80 != 0);
 
         self.a = result_u8;
     }
 
     // SBC implementation
-    fn sbc(&mut self, memory: &mem::Memory, operand: u8) {
+    fn sbc(&mut self, operand: u8) {
         // Invert the carry flag for subtraction (we borrow if carry is 0)
-        let borrow = if (self.status & 0b0000_0001) == 0 { 1 } else { 0 };
+        let borrow = !self.get_flag(Flag::Carry) as u16;
         let a = self.a as u16;
         let m = operand as u16;
         let result = a.wrapping_sub(m).wrapping_sub(borrow);
-
-        // Update Carry flag (bit 0) - set if result >= 0
-        self.status = if result <= 0xFF {
-            self.status | 0b0000_0001 
-        } else { 
-            self.status & 0b1111_1110 
-        };
-
-        // Update Zero flag (bit 1)
         let result_u8 = result as u8;
-        self.status = if result_u8 == 0 { 
-            self.status | 0b0000_0010 
-        } else { 
-            self.status & 0b1111_1101 
-        };
-
-        // Update Negative flag (bit 7)
-        self.status = if result_u8 & 0x80 != 0 { 
-            self.status | 0b1000_0000 
-        } else { 
-            self.status & 0b0111_1111 
-        };
 
-        // Update Overflow flag (bit 6)
+        // Carry is set when no borrow was needed
+        self.set_flag(Flag::Carry, result <= 0xFF);
+        self.update_zero_and_negative_flags(result_u8);
         // Overflow occurs when the sign of the inputs differs and
         // the sign of the result differs from the accumulator
-        let overflow = ((a ^ m) & (a ^ result) & 0x80) != 0;
-        self.status = if overflow { 
-            self.status | 0b0100_0000 
-        } else { 
-            self.status & 0b1011_1111 
-        };
+        self.set_flag(Flag::Overflow, (a ^ m) & (a ^ result) & 0x80 != 0);
 
         self.a = result_u8;
     }
@@ -176,1583 +504,2066 @@ impl Cpu {
         self.update_zero_and_negative_flags(self.a);
     }
 
-    // BIT implementation
-    fn bit(&mut self, memory: &mem::Memory, operand: u8) {
-        // Set Zero flag based on A & operand
-        self.status = if (self.a & operand) == 0 {
-            self.status | 0b0000_0010  // Set Zero flag
-        } else {
-            self.status & 0b1111_1101  // Clear Zero flag
-        };
-
-        // Copy bit 7 of operand to Negative flag
-        self.status = if (operand & 0x80) != 0 {
-            self.status | 0b1000_0000  // Set Negative flag
-        } else {
-            self.status & 0b0111_1111  // Clear Negative flag
-        };
-
-        // Copy bit 6 of operand to Overflow flag
-        self.status = if (operand & 0x40) != 0 {
-            self.status | 0b0100_0000  // Set Overflow flag
-        } else {
-            self.status & 0b1011_1111  // Clear Overflow flag
-        };
+    // BIT implementation: Z from A & operand, N and V copied from bits 7
+    // and 6 of the operand
+    fn bit(&mut self, operand: u8) {
+        self.set_flag(Flag::Zero, self.a & operand == 0);
+        self.set_flag(Flag::Negative, operand & 0x80 != 0);
+        self.set_flag(Flag::Overflow, operand & 0x40 != 0);
     }
 
-
     // ASL implementation
-    fn asl(&mut self, memory: &mut mem::Memory, operand: u8, is_accumulator: bool) -> u8 {
+    fn asl(&mut self, operand: u8) -> u8 {
         let result = operand << 1;
-        
-        // Update Carry flag (bit 0) with the shifted-out bit
-        self.status = if (operand & 0x80) != 0 {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
-        
+        // Carry gets the shifted-out bit
+        self.set_flag(Flag::Carry, operand & 0x80 != 0);
         self.update_zero_and_negative_flags(result);
-        
         result
     }
 
-    fn lsr(&mut self, memory: &mut mem::Memory, operand: u8, is_accumulator: bool) -> u8 {
+    fn lsr(&mut self, operand: u8) -> u8 {
         let result = operand >> 1;
-        
-        // Update Carry flag (bit 0) with the shifted-out bit
-        self.status = if (operand & 0x01) != 0 {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
-        
-        //self.update_zero_and_negative_flags(result);
-        self.status &= 0b0111_1111;
-
+        self.set_flag(Flag::Carry, operand & 0x01 != 0);
+        // Bit 7 of the result is always 0, so N always ends up clear
+        self.update_zero_and_negative_flags(result);
         result
     }
 
     // ROL implementation
-    fn rol(&mut self, memory: &mut mem::Memory, operand: u8, is_accumulator: bool) -> u8 {
-        let carry_in = (self.status & 0b0000_0001) as u16;
-        let result = ((operand as u16) << 1) | carry_in;
-        
-        // Update Carry flag (bit 0) with the shifted-out bit (bit 7)
-        self.status = if (operand & 0x80) != 0 {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
-        
-        let result_u8 = result as u8;
-        self.update_zero_and_negative_flags(result_u8);
-        
-        result_u8
+    fn rol(&mut self, operand: u8) -> u8 {
+        let result = (operand << 1) | self.get_flag(Flag::Carry) as u8;
+        self.set_flag(Flag::Carry, operand & 0x80 != 0);
+        self.update_zero_and_negative_flags(result);
+        result
     }
 
     // ROR implementation
-    fn ror(&mut self, memory: &mut mem::Memory, operand: u8, is_accumulator: bool) -> u8 {
-        let carry_in = (self.status & 0b0000_0001) << 7; // Move carry to bit 7 position
-        let result = (operand >> 1) | carry_in;
-        
-        // Update Carry flag (bit 0) with the shifted-out bit (bit 0)
-        self.status = if (operand & 0x01) != 0 {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
-        
+    fn ror(&mut self, operand: u8) -> u8 {
+        let result = (operand >> 1) | (self.get_flag(Flag::Carry) as u8) << 7;
+        self.set_flag(Flag::Carry, operand & 0x01 != 0);
         self.update_zero_and_negative_flags(result);
         result
     }
 
-    // CMP implementation
+    // CMP, CPX and CPY: carry set if the register >= operand, Z and N from
+    // the difference
+    fn compare(&mut self, register: u8, operand: u8) {
+        self.set_flag(Flag::Carry, register >= operand);
+        self.update_zero_and_negative_flags(register.wrapping_sub(operand));
+    }
+
     fn cmp(&mut self, operand: u8) {
-        let a = self.a as u16;
-        let m = operand as u16;
-        let result = a.wrapping_sub(m);
+        self.compare(self.a, operand);
+    }
 
-        // Update Carry flag (bit 0) - set if A >= operand
-        self.status = if a >= m {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
+    fn cpx(&mut self, operand: u8) {
+        self.compare(self.x, operand);
+    }
 
-        // Update Zero flag (bit 1)
-        self.status = if (result as u8) == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & 0b1111_1101
-        };
+    fn cpy(&mut self, operand: u8) {
+        self.compare(self.y, operand);
+    }
 
-        // Update Negative flag (bit 7)
-        self.status = if (result as u8) & 0x80 != 0 {
-            self.status | 0b1000_0000
-        } else {
-            self.status & 0b0111_1111
+    // Read the operand bytes for `mode` and return the address they refer
+    // to, leaving PC on the next instruction. Immediate operands are
+    // addressed in place; branches get their target.
+    fn operand_address<B: Bus>(&mut self, bus: &mut B, mode: AddrMode) -> u16 {
+        let operand = self.pc;
+        self.pc = self.pc.wrapping_add(mode.operand_len());
+
+        let byte = |bus: &mut B| bus.read(operand);
+        let word = |bus: &mut B| bus.read_u16(operand);
+        let addr = match mode {
+            AddrMode::Implied | AddrMode::Accumulator => 0,
+            AddrMode::Immediate => operand,
+            AddrMode::ZeroPage => byte(bus) as u16,
+            AddrMode::ZeroPageX => byte(bus).wrapping_add(self.x) as u16,
+            AddrMode::ZeroPageY => byte(bus).wrapping_add(self.y) as u16,
+            AddrMode::Absolute => word(bus),
+            AddrMode::AbsoluteX => word(bus).wrapping_add(self.x as u16),
+            AddrMode::AbsoluteY => word(bus).wrapping_add(self.y as u16),
+            AddrMode::Indirect => {
+                // 6502 indirect jump has a bug with page boundaries:
+                // it doesn't carry over to the next page when fetching the high byte
+                let word = word(bus);
+                let hi_addr = (word & 0xFF00) | (word.wrapping_add(1) & 0x00FF);
+                ((bus.read(hi_addr) as u16) << 8) | bus.read(word) as u16
+            }
+            AddrMode::IndirectX => {
+                let ptr = byte(bus).wrapping_add(self.x);
+                bus.read_zp_u16(ptr)
+            }
+            AddrMode::IndirectY => {
+                let ptr = byte(bus);
+                bus.read_zp_u16(ptr).wrapping_add(self.y as u16)
+            }
+            AddrMode::Relative => self.pc.wrapping_add(byte(bus) as i8 as u16),
         };
+        if !matches!(mode, AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate | AddrMode::Relative) {
+            self.exec.addr = Some(addr);
+        }
+        addr
     }
 
-    // CPX implementation
-    fn cpx(&mut self, memory: &mem::Memory, operand: u8) {
-        let x = self.x as u16;
-        let m = operand as u16;
-        let result = x.wrapping_sub(m);
+    fn read_operand<B: Bus>(&mut self, bus: &mut B, mode: AddrMode) -> u8 {
+        let addr = self.operand_address(bus, mode);
+        let value = bus.read(addr);
+        self.exec.value = Some(value);
+        value
+    }
 
-        // Update Carry flag (bit 0) - set if X >= operand
-        self.status = if x >= m {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
+    fn store<B: Bus>(&mut self, bus: &mut B, mode: AddrMode, value: u8) {
+        let addr = self.operand_address(bus, mode);
+        bus.write(addr, value);
+        self.exec.value = Some(value);
+    }
 
-        // Update Zero flag (bit 1)
-        self.status = if (result as u8) == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & 0b1111_1101
-        };
+    // SHX, SHY, AHX and TAS store `value & (H + 1)`, H being the high byte
+    // of the address before indexing. When the index carries into the high
+    // byte, the stored value replaces that byte of the address too.
+    fn store_high_and<B: Bus>(&mut self, bus: &mut B, mode: AddrMode, value: u8) {
+        let addr = self.operand_address(bus, mode);
+        let index = if mode == AddrMode::AbsoluteX { self.x } else { self.y };
+        let base = addr.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if base & 0xFF00 != addr & 0xFF00 { (value as u16) << 8 | addr & 0x00FF } else { addr };
+        bus.write(addr, value);
+        self.exec.addr = Some(addr);
+        self.exec.value = Some(value);
+    }
 
-        // Update Negative flag (bit 7)
-        self.status = if (result as u8) & 0x80 != 0 {
-            self.status | 0b1000_0000
-        } else {
-            self.status & 0b0111_1111
+    // Whether adding the index to the operand at PC carries into the high
+    // byte. Called before operand_address moves PC past the operand.
+    fn crosses_page<B: Bus>(&self, bus: &B, mode: AddrMode) -> bool {
+        let (base, index) = match mode {
+            AddrMode::AbsoluteX => (bus.peek_u16(self.pc), self.x),
+            AddrMode::AbsoluteY => (bus.peek_u16(self.pc), self.y),
+            AddrMode::IndirectY => (bus.peek_zp_u16(bus.peek(self.pc)), self.y),
+            _ => return false,
         };
+        (base & 0xFF) + index as u16 > 0xFF
     }
 
-    // CPY implementation
-    fn cpy(&mut self, memory: &mem::Memory, operand: u8) {
-        let y = self.y as u16;
-        let m = operand as u16;
-        let result = y.wrapping_sub(m);
+    // Read-modify-write on A or memory. On memory the CPU writes the
+    // unmodified value back while it computes the result, so registers see
+    // two writes. The opcode table's cycle counts already include that one.
+    fn modify<B: Bus>(&mut self, bus: &mut B, mode: AddrMode, op: fn(&mut Cpu, u8) -> u8) {
+        if mode == AddrMode::Accumulator {
+            self.a = op(self, self.a);
+            return;
+        }
+        let addr = self.operand_address(bus, mode);
+        let value = bus.read(addr);
+        bus.write(addr, value);
+        let result = op(self, value);
+        bus.write(addr, result);
+        self.exec.value = Some(result);
+    }
 
-        // Update Carry flag (bit 0) - set if Y >= operand
-        self.status = if y >= m {
-            self.status | 0b0000_0001
-        } else {
-            self.status & 0b1111_1110
-        };
+    fn branch<B: Bus>(&mut self, bus: &mut B, mode: AddrMode, taken: bool) {
+        let target = self.operand_address(bus, mode);
+        self.exec.branch_taken = taken;
+        if taken {
+            // +1 for taking it, +1 more when it lands on another page
+            self.cycles += if self.pc & 0xFF00 == target & 0xFF00 { 1 } else { 2 };
+            self.pc = target;
+        }
+    }
 
-        // Update Zero flag (bit 1)
-        self.status = if (result as u8) == 0 {
-            self.status | 0b0000_0010
-        } else {
-            self.status & 0b1111_1101
-        };
+    fn verify_alu(&self, opcode: u8, before: &CpuState) {
+        let Some(op) = opcodes::lookup(opcode) else { return };
+        let operand = self.exec.value.unwrap_or(before.a);
+        if let Err(mismatch) = verify::check(op.mnemonic, op.mode, before, operand, &self.snapshot()) {
+            panic!("CPU verify failed: {}", mismatch);
+        }
+    }
 
-        // Update Negative flag (bit 7)
-        self.status = if (result as u8) & 0x80 != 0 {
-            self.status | 0b1000_0000
-        } else {
-            self.status & 0b0111_1111
-        };
+    // Returns the cycles taken, any OAM DMA stall included; see step for the
+    // details. A pending interrupt is taken in place of the next
+    // instruction; the handler's first instruction runs on the following
+    // call. A halted CPU only lets the clock run on, so frame loops still
+    // make progress. An unknown opcode changes nothing, PC included, and
+    // what to do about it is up to the caller.
+    pub fn exec_next_instr<B: Bus>(&mut self, bus: &mut B) -> Result<u16, CpuError> {
+        let start = self.cycles;
+        self.tick(bus)?;
+        while self.busy_cycles > 0 {
+            self.tick(bus)?;
+        }
+        Ok((self.cycles - start) as u16)
     }
 
+    // Advance exactly one cycle. For now the whole instruction runs on its
+    // first cycle and the rest only move the clock, so its memory accesses
+    // all happen then. An unknown opcode leaves the clock where it was.
+    pub fn tick<B: Bus>(&mut self, bus: &mut B) -> Result<(), CpuError> {
+        if self.busy_cycles == 0 {
+            let start = self.cycles;
+            self.step(bus)?;
+            self.busy_cycles = self.cycles - start;
+            self.cycles = start;
+        }
+        self.busy_cycles -= 1;
+        self.cycles += 1;
+        Ok(())
+    }
 
-    pub fn exec_next_instr(&mut self, memory: &mut mem::Memory) {
-        let opcode = memory.read(self.pc);
-        self.pc = self.pc.wrapping_add(1);
+    // Between instructions, as opposed to partway through one tick started
+    pub fn at_instruction_boundary(&self) -> bool {
+        self.busy_cycles == 0
+    }
 
-        match opcode {
-            // ----- LDA,LDX,LDY Instructions -----
-            0xA9 => { // LDA Immediate
-                let value = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.a = value;
-                self.update_zero_and_negative_flags(self.a);
+    // exec_next_instr, also reporting what the step did. The rest of an
+    // instruction tick started is finished first, outside the report.
+    pub fn step<B: Bus>(&mut self, bus: &mut B) -> Result<ExecInfo, CpuError> {
+        self.cycles += core::mem::take(&mut self.busy_cycles);
+        let start = self.cycles;
+        self.exec = ExecInfo::default();
+        let interrupt = self.pending_interrupt(bus);
+        if let Some(asserted) = self.nmi_line_after_poll.take() {
+            self.set_nmi_line(asserted);
+        }
+        if self.halted {
+            self.cycles += 1;
+        } else {
+            match interrupt {
+                Some(Interrupt::Nmi) => self.nmi(bus),
+                Some(Interrupt::Irq) => self.irq(bus),
+                None => {
+                    let pc = self.pc;
+                    let opcode = bus.read(pc);
+                    let i = self.get_flag(Flag::InterruptDisable);
+                    let before = self.verify.then(|| self.snapshot());
+                    self.pc = pc.wrapping_add(1);
+                    if !Dispatch::<B>::TABLE[opcode as usize](self, bus) {
+                        self.pc = pc;
+                        return Err(CpuError::UnknownOpcode { opcode, pc });
+                    }
+                    if let Some(before) = before {
+                        self.verify_alu(opcode, &before);
+                    }
+                    self.delayed_i = matches!(opcode, PLP | CLI | SEI).then_some(i);
+                    self.exec.opcode = opcode;
+                    self.exec.len = opcodes::lookup(opcode).map_or(1, |op| op.size() as u8);
+                }
             }
-
-            0xA5 => { // LDA Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
+            self.exec.interrupt = interrupt;
+            if let Some(page) = bus.take_oam_dma() {
+                self.cycles += OAM_DMA_CYCLES + self.cycles % 2;
+                self.exec.oam_dma = Some(page);
             }
+        }
+        self.exec.cycles = (self.cycles - start) as u16;
+        Ok(self.exec)
+    }
 
-            0xB5 => { // LDA Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
-            }
+    // Run whole instructions until at least `budget` cycles have passed.
+    // Returns how far the last instruction overshot.
+    pub fn run_cycles<B: Bus>(&mut self, bus: &mut B, budget: u64) -> Result<u64, CpuError> {
+        let target = self.cycles + budget;
+        self.run_until(bus, target)
+    }
 
-            0xAD => { // LDA Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
+    // Run whole instructions until the cycle counter reaches `target_cycle`,
+    // returning the overshoot. A jammed CPU just has its clock moved on. On
+    // an unknown opcode the CPU stops in front of it.
+    pub fn run_until<B: Bus>(&mut self, bus: &mut B, target_cycle: u64) -> Result<u64, CpuError> {
+        while self.cycles < target_cycle {
+            if self.halted {
+                self.cycles = target_cycle;
+                break;
             }
+            self.exec_next_instr(bus)?;
+        }
+        Ok(self.cycles.saturating_sub(target_cycle))
+    }
+}
 
-            0xBD => { // LDA Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
-                // Optional: add cycle penalty if (base & 0xFF00) != (addr & 0xFF00)
-            }
+// The last step's ExecInfo is not state
+impl PartialEq for Cpu {
+    fn eq(&self, other: &Cpu) -> bool {
+        self.diff(other).is_none()
+    }
+}
 
-            0xB9 => { // LDA Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
-                // Optional: add cycle penalty if page crossed
-            }
+impl Eq for Cpu {}
 
-            0xA1 => { // LDA (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
-            }
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-            0xB1 => { // LDA (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
-                // Optional: cycle penalty on page cross
-            }
+// False for an opcode missing from the table
+type Handler<B> = fn(&mut Cpu, &mut B) -> bool;
+
+macro_rules! row {
+    ($hi:literal) => {
+        [
+            execute::<B, { $hi * 16 }>, execute::<B, { $hi * 16 + 1 }>,
+            execute::<B, { $hi * 16 + 2 }>, execute::<B, { $hi * 16 + 3 }>,
+            execute::<B, { $hi * 16 + 4 }>, execute::<B, { $hi * 16 + 5 }>,
+            execute::<B, { $hi * 16 + 6 }>, execute::<B, { $hi * 16 + 7 }>,
+            execute::<B, { $hi * 16 + 8 }>, execute::<B, { $hi * 16 + 9 }>,
+            execute::<B, { $hi * 16 + 10 }>, execute::<B, { $hi * 16 + 11 }>,
+            execute::<B, { $hi * 16 + 12 }>, execute::<B, { $hi * 16 + 13 }>,
+            execute::<B, { $hi * 16 + 14 }>, execute::<B, { $hi * 16 + 15 }>,
+        ]
+    };
+}
 
-            0xA2 => { // LDX Immediate
-                let value = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.x = value;
-                self.update_zero_and_negative_flags(self.x);
-            }
+// One entry per opcode byte, each specialised for that opcode's handler,
+// addressing mode and base cycle count from opcodes::OPCODES. There is one
+// table per bus type, so the handlers call the bus directly.
+struct Dispatch<B>(PhantomData<B>);
 
-            0xA6 => { // LDX Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                self.x = memory.read(addr);
-                self.update_zero_and_negative_flags(self.x);
-            }
+impl<B: Bus> Dispatch<B> {
+    const TABLE: &'static [Handler<B>; 256] = &flatten([
+        row!(0x0), row!(0x1), row!(0x2), row!(0x3), row!(0x4), row!(0x5), row!(0x6), row!(0x7),
+        row!(0x8), row!(0x9), row!(0xA), row!(0xB), row!(0xC), row!(0xD), row!(0xE), row!(0xF),
+    ]);
+}
 
-            0xB6 => { // LDX Zero Page,Y
-                let addr = memory.read(self.pc).wrapping_add(self.y) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                self.x = memory.read(addr);
-                self.update_zero_and_negative_flags(self.x);
-            }
+const fn flatten<B: Bus>(rows: [[Handler<B>; 16]; 16]) -> [Handler<B>; 256] {
+    let mut t: [Handler<B>; 256] = [execute::<B, 0>; 256];
+    let mut i = 0;
+    while i < 256 {
+        t[i] = rows[i / 16][i % 16];
+        i += 1;
+    }
+    t
+}
 
-            0xAE => { // LDX Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                self.x = memory.read(addr);
-                self.update_zero_and_negative_flags(self.x);
-            }
+fn execute<B: Bus, const OPCODE: u8>(cpu: &mut Cpu, bus: &mut B) -> bool {
+    let Some(op) = (const { opcodes::OPCODES[OPCODE as usize] }) else {
+        return false;
+    };
+    cpu.cycles += op.cycles as u64;
+    if op.page_cross && cpu.crosses_page(bus, op.mode) {
+        cpu.cycles += 1;
+    }
+    let handler = const { handler::<B>(OPCODE) };
+    handler(cpu, bus, op.mode);
+    true
+}
 
-            0xBE => { // LDX Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                self.x = memory.read(addr);
-                self.update_zero_and_negative_flags(self.x);
-                // Optional: add cycle penalty if page crossed
-            }
+// One function per mnemonic; the addressing mode comes from the opcode table.
+// A mnemonic added there without a handler here fails to compile.
+type OpFn<B> = fn(&mut Cpu, &mut B, AddrMode);
+
+const fn handler<B: Bus>(opcode: u8) -> OpFn<B> {
+    let Some(op) = &opcodes::OPCODES[opcode as usize] else {
+        return ops::nop;
+    };
+    match op.mnemonic.as_bytes() {
+        b"LDA" => ops::lda,
+        b"LDX" => ops::ldx,
+        b"LDY" => ops::ldy,
+        b"STA" => ops::sta,
+        b"STX" => ops::stx,
+        b"STY" => ops::sty,
+        b"TAX" => ops::tax,
+        b"TAY" => ops::tay,
+        b"TSX" => ops::tsx,
+        b"TXA" => ops::txa,
+        b"TXS" => ops::txs,
+        b"TYA" => ops::tya,
+        b"PHA" => ops::pha,
+        b"PHP" => ops::php,
+        b"PLA" => ops::pla,
+        b"PLP" => ops::plp,
+        b"ADC" => ops::adc,
+        b"SBC" => ops::sbc,
+        b"INC" => ops::inc,
+        b"INX" => ops::inx,
+        b"INY" => ops::iny,
+        b"DEC" => ops::dec,
+        b"DEX" => ops::dex,
+        b"DEY" => ops::dey,
+        b"AND" => ops::and,
+        b"ORA" => ops::ora,
+        b"EOR" => ops::eor,
+        b"BIT" => ops::bit,
+        b"ASL" => ops::asl,
+        b"LSR" => ops::lsr,
+        b"ROL" => ops::rol,
+        b"ROR" => ops::ror,
+        b"CMP" => ops::cmp,
+        b"CPX" => ops::cpx,
+        b"CPY" => ops::cpy,
+        b"JMP" => ops::jmp,
+        b"JSR" => ops::jsr,
+        b"RTS" => ops::rts,
+        b"BEQ" => ops::beq,
+        b"BNE" => ops::bne,
+        b"BCS" => ops::bcs,
+        b"BCC" => ops::bcc,
+        b"BMI" => ops::bmi,
+        b"BPL" => ops::bpl,
+        b"BVS" => ops::bvs,
+        b"BVC" => ops::bvc,
+        b"BRK" => ops::brk,
+        b"RTI" => ops::rti,
+        b"NOP" => ops::nop,
+        b"CLC" => ops::clc,
+        b"SEC" => ops::sec,
+        b"CLD" => ops::cld,
+        b"SED" => ops::sed,
+        b"CLI" => ops::cli,
+        b"SEI" => ops::sei,
+        b"CLV" => ops::clv,
+        b"LAX" => ops::lax,
+        b"SAX" => ops::sax,
+        b"SLO" => ops::slo,
+        b"RLA" => ops::rla,
+        b"SRE" => ops::sre,
+        b"RRA" => ops::rra,
+        b"DCP" => ops::dcp,
+        b"ISC" => ops::isc,
+        b"ANC" => ops::anc,
+        b"ALR" => ops::alr,
+        b"ARR" => ops::arr,
+        b"AXS" => ops::axs,
+        b"SHY" => ops::shy,
+        b"SHX" => ops::shx,
+        b"AHX" => ops::ahx,
+        b"TAS" => ops::tas,
+        b"LAS" => ops::las,
+        b"XAA" => ops::xaa,
+        b"JAM" => ops::jam,
+        _ => panic!("opcode table has a mnemonic with no handler"),
+    }
+}
 
-            0xA0 => { // LDY Immediate
-                let value = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.y = value;
-                self.update_zero_and_negative_flags(self.y);
-            }
+mod ops {
+    use super::*;
 
-            0xA4 => { // LDY Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                self.y = memory.read(addr);
-                self.update_zero_and_negative_flags(self.y);
-            }
+    // ----- Loads and stores -----
+    pub fn lda<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.a = cpu.read_operand(bus, mode);
+        cpu.update_zero_and_negative_flags(cpu.a);
+    }
 
-            0xB4 => { // LDY Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                self.y = memory.read(addr);
-                self.update_zero_and_negative_flags(self.y);
-            }
+    pub fn ldx<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.x = cpu.read_operand(bus, mode);
+        cpu.update_zero_and_negative_flags(cpu.x);
+    }
 
-            0xAC => { // LDY Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                self.y = memory.read(addr);
-                self.update_zero_and_negative_flags(self.y);
-            }
+    pub fn ldy<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.y = cpu.read_operand(bus, mode);
+        cpu.update_zero_and_negative_flags(cpu.y);
+    }
 
-            0xBC => { // LDY Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                self.y = memory.read(addr);
-                self.update_zero_and_negative_flags(self.y);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn sta<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store(bus, mode, cpu.a);
+    }
 
+    pub fn stx<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store(bus, mode, cpu.x);
+    }
 
+    pub fn sty<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store(bus, mode, cpu.y);
+    }
 
+    // ----- Transfers -----
+    pub fn tax<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.x = cpu.a;
+        cpu.update_zero_and_negative_flags(cpu.x);
+    }
 
-            0x00 => { // BRK (Software interrupt)
-                self.pc = self.pc.wrapping_add(1);
-                self.push_u16(memory, self.pc);
-                self.push_u8(memory, self.status | 0x10); // Set Break flag
-                self.status |= 0x04; // Set Interrupt Disable
-                self.pc = memory.read_u16(0xFFFE);
-            }
+    pub fn tay<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.y = cpu.a;
+        cpu.update_zero_and_negative_flags(cpu.y);
+    }
 
-            // ----- STA, STX, STY Instructions -----
-            // STA instructions
-            0x85 => { // STA Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                memory.write(addr, self.a);
-            }
+    pub fn tsx<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.x = cpu.sp;
+        cpu.update_zero_and_negative_flags(cpu.x);
+    }
 
-            0x95 => { // STA Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                memory.write(addr, self.a);
-            }
+    pub fn txa<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.a = cpu.x;
+        cpu.update_zero_and_negative_flags(cpu.a);
+    }
 
-            0x8D => { // STA Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                memory.write(addr, self.a);
-            }
+    // TXS does NOT update any flags
+    pub fn txs<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.sp = cpu.x;
+    }
 
-            0x9D => { // STA Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                memory.write(addr, self.a);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn tya<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.a = cpu.y;
+        cpu.update_zero_and_negative_flags(cpu.a);
+    }
 
-            0x99 => { // STA Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                memory.write(addr, self.a);
-                // Optional: add cycle penalty if page crossed
-            }
+    // ----- Stack -----
+    pub fn pha<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.push_u8(bus, cpu.a);
+    }
 
-            0x81 => { // STA (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                memory.write(addr, self.a);
-            }
+    // Pushed status has the Break flag and bit 5 set
+    pub fn php<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.push_u8(bus, cpu.status | BREAK_FLAG | UNUSED_FLAG);
+    }
 
-            0x91 => { // STA (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                memory.write(addr, self.a);
-                // Optional: cycle penalty on page cross
-            }
+    pub fn pla<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.sp = cpu.sp.wrapping_add(1);
+        cpu.a = bus.read(0x0100 | cpu.sp as u16);
+        cpu.update_zero_and_negative_flags(cpu.a);
+    }
 
-            // STX instructions
-            0x86 => { // STX Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                memory.write(addr, self.x);
-            }
+    pub fn plp<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.pull_status(bus);
+    }
 
-            0x96 => { // STX Zero Page,Y
-                let addr = memory.read(self.pc).wrapping_add(self.y) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                memory.write(addr, self.x);
-            }
+    // ----- Arithmetic and logic -----
+    pub fn adc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.adc(operand);
+    }
 
-            0x8E => { // STX Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                memory.write(addr, self.x);
-            }
+    pub fn sbc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.sbc(operand);
+    }
 
-            // STY instructions
-            0x84 => { // STY Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                memory.write(addr, self.y);
-            }
+    pub fn and<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.and(operand);
+    }
 
-            0x94 => { // STY Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                memory.write(addr, self.y);
-            }
+    pub fn ora<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.ora(operand);
+    }
 
-            0x8C => { // STY Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                memory.write(addr, self.y);
-            }
+    pub fn eor<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.eor(operand);
+    }
 
-            // ------ TRANSFER INSTRUCTIONS ------
-            0xAA => { // TAX (Transfer A to X)
-                self.x = self.a;
-                self.update_zero_and_negative_flags(self.x);
-            }
+    pub fn bit<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.bit(operand);
+    }
 
-            0xA8 => { // TAY (Transfer A to Y)
-                self.y = self.a;
-                self.update_zero_and_negative_flags(self.y);
-            }
+    pub fn cmp<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.cmp(operand);
+    }
 
-            0xBA => { // TSX (Transfer SP to X)
-                self.x = self.sp;
-                self.update_zero_and_negative_flags(self.x);
-            }
+    pub fn cpx<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.cpx(operand);
+    }
 
-            0x8A => { // TXA (Transfer X to A)
-                self.a = self.x;
-                self.update_zero_and_negative_flags(self.a);
-            }
+    pub fn cpy<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.cpy(operand);
+    }
 
-            0x9A => { // TXS (Transfer X to SP)
-                self.sp = self.x;
-                // Note: TXS does NOT update any flags
-            }
+    // ----- Increments, decrements and shifts -----
+    pub fn inc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = value.wrapping_add(1);
+            cpu.update_zero_and_negative_flags(result);
+            result
+        });
+    }
 
-            0x98 => { // TYA (Transfer Y to A)
-                self.a = self.y;
-                self.update_zero_and_negative_flags(self.a);
-            }
+    pub fn dec<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = value.wrapping_sub(1);
+            cpu.update_zero_and_negative_flags(result);
+            result
+        });
+    }
 
-            // stack operations
-            // ----- PHA, PHP, PLA, PLP Instructions -----
-            0x48 => { // PHA (Push Accumulator)
-                self.push_u8(memory, self.a);
-            }
+    pub fn inx<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.x = cpu.x.wrapping_add(1);
+        cpu.update_zero_and_negative_flags(cpu.x);
+    }
 
-            0x08 => { // PHP (Push Processor Status)
-                // Push status with Break flag and bit 5 set
-                let status = self.status | 0b0011_0000; // Set bits 4 and 5
-                self.push_u8(memory, status);
-            }
+    pub fn iny<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.y = cpu.y.wrapping_add(1);
+        cpu.update_zero_and_negative_flags(cpu.y);
+    }
 
-            0x68 => { // PLA (Pull Accumulator)
-                self.sp = self.sp.wrapping_add(1);
-                let addr = 0x0100 | self.sp as u16;
-                self.a = memory.read(addr);
-                self.update_zero_and_negative_flags(self.a);
-            }
-
-            0x28 => { // PLP (Pull Processor Status)
-                self.sp = self.sp.wrapping_add(1);
-                let addr = 0x0100 | self.sp as u16;
-                let status = memory.read(addr);
-                // Note: Break flag and bit 5 are ignored when pulled
-                self.status = (status & !0b0011_0000) | (self.status & 0b0011_0000);
-                // Alternative implementation that properly handles all flags:
-                // self.status = (status & 0b1100_1111) | 0b0010_0000; // Clear bits 4 and 5, set bit 5
-            }
-
-            0x69 => { // ADC Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.adc(memory, operand);
-            }
-
-            0x65 => { // ADC Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-            }
-
-            0x75 => { // ADC Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-            }
-
-            0x6D => { // ADC Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-            }
-
-            0x7D => { // ADC Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-                // Optional: add cycle penalty if page crossed
-            }
-
-            0x79 => { // ADC Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-                // Optional: add cycle penalty if page crossed
-            }
-
-            0x61 => { // ADC (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-            }
-
-            0x71 => { // ADC (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.adc(memory, operand);
-                // Optional: cycle penalty on page cross
-            }
-
-            // SBC instructions
-            0xE9 => { // SBC Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.sbc(memory, operand);
-            }
-
-            0xE5 => { // SBC Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-            }
+    pub fn dex<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.x = cpu.x.wrapping_sub(1);
+        cpu.update_zero_and_negative_flags(cpu.x);
+    }
 
-            0xF5 => { // SBC Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-            }
+    pub fn dey<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.y = cpu.y.wrapping_sub(1);
+        cpu.update_zero_and_negative_flags(cpu.y);
+    }
 
-            0xED => { // SBC Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-            }
+    pub fn asl<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, Cpu::asl);
+    }
 
-            0xFD => { // SBC Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn lsr<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, Cpu::lsr);
+    }
 
-            0xF9 => { // SBC Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn rol<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, Cpu::rol);
+    }
 
-            0xE1 => { // SBC (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-            }
+    pub fn ror<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, Cpu::ror);
+    }
 
-            0xF1 => { // SBC (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.sbc(memory, operand);
-                // Optional: cycle penalty on page cross
-            }
+    // ----- Jumps and subroutines -----
+    pub fn jmp<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.pc = cpu.operand_address(bus, mode);
+    }
 
+    // JSR pushes the address of the last byte of the instruction
+    pub fn jsr<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let target = cpu.operand_address(bus, mode);
+        cpu.push_u16(bus, cpu.pc.wrapping_sub(1));
+        cpu.pc = target;
+    }
 
-            // INC implementations
-            0xE6 => { // INC Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let value = memory.read(addr).wrapping_add(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-            }
+    // Return address + 1 corrects for JSR pushing its last byte
+    pub fn rts<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.pc = cpu.pull_u16(bus).wrapping_add(1);
+    }
 
-            0xF6 => { // INC Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let value = memory.read(addr).wrapping_add(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-            }
+    // ----- Branches -----
+    pub fn beq<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, cpu.get_flag(Flag::Zero));
+    }
 
-            0xEE => { // INC Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let value = memory.read(addr).wrapping_add(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-            }
+    pub fn bne<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, !cpu.get_flag(Flag::Zero));
+    }
 
-            0xFE => { // INC Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let value = memory.read(addr).wrapping_add(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn bcs<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, cpu.get_flag(Flag::Carry));
+    }
 
-            // INX implementation
-            0xE8 => { // INX (Increment X Register)
-                self.x = self.x.wrapping_add(1);
-                self.update_zero_and_negative_flags(self.x);
-            }
+    pub fn bcc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, !cpu.get_flag(Flag::Carry));
+    }
 
-            // INY implementation
-            0xC8 => { // INY (Increment Y Register)
-                self.y = self.y.wrapping_add(1);
-                self.update_zero_and_negative_flags(self.y);
-            }
+    pub fn bmi<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, cpu.get_flag(Flag::Negative));
+    }
 
-            // DEC implementations
-            0xC6 => { // DEC Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let value = memory.read(addr).wrapping_sub(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-            }
+    pub fn bpl<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, !cpu.get_flag(Flag::Negative));
+    }
 
-            0xD6 => { // DEC Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let value = memory.read(addr).wrapping_sub(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-            }
+    pub fn bvs<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, cpu.get_flag(Flag::Overflow));
+    }
 
-            0xCE => { // DEC Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let value = memory.read(addr).wrapping_sub(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-            }
+    pub fn bvc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.branch(bus, mode, !cpu.get_flag(Flag::Overflow));
+    }
 
-            0xDE => { // DEC Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let value = memory.read(addr).wrapping_sub(1);
-                memory.write(addr, value);
-                self.update_zero_and_negative_flags(value);
-                // Optional: add cycle penalty if page crossed
-            }
+    // ----- Interrupts, MAY HAVE ERRORS -----
+    pub fn brk<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.pc = cpu.pc.wrapping_add(1); // Skip next byte (BRK padding)
+        cpu.push_u16(bus, cpu.pc);
+        cpu.push_u8(bus, cpu.status | BREAK_FLAG | UNUSED_FLAG);
+        cpu.set_flag(Flag::InterruptDisable, true);
+        // An NMI latched before the vector fetch hijacks it: the pushes were
+        // BRK's, B included, but the NMI handler runs. An IRQ arriving now
+        // needs nothing special, as BRK shares its vector and sets I.
+        let vector = if core::mem::take(&mut cpu.nmi_pending) { NMI_VECTOR } else { IRQ_VECTOR };
+        cpu.pc = bus.read_u16(vector);
+    }
 
-            // DEX implementation (to complement DEC)
-            0xCA => { // DEX (Decrement X Register)
-                self.x = self.x.wrapping_sub(1);
-                self.update_zero_and_negative_flags(self.x);
-            }
+    pub fn rti<B: Bus>(cpu: &mut Cpu, bus: &mut B, _: AddrMode) {
+        cpu.pull_status(bus);
+        cpu.pc = cpu.pull_u16(bus);
+    }
 
-            // DEY implementation (to complement DEC)
-            0x88 => { // DEY (Decrement Y Register)
-                self.y = self.y.wrapping_sub(1);
-                self.update_zero_and_negative_flags(self.y);
-            }
+    // Official and unofficial NOPs; the latter skip their operand bytes
+    pub fn nop<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.operand_address(bus, mode);
+    }
 
-            // AND
-            0x29 => { // AND Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.and(operand);
-            }
+    // ----- Flags -----
+    pub fn clc<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::Carry, false);
+    }
 
-            0x25 => { // AND Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.and(operand);
-            }
+    pub fn sec<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::Carry, true);
+    }
 
-            0x35 => { // AND Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.and(operand);
-            }
+    pub fn cld<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::Decimal, false);
+    }
 
-            0x2D => { // AND Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.and(operand);
-            }
+    pub fn sed<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::Decimal, true);
+    }
 
-            0x3D => { // AND Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                self.and(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn cli<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::InterruptDisable, false);
+    }
 
-            0x39 => { // AND Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.and(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn sei<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::InterruptDisable, true);
+    }
 
-            0x21 => { // AND (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.and(operand);
-            }
+    pub fn clv<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.set_flag(Flag::Overflow, false);
+    }
 
-            0x31 => { // AND (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.and(operand);
-                // Optional: cycle penalty on page cross
-            }
+    // ----- Unofficial -----
+    pub fn lax<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.a = cpu.read_operand(bus, mode);
+        cpu.x = cpu.a;
+        cpu.update_zero_and_negative_flags(cpu.a);
+    }
 
-            0x09 => { // ORA Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.ora(operand);
-            }
+    // SAX stores A & X and leaves the flags alone
+    pub fn sax<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store(bus, mode, cpu.a & cpu.x);
+    }
 
-            0x05 => { // ORA Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.ora(operand);
-            }
+    // Read-modify-writes that also combine the result into A. Their table
+    // cycle counts are the RMW ones, so indexed forms never add a cycle.
+    pub fn slo<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = cpu.asl(value);
+            cpu.ora(result);
+            result
+        });
+    }
 
-            0x15 => { // ORA Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.ora(operand);
-            }
+    pub fn rla<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = cpu.rol(value);
+            cpu.and(result);
+            result
+        });
+    }
 
-            0x0D => { // ORA Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.ora(operand);
-            }
+    pub fn sre<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = cpu.lsr(value);
+            cpu.eor(result);
+            result
+        });
+    }
 
-            0x1D => { // ORA Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                self.ora(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    // ADC sees the carry ROR just shifted out
+    pub fn rra<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = cpu.ror(value);
+            cpu.adc(result);
+            result
+        });
+    }
 
-            0x19 => { // ORA Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.ora(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn dcp<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = value.wrapping_sub(1);
+            cpu.cmp(result);
+            result
+        });
+    }
 
-            0x01 => { // ORA (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.ora(operand);
-            }
+    pub fn isc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.modify(bus, mode, |cpu, value| {
+            let result = value.wrapping_add(1);
+            cpu.sbc(result);
+            result
+        });
+    }
 
-            0x11 => { // ORA (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.ora(operand);
-                // Optional: cycle penalty on page cross
-            }
+    // AND, then N is copied into C
+    pub fn anc<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.and(operand);
+        cpu.set_flag(Flag::Carry, cpu.get_flag(Flag::Negative));
+    }
 
-            0x49 => { // EOR Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.eor(operand);
-            }
+    // AND, then LSR A
+    pub fn alr<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.and(operand);
+        cpu.a = cpu.lsr(cpu.a);
+    }
 
-            0x45 => { // EOR Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.eor(operand);
-            }
+    // AND, then ROR A, except C comes from bit 6 of the result and V from
+    // bit 6 xor bit 5
+    pub fn arr<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.and(operand);
+        cpu.a = cpu.ror(cpu.a);
+        let bit6 = cpu.a & 0x40 != 0;
+        let bit5 = cpu.a & 0x20 != 0;
+        cpu.set_flag(Flag::Carry, bit6);
+        cpu.set_flag(Flag::Overflow, bit6 != bit5);
+    }
 
-            0x55 => { // EOR Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.eor(operand);
-            }
+    // X = (A & X) - operand, setting C/Z/N like CMP; no borrow in, V untouched
+    pub fn axs<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        let value = cpu.a & cpu.x;
+        cpu.x = value.wrapping_sub(operand);
+        cpu.compare(value, operand);
+    }
 
-            0x4D => { // EOR Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.eor(operand);
-            }
+    pub fn shy<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store_high_and(bus, mode, cpu.y);
+    }
 
-            0x5D => { // EOR Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                self.eor(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn shx<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store_high_and(bus, mode, cpu.x);
+    }
 
-            0x59 => { // EOR Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.eor(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    pub fn ahx<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.store_high_and(bus, mode, cpu.a & cpu.x);
+    }
 
-            0x41 => { // EOR (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.eor(operand);
-            }
+    // SP = A & X, then stored like AHX
+    pub fn tas<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        cpu.sp = cpu.a & cpu.x;
+        cpu.store_high_and(bus, mode, cpu.sp);
+    }
 
-            0x51 => { // EOR (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.eor(operand);
-                // Optional: cycle penalty on page cross
-            }
+    // A, X and SP all get memory & SP
+    pub fn las<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let value = cpu.read_operand(bus, mode) & cpu.sp;
+        cpu.a = value;
+        cpu.x = value;
+        cpu.sp = value;
+        cpu.update_zero_and_negative_flags(value);
+    }
 
-            // BIT
-            0x24 => { // BIT Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.bit(memory, operand);
-            }
+    // A = (A | magic) & X & operand. The magic constant varies between
+    // chips and even with temperature; $EE is the commonly documented one,
+    // and code that relies on it is broken on real hardware anyway.
+    pub fn xaa<B: Bus>(cpu: &mut Cpu, bus: &mut B, mode: AddrMode) {
+        let operand = cpu.read_operand(bus, mode);
+        cpu.a = (cpu.a | 0xEE) & cpu.x & operand;
+        cpu.update_zero_and_negative_flags(cpu.a);
+    }
 
-            0x2C => { // BIT Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.bit(memory, operand);
-            }
+    // Interrupts are ignored too; PC stays on the JAM so it can be reported
+    pub fn jam<B: Bus>(cpu: &mut Cpu, _: &mut B, _: AddrMode) {
+        cpu.pc = cpu.pc.wrapping_sub(1);
+        cpu.halted = true;
+    }
+}
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::mem::Memory;
+    use crate::testbus::{self, FlatBus};
+
+    // Each case runs one instruction from $0600 (in RAM, so RMW and store
+    // cases can share the addresses below) and checks the registers, the
+    // whole status byte and any memory it names. Operands used throughout:
+    //
+    //   zp $10, or $0E,X with X = 2
+    //   abs $0300, or $02FE,X/Y with X/Y = 2
+    //   ($1E,X) with X = 2 and ($30),Y with Y = $10, both reaching $0300
+    const ORIGIN: u16 = 0x0600;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    struct Regs {
+        a: u8,
+        x: u8,
+        y: u8,
+        sp: u8,
+        p: u8,
+    }
 
-            // ASL
-            0x0A => { // ASL Accumulator
-                self.a = self.asl(memory, self.a, true);
-            }
+    const fn regs(a: u8, x: u8, y: u8, sp: u8, p: u8) -> Regs {
+        Regs { a, x, y, sp, p }
+    }
 
-            0x06 => { // ASL Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.asl(memory, operand, false);
-                memory.write(addr, result);
-            }
+    struct Case {
+        code: &'static [u8],
+        before: Regs,
+        ram: &'static [(u16, u8)],
+        after: Regs,
+        ram_after: &'static [(u16, u8)],
+        pc: Option<u16>, // Where control goes; None means just past the instruction
+    }
 
-            0x16 => { // ASL Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.asl(memory, operand, false);
-                memory.write(addr, result);
-            }
+    const fn case(
+        code: &'static [u8],
+        before: Regs,
+        ram: &'static [(u16, u8)],
+        after: Regs,
+        ram_after: &'static [(u16, u8)],
+    ) -> Case {
+        Case { code, before, ram, after, ram_after, pc: None }
+    }
 
-            0x0E => { // ASL Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                let result = self.asl(memory, operand, false);
-                memory.write(addr, result);
-            }
+    impl Case {
+        const fn jumps_to(self, pc: u16) -> Case {
+            Case { pc: Some(pc), ..self }
+        }
+    }
 
-            0x1E => { // ASL Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                let result = self.asl(memory, operand, false);
-                memory.write(addr, result);
-                // Optional: add cycle penalty if page crossed
-            }
+    const IX: &[(u16, u8)] = &[(0x20, 0x00), (0x21, 0x03)]; // ($1E,X) pointer
+    const IY: &[(u16, u8)] = &[(0x30, 0xF0), (0x31, 0x02)]; // ($30),Y pointer
+
+    const CASES: &[Case] = &[
+        // Loads
+        case(&[0xA9, 0x80], regs(0x00, 0, 0, 0xFD, 0x24), &[], regs(0x80, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0xA5, 0x10], regs(0x55, 0, 0, 0xFD, 0x24), &[(0x10, 0x00)], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0xB5, 0x0E], regs(0x00, 2, 0, 0xFD, 0x26), &[(0x10, 0x42)], regs(0x42, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0xAD, 0x00, 0x03], regs(0x00, 0, 0, 0xFD, 0x24), &[(0x300, 0x80)], regs(0x80, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0xBD, 0xFE, 0x02], regs(0x00, 2, 0, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x01, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0xB9, 0xFE, 0x02], regs(0x00, 0, 2, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x01, 0, 2, 0xFD, 0x24), &[]),
+        case(&[0xA1, 0x1E], regs(0x00, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x7F)], regs(0x7F, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0xB1, 0x30], regs(0x00, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0xFF)], regs(0xFF, 0, 0x10, 0xFD, 0xA4), &[]),
+        case(&[0xA2, 0x00], regs(0, 0x05, 0, 0xFD, 0x24), &[], regs(0, 0x00, 0, 0xFD, 0x26), &[]),
+        case(&[0xA6, 0x10], regs(0, 0x00, 0, 0xFD, 0x24), &[(0x10, 0x80)], regs(0, 0x80, 0, 0xFD, 0xA4), &[]),
+        case(&[0xB6, 0x0E], regs(0, 0x00, 2, 0xFD, 0x24), &[(0x10, 0x01)], regs(0, 0x01, 2, 0xFD, 0x24), &[]),
+        case(&[0xAE, 0x00, 0x03], regs(0, 0x00, 0, 0xFD, 0x24), &[(0x300, 0x33)], regs(0, 0x33, 0, 0xFD, 0x24), &[]),
+        case(&[0xBE, 0xFE, 0x02], regs(0, 0x00, 2, 0xFD, 0x24), &[(0x300, 0x44)], regs(0, 0x44, 2, 0xFD, 0x24), &[]),
+        case(&[0xA0, 0xFF], regs(0, 0, 0x00, 0xFD, 0x24), &[], regs(0, 0, 0xFF, 0xFD, 0xA4), &[]),
+        case(&[0xA4, 0x10], regs(0, 0, 0x03, 0xFD, 0x24), &[(0x10, 0x00)], regs(0, 0, 0x00, 0xFD, 0x26), &[]),
+        case(&[0xB4, 0x0E], regs(0, 2, 0x00, 0xFD, 0x24), &[(0x10, 0x12)], regs(0, 2, 0x12, 0xFD, 0x24), &[]),
+        case(&[0xAC, 0x00, 0x03], regs(0, 0, 0x00, 0xFD, 0x24), &[(0x300, 0x80)], regs(0, 0, 0x80, 0xFD, 0xA4), &[]),
+        case(&[0xBC, 0xFE, 0x02], regs(0, 2, 0x00, 0xFD, 0x24), &[(0x300, 0x05)], regs(0, 2, 0x05, 0xFD, 0x24), &[]),
+        // Stores leave the flags alone, even storing 0
+        case(&[0x85, 0x10], regs(0x00, 0, 0, 0xFD, 0x24), &[(0x10, 0xFF)], regs(0x00, 0, 0, 0xFD, 0x24), &[(0x10, 0x00)]),
+        case(&[0x95, 0x0E], regs(0x42, 2, 0, 0xFD, 0x24), &[], regs(0x42, 2, 0, 0xFD, 0x24), &[(0x10, 0x42)]),
+        case(&[0x8D, 0x00, 0x03], regs(0x80, 0, 0, 0xFD, 0x24), &[], regs(0x80, 0, 0, 0xFD, 0x24), &[(0x300, 0x80)]),
+        case(&[0x9D, 0xFE, 0x02], regs(0x01, 2, 0, 0xFD, 0x24), &[], regs(0x01, 2, 0, 0xFD, 0x24), &[(0x300, 0x01)]),
+        case(&[0x99, 0xFE, 0x02], regs(0x02, 0, 2, 0xFD, 0x24), &[], regs(0x02, 0, 2, 0xFD, 0x24), &[(0x300, 0x02)]),
+        case(&[0x81, 0x1E], regs(0x03, 2, 0, 0xFD, 0x24), IX, regs(0x03, 2, 0, 0xFD, 0x24), &[(0x300, 0x03)]),
+        case(&[0x91, 0x30], regs(0x04, 0, 0x10, 0xFD, 0x24), IY, regs(0x04, 0, 0x10, 0xFD, 0x24), &[(0x300, 0x04)]),
+        case(&[0x86, 0x10], regs(0, 0x11, 0, 0xFD, 0x24), &[], regs(0, 0x11, 0, 0xFD, 0x24), &[(0x10, 0x11)]),
+        case(&[0x96, 0x0E], regs(0, 0x12, 2, 0xFD, 0x24), &[], regs(0, 0x12, 2, 0xFD, 0x24), &[(0x10, 0x12)]),
+        case(&[0x8E, 0x00, 0x03], regs(0, 0x13, 0, 0xFD, 0x24), &[], regs(0, 0x13, 0, 0xFD, 0x24), &[(0x300, 0x13)]),
+        case(&[0x84, 0x10], regs(0, 0, 0x21, 0xFD, 0x24), &[], regs(0, 0, 0x21, 0xFD, 0x24), &[(0x10, 0x21)]),
+        case(&[0x94, 0x0E], regs(0, 2, 0x22, 0xFD, 0x24), &[], regs(0, 2, 0x22, 0xFD, 0x24), &[(0x10, 0x22)]),
+        case(&[0x8C, 0x00, 0x03], regs(0, 0, 0x23, 0xFD, 0x24), &[], regs(0, 0, 0x23, 0xFD, 0x24), &[(0x300, 0x23)]),
+        // Transfers; TXS is the one that leaves the flags alone
+        case(&[0xAA], regs(0x80, 0x00, 0, 0xFD, 0x24), &[], regs(0x80, 0x80, 0, 0xFD, 0xA4), &[]),
+        case(&[0xA8], regs(0x00, 0, 0x05, 0xFD, 0x24), &[], regs(0x00, 0, 0x00, 0xFD, 0x26), &[]),
+        case(&[0xBA], regs(0, 0x00, 0, 0xFD, 0x24), &[], regs(0, 0xFD, 0, 0xFD, 0xA4), &[]),
+        case(&[0x8A], regs(0x00, 0x01, 0, 0xFD, 0xA6), &[], regs(0x01, 0x01, 0, 0xFD, 0x24), &[]),
+        case(&[0x9A], regs(0, 0x00, 0, 0xFD, 0x24), &[], regs(0, 0x00, 0, 0x00, 0x24), &[]),
+        case(&[0x98], regs(0x00, 0, 0x80, 0xFD, 0x24), &[], regs(0x80, 0, 0x80, 0xFD, 0xA4), &[]),
+        // Stack. PHP pushes B and bit 5 set whatever P holds; PLP ignores
+        // both bits of the pulled byte.
+        case(&[0x48], regs(0x42, 0, 0, 0xFD, 0x24), &[], regs(0x42, 0, 0, 0xFC, 0x24), &[(0x1FD, 0x42)]),
+        case(&[0x08], regs(0, 0, 0, 0xFD, 0xA5), &[], regs(0, 0, 0, 0xFC, 0xA5), &[(0x1FD, 0xB5)]),
+        case(&[0x08], regs(0, 0, 0, 0xFD, 0x04), &[], regs(0, 0, 0, 0xFC, 0x04), &[(0x1FD, 0x34)]),
+        case(&[0x68], regs(0x05, 0, 0, 0xFC, 0x24), &[(0x1FD, 0x00)], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0x28], regs(0, 0, 0, 0xFC, 0x24), &[(0x1FD, 0xFF)], regs(0, 0, 0, 0xFD, 0xEF), &[]),
+        case(&[0x28], regs(0, 0, 0, 0xFC, 0x24), &[(0x1FD, 0x10)], regs(0, 0, 0, 0xFD, 0x20), &[]),
+        // ADC: V when both inputs share a sign the result doesn't; D is ignored
+        case(&[0x69, 0x50], regs(0x50, 0, 0, 0xFD, 0x24), &[], regs(0xA0, 0, 0, 0xFD, 0xE4), &[]),
+        case(&[0x69, 0x01], regs(0x09, 0, 0, 0xFD, 0x2C), &[], regs(0x0A, 0, 0, 0xFD, 0x2C), &[]),
+        case(&[0x65, 0x10], regs(0xFF, 0, 0, 0xFD, 0x24), &[(0x10, 0x01)], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0x75, 0x0E], regs(0x01, 2, 0, 0xFD, 0x25), &[(0x10, 0x01)], regs(0x03, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x6D, 0x00, 0x03], regs(0x80, 0, 0, 0xFD, 0x24), &[(0x300, 0x80)], regs(0x00, 0, 0, 0xFD, 0x67), &[]),
+        case(&[0x7D, 0xFE, 0x02], regs(0x10, 2, 0, 0xFD, 0x24), &[(0x300, 0x20)], regs(0x30, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x79, 0xFE, 0x02], regs(0x7F, 0, 2, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x80, 0, 2, 0xFD, 0xE4), &[]),
+        case(&[0x61, 0x1E], regs(0x01, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0xFF)], regs(0x00, 2, 0, 0xFD, 0x27), &[]),
+        case(&[0x71, 0x30], regs(0x22, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0x11)], regs(0x33, 0, 0x10, 0xFD, 0x24), &[]),
+        // SBC: C clear means borrow, in and out
+        case(&[0xE9, 0x01], regs(0x00, 0, 0, 0xFD, 0x25), &[], regs(0xFF, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0xE5, 0x10], regs(0x50, 0, 0, 0xFD, 0x25), &[(0x10, 0xB0)], regs(0xA0, 0, 0, 0xFD, 0xE4), &[]),
+        case(&[0xF5, 0x0E], regs(0x05, 2, 0, 0xFD, 0x25), &[(0x10, 0x03)], regs(0x02, 2, 0, 0xFD, 0x25), &[]),
+        case(&[0xED, 0x00, 0x03], regs(0x05, 0, 0, 0xFD, 0x25), &[(0x300, 0x05)], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0xFD, 0xFE, 0x02], regs(0x05, 2, 0, 0xFD, 0x24), &[(0x300, 0x04)], regs(0x00, 2, 0, 0xFD, 0x27), &[]),
+        case(&[0xF9, 0xFE, 0x02], regs(0x80, 0, 2, 0xFD, 0x25), &[(0x300, 0x01)], regs(0x7F, 0, 2, 0xFD, 0x65), &[]),
+        case(&[0xE1, 0x1E], regs(0x10, 2, 0, 0xFD, 0x25), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x20)], regs(0xF0, 2, 0, 0xFD, 0xA4), &[]),
+        case(&[0xF1, 0x30], regs(0x40, 0, 0x10, 0xFD, 0x25), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0x10)], regs(0x30, 0, 0x10, 0xFD, 0x25), &[]),
+        // AND
+        case(&[0x29, 0x0F], regs(0xF0, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0x25, 0x10], regs(0xFF, 0, 0, 0xFD, 0x24), &[(0x10, 0x80)], regs(0x80, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x35, 0x0E], regs(0x3C, 2, 0, 0xFD, 0x24), &[(0x10, 0x0F)], regs(0x0C, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x2D, 0x00, 0x03], regs(0xAA, 0, 0, 0xFD, 0x24), &[(0x300, 0xFF)], regs(0xAA, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x3D, 0xFE, 0x02], regs(0x01, 2, 0, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x01, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x39, 0xFE, 0x02], regs(0x02, 0, 2, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x00, 0, 2, 0xFD, 0x26), &[]),
+        case(&[0x21, 0x1E], regs(0xFF, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x7F)], regs(0x7F, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x31, 0x30], regs(0x81, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0x80)], regs(0x80, 0, 0x10, 0xFD, 0xA4), &[]),
+        // ORA
+        case(&[0x09, 0x00], regs(0x00, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0x05, 0x10], regs(0x01, 0, 0, 0xFD, 0x24), &[(0x10, 0x80)], regs(0x81, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x15, 0x0E], regs(0x00, 2, 0, 0xFD, 0x24), &[(0x10, 0x01)], regs(0x01, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x0D, 0x00, 0x03], regs(0x0F, 0, 0, 0xFD, 0x24), &[(0x300, 0xF0)], regs(0xFF, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x1D, 0xFE, 0x02], regs(0x00, 2, 0, 0xFD, 0xA4), &[(0x300, 0x00)], regs(0x00, 2, 0, 0xFD, 0x26), &[]),
+        case(&[0x19, 0xFE, 0x02], regs(0x10, 0, 2, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x11, 0, 2, 0xFD, 0x24), &[]),
+        case(&[0x01, 0x1E], regs(0x40, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x02)], regs(0x42, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x11, 0x30], regs(0x00, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0x80)], regs(0x80, 0, 0x10, 0xFD, 0xA4), &[]),
+        // EOR
+        case(&[0x49, 0xFF], regs(0xFF, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0x45, 0x10], regs(0x0F, 0, 0, 0xFD, 0x24), &[(0x10, 0xF0)], regs(0xFF, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x55, 0x0E], regs(0x01, 2, 0, 0xFD, 0x24), &[(0x10, 0x03)], regs(0x02, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x4D, 0x00, 0x03], regs(0x80, 0, 0, 0xFD, 0x24), &[(0x300, 0x80)], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0x5D, 0xFE, 0x02], regs(0x00, 2, 0, 0xFD, 0x24), &[(0x300, 0x80)], regs(0x80, 2, 0, 0xFD, 0xA4), &[]),
+        case(&[0x59, 0xFE, 0x02], regs(0xAA, 0, 2, 0xFD, 0x24), &[(0x300, 0x55)], regs(0xFF, 0, 2, 0xFD, 0xA4), &[]),
+        case(&[0x41, 0x1E], regs(0x12, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x02)], regs(0x10, 2, 0, 0xFD, 0x24), &[]),
+        case(&[0x51, 0x30], regs(0x81, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0x01)], regs(0x80, 0, 0x10, 0xFD, 0xA4), &[]),
+        // Compares: C when the register is >= the operand, N and Z from the difference
+        case(&[0xC9, 0x10], regs(0x10, 0, 0, 0xFD, 0x24), &[], regs(0x10, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0xC5, 0x10], regs(0x10, 0, 0, 0xFD, 0x25), &[(0x10, 0x20)], regs(0x10, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0xD5, 0x0E], regs(0x20, 2, 0, 0xFD, 0x24), &[(0x10, 0x10)], regs(0x20, 2, 0, 0xFD, 0x25), &[]),
+        case(&[0xCD, 0x00, 0x03], regs(0x00, 0, 0, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x00, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0xDD, 0xFE, 0x02], regs(0x80, 2, 0, 0xFD, 0x24), &[(0x300, 0x00)], regs(0x80, 2, 0, 0xFD, 0xA5), &[]),
+        case(&[0xD9, 0xFE, 0x02], regs(0xFF, 0, 2, 0xFD, 0x24), &[(0x300, 0xFF)], regs(0xFF, 0, 2, 0xFD, 0x27), &[]),
+        case(&[0xC1, 0x1E], regs(0x01, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x80)], regs(0x01, 2, 0, 0xFD, 0xA4), &[]),
+        case(&[0xD1, 0x30], regs(0x7F, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0x7F)], regs(0x7F, 0, 0x10, 0xFD, 0x27), &[]),
+        case(&[0xE0, 0x05], regs(0, 0x05, 0, 0xFD, 0x24), &[], regs(0, 0x05, 0, 0xFD, 0x27), &[]),
+        case(&[0xE0, 0x00], regs(0, 0xFF, 0, 0xFD, 0x24), &[], regs(0, 0xFF, 0, 0xFD, 0xA5), &[]),
+        case(&[0xE4, 0x10], regs(0, 0x04, 0, 0xFD, 0x25), &[(0x10, 0x05)], regs(0, 0x04, 0, 0xFD, 0xA4), &[]),
+        case(&[0xEC, 0x00, 0x03], regs(0, 0x06, 0, 0xFD, 0x24), &[(0x300, 0x05)], regs(0, 0x06, 0, 0xFD, 0x25), &[]),
+        case(&[0xC0, 0x00], regs(0, 0, 0x00, 0xFD, 0x24), &[], regs(0, 0, 0x00, 0xFD, 0x27), &[]),
+        case(&[0xC0, 0xFF], regs(0, 0, 0x01, 0xFD, 0x25), &[], regs(0, 0, 0x01, 0xFD, 0x24), &[]),
+        case(&[0xC4, 0x10], regs(0, 0, 0x80, 0xFD, 0x24), &[(0x10, 0x7F)], regs(0, 0, 0x80, 0xFD, 0x25), &[]),
+        case(&[0xCC, 0x00, 0x03], regs(0, 0, 0x10, 0xFD, 0x25), &[(0x300, 0x20)], regs(0, 0, 0x10, 0xFD, 0xA4), &[]),
+        // BIT: Z from A & M, N and V copied from bits 7 and 6 of M
+        case(&[0x24, 0x10], regs(0x01, 0, 0, 0xFD, 0x24), &[(0x10, 0xC0)], regs(0x01, 0, 0, 0xFD, 0xE6), &[]),
+        case(&[0x24, 0x10], regs(0x80, 0, 0, 0xFD, 0x24), &[(0x10, 0x80)], regs(0x80, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x2C, 0x00, 0x03], regs(0xFF, 0, 0, 0xFD, 0xE6), &[(0x300, 0x3F)], regs(0xFF, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0x2C, 0x00, 0x03], regs(0x00, 0, 0, 0xFD, 0x24), &[(0x300, 0x40)], regs(0x00, 0, 0, 0xFD, 0x66), &[]),
+        // Increments and decrements
+        case(&[0xE6, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[(0x10, 0xFF)], regs(0, 0, 0, 0xFD, 0x26), &[(0x10, 0x00)]),
+        case(&[0xF6, 0x0E], regs(0, 2, 0, 0xFD, 0x24), &[(0x10, 0x7F)], regs(0, 2, 0, 0xFD, 0xA4), &[(0x10, 0x80)]),
+        case(&[0xEE, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0x26), &[(0x300, 0x00)], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0x01)]),
+        case(&[0xFE, 0xFE, 0x02], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x10)], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x11)]),
+        case(&[0xC6, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[(0x10, 0x01)], regs(0, 0, 0, 0xFD, 0x26), &[(0x10, 0x00)]),
+        case(&[0xD6, 0x0E], regs(0, 2, 0, 0xFD, 0x24), &[(0x10, 0x00)], regs(0, 2, 0, 0xFD, 0xA4), &[(0x10, 0xFF)]),
+        case(&[0xCE, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0xA4), &[(0x300, 0x80)], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0x7F)]),
+        case(&[0xDE, 0xFE, 0x02], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x02)], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x01)]),
+        case(&[0xE8], regs(0, 0xFF, 0, 0xFD, 0x24), &[], regs(0, 0x00, 0, 0xFD, 0x26), &[]),
+        case(&[0xC8], regs(0, 0, 0x7F, 0xFD, 0x24), &[], regs(0, 0, 0x80, 0xFD, 0xA4), &[]),
+        case(&[0xCA], regs(0, 0x01, 0, 0xFD, 0x24), &[], regs(0, 0x00, 0, 0xFD, 0x26), &[]),
+        case(&[0x88], regs(0, 0, 0x00, 0xFD, 0x24), &[], regs(0, 0, 0xFF, 0xFD, 0xA4), &[]),
+        // Shifts and rotates: C gets the bit shifted out
+        case(&[0x0A], regs(0x80, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0x06, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[(0x10, 0x40)], regs(0, 0, 0, 0xFD, 0xA4), &[(0x10, 0x80)]),
+        case(&[0x16, 0x0E], regs(0, 2, 0, 0xFD, 0x24), &[(0x10, 0xC0)], regs(0, 2, 0, 0xFD, 0xA5), &[(0x10, 0x80)]),
+        case(&[0x0E, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0x25), &[(0x300, 0x01)], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0x02)]),
+        case(&[0x1E, 0xFE, 0x02], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0xFF)], regs(0, 2, 0, 0xFD, 0xA5), &[(0x300, 0xFE)]),
+        case(&[0x4A], regs(0x01, 0, 0, 0xFD, 0xA4), &[], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0x4A], regs(0xFE, 0, 0, 0xFD, 0xA5), &[], regs(0x7F, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0x46, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[(0x10, 0x01)], regs(0, 0, 0, 0xFD, 0x27), &[(0x10, 0x00)]),
+        case(&[0x56, 0x0E], regs(0, 2, 0, 0xFD, 0x25), &[(0x10, 0x00)], regs(0, 2, 0, 0xFD, 0x26), &[(0x10, 0x00)]),
+        case(&[0x4E, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0xA4), &[(0x300, 0xFE)], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0x7F)]),
+        case(&[0x5E, 0xFE, 0x02], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x81)], regs(0, 2, 0, 0xFD, 0x25), &[(0x300, 0x40)]),
+        case(&[0x2A], regs(0x80, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0x2A], regs(0x40, 0, 0, 0xFD, 0x25), &[], regs(0x81, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x26, 0x10], regs(0, 0, 0, 0xFD, 0x25), &[(0x10, 0x80)], regs(0, 0, 0, 0xFD, 0x25), &[(0x10, 0x01)]),
+        case(&[0x36, 0x0E], regs(0, 2, 0, 0xFD, 0x24), &[(0x10, 0x01)], regs(0, 2, 0, 0xFD, 0x24), &[(0x10, 0x02)]),
+        case(&[0x2E, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0xC0)], regs(0, 0, 0, 0xFD, 0xA5), &[(0x300, 0x80)]),
+        case(&[0x3E, 0xFE, 0x02], regs(0, 2, 0, 0xFD, 0x25), &[(0x300, 0x00)], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x01)]),
+        case(&[0x6A], regs(0x01, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        case(&[0x6A], regs(0x00, 0, 0, 0xFD, 0x25), &[], regs(0x80, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x66, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[(0x10, 0x02)], regs(0, 0, 0, 0xFD, 0x24), &[(0x10, 0x01)]),
+        case(&[0x76, 0x0E], regs(0, 2, 0, 0xFD, 0x25), &[(0x10, 0x01)], regs(0, 2, 0, 0xFD, 0xA5), &[(0x10, 0x80)]),
+        case(&[0x6E, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0xFF)], regs(0, 0, 0, 0xFD, 0x25), &[(0x300, 0x7F)]),
+        case(&[0x7E, 0xFE, 0x02], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x80)], regs(0, 2, 0, 0xFD, 0x24), &[(0x300, 0x40)]),
+        // Jumps and subroutines. JMP (ind) takes its high byte from the
+        // start of the same page; JSR pushes the address of its last byte.
+        case(&[0x4C, 0x34, 0x12], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x1234),
+        case(&[0x6C, 0x00, 0x03], regs(0, 0, 0, 0xFD, 0x24), &[(0x300, 0x78), (0x301, 0x56)], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x5678),
+        case(&[0x6C, 0xFF, 0x02], regs(0, 0, 0, 0xFD, 0x24), &[(0x2FF, 0x34), (0x200, 0x12), (0x300, 0xFF)], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x1234),
+        case(&[0x20, 0x00, 0x09], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFB, 0x24), &[(0x1FD, 0x06), (0x1FC, 0x02)]).jumps_to(0x0900),
+        case(&[0x60], regs(0, 0, 0, 0xFB, 0x24), &[(0x1FC, 0x02), (0x1FD, 0x06)], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x0603),
+        // Interrupts. BRK skips a padding byte and pushes P with B set;
+        // RTI pulls P like PLP and returns to the address as pulled.
+        case(&[0x00, 0xEA], regs(0, 0, 0, 0xFD, 0xA1), &[(0xFFFE, 0x00), (0xFFFF, 0x80)], regs(0, 0, 0, 0xFA, 0xA5), &[(0x1FD, 0x06), (0x1FC, 0x02), (0x1FB, 0xB1)]).jumps_to(0x8000),
+        case(&[0x40], regs(0, 0, 0, 0xFA, 0x24), &[(0x1FB, 0xC3), (0x1FC, 0x34), (0x1FD, 0x12)], regs(0, 0, 0, 0xFD, 0xE3), &[]).jumps_to(0x1234),
+        // Branches, not taken and taken (forward and back)
+        case(&[0x90, 0x10], regs(0, 0, 0, 0xFD, 0x25), &[], regs(0, 0, 0, 0xFD, 0x25), &[]),
+        case(&[0x90, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x0612),
+        case(&[0xB0, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0xB0, 0xFE], regs(0, 0, 0, 0xFD, 0x25), &[], regs(0, 0, 0, 0xFD, 0x25), &[]).jumps_to(0x0600),
+        case(&[0xF0, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0xF0, 0x10], regs(0, 0, 0, 0xFD, 0x26), &[], regs(0, 0, 0, 0xFD, 0x26), &[]).jumps_to(0x0612),
+        case(&[0xD0, 0x10], regs(0, 0, 0, 0xFD, 0x26), &[], regs(0, 0, 0, 0xFD, 0x26), &[]),
+        case(&[0xD0, 0xF0], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x05F2),
+        case(&[0x30, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0x30, 0x10], regs(0, 0, 0, 0xFD, 0xA4), &[], regs(0, 0, 0, 0xFD, 0xA4), &[]).jumps_to(0x0612),
+        case(&[0x10, 0x10], regs(0, 0, 0, 0xFD, 0xA4), &[], regs(0, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x10, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x0612),
+        case(&[0x50, 0x10], regs(0, 0, 0, 0xFD, 0x64), &[], regs(0, 0, 0, 0xFD, 0x64), &[]),
+        case(&[0x50, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]).jumps_to(0x0612),
+        case(&[0x70, 0x10], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0x70, 0x10], regs(0, 0, 0, 0xFD, 0x64), &[], regs(0, 0, 0, 0xFD, 0x64), &[]).jumps_to(0x0612),
+        // Flag instructions and NOP
+        case(&[0x18], regs(0, 0, 0, 0xFD, 0x25), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0x38], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x25), &[]),
+        case(&[0x58], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x20), &[]),
+        case(&[0x78], regs(0, 0, 0, 0xFD, 0x20), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0xD8], regs(0, 0, 0, 0xFD, 0x2C), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0xF8], regs(0, 0, 0, 0xFD, 0x24), &[], regs(0, 0, 0, 0xFD, 0x2C), &[]),
+        case(&[0xB8], regs(0, 0, 0, 0xFD, 0x64), &[], regs(0, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0xEA], regs(0x12, 0x34, 0x56, 0xFD, 0xE7), &[], regs(0x12, 0x34, 0x56, 0xFD, 0xE7), &[]),
+        // Unofficial LAX loads A and X together, setting N and Z once
+        case(&[0xA7, 0x10], regs(0x00, 0x00, 0, 0xFD, 0x24), &[(0x10, 0x80)], regs(0x80, 0x80, 0, 0xFD, 0xA4), &[]),
+        case(&[0xB7, 0x0E], regs(0x55, 0x55, 2, 0xFD, 0x24), &[(0x10, 0x00)], regs(0x00, 0x00, 2, 0xFD, 0x26), &[]),
+        case(&[0xAF, 0x00, 0x03], regs(0x00, 0x00, 0, 0xFD, 0xA6), &[(0x300, 0x42)], regs(0x42, 0x42, 0, 0xFD, 0x24), &[]),
+        case(&[0xBF, 0xFE, 0x02], regs(0x00, 0x00, 2, 0xFD, 0x24), &[(0x300, 0x01)], regs(0x01, 0x01, 2, 0xFD, 0x24), &[]),
+        case(&[0xA3, 0x1E], regs(0x00, 2, 0, 0xFD, 0x24), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x7F)], regs(0x7F, 0x7F, 0, 0xFD, 0x24), &[]),
+        case(&[0xB3, 0x30], regs(0x00, 0x00, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0xFF)], regs(0xFF, 0xFF, 0x10, 0xFD, 0xA4), &[]),
+        // SAX stores A & X and, like the other stores, leaves the flags alone
+        case(&[0x87, 0x10], regs(0xF0, 0x3C, 0, 0xFD, 0x24), &[], regs(0xF0, 0x3C, 0, 0xFD, 0x24), &[(0x10, 0x30)]),
+        case(&[0x87, 0x10], regs(0xF0, 0x0F, 0, 0xFD, 0xA4), &[(0x10, 0xFF)], regs(0xF0, 0x0F, 0, 0xFD, 0xA4), &[(0x10, 0x00)]),
+        case(&[0x97, 0x0E], regs(0xFF, 0x81, 2, 0xFD, 0x24), &[], regs(0xFF, 0x81, 2, 0xFD, 0x24), &[(0x10, 0x81)]),
+        case(&[0x8F, 0x00, 0x03], regs(0x0F, 0x3C, 0, 0xFD, 0x26), &[], regs(0x0F, 0x3C, 0, 0xFD, 0x26), &[(0x300, 0x0C)]),
+        case(&[0x83, 0x1E], regs(0x03, 2, 0, 0xFD, 0x24), IX, regs(0x03, 2, 0, 0xFD, 0x24), &[(0x300, 0x02)]),
+        // The unofficial RMWs write the shifted or stepped value back, then
+        // combine it into A: SLO = ASL + ORA, RLA = ROL + AND, SRE = LSR +
+        // EOR, RRA = ROR + ADC (with ROR's carry), DCP = DEC + CMP and
+        // ISC = INC + SBC
+        case(&[0x07, 0x10], regs(0x02, 0, 0, 0xFD, 0x24), &[(0x10, 0x81)], regs(0x02, 0, 0, 0xFD, 0x25), &[(0x10, 0x02)]),
+        case(&[0x17, 0x0E], regs(0x00, 2, 0, 0xFD, 0x24), &[(0x10, 0x40)], regs(0x80, 2, 0, 0xFD, 0xA4), &[(0x10, 0x80)]),
+        case(&[0x1B, 0xFE, 0x02], regs(0x00, 0, 2, 0xFD, 0x25), &[(0x300, 0x00)], regs(0x00, 0, 2, 0xFD, 0x26), &[(0x300, 0x00)]),
+        case(&[0x27, 0x10], regs(0xFF, 0, 0, 0xFD, 0x25), &[(0x10, 0x80)], regs(0x01, 0, 0, 0xFD, 0x25), &[(0x10, 0x01)]),
+        case(&[0x3F, 0xFE, 0x02], regs(0xF0, 2, 0, 0xFD, 0x24), &[(0x300, 0x40)], regs(0x80, 2, 0, 0xFD, 0xA4), &[(0x300, 0x80)]),
+        case(&[0x47, 0x10], regs(0x01, 0, 0, 0xFD, 0x24), &[(0x10, 0x03)], regs(0x00, 0, 0, 0xFD, 0x27), &[(0x10, 0x01)]),
+        case(&[0x53, 0x30], regs(0x80, 0, 0x10, 0xFD, 0x24), &[(0x30, 0xF0), (0x31, 0x02), (0x300, 0xFE)], regs(0xFF, 0, 0x10, 0xFD, 0xA4), &[(0x300, 0x7F)]),
+        case(&[0x67, 0x10], regs(0x10, 0, 0, 0xFD, 0x24), &[(0x10, 0x03)], regs(0x12, 0, 0, 0xFD, 0x24), &[(0x10, 0x01)]),
+        case(&[0x6F, 0x00, 0x03], regs(0x7F, 0, 0, 0xFD, 0x25), &[(0x300, 0x02)], regs(0x00, 0, 0, 0xFD, 0x27), &[(0x300, 0x81)]),
+        case(&[0xC7, 0x10], regs(0x42, 0, 0, 0xFD, 0x24), &[(0x10, 0x43)], regs(0x42, 0, 0, 0xFD, 0x27), &[(0x10, 0x42)]),
+        case(&[0xDB, 0xFE, 0x02], regs(0x10, 0, 2, 0xFD, 0xA7), &[(0x300, 0x00)], regs(0x10, 0, 2, 0xFD, 0x24), &[(0x300, 0xFF)]),
+        case(&[0xE7, 0x10], regs(0x20, 0, 0, 0xFD, 0x25), &[(0x10, 0x0F)], regs(0x10, 0, 0, 0xFD, 0x25), &[(0x10, 0x10)]),
+        case(&[0xFF, 0xFE, 0x02], regs(0x80, 2, 0, 0xFD, 0x25), &[(0x300, 0xFF)], regs(0x80, 2, 0, 0xFD, 0xA5), &[(0x300, 0x00)]),
+        case(&[0xE3, 0x1E], regs(0x7F, 2, 0, 0xFD, 0x25), &[(0x20, 0x00), (0x21, 0x03), (0x300, 0x7F)], regs(0xFF, 2, 0, 0xFD, 0xE4), &[(0x300, 0x80)]),
+        // ANC: AND, then C copied from N
+        case(&[0x0B, 0x80], regs(0xFF, 0, 0, 0xFD, 0x24), &[], regs(0x80, 0, 0, 0xFD, 0xA5), &[]),
+        case(&[0x2B, 0x7F], regs(0xFF, 0, 0, 0xFD, 0xA5), &[], regs(0x7F, 0, 0, 0xFD, 0x24), &[]),
+        case(&[0x0B, 0x00], regs(0xFF, 0, 0, 0xFD, 0x25), &[], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        // ALR: AND, then LSR A
+        case(&[0x4B, 0x03], regs(0xFF, 0, 0, 0xFD, 0xA4), &[], regs(0x01, 0, 0, 0xFD, 0x25), &[]),
+        case(&[0x4B, 0x01], regs(0x01, 0, 0, 0xFD, 0x24), &[], regs(0x00, 0, 0, 0xFD, 0x27), &[]),
+        // ARR: AND, then ROR A with the old C in bit 7, but C is bit 6 of
+        // the result and V is bit 6 xor bit 5
+        case(&[0x6B, 0xFF], regs(0xFF, 0, 0, 0xFD, 0x25), &[], regs(0xFF, 0, 0, 0xFD, 0xA5), &[]),
+        case(&[0x6B, 0xFF], regs(0x80, 0, 0, 0xFD, 0x24), &[], regs(0x40, 0, 0, 0xFD, 0x65), &[]),
+        case(&[0x6B, 0xFF], regs(0x40, 0, 0, 0xFD, 0x25), &[], regs(0xA0, 0, 0, 0xFD, 0xE4), &[]),
+        case(&[0x6B, 0x0F], regs(0xF1, 0, 0, 0xFD, 0x65), &[], regs(0x80, 0, 0, 0xFD, 0xA4), &[]),
+        case(&[0x6B, 0xFF], regs(0x01, 0, 0, 0xFD, 0x64), &[], regs(0x00, 0, 0, 0xFD, 0x26), &[]),
+        // AXS: X = (A & X) - operand like CMP, ignoring C in and leaving V
+        case(&[0xCB, 0x02], regs(0x0F, 0x05, 0, 0xFD, 0x24), &[], regs(0x0F, 0x03, 0, 0xFD, 0x25), &[]),
+        case(&[0xCB, 0x06], regs(0xFF, 0x05, 0, 0xFD, 0x65), &[], regs(0xFF, 0xFF, 0, 0xFD, 0xE4), &[]),
+        case(&[0xCB, 0x05], regs(0xFF, 0x05, 0, 0xFD, 0x24), &[], regs(0xFF, 0x00, 0, 0xFD, 0x27), &[]),
+        // The unstable stores keep only the bits set in the base address's
+        // high byte + 1 ($07 + 1 here). Crossing a page, the stored value
+        // also becomes the high byte of the address. Flags are untouched.
+        case(&[0x9C, 0x10, 0x07], regs(0, 2, 0x0C, 0xFD, 0x24), &[], regs(0, 2, 0x0C, 0xFD, 0x24), &[(0x712, 0x08)]),
+        case(&[0x9C, 0xFE, 0x02], regs(0, 2, 0x01, 0xFD, 0x24), &[(0x300, 0xAA)], regs(0, 2, 0x01, 0xFD, 0x24), &[(0x100, 0x01), (0x300, 0xAA)]),
+        case(&[0x9E, 0x10, 0x07], regs(0, 0xFF, 2, 0xFD, 0xA6), &[], regs(0, 0xFF, 2, 0xFD, 0xA6), &[(0x712, 0x08)]),
+        case(&[0x9F, 0x10, 0x07], regs(0xFC, 0x0F, 2, 0xFD, 0x24), &[], regs(0xFC, 0x0F, 2, 0xFD, 0x24), &[(0x712, 0x08)]),
+        case(&[0x9F, 0x10, 0x07], regs(0xF7, 0x3F, 2, 0xFD, 0x24), &[(0x712, 0xAA)], regs(0xF7, 0x3F, 2, 0xFD, 0x24), &[(0x712, 0x00)]),
+        case(&[0x93, 0x40], regs(0xFF, 0x0F, 0x12, 0xFD, 0x24), &[(0x40, 0x00), (0x41, 0x07)], regs(0xFF, 0x0F, 0x12, 0xFD, 0x24), &[(0x712, 0x08)]),
+        // TAS sets SP = A & X and stores it like AHX
+        case(&[0x9B, 0x10, 0x07], regs(0xFB, 0x3E, 2, 0xFD, 0x24), &[], regs(0xFB, 0x3E, 2, 0x3A, 0x24), &[(0x712, 0x08)]),
+        // LAS loads memory & SP into A, X and SP
+        case(&[0xBB, 0xFE, 0x02], regs(0, 0, 2, 0xFD, 0x24), &[(0x300, 0xF0)], regs(0xF0, 0xF0, 2, 0xF0, 0xA4), &[]),
+        case(&[0xBB, 0xFE, 0x02], regs(0, 0, 2, 0xFD, 0x24), &[(0x300, 0x02)], regs(0x00, 0x00, 2, 0x00, 0x26), &[]),
+        // XAA: A = (A | $EE) & X & operand
+        case(&[0x8B, 0xFF], regs(0x00, 0xFF, 0, 0xFD, 0x24), &[], regs(0xEE, 0xFF, 0, 0xFD, 0xA4), &[]),
+        case(&[0x8B, 0xFF], regs(0x11, 0x0F, 0, 0xFD, 0x24), &[], regs(0x0F, 0x0F, 0, 0xFD, 0x24), &[]),
+        case(&[0x8B, 0x3C], regs(0x00, 0xFF, 0, 0xFD, 0x24), &[], regs(0x2C, 0xFF, 0, 0xFD, 0x24), &[]),
+        case(&[0x8B, 0xFF], regs(0x00, 0x11, 0, 0xFD, 0xA4), &[], regs(0x00, 0x11, 0, 0xFD, 0x26), &[]),
+    ];
+
+    fn run_case(case: &Case) -> (Cpu, Memory) {
+        let mut memory = Memory::with_program(case.code, ORIGIN);
+        for &(addr, value) in case.ram {
+            memory.poke(addr, value);
+        }
+        let r = case.before;
+        let mut cpu = Cpu::with_state(ORIGIN, r.sp, r.a, r.x, r.y, r.p);
+        cpu.exec_next_instr(&mut memory).unwrap();
+        (cpu, memory)
+    }
 
-            // LSR
-            0x4A => { // LSR Accumulator
-                self.a = self.lsr(memory, self.a, true);
-            }
+    #[test]
+    fn opcodes_match_their_cases() {
+        for case in CASES {
+            let op = opcodes::lookup(case.code[0]).unwrap();
+            let name = format!("{:02X?} ({} {:?})", case.code, op.mnemonic, op.mode);
+            let (cpu, memory) = run_case(case);
 
-            0x46 => { // LSR Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.lsr(memory, operand, false);
-                memory.write(addr, result);
+            let expected_pc = case.pc.unwrap_or(ORIGIN + op.size());
+            assert_eq!(cpu.pc, expected_pc, "{}: PC", name);
+            let after = regs(cpu.a, cpu.x, cpu.y, cpu.sp, cpu.status);
+            assert_eq!(after, case.after, "{}: registers", name);
+            for &(addr, value) in case.ram_after {
+                assert_eq!(memory.peek(addr), value, "{}: ${:04X}", name, addr);
             }
+        }
+    }
 
-            0x56 => { // LSR Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.lsr(memory, operand, false);
-                memory.write(addr, result);
-            }
+    // A new opcode in the table needs a row above
+    #[test]
+    fn every_official_opcode_has_a_case() {
+        let missing: Vec<String> = (0..=255u8)
+            .filter(|&opcode| opcodes::lookup(opcode).is_some_and(|op| op.official))
+            .filter(|&opcode| !CASES.iter().any(|case| case.code[0] == opcode))
+            .map(|opcode| format!("${:02X}", opcode))
+            .collect();
+        assert!(missing.is_empty(), "no case for {}", missing.join(", "));
+    }
 
-            0x4E => { // LSR Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                let result = self.lsr(memory, operand, false);
-                memory.write(addr, result);
+    // All 256 x 256 x 2 combinations of A, operand and carry in, against
+    // the reference model in verify.rs
+    #[test]
+    fn adc_and_sbc_match_the_reference_model() {
+        for opcode in [0x69, 0xE9] {
+            let op = opcodes::lookup(opcode).unwrap();
+            let mut memory = Memory::with_program(&[opcode, 0x00], ORIGIN);
+            for a in 0..=255u8 {
+                for operand in 0..=255u8 {
+                    for carry in [false, true] {
+                        memory.poke(ORIGIN + 1, operand);
+                        let mut cpu = Cpu::with_state(ORIGIN, 0xFD, a, 0, 0, 0x24 | carry as u8);
+                        let before = cpu.snapshot();
+                        cpu.exec_next_instr(&mut memory).unwrap();
+                        let expected = verify::expected(op.mnemonic, op.mode, &before, operand).unwrap();
+                        assert_eq!(
+                            (cpu.a, cpu.status),
+                            expected,
+                            "{} #${:02X} with A=${:02X} C={}",
+                            op.mnemonic,
+                            operand,
+                            a,
+                            carry as u8
+                        );
+                    }
+                }
             }
+        }
+    }
 
-            0x5E => { // LSR Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                let result = self.lsr(memory, operand, false);
-                memory.write(addr, result);
-                // Optional: add cycle penalty if page crossed
-            }
+    // ADC, SBC and branches written as assembly and run on the flat test bus
 
-            0x2A => { // ROL Accumulator
-                self.a = self.rol(memory, self.a, true);
-            }
+    fn run_flat(source: &str) -> Cpu {
+        let (cpu, _) = testbus::run_flat_program(source, 100).unwrap();
+        cpu
+    }
 
-            0x26 => { // ROL Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.rol(memory, operand, false);
-                memory.write(addr, result);
-            }
+    #[test]
+    fn adc_sets_overflow_on_signed_wraparound() {
+        let cpu = run_flat("clc\n lda #$50\n adc #$50\n brk");
+        assert_eq!(cpu.a, 0xA0);
+        assert!(cpu.get_flag(Flag::Overflow) && cpu.get_flag(Flag::Negative));
+        assert!(!cpu.get_flag(Flag::Carry));
+
+        let cpu = run_flat("clc\n lda #$D0\n adc #$90\n brk");
+        assert_eq!(cpu.a, 0x60);
+        assert!(cpu.get_flag(Flag::Overflow) && cpu.get_flag(Flag::Carry));
+
+        // Carry out alone is not overflow
+        let cpu = run_flat("clc\n lda #$FF\n adc #$01\n brk");
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_flag(Flag::Carry) && cpu.get_flag(Flag::Zero));
+        assert!(!cpu.get_flag(Flag::Overflow));
+    }
 
-            0x36 => { // ROL Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.rol(memory, operand, false);
-                memory.write(addr, result);
-            }
+    #[test]
+    fn sbc_sets_overflow_and_borrow() {
+        let cpu = run_flat("sec\n lda #$50\n sbc #$B0\n brk");
+        assert_eq!(cpu.a, 0xA0);
+        assert!(cpu.get_flag(Flag::Overflow));
+        assert!(!cpu.get_flag(Flag::Carry));
+
+        // C clear borrows one more
+        let cpu = run_flat("clc\n lda #$05\n sbc #$04\n brk");
+        assert_eq!(cpu.a, 0x00);
+        assert!(cpu.get_flag(Flag::Zero) && cpu.get_flag(Flag::Carry));
+    }
 
-            0x2E => { // ROL Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                let result = self.rol(memory, operand, false);
-                memory.write(addr, result);
-            }
+    // The NES CPU has no decimal mode: D can be set, but ADC and SBC stay binary
+    #[test]
+    fn decimal_flag_is_ignored() {
+        let cpu = run_flat("sed\n clc\n lda #$09\n adc #$01\n brk");
+        assert_eq!(cpu.a, 0x0A);
+        assert!(cpu.get_flag(Flag::Decimal));
 
-            0x3E => { // ROL Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                let result = self.rol(memory, operand, false);
-                memory.write(addr, result);
-                // Optional: add cycle penalty if page crossed
-            }
+        let cpu = run_flat("sed\n sec\n lda #$10\n sbc #$01\n brk");
+        assert_eq!(cpu.a, 0x0F);
+    }
 
-            0x6A => { // ROR Accumulator
-                self.a = self.ror(memory, self.a, true);
-            }
+    // LDX #$FF before the shift leaves N set, so the result shows LSR clearing it
+    #[test]
+    fn lsr_shifts_bit_0_into_carry_and_clears_negative() {
+        let cases = [(0x01, 0x00, true), (0x00, 0x00, false), (0xFE, 0x7F, false), (0xFF, 0x7F, true)];
+        for (value, result, carry) in cases {
+            let cpu = run_flat(&format!("lda #${value:02X}\n ldx #$FF\n lsr a\n brk"));
+            assert_eq!(cpu.a, result, "LSR A of ${value:02X}");
+            assert_eq!(cpu.get_flag(Flag::Carry), carry, "C after LSR A of ${value:02X}");
+            assert_eq!(cpu.get_flag(Flag::Zero), result == 0, "Z after LSR A of ${value:02X}");
+            assert!(!cpu.get_flag(Flag::Negative), "N after LSR A of ${value:02X}");
+
+            let source = format!("lda #${value:02X}\n sta $10\n ldx #$FF\n lsr $10\n brk");
+            let (cpu, mut bus) = testbus::run_flat_program(&source, 100).unwrap();
+            assert_eq!(bus.read(0x0010), result, "LSR $10 of ${value:02X}");
+            assert_eq!(cpu.get_flag(Flag::Carry), carry, "C after LSR $10 of ${value:02X}");
+            assert_eq!(cpu.get_flag(Flag::Zero), result == 0, "Z after LSR $10 of ${value:02X}");
+            assert!(!cpu.get_flag(Flag::Negative), "N after LSR $10 of ${value:02X}");
+        }
+    }
 
-            0x66 => { // ROR Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.ror(memory, operand, false);
-                memory.write(addr, result);
-            }
+    // The ALU helpers on their own: name, A, operand and P before, then
+    // the result (A, or the shifted value) and P after, worked out by hand
+    // so any rework of the flag code has to reproduce them bit for bit
+    const ALU_CASES: &[(&str, u8, u8, u8, u8, u8)] = &[
+        ("adc", 0x7F, 0x01, 0x24, 0x80, 0xE4),
+        ("adc", 0x7F, 0x00, 0x25, 0x80, 0xE4),
+        ("adc", 0xFF, 0x01, 0x24, 0x00, 0x27),
+        ("adc", 0x80, 0xFF, 0x24, 0x7F, 0x65),
+        ("sbc", 0x80, 0x01, 0x25, 0x7F, 0x65),
+        ("sbc", 0x00, 0x01, 0x25, 0xFF, 0xA4),
+        ("sbc", 0x01, 0x01, 0x24, 0xFF, 0xA4),
+        ("sbc", 0x7F, 0xFF, 0x25, 0x80, 0xE4),
+        ("cmp", 0x80, 0x01, 0x24, 0x80, 0x25),
+        ("cmp", 0x01, 0x80, 0x27, 0x01, 0xA4),
+        ("cmp", 0x42, 0x42, 0xA4, 0x42, 0x27),
+        ("bit", 0x01, 0xC0, 0x24, 0x01, 0xE6),
+        ("bit", 0x40, 0x40, 0xE6, 0x40, 0x64),
+        ("asl", 0x00, 0x80, 0x24, 0x00, 0x27),
+        ("lsr", 0x00, 0x01, 0xA4, 0x00, 0x27),
+        ("rol", 0x00, 0x80, 0x25, 0x01, 0x25),
+        ("ror", 0x00, 0x01, 0x25, 0x80, 0xA5),
+    ];
+
+    #[test]
+    fn alu_helpers_keep_their_flags() {
+        for &(name, a, operand, p, result, p_after) in ALU_CASES {
+            let mut cpu = Cpu::with_state(0, 0xFD, a, 0, 0, p);
+            let got = match name {
+                "adc" => {
+                    cpu.adc(operand);
+                    cpu.a
+                }
+                "sbc" => {
+                    cpu.sbc(operand);
+                    cpu.a
+                }
+                "cmp" => {
+                    cpu.cmp(operand);
+                    cpu.a
+                }
+                "bit" => {
+                    cpu.bit(operand);
+                    cpu.a
+                }
+                "asl" => cpu.asl(operand),
+                "lsr" => cpu.lsr(operand),
+                "rol" => cpu.rol(operand),
+                _ => cpu.ror(operand),
+            };
+            let label = format!("{name} ${a:02X}, ${operand:02X} with P=${p:02X}");
+            assert_eq!((got, cpu.status), (result, p_after), "{label}");
+        }
+    }
 
-            0x76 => { // ROR Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                let result = self.ror(memory, operand, false);
-                memory.write(addr, result);
-            }
+    // Unmapped opcodes leave the CPU untouched so the error's PC is the
+    // opcode's own address, and the caller decides what to do next
+    #[test]
+    fn unknown_opcodes_report_their_address() {
+        let unknown: Vec<u8> = (0..=0xFF).filter(|&op| opcodes::lookup(op).is_none()).collect();
+        assert!(!unknown.is_empty());
+        for opcode in unknown {
+            let mut bus = FlatBus::with_program(&[0xEA, opcode, 0x12, 0x34], 0x0400);
+            let mut cpu = Cpu::power_on(&mut bus);
+            cpu.exec_next_instr(&mut bus).unwrap(); // NOP
+            let before = cpu.clone();
+
+            let err = cpu.exec_next_instr(&mut bus).unwrap_err();
+            assert_eq!(err, CpuError::UnknownOpcode { opcode, pc: 0x0401 });
+            assert_eq!(cpu.pc, 0x0401, "PC after ${opcode:02X}");
+            assert_eq!(cpu.snapshot(), before.snapshot(), "registers after ${opcode:02X}");
+
+            // step reports the same thing, and running again doesn't move on
+            assert_eq!(cpu.step(&mut bus).unwrap_err(), err);
+            assert_eq!(cpu.pc, 0x0401);
+        }
+    }
 
-            0x6E => { // ROR Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                let result = self.ror(memory, operand, false);
-                memory.write(addr, result);
-            }
+    // STA $4014 is 4 cycles, then 513 more, plus one to line up with a
+    // read cycle when the write ends on an odd one
+    #[test]
+    fn oam_dma_stall_depends_on_cycle_parity() {
+        for (start, stall) in [(0, 513), (1, 514), (2, 513), (7, 514)] {
+            let mut memory = Memory::with_program(&[0x8D, 0x14, 0x40], 0x8000); // STA $4014
+            let mut cpu = Cpu::with_state(0x8000, 0xFD, 0x02, 0, 0, 0x24);
+            cpu.cycles = start;
+            let exec = cpu.step(&mut memory).unwrap();
+            assert_eq!(exec.oam_dma, Some(0x02));
+            assert_eq!(exec.cycles, 4 + stall, "starting on cycle {start}");
+            assert_eq!(cpu.cycles, start + 4 + stall as u64);
+        }
+    }
 
-            0x7E => { // ROR Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                let result = self.ror(memory, operand, false);
-                memory.write(addr, result);
-                // Optional: add cycle penalty if page crossed
-            }
+    // Cycles for the one branch assembled at `addr`, run with P = `status`
+    fn branch_cycles(addr: u16, source: &str, status: u8) -> (u16, u16) {
+        let program = asm::assemble(addr, source).unwrap();
+        let mut bus = FlatBus::new();
+        bus.load(addr, &program.bytes());
+        let mut cpu = Cpu::with_state(addr, 0xFD, 0, 0, 0, status);
+        let cycles = cpu.exec_next_instr(&mut bus).unwrap();
+        (cycles, cpu.pc)
+    }
 
-            0xC9 => { // CMP Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.cmp(operand);
-            }
+    #[test]
+    fn branch_cycles_for_taken_and_page_cross() {
+        const Z: u8 = 0x26;
+        const NOT_Z: u8 = 0x24;
+        // Not taken: 2
+        assert_eq!(branch_cycles(0x8000, "beq $8010", NOT_Z), (2, 0x8002));
+        // Taken within the page: 3
+        assert_eq!(branch_cycles(0x8000, "beq $8010", Z), (3, 0x8010));
+        assert_eq!(branch_cycles(0x8010, "bne $8000", NOT_Z), (3, 0x8000));
+        // Taken onto another page, forward or back: 4
+        assert_eq!(branch_cycles(0x80FD, "beq $8101", Z), (4, 0x8101));
+        assert_eq!(branch_cycles(0x8100, "bne $80FE", NOT_Z), (4, 0x80FE));
+        // The page that counts is the one after the branch, not the branch's own
+        assert_eq!(branch_cycles(0x80FE, "beq $8105", Z), (3, 0x8105));
+    }
 
-            0xC5 => { // CMP Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.cmp(operand);
-            }
+    // Cycles for one indexed instruction run with X = Y = `index`; ($30)
+    // points at $02F0
+    fn indexed_cycles(source: &str, index: u8) -> u16 {
+        let program = asm::assemble(0x8000, source).unwrap();
+        let mut bus = FlatBus::new();
+        bus.load(0x8000, &program.bytes());
+        bus.load(0x0030, &[0xF0, 0x02]);
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, index, index, 0x24);
+        cpu.exec_next_instr(&mut bus).unwrap()
+    }
 
-            0xD5 => { // CMP Zero Page,X
-                let addr = memory.read(self.pc).wrapping_add(self.x) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.cmp(operand);
-            }
+    #[test]
+    fn indexed_reads_pay_for_a_page_cross_and_writes_always_do() {
+        // Reads: +1 only when the index carries into the high byte
+        assert_eq!(indexed_cycles("lda $02FF,x", 0), 4);
+        assert_eq!(indexed_cycles("lda $02FF,x", 1), 5);
+        assert_eq!(indexed_cycles("ldx $02FF,y", 1), 5);
+        assert_eq!(indexed_cycles("lda ($30),y", 0x0F), 5);
+        assert_eq!(indexed_cycles("lda ($30),y", 0x10), 6);
+        // Stores and read-modify-writes take the longer time either way
+        assert_eq!(indexed_cycles("sta $02FF,x", 0), 5);
+        assert_eq!(indexed_cycles("sta $02FF,x", 1), 5);
+        assert_eq!(indexed_cycles("sta ($30),y", 0x0F), 6);
+        assert_eq!(indexed_cycles("sta ($30),y", 0x10), 6);
+        assert_eq!(indexed_cycles("inc $02FF,x", 0), 7);
+        assert_eq!(indexed_cycles("inc $02FF,x", 1), 7);
+    }
 
-            0xCD => { // CMP Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.cmp(operand);
-            }
+    // A PRG image with NMI, reset and IRQ vectors in its last six bytes and
+    // a decoy set where a 16 KiB image's would be, if it's bigger
+    fn prg_with_vectors(len: usize) -> Vec<u8> {
+        let mut prg = vec![0; len];
+        prg[0x3FFA..0x4000].copy_from_slice(&[0x11, 0xDE, 0x22, 0xDE, 0x33, 0xDE]);
+        prg[len - 6..].copy_from_slice(&[0x00, 0xC1, 0x34, 0xC2, 0x00, 0xC3]);
+        prg
+    }
 
-            0xDD => { // CMP Absolute,X
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.x as u16);
-                let operand = memory.read(addr);
-                self.cmp(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    fn memory_with_prg(prg: Vec<u8>) -> Memory {
+        Memory::new(crate::cartridge::Cartridge::from_prg(prg).unwrap())
+    }
 
-            0xD9 => { // CMP Absolute,Y
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let base = (hi << 8) | lo;
-                let addr = base.wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.cmp(operand);
-                // Optional: add cycle penalty if page crossed
-            }
+    #[test]
+    fn vectors_of_a_16_kib_image_come_from_its_end() {
+        let mut prg = vec![0; 0x4000];
+        prg[0x3FFA..].copy_from_slice(&[0x00, 0xC1, 0x34, 0xC2, 0x00, 0xC3]);
+        let mut memory = memory_with_prg(prg);
+        assert_eq!(memory.read_u16(0xFFFA), 0xC100);
+        assert_eq!(memory.read_u16(0xFFFC), 0xC234);
+        assert_eq!(memory.read_u16(0xFFFE), 0xC300);
+        // ...which is also where they sit in the $8000 copy
+        assert_eq!(memory.read_u16(0xBFFC), 0xC234);
+        assert_eq!(Cpu::power_on(&mut memory).pc, 0xC234);
+    }
 
-            0xC1 => { // CMP (Indirect,X)
-                let base = memory.read(self.pc).wrapping_add(self.x);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.cmp(operand);
-            }
+    #[test]
+    fn vectors_of_a_32_kib_image_come_from_its_end() {
+        let mut memory = memory_with_prg(prg_with_vectors(0x8000));
+        assert_eq!(memory.read_u16(0xFFFA), 0xC100);
+        assert_eq!(memory.read_u16(0xFFFC), 0xC234);
+        assert_eq!(memory.read_u16(0xFFFE), 0xC300);
+        assert_eq!(Cpu::power_on(&mut memory).pc, 0xC234);
+    }
 
-            0xD1 => { // CMP (Indirect),Y
-                let base = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                let lo = memory.read(base as u16) as u16;
-                let hi = memory.read(base.wrapping_add(1) as u16) as u16;
-                let addr = ((hi << 8) | lo).wrapping_add(self.y as u16);
-                let operand = memory.read(addr);
-                self.cmp(operand);
-                // Optional: cycle penalty on page cross
-            }
+    // BRK and the interrupts fetch theirs through the same mapping
+    #[test]
+    fn interrupts_use_the_mapped_vectors() {
+        for len in [0x4000, 0x8000] {
+            let mut memory = memory_with_prg(prg_with_vectors(len));
+            memory.poke(0x0200, 0x00); // BRK
+            let mut cpu = Cpu::with_state(0x0200, 0xFD, 0, 0, 0, 0x20);
+            cpu.exec_next_instr(&mut memory).unwrap();
+            assert_eq!(cpu.pc, 0xC300, "BRK with {} KiB", len / 1024);
+            cpu.set_nmi_line(true);
+            cpu.exec_next_instr(&mut memory).unwrap();
+            assert_eq!(cpu.pc, 0xC100, "NMI with {} KiB", len / 1024);
+        }
+    }
 
-            // CPX instructions
-            0xE0 => { // CPX Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.cpx(memory, operand);
-            }
+    // Reset runs as a 7-cycle interrupt with the stack writes suppressed
+    #[test]
+    fn power_on_runs_the_reset_sequence() {
+        let mut bus = FlatBus::with_program(&[0xEA], 0x0400);
+        let cpu = Cpu::power_on(&mut bus);
+        assert_eq!(cpu.pc, 0x0400);
+        assert_eq!(cpu.sp, 0xFD); // $00 less 3
+        assert!(cpu.get_flag(Flag::InterruptDisable));
+        assert_eq!(cpu.cycles, 7);
+        assert_eq!(&bus.ram()[0x100..0x200], &[0; 0x100][..], "reset wrote the stack");
+    }
 
-            0xE4 => { // CPX Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.cpx(memory, operand);
-            }
+    #[test]
+    fn reset_keeps_a_x_y_and_repeats_the_same_way() {
+        let mut bus = FlatBus::with_program(&[0xEA], 0x0400);
+        let mut cpu = Cpu::with_state(0x1234, 0xF0, 0x11, 0x22, 0x33, 0x20);
+        cpu.cycles = 1000;
+        for n in 1..=3u8 {
+            let cycles = cpu.cycles;
+            cpu.reset(&mut bus);
+            assert_eq!(cpu.pc, 0x0400);
+            assert_eq!(cpu.sp, 0xF0 - 3 * n, "SP after reset {n}");
+            assert_eq!(cpu.status, 0x24, "P after reset {n}");
+            assert_eq!((cpu.a, cpu.x, cpu.y), (0x11, 0x22, 0x33), "A/X/Y after reset {n}");
+            assert_eq!(cpu.cycles, cycles + 7, "cycles after reset {n}");
+            cpu.exec_next_instr(&mut bus).unwrap(); // NOP, to reset mid-program
+        }
+        assert_eq!(&bus.ram()[0x100..0x200], &[0; 0x100][..], "reset wrote the stack");
+    }
 
-            0xEC => { // CPX Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.cpx(memory, operand);
-            }
+    // Pressing Reset halfway through a ticked instruction drops the rest of
+    // it: the next tick starts the first instruction at the reset vector
+    #[test]
+    fn reset_abandons_a_ticked_instruction() {
+        let mut bus = FlatBus::with_program(&[0xEE, 0x00, 0x03], 0x0400); // INC $0300, 6 cycles
+        let mut cpu = Cpu::power_on(&mut bus);
+        cpu.tick(&mut bus).unwrap();
+        assert!(!cpu.at_instruction_boundary());
+
+        cpu.reset(&mut bus);
+        assert!(cpu.at_instruction_boundary());
+        assert_eq!(cpu.cycles, 7 + 1 + 7);
+        let exec = cpu.step(&mut bus).unwrap();
+        assert_eq!((exec.opcode, exec.cycles), (0xEE, 6));
+        assert_eq!(cpu.cycles, 7 + 1 + 7 + 6);
+        assert_eq!(bus.ram()[0x300], 2); // Once from the first tick, once now
+    }
 
-            // CPY instructions
-            0xC0 => { // CPY Immediate
-                let operand = memory.read(self.pc);
-                self.pc = self.pc.wrapping_add(1);
-                self.cpy(memory, operand);
-            }
+    #[test]
+    fn step_reports_what_ran() {
+        let code = [0xA9, 0x42, 0x9D, 0xFE, 0x02, 0xD0, 0xF9]; // LDA #$42, STA $02FE,X, BNE back to the start
+        let mut memory = Memory::with_program(&code, ORIGIN);
+        let mut cpu = Cpu::with_state(ORIGIN, 0xFD, 0, 2, 0, 0x24);
 
-            0xC4 => { // CPY Zero Page
-                let addr = memory.read(self.pc) as u16;
-                self.pc = self.pc.wrapping_add(1);
-                let operand = memory.read(addr);
-                self.cpy(memory, operand);
-            }
+        let load = cpu.step(&mut memory).unwrap();
+        let info = ExecInfo { opcode: 0xA9, len: 2, value: Some(0x42), cycles: 2, ..ExecInfo::default() };
+        assert_eq!(load, info);
 
-            0xCC => { // CPY Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = self.pc.wrapping_add(2);
-                let addr = (hi << 8) | lo;
-                let operand = memory.read(addr);
-                self.cpy(memory, operand);
-            }
+        let store = cpu.step(&mut memory).unwrap();
+        let info = ExecInfo { opcode: 0x9D, len: 3, addr: Some(0x0300), value: Some(0x42), cycles: 5, ..ExecInfo::default() };
+        assert_eq!(store, info);
 
-            // JMP implementation
-            0x4C => { // JMP Absolute
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                self.pc = (hi << 8) | lo;
-                // Note: Don't increment PC as we're jumping
-            }
+        let branch = cpu.step(&mut memory).unwrap();
+        let info = ExecInfo { opcode: 0xD0, len: 2, branch_taken: true, cycles: 3, ..ExecInfo::default() };
+        assert_eq!(branch, info);
+        assert_eq!(cpu.pc, ORIGIN);
+    }
 
-            0x6C => { // JMP Indirect
-                let addr_lo = memory.read(self.pc) as u16;
-                let addr_hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                let addr = (addr_hi << 8) | addr_lo;
-                
-                // 6502 indirect jump has a bug with page boundaries:
-                // It doesn't carry over to the next page when fetching the high byte
-                let lo = memory.read(addr) as u16;
-                let hi = if (addr & 0xFF) == 0xFF {
-                    // Page boundary bug - high byte is fetched from same page
-                    memory.read(addr & 0xFF00) as u16
-                } else {
-                    memory.read(addr.wrapping_add(1)) as u16
-                };
-                
-                self.pc = (hi << 8) | lo;
-                // Note: Don't increment PC as we're jumping
-            }
+    // The start of nestest.log, minus the PPU column
+    const NESTEST_START: &[&str] = &[
+        "C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD CYC:7",
+        "C5F5  A2 00     LDX #$00                        A:00 X:00 Y:00 P:24 SP:FD CYC:10",
+        "C5F7  86 00     STX $00 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:12",
+        "C5F9  86 10     STX $10 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:15",
+        "C5FB  86 11     STX $11 = 00                    A:00 X:00 Y:00 P:26 SP:FD CYC:18",
+        "C5FD  20 2D C7  JSR $C72D                       A:00 X:00 Y:00 P:26 SP:FD CYC:21",
+        "C72D  EA        NOP                             A:00 X:00 Y:00 P:26 SP:FB CYC:27",
+        "C72E  38        SEC                             A:00 X:00 Y:00 P:26 SP:FB CYC:29",
+        "C72F  B0 04     BCS $C735                       A:00 X:00 Y:00 P:27 SP:FB CYC:31",
+    ];
+
+    #[test]
+    fn trace_matches_nestest_log() {
+        let mut bus = FlatBus::new();
+        bus.load(0xC000, &[0x4C, 0xF5, 0xC5]);
+        bus.load(0xC5F5, &[0xA2, 0x00, 0x86, 0x00, 0x86, 0x10, 0x86, 0x11, 0x20, 0x2D, 0xC7]);
+        bus.load(0xC72D, &[0xEA, 0x38, 0xB0, 0x04]);
+        let mut cpu = Cpu::with_state(0xC000, 0xFD, 0, 0, 0, 0x24);
+        cpu.cycles = 7;
+        for line in NESTEST_START {
+            assert_eq!(cpu.trace(&bus), *line);
+            cpu.exec_next_instr(&mut bus).unwrap();
+        }
+    }
 
-            0x20 => { // JSR Absolute
-                // Read the target address first
-                let lo = memory.read(self.pc) as u16;
-                let hi = memory.read(self.pc.wrapping_add(1)) as u16;
-                let target_addr = (hi << 8) | lo;
-                
-                // Push return address (PC + 1) onto stack
-                // JSR pushes the address of the last byte of the instruction
-                let return_addr = self.pc.wrapping_add(1);
-                self.push_u16(memory, return_addr);
-                
-                // Jump to the target address
-                self.pc = target_addr;
-            }
+    // The operand annotations of the other modes, and unofficial opcodes'
+    // '*', laid out as in nestest.log
+    #[test]
+    fn trace_annotates_operands_like_nestest() {
+        let mut bus = FlatBus::new();
+        bus.load(0x0080, &[0x00, 0x02]);
+        bus.load(0x0089, &[0x00, 0x03]);
+        bus.load(0x0200, &[0x7E, 0xDB, 0x5A]);
+        bus.load(0x0300, &[0x89]);
+        let trace = |pc: u16, code: &[u8], y: u8, bus: &mut FlatBus| {
+            bus.load(pc, code);
+            Cpu::with_state(pc, 0xFB, 0x00, 0x00, y, 0x27).trace(bus)
+        };
+        // The registers start in column 48
+        let line = |text: &str, y: u8| format!("{:<48}A:00 X:00 Y:{:02X} P:27 SP:FB CYC:0", text, y);
+        assert_eq!(trace(0xD959, &[0xB1, 0x89], 0, &mut bus), line("D959  B1 89     LDA ($89),Y = 0300 @ 0300 = 89", 0));
+        assert_eq!(trace(0xD95B, &[0xA1, 0x80], 0, &mut bus), line("D95B  A1 80     LDA ($80,X) @ 80 = 0200 = 7E", 0));
+        assert_eq!(trace(0xDB7B, &[0x6C, 0x00, 0x02], 0, &mut bus), line("DB7B  6C 00 02  JMP ($0200) = DB7E", 0));
+        assert_eq!(trace(0xDC00, &[0xB9, 0xFE, 0x01], 4, &mut bus), line("DC00  B9 FE 01  LDA $01FE,Y @ 0202 = 5A", 4));
+        assert_eq!(trace(0xDC03, &[0x96, 0x88], 1, &mut bus), line("DC03  96 88     STX $88,Y @ 89 = 00", 1));
+        assert_eq!(trace(0xDC05, &[0x04, 0xA9], 0, &mut bus), line("DC05  04 A9    *NOP $A9 = 00", 0));
+        assert_eq!(trace(0xDC07, &[0xAB, 0x01], 0, &mut bus), line("DC07  AB        .byte $AB", 0));
+    }
 
-            0x60 => { // RTS (Return from Subroutine)
-                // Pull return address from stack
-                let return_addr = self.pull_u16(memory);
-                
-                // Set PC to return address + 1 (corrects the +2 from JSR)
-                self.pc = return_addr.wrapping_add(1);
-                
-                // Takes 6 cycles total:
-                // 1. Fetch opcode
-                // 2. Read next opcode (discarded)
-                // 3. Pull low byte from stack
-                // 4. Pull high byte from stack
-                // 5-6. Internal PC increment
-            }
+    // Ticking through the cycles exec_next_instr reports ends in the same
+    // place, one cycle per tick
+    #[test]
+    fn ticks_add_up_to_whole_instructions() {
+        let source = "
+                    ldx #$FF
+            loop:   lda $02F0,x
+                    inc $0300
+                    asl a
+                    dex
+                    bne loop
+                    jmp loop";
+        let (mut memory, _) = memory_with_source(source);
+        let mut cpu = Cpu::power_on(&mut memory);
+        let (mut ticked_memory, _) = memory_with_source(source);
+        let mut ticked = Cpu::power_on(&mut ticked_memory);
+
+        for _ in 0..200 {
+            let cycles = cpu.exec_next_instr(&mut memory).unwrap();
+            for _ in 0..cycles {
+                let before = ticked.cycles;
+                ticked.tick(&mut ticked_memory).unwrap();
+                assert_eq!(ticked.cycles, before + 1);
+            }
+            assert!(ticked.at_instruction_boundary());
+            assert_eq!(ticked.snapshot(), cpu.snapshot());
+        }
+        assert_eq!(ticked_memory.diff(&memory), None);
+    }
 
-            // ALL BRANCH INSTRUCTIONS:
-            // BEQ - Branch if Equal (Zero flag set)
-            0xF0 => { // BEQ Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & ZERO_FLAG) != 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    #[test]
+    fn run_cycles_runs_whole_instructions() {
+        let mut bus = FlatBus::with_program(&[0xEA; 64], 0x0400);
+        let mut cpu = Cpu::power_on(&mut bus);
+        assert_eq!(cpu.run_cycles(&mut bus, 20).unwrap(), 0);
+        assert_eq!((cpu.pc, cpu.cycles), (0x040A, 7 + 20)); // 10 NOPs
+        assert_eq!(cpu.run_until(&mut bus, 10).unwrap(), 17); // Already past it: nothing runs
+        assert_eq!(cpu.pc, 0x040A);
+
+        // NOP, LDA $10: a 3-cycle budget ends partway into the LDA, which still runs to the end
+        let mut bus = FlatBus::with_program(&[0xEA, 0xA5, 0x10, 0xEA], 0x0400);
+        let mut cpu = Cpu::power_on(&mut bus);
+        assert_eq!(cpu.run_cycles(&mut bus, 3).unwrap(), 2);
+        assert_eq!((cpu.pc, cpu.cycles), (0x0403, 7 + 5));
+    }
 
-            // BNE - Branch if Not Equal (Zero flag clear)
-            0xD0 => { // BNE Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & ZERO_FLAG) == 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    #[test]
+    fn run_cycles_stops_at_an_unknown_opcode_and_idles_when_jammed() {
+        let mut bus = FlatBus::with_program(&[0xEA, 0xAB, 0x00], 0x0400);
+        let mut cpu = Cpu::power_on(&mut bus);
+        let err = cpu.run_cycles(&mut bus, 100).unwrap_err();
+        assert_eq!(err, CpuError::UnknownOpcode { opcode: 0xAB, pc: 0x0401 });
+        assert_eq!((cpu.pc, cpu.cycles), (0x0401, 7 + 2));
+        assert_eq!(cpu.run_until(&mut bus, 1_000).unwrap_err(), err);
+
+        // After the NOP and the JAM the clock just moves to the target
+        let mut bus = FlatBus::with_program(&[0xEA, 0x02, 0xEA], 0x0400);
+        let mut cpu = Cpu::power_on(&mut bus);
+        assert_eq!(cpu.run_cycles(&mut bus, 101).unwrap(), 0);
+        assert!(cpu.is_halted());
+        assert_eq!((cpu.pc, cpu.cycles), (0x0401, 7 + 101));
+        assert_eq!(cpu.run_until(&mut bus, 1_000).unwrap(), 0);
+        assert_eq!((cpu.pc, cpu.cycles), (0x0401, 1_000));
+    }
 
-            // BCS - Branch if Carry Set (Carry flag set)
-            0xB0 => { // BCS Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & CARRY_FLAG) != 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
+    // CpuState plus a copy of memory is enough to replay a run exactly,
+    // even restored into a different Cpu
+    #[test]
+    fn restoring_a_snapshot_replays_the_same_run() {
+        let (mut memory, _) = memory_with_source(
+            "
+            loop:   txa
+                    adc $10
+                    sta $10
+                    pha
+                    rol
+                    inx
+                    bne loop
+                    jmp loop",
+        );
+        let run = |cpu: &mut Cpu, memory: &mut Memory| {
+            for _ in 0..100 {
+                cpu.exec_next_instr(memory).unwrap();
             }
+        };
+        let mut cpu = Cpu::power_on(&mut memory);
+        cpu.exec_next_instr(&mut memory).unwrap();
+        let (state, saved) = (cpu.snapshot(), memory.clone());
+
+        run(&mut cpu, &mut memory);
+        let (end, end_memory) = (cpu.snapshot(), memory);
+        assert_ne!(end, state);
+
+        let mut cpu = Cpu::new();
+        cpu.restore(&state);
+        assert_eq!(cpu.snapshot(), state);
+        let mut memory = saved;
+        run(&mut cpu, &mut memory);
+        assert_eq!(cpu.snapshot(), end);
+        assert_eq!(memory.diff(&end_memory), None);
+    }
 
-            // BCC - Branch if Carry Clear (Carry flag clear)
-            0x90 => { // BCC Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & CARRY_FLAG) == 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    // An RMW on memory writes the value it read straight back, then the
+    // result; mappers and PPU registers see both writes
+    #[test]
+    fn rmw_writes_the_old_value_then_the_new_one() {
+        use crate::mem::Access::{Read, Write};
+
+        let code = [0xEE, 0x00, 0x03, 0x16, 0x0E]; // INC $0300, ASL $0E,X
+        let mut memory = Memory::with_program(&code, ORIGIN);
+        memory.poke(0x0300, 0x41);
+        memory.poke(0x0010, 0xC0);
+        memory.record_accesses(true);
+        let mut cpu = Cpu::with_state(ORIGIN, 0xFD, 0, 2, 0, 0x24);
+        let mut log = Vec::new();
+
+        cpu.exec_next_instr(&mut memory).unwrap();
+        memory.drain_accesses(&mut log);
+        let data: Vec<_> = log.iter().filter(|&&(_, addr, _)| addr == 0x0300).collect();
+        assert_eq!(data, [&(Read, 0x0300, 0x41), &(Write, 0x0300, 0x41), &(Write, 0x0300, 0x42)]);
+
+        cpu.exec_next_instr(&mut memory).unwrap();
+        memory.drain_accesses(&mut log);
+        let data: Vec<_> = log.iter().filter(|&&(_, addr, _)| addr == 0x0010).collect();
+        assert_eq!(data, [&(Read, 0x0010, 0xC0), &(Write, 0x0010, 0xC0), &(Write, 0x0010, 0x80)]);
+        assert!(cpu.get_flag(Flag::Carry));
+    }
 
-            // BMI - Branch if Minus (Negative flag set)
-            0x30 => { // BMI Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & NEGATIVE_FLAG) != 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    // JAM stops the CPU on its own address. After that a step only moves
+    // the clock, until reset.
+    #[test]
+    fn jam_halts_until_reset() {
+        let mut bus = FlatBus::with_program(&[0xA9, 0x01, 0x02, 0xA9, 0x02], 0x0400); // LDA #1, JAM, LDA #2
+        let mut cpu = Cpu::power_on(&mut bus);
+        cpu.exec_next_instr(&mut bus).unwrap();
+        let exec = cpu.step(&mut bus).unwrap();
+        assert_eq!((exec.opcode, exec.len, exec.cycles), (0x02, 1, 2));
+        assert!(cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0402);
+
+        let jammed = cpu.snapshot();
+        for n in 1..=3 {
+            let exec = cpu.step(&mut bus).unwrap();
+            assert_eq!(exec, ExecInfo { cycles: 1, ..ExecInfo::default() }, "step {n}");
+            assert_eq!(cpu.snapshot(), CpuState { cycles: jammed.cycles + n, ..jammed }, "step {n}");
+        }
+        assert_eq!(cpu.exec_next_instr(&mut bus).unwrap(), 1);
+        assert_eq!(cpu.run_cycles(&mut bus, 100).unwrap(), 0);
+        assert!(cpu.is_halted());
+
+        cpu.reset(&mut bus);
+        assert!(!cpu.is_halted());
+        assert_eq!(cpu.pc, 0x0400);
+        cpu.exec_next_instr(&mut bus).unwrap();
+        assert_eq!((cpu.a, cpu.pc), (0x01, 0x0402));
+    }
 
-            // BPL - Branch if Plus/Positive (Negative flag clear)
-            0x10 => { // BPL Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & NEGATIVE_FLAG) == 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    // `source` assembled at $8000 on the NES memory map, with the reset
+    // vector on it; sources set the NMI and IRQ vectors with `* = $FFFA`
+    // and `* = $FFFE`
+    fn memory_with_source(source: &str) -> (Memory, asm::Program) {
+        let program = asm::assemble(0x8000, source).unwrap();
+        let mut builder = testbus::TestBus::builder().reset_vector(0x8000);
+        for (addr, bytes) in &program.segments {
+            builder = builder.program_at(*addr, bytes);
+        }
+        (builder.build(), program)
+    }
 
-            // BVS - Branch if Overflow Set (Overflow flag set)
-            0x70 => { // BVS Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & OVERFLOW_FLAG) != 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    // Counts NMIs in $10 and returns
+    const NMI_PROGRAM: &str = "
+            lda #$01
+    next:   ldx #$02
+            ldy #$03
+    spin:   jmp spin
+    nmi:    inc $10
+            rti
+            * = $FFFA
+            .word nmi";
+
+    #[test]
+    fn nmi_pushes_pc_and_p_and_vectors_through_fffa() {
+        let (mut memory, program) = memory_with_source(NMI_PROGRAM);
+        let mut cpu = Cpu::power_on(&mut memory);
+        cpu.exec_next_instr(&mut memory).unwrap(); // LDA
+        cpu.set_flag(Flag::InterruptDisable, false);
+        let status = cpu.status;
+
+        cpu.set_nmi_line(true);
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!(exec.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(exec.cycles, 7);
+        assert_eq!(cpu.pc, program.label("nmi").unwrap());
+        assert_eq!(cpu.sp, 0xFA);
+        // The interrupted instruction's address, high byte first
+        assert_eq!(memory.peek_u16(0x01FC), program.label("next").unwrap());
+        let pushed = memory.peek(0x01FB);
+        assert_eq!(pushed & BREAK_FLAG, 0, "B in the pushed P");
+        assert_ne!(pushed & UNUSED_FLAG, 0, "bit 5 in the pushed P");
+        assert_eq!(pushed, status | UNUSED_FLAG);
+        assert!(cpu.get_flag(Flag::InterruptDisable));
+
+        // INC, RTI: back to LDX with the old P
+        cpu.exec_next_instr(&mut memory).unwrap();
+        cpu.exec_next_instr(&mut memory).unwrap();
+        assert_eq!(cpu.pc, program.label("next").unwrap());
+        assert_eq!((cpu.sp, cpu.status), (0xFD, status));
+        assert_eq!(memory.peek(0x10), 1);
+    }
 
-            // BVC - Branch if Overflow Clear (Overflow flag clear)
-            0x50 => { // BVC Relative
-                let offset = memory.read(self.pc) as i8;
-                self.pc = self.pc.wrapping_add(1);
-                
-                if (self.status & OVERFLOW_FLAG) == 0 {
-                    // Branch taken - add 1 cycle for branch taken
-                    let target = self.pc.wrapping_add((offset as i16) as u16);
-                    
-                    // Add 1 more cycle if page boundary crossed
-                    if (self.pc & 0xFF00) != (target & 0xFF00) {
-                        // Page boundary crossed - add extra cycle
-                    }
-                    
-                    self.pc = target;
-                }
-            }
+    // Edge-triggered: a line held high fires once, and has to drop before
+    // it can fire again
+    #[test]
+    fn nmi_line_held_high_fires_once() {
+        let (mut memory, _) = memory_with_source(NMI_PROGRAM);
+        let mut cpu = Cpu::power_on(&mut memory);
+        cpu.set_nmi_line(true);
+        for _ in 0..20 {
+            cpu.set_nmi_line(true);
+            cpu.exec_next_instr(&mut memory).unwrap();
+        }
+        assert_eq!(memory.peek(0x10), 1);
+        assert!(!cpu.nmi_pending());
+
+        cpu.set_nmi_line(false);
+        cpu.set_nmi_line(true);
+        assert!(cpu.nmi_pending());
+        for _ in 0..20 {
+            cpu.exec_next_instr(&mut memory).unwrap();
+        }
+        assert_eq!(memory.peek(0x10), 2);
+    }
 
+    // Counts IRQs in $10 without acknowledging them
+    const IRQ_PROGRAM: &str = "
+    start:  nop
+            nop
+            nop
+    spin:   jmp spin
+    irq:    inc $10
+            rti
+            * = $FFFE
+            .word irq";
+
+    fn irq_console(status: u8) -> (Cpu, Memory, asm::Program) {
+        let (mut memory, program) = memory_with_source(IRQ_PROGRAM);
+        memory.irq_line_mut().assert(crate::irq::Source::Mapper);
+        (Cpu::with_state(0x8000, 0xFD, 0, 0, 0, status), memory, program)
+    }
 
-            // INTERRUPT HANDLING, MAY HAVE ERRORS
-            0x00 => { // BRK (Force Interrupt)
-                self.pc = self.pc.wrapping_add(1); // Skip next byte (BRK padding)
-                self.push_u16(memory, self.pc);
-                // Push status with Break flag set
-                self.push_u8(memory, self.status | 0b00110000); // Set B and unused flags
-                self.status |= 0b00000100; // Set Interrupt Disable flag
-                self.pc = memory.read_u16(0xFFFE); // Jump to IRQ/BRK vector
-            }
+    #[test]
+    fn masked_irq_waits() {
+        let (mut cpu, mut memory, _) = irq_console(0x24);
+        for _ in 0..3 {
+            let exec = cpu.step(&mut memory).unwrap();
+            assert_eq!((exec.interrupt, exec.opcode), (None, 0xEA));
+        }
+        assert_eq!(cpu.pc, 0x8003);
+        assert_eq!(memory.peek(0x10), 0);
+        assert_eq!(cpu.pending_interrupt(&memory), None);
+    }
 
-            0x40 => { // RTI (Return from Interrupt)
-                self.pull_status(memory);
-                self.pc = self.pull_u16(memory);
-            }
+    #[test]
+    fn unmasked_irq_vectors_through_fffe() {
+        let (mut cpu, mut memory, program) = irq_console(0x21);
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!(exec.interrupt, Some(Interrupt::Irq));
+        assert_eq!(exec.cycles, 7);
+        assert_eq!(cpu.pc, program.label("irq").unwrap());
+        assert_eq!(memory.peek_u16(0x01FC), 0x8000);
+        assert_eq!(memory.peek(0x01FB), 0x21, "pushed P: B clear, bit 5 set");
+        assert!(cpu.get_flag(Flag::InterruptDisable));
+    }
 
-            0xEA => { // NOP (No Operation)
-                // Does nothing
-            }
+    // RTI restores I at once, so a source still asserting gets straight
+    // back in, without the instruction at the return address running
+    #[test]
+    fn rti_into_a_still_pending_irq_fires_again_at_once() {
+        let (mut cpu, mut memory, _) = irq_console(0x20);
+        cpu.exec_next_instr(&mut memory).unwrap(); // IRQ
+        cpu.exec_next_instr(&mut memory).unwrap(); // INC $10
+        cpu.exec_next_instr(&mut memory).unwrap(); // RTI
+        assert_eq!((cpu.pc, cpu.status), (0x8000, 0x20));
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!(exec.interrupt, Some(Interrupt::Irq));
+        assert_eq!(memory.peek_u16(0x01FC), 0x8000);
+
+        // Acknowledged in the handler this time, so the program goes on
+        memory.irq_line_mut().acknowledge(crate::irq::Source::Mapper);
+        cpu.exec_next_instr(&mut memory).unwrap(); // INC $10
+        cpu.exec_next_instr(&mut memory).unwrap(); // RTI
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!((exec.interrupt, exec.opcode), (None, 0xEA));
+        assert_eq!(memory.peek(0x10), 2);
+    }
 
-            // Flag manipulation instructions
-            0x18 => { // CLC (Clear Carry)
-                self.status &= 0b11111110;
-            }
+    // Two devices share the line: acknowledging one leaves the CPU
+    // interrupted again by the other
+    #[test]
+    fn irq_sources_are_acknowledged_one_at_a_time() {
+        let (mut cpu, mut memory, _) = irq_console(0x20);
+        memory.irq_line_mut().assert(crate::irq::Source::ApuFrame);
+        cpu.exec_next_instr(&mut memory).unwrap(); // IRQ
+        memory.irq_line_mut().acknowledge(crate::irq::Source::Mapper);
+        cpu.exec_next_instr(&mut memory).unwrap(); // INC $10
+        cpu.exec_next_instr(&mut memory).unwrap(); // RTI
+        assert_eq!(cpu.step(&mut memory).unwrap().interrupt, Some(Interrupt::Irq), "the frame IRQ");
+
+        memory.irq_line_mut().acknowledge(crate::irq::Source::ApuFrame);
+        cpu.exec_next_instr(&mut memory).unwrap(); // INC $10
+        cpu.exec_next_instr(&mut memory).unwrap(); // RTI
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!((exec.interrupt, exec.opcode), (None, 0xEA));
+        assert_eq!(memory.peek(0x10), 2);
+    }
 
-            0x38 => { // SEC (Set Carry)
-                self.status |= 0b00000001;
-            }
+    // Where each of the next `steps` steps went: the opcode run, or None
+    // for an interrupt taken
+    fn step_trail(cpu: &mut Cpu, memory: &mut Memory, steps: usize) -> Vec<(u16, Option<u8>)> {
+        (0..steps)
+            .map(|_| {
+                let pc = cpu.pc;
+                let exec = cpu.step(memory).unwrap();
+                (pc, exec.interrupt.is_none().then_some(exec.opcode))
+            })
+            .collect()
+    }
 
-            0xD8 => { // CLD (Clear Decimal)
-                self.status &= 0b11110111;
-            }
+    fn irq_pending_memory(source: &str) -> Memory {
+        let (mut memory, _) = memory_with_source(&format!("{source}\n irq: rti\n * = $FFFE\n .word irq"));
+        memory.irq_line_mut().assert(crate::irq::Source::Mapper);
+        memory
+    }
 
-            0xF8 => { // SED (Set Decimal)
-                self.status |= 0b00001000;
-            }
+    // The IRQ waiting on CLI gets in after the instruction following it
+    #[test]
+    fn cli_lets_a_pending_irq_in_one_instruction_late() {
+        let mut memory = irq_pending_memory("cli\n nop\n nop\n nop");
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x24);
+        let trail = step_trail(&mut cpu, &mut memory, 3);
+        assert_eq!(trail, [(0x8000, Some(0x58)), (0x8001, Some(0xEA)), (0x8002, None)]);
+        assert_eq!(memory.peek_u16(0x01FC), 0x8002, "return address");
+    }
 
-            0x58 => { // CLI (Clear Interrupt Disable)
-                self.status &= 0b11111011;
-            }
+    #[test]
+    fn plp_clearing_i_lets_a_pending_irq_in_one_instruction_late() {
+        let mut memory = irq_pending_memory("lda #$20\n pha\n plp\n nop\n nop\n nop");
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x24);
+        let trail = step_trail(&mut cpu, &mut memory, 5);
+        assert_eq!(
+            trail,
+            [(0x8000, Some(0xA9)), (0x8002, Some(0x48)), (0x8003, Some(0x28)), (0x8004, Some(0xEA)), (0x8005, None)]
+        );
+    }
 
-            0x78 => { // SEI (Set Interrupt Disable)
-                self.status |= 0b00000100;
-            }
+    // An IRQ arriving just after SEI still gets in, with I set in the pushed
+    // P; one instruction later SEI has taken hold
+    #[test]
+    fn sei_still_lets_an_irq_in_right_after_it() {
+        let mut memory = irq_pending_memory("sei\n nop\n nop");
+        memory.irq_line_mut().acknowledge(crate::irq::Source::Mapper);
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x20);
+        cpu.exec_next_instr(&mut memory).unwrap(); // SEI
+        memory.irq_line_mut().assert(crate::irq::Source::Mapper);
+        assert_eq!(step_trail(&mut cpu, &mut memory, 1), [(0x8001, None)]);
+        assert_eq!(memory.peek(0x01FB), 0x24);
+
+        let mut memory = irq_pending_memory("sei\n nop\n nop");
+        memory.irq_line_mut().acknowledge(crate::irq::Source::Mapper);
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x20);
+        cpu.exec_next_instr(&mut memory).unwrap(); // SEI
+        cpu.exec_next_instr(&mut memory).unwrap(); // NOP
+        memory.irq_line_mut().assert(crate::irq::Source::Mapper);
+        assert_eq!(step_trail(&mut cpu, &mut memory, 1), [(0x8002, Some(0xEA))]);
+    }
 
-            0xB8 => { // CLV (Clear Overflow)
-                self.status &= 0b10111111;
-            }
+    // RTI has no delay: the pulled I applies to the very next poll
+    #[test]
+    fn rti_clearing_i_takes_effect_at_once() {
+        let mut memory = irq_pending_memory("rti\n nop\n nop");
+        for (addr, value) in [(0x01FB, 0x20), (0x01FC, 0x01), (0x01FD, 0x80)] {
+            memory.poke(addr, value);
+        }
+        let mut cpu = Cpu::with_state(0x8000, 0xFA, 0, 0, 0, 0x24);
+        let trail = step_trail(&mut cpu, &mut memory, 2);
+        assert_eq!(trail, [(0x8000, Some(0x40)), (0x8001, None)]);
+    }
 
-            // Additional NOP variants (do nothing but take cycles)
-            0x1A => { /* NOP */ }
-            0x3A => { /* NOP */ }
-            0x5A => { /* NOP */ }
-            0x7A => { /* NOP */ }
-            0xDA => { /* NOP */ }
-            0xFA => { /* NOP */ }
-            0x80 => { /* NOP (immediate) */ self.pc += 1; }
-            0x82 => { /* NOP (immediate) */ self.pc += 1; }
-            0x89 => { /* NOP (immediate) */ self.pc += 1; }
-            0xC2 => { /* NOP (immediate) */ self.pc += 1; }
-            0xE2 => { /* NOP (immediate) */ self.pc += 1; }
-            0x04 => { /* NOP (zeropage) */ self.pc += 1; }
-            0x44 => { /* NOP (zeropage) */ self.pc += 1; }
-            0x64 => { /* NOP (zeropage) */ self.pc += 1; }
-            0x14 => { /* NOP (zeropage,X) */ self.pc += 1; }
-            0x34 => { /* NOP (zeropage,X) */ self.pc += 1; }
-            0x54 => { /* NOP (zeropage,X) */ self.pc += 1; }
-            0x74 => { /* NOP (zeropage,X) */ self.pc += 1; }
-            0xD4 => { /* NOP (zeropage,X) */ self.pc += 1; }
-            0xF4 => { /* NOP (zeropage,X) */ self.pc += 1; }
-            0x0C => { /* NOP (absolute) */ self.pc += 2; }
-            0x1C => { /* NOP (absolute,X) */ self.pc += 2; }
-            0x3C => { /* NOP (absolute,X) */ self.pc += 2; }
-            0x5C => { /* NOP (absolute,X) */ self.pc += 2; }
-            0x7C => { /* NOP (absolute,X) */ self.pc += 2; }
-            0xDC => { /* NOP (absolute,X) */ self.pc += 2; }
-            0xFC => { /* NOP (absolute,X) */ self.pc += 2; }
-
-            _ => {
-                let log_line = format!("Unimplemented opcode: {:02X} at PC: {:04X}\n", opcode, self.pc - 1);
-                let hex_line = format!("{:02X}\n", opcode);
-                // debug
-                if let Ok(mut file) = OpenOptions::new()
-                    .create(true)           // Create if doesn't exist
-                    .append(true)           // Append to end of file
-                    .open("unimplemented_opcodes.log")
-                {
-                    let _ = file.write_all(hex_line.as_bytes());
-                }
-                println!("{}", log_line);
-               
+    // BRK at $8000 with separate NMI and IRQ/BRK handlers
+    const BRK_PROGRAM: &str = "
+            brk
+            nop
+    nmi:    rti
+    irq:    rti
+            * = $FFFA
+            .word nmi
+            * = $FFFE
+            .word irq";
+
+    // An NMI raised once BRK has started takes over its vector fetch: BRK's
+    // pushes, B included, then the NMI handler
+    #[test]
+    fn nmi_during_brk_hijacks_the_vector() {
+        let (mut memory, program) = memory_with_source(BRK_PROGRAM);
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x20);
+        cpu.set_nmi_line_after_poll(true);
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!((exec.opcode, exec.interrupt, exec.cycles), (0x00, None, 7));
+        assert_eq!(cpu.pc, program.label("nmi").unwrap());
+        assert_eq!(memory.peek_u16(0x01FC), 0x8002, "BRK's return address");
+        assert_eq!(memory.peek(0x01FB), 0x30, "pushed P: B and bit 5 set");
+        // The NMI was used up by the hijack
+        assert!(!cpu.nmi_pending());
+        assert_eq!(cpu.step(&mut memory).unwrap().interrupt, None);
+
+        // Without it, BRK goes through $FFFE as usual
+        let (mut memory, program) = memory_with_source(BRK_PROGRAM);
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x20);
+        cpu.exec_next_instr(&mut memory).unwrap();
+        assert_eq!(cpu.pc, program.label("irq").unwrap());
+        assert_eq!(memory.peek(0x01FB), 0x30);
+    }
 
-            }
-        }
+    // An NMI already latched before BRK is polled is taken first, as a
+    // plain NMI with B clear; BRK runs after the handler returns
+    #[test]
+    fn nmi_before_brk_is_not_a_hijack() {
+        let (mut memory, program) = memory_with_source(BRK_PROGRAM);
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0, 0, 0x20);
+        cpu.set_nmi_line(true);
+        let exec = cpu.step(&mut memory).unwrap();
+        assert_eq!(exec.interrupt, Some(Interrupt::Nmi));
+        assert_eq!(cpu.pc, program.label("nmi").unwrap());
+        assert_eq!(memory.peek_u16(0x01FC), 0x8000);
+        assert_eq!(memory.peek(0x01FB), 0x20);
     }
-}
\ No newline at end of file
+}
+
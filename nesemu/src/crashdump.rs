@@ -0,0 +1,135 @@
+use crate::disasm;
+use crate::monitor;
+use crate::nes::Nes;
+
+// Post-mortem dump written when the emulator panics or the CPU stops. Plain
+// text split into sections, each starting with a `== <name> ==` line and
+// ending at the next header or end of file, always in this order:
+//
+//   == nesemu crash dump ==   reason, cycle count, PPU position
+//   == rom ==                 header summary
+//   == cpu ==                 registers and flags
+//   == history ==             recently executed instructions, oldest first
+//   == disassembly ==         instructions from PC onwards
+//   == events ==              event log tail (or "event log disabled")
+//   == zero page ==           hexdump of $0000-$00FF
+//   == stack ==               hexdump of $0100-$01FF
+//   == frame ==               "not available" (no PPU output yet)
+//   == unknown opcodes ==     see unknownops.rs (or "none")
+
+const DISASM_LINES: usize = 8;
+const EVENT_LINES: usize = 32;
+
+pub fn crash_dump(nes: &Nes, reason: &str) -> String {
+    let mut out = String::new();
+    let mut section = |name: &str, body: String| {
+        out.push_str(&format!("== {} ==\n", name));
+        out.push_str(body.trim_end());
+        out.push_str("\n\n");
+    };
+
+    let position = nes.ppu_position();
+    section(
+        "nesemu crash dump",
+        format!(
+            "reason: {}\ncycle: {}\nframe: {} scanline: {} dot: {}",
+            reason, nes.cpu.cycles, position.frame, position.scanline, position.dot
+        ),
+    );
+
+    let info = nes.rom_info();
+    section(
+        "rom",
+        format!(
+            "PRG-ROM: {} KB\nCHR-ROM: {} KB\nmapper: {}\ntrainer: {}\nbattery: {}",
+            info.prg_rom_size / 1024,
+            info.chr_rom_size / 1024,
+            info.mapper,
+            info.has_trainer,
+            info.has_battery
+        ),
+    );
+
+    section("cpu", monitor::registers(nes));
+
+    let history: Vec<String> = nes
+        .instruction_history()
+        .into_iter()
+        .map(|pc| disasm::disassemble_one(&nes.memory, pc).to_string())
+        .collect();
+    section("history", history.join("\n"));
+
+    let lines: Vec<String> = disasm::disassemble(&nes.memory, nes.cpu.pc, DISASM_LINES)
+        .iter()
+        .map(|line| line.to_string())
+        .collect();
+    section("disassembly", lines.join("\n"));
+
+    section(
+        "events",
+        match nes.event_log() {
+            Some(log) => log.dump(EVENT_LINES),
+            None => "event log disabled".to_string(),
+        },
+    );
+
+    section("zero page", monitor::hexdump(nes, 0x0000, 0x00FF));
+    section("stack", monitor::hexdump(nes, 0x0100, 0x01FF));
+    section("frame", "not available".to_string());
+
+    let report = nes.unknown_opcode_report();
+    section(
+        "unknown opcodes",
+        if report.is_empty() {
+            "none".to_string()
+        } else {
+            report.to_string()
+        },
+    );
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+
+    const SECTIONS: [&str; 10] = [
+        "nesemu crash dump",
+        "rom",
+        "cpu",
+        "history",
+        "disassembly",
+        "events",
+        "zero page",
+        "stack",
+        "frame",
+        "unknown opcodes",
+    ];
+
+    // $02 jams the CPU, which is what the frontend dumps on
+    #[test]
+    fn jam_dump_has_every_section_in_order() {
+        let source = "lda #$42\n sta $10\n .byte $02";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        for _ in 0..3 {
+            nes.step();
+        }
+        assert!(nes.cpu.is_halted());
+
+        let dump = crash_dump(&nes, "cpu jammed");
+        let headers: Vec<_> = dump.lines().filter(|line| line.starts_with("== ")).collect();
+        let expected: Vec<_> = SECTIONS.iter().map(|name| format!("== {} ==", name)).collect();
+        assert_eq!(headers, expected);
+        assert!(dump.starts_with("== nesemu crash dump ==\nreason: cpu jammed\n"), "{dump}");
+
+        // Each section ends with a blank line before the next header
+        for header in &expected[1..] {
+            assert!(dump.contains(&format!("\n\n{}\n", header)), "{header}");
+        }
+        assert!(dump.contains("\n== history ==\nC000  A9 42     LDA #$42\nC002  85 10     STA $10\n"), "{dump}");
+        assert!(dump.contains("\n== events ==\nevent log disabled\n"));
+        assert!(dump.ends_with("\n== unknown opcodes ==\nnone\n\n"));
+    }
+}
@@ -0,0 +1,191 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::mem;
+
+// Debug views of PPU state. There is no PPU core yet, so these read the
+// OAM and PPUCTRL state kept by the memory map.
+
+pub const SCREEN_WIDTH: usize = 256;
+pub const SCREEN_HEIGHT: usize = 240;
+
+const PPUCTRL_SPRITE_TABLE: u8 = 0x08;
+const PPUCTRL_SPRITE_SIZE: u8 = 0x20;
+
+const ATTR_PALETTE: u8 = 0x03;
+const ATTR_BEHIND_BACKGROUND: u8 = 0x20;
+const ATTR_FLIP_H: u8 = 0x40;
+const ATTR_FLIP_V: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OamEntry {
+    pub index: u8,
+    pub x: u8,
+    pub y: u8,             // Top scanline minus one, as stored in OAM
+    pub tile: u8,          // Raw tile byte
+    pub pattern_addr: u16, // Address of the (top) tile in the pattern tables
+    pub height: u8,        // 8 or 16
+    pub attributes: u8,
+    pub palette: u8,       // Sprite palette 0-3 (palettes 4-7 overall)
+    pub behind_background: bool,
+    pub flip_h: bool,
+    pub flip_v: bool,
+}
+
+impl OamEntry {
+    // Decode one 4-byte OAM entry. In 8x16 mode bit 0 of the tile byte picks
+    // the pattern table and the top tile is the even tile number.
+    pub fn decode(index: u8, bytes: &[u8], ppu_ctrl: u8) -> Self {
+        let (y, tile, attributes, x) = (bytes[0], bytes[1], bytes[2], bytes[3]);
+        let tall = ppu_ctrl & PPUCTRL_SPRITE_SIZE != 0;
+
+        let pattern_addr = if tall {
+            ((tile as u16 & 0x01) << 12) | ((tile as u16 & 0xFE) << 4)
+        } else {
+            let table = if ppu_ctrl & PPUCTRL_SPRITE_TABLE != 0 { 0x1000 } else { 0 };
+            table | ((tile as u16) << 4)
+        };
+
+        Self {
+            index,
+            x,
+            y,
+            tile,
+            pattern_addr,
+            height: if tall { 16 } else { 8 },
+            attributes,
+            palette: attributes & ATTR_PALETTE,
+            behind_background: attributes & ATTR_BEHIND_BACKGROUND != 0,
+            flip_h: attributes & ATTR_FLIP_H != 0,
+            flip_v: attributes & ATTR_FLIP_V != 0,
+        }
+    }
+
+    // Sprites are drawn one line below their OAM Y; Y >= $EF hides them
+    pub fn is_visible(&self) -> bool {
+        self.y < 0xEF
+    }
+}
+
+impl fmt::Display for OamEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "#{:02}  x={:3} y={:3}  tile={:02X} (${:04X}, 8x{:<2})  pal={}  {}{}{}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.pattern_addr,
+            self.height,
+            self.palette,
+            if self.behind_background { "back" } else { "front" },
+            if self.flip_h { " H" } else { "" },
+            if self.flip_v { " V" } else { "" },
+        )
+    }
+}
+
+pub fn oam_entries(memory: &mem::Memory) -> Vec<OamEntry> {
+    let ppu_ctrl = memory.ppu_ctrl();
+    memory
+        .oam()
+        .chunks(4)
+        .enumerate()
+        .map(|(i, bytes)| OamEntry::decode(i as u8, bytes, ppu_ctrl))
+        .collect()
+}
+
+// 256x240 RGB image with the bounding box of every visible sprite, coloured
+// by sprite palette. Boxes are clipped at the screen edges.
+pub fn sprite_overlay(memory: &mem::Memory) -> Vec<u8> {
+    const COLORS: [[u8; 3]; 4] = [[255, 64, 64], [64, 255, 64], [64, 128, 255], [255, 255, 64]];
+
+    let mut image = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT * 3];
+    let mut plot = |x: usize, y: usize, color: [u8; 3]| {
+        if x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+            let i = (y * SCREEN_WIDTH + x) * 3;
+            image[i..i + 3].copy_from_slice(&color);
+        }
+    };
+
+    // Draw in reverse so lower-indexed (higher priority) sprites end up on top
+    for entry in oam_entries(memory).iter().rev().filter(|e| e.is_visible()) {
+        let color = COLORS[entry.palette as usize];
+        let (left, top) = (entry.x as usize, entry.y as usize + 1);
+        let (right, bottom) = (left + 7, top + entry.height as usize - 1);
+        for x in left..=right {
+            plot(x, top, color);
+            plot(x, bottom, color);
+        }
+        for y in top..=bottom {
+            plot(left, y, color);
+            plot(right, y, color);
+        }
+    }
+    image
+}
+
+// Binary PPM (P6), viewable without any image library
+pub fn write_ppm(path: &Path, width: usize, height: usize, rgb: &[u8]) -> io::Result<()> {
+    let mut data = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    data.extend_from_slice(rgb);
+    fs::write(path, data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Sprites 1 and 2 written through OAMADDR/OAMDATA, the way a game
+    // without DMA would
+    fn memory_with_sprites(ppu_ctrl: u8) -> mem::Memory {
+        let mut memory = mem::Memory::with_program(&[], 0x8000);
+        memory.write(0x2000, ppu_ctrl);
+        memory.write(0x2003, 4);
+        for value in [0x20, 0x05, 0xFF, 0x30, 0xF0, 0x81, 0x01, 0xFF] {
+            memory.write(0x2004, value);
+        }
+        memory
+    }
+
+    #[test]
+    fn oam_written_through_oamdata_decodes() {
+        let entries = oam_entries(&memory_with_sprites(PPUCTRL_SPRITE_TABLE));
+        assert_eq!(entries.len(), 64);
+        assert_eq!(entries[0], OamEntry::decode(0, &[0; 4], PPUCTRL_SPRITE_TABLE));
+
+        let expected = OamEntry {
+            index: 1,
+            x: 0x30,
+            y: 0x20,
+            tile: 0x05,
+            pattern_addr: 0x1050,
+            height: 8,
+            attributes: 0xE3, // Bits 2-4 don't exist
+            palette: 3,
+            behind_background: true,
+            flip_h: true,
+            flip_v: true,
+        };
+        assert_eq!(entries[1], expected);
+        assert!(entries[1].is_visible());
+        assert_eq!(entries[1].to_string(), "#01  x= 48 y= 32  tile=05 ($1050, 8x8 )  pal=3  back H V");
+
+        let hidden = entries[2];
+        assert_eq!((hidden.x, hidden.y, hidden.palette, hidden.flip_h), (0xFF, 0xF0, 1, false));
+        assert!(!hidden.is_visible());
+        assert_eq!(entries[3].index, 3);
+    }
+
+    // 8x16 sprites take their table from bit 0 of the tile byte
+    #[test]
+    fn tall_sprites_pick_their_table_from_the_tile() {
+        let entries = oam_entries(&memory_with_sprites(PPUCTRL_SPRITE_SIZE));
+        assert_eq!((entries[1].pattern_addr, entries[1].height), (0x1040, 16)); // Tiles 4 and 5
+        assert_eq!((entries[2].pattern_addr, entries[2].height), (0x1800, 16));
+        assert_eq!(entries[2].to_string(), "#02  x=255 y=240  tile=81 ($1800, 8x16)  pal=1  front");
+    }
+}
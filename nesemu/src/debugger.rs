@@ -0,0 +1,416 @@
+use std::collections::{BTreeSet, VecDeque};
+use std::fmt;
+use std::ops::RangeInclusive;
+
+use crate::callstack::{CallStack, Frame};
+use crate::cpu::CpuError;
+use crate::mem::Access;
+use crate::nes::Nes;
+use crate::opcodes;
+use crate::symbols::Symbols;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    StepOut,
+    MaxInstructions,
+    Error(CpuError), // Stopped in front of an instruction the CPU can't run
+    Jammed(u16),     // A JAM opcode at this address halted the CPU
+    // The instruction at `pc` accessed a watched address. The stop comes
+    // after the instruction, so a write has already landed.
+    Watchpoint { addr: u16, kind: Access, value: u8, pc: u16 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub start: u16,
+    pub end: u16, // Inclusive
+    pub kind: WatchKind,
+}
+
+pub struct Debugger {
+    pub symbols: Symbols,
+    breakpoints: BTreeSet<u16>,
+    watchpoints: Vec<Watchpoint>,
+    accesses: Vec<(Access, u16, u8)>, // Scratch buffer for the step's accesses
+    pending_hits: VecDeque<StopReason>, // Later watchpoint hits of the last step
+    call_stack: CallStack,
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Self {
+            symbols: Symbols::new(),
+            breakpoints: BTreeSet::new(),
+            watchpoints: Vec::new(),
+            accesses: Vec::new(),
+            pending_hits: VecDeque::new(),
+            call_stack: CallStack::new(),
+        }
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr);
+    }
+
+    // Returns false if there was no breakpoint at `addr`
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr)
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = u16> + '_ {
+        self.breakpoints.iter().copied()
+    }
+
+    // Replaces any watchpoint on the same range
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        let (start, end) = range.into_inner();
+        self.watchpoints.retain(|w| (w.start, w.end) != (start, end));
+        self.watchpoints.push(Watchpoint { start, end, kind });
+    }
+
+    // Removes every watchpoint starting at `addr`; false if there were none
+    pub fn remove_watchpoint(&mut self, addr: u16) -> bool {
+        let count = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.start != addr);
+        self.watchpoints.len() != count
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // Innermost frame last
+    pub fn call_stack(&self) -> Vec<Frame> {
+        self.call_stack.frames().to_vec()
+    }
+
+    // Returns the watchpoint stop if the instruction touched a watched
+    // address. Further hits by the same instruction, like the write of an
+    // RMW after its read, are returned by the next run before it executes
+    // anything.
+    pub fn step(&mut self, nes: &mut Nes) -> Option<StopReason> {
+        self.pending_hits.clear();
+        let pc = nes.cpu.pc;
+        let sp = nes.cpu.sp;
+        let opcode = nes.memory.peek(pc);
+        // An NMI or IRQ is taken in place of the instruction at PC
+        let interrupt = nes.cpu.pending_interrupt(&nes.memory);
+
+        // Reads are only recorded while something is watched
+        nes.memory.record_accesses(!self.watchpoints.is_empty());
+        nes.step();
+
+        if interrupt.is_some() {
+            self.call_stack.on_interrupt(pc, nes.cpu.pc, sp);
+        } else {
+            self.call_stack.on_instruction(opcode, pc, sp, nes.cpu.pc, nes.cpu.sp);
+        }
+        self.watch_hit(nes, pc)
+    }
+
+    // Queue every access the last step made to a watched address, in the
+    // order the CPU made them, and return the first. An RMW's dummy write of
+    // the old value and its final write count as one write, of the final
+    // value.
+    fn watch_hit(&mut self, nes: &mut Nes, pc: u16) -> Option<StopReason> {
+        if self.watchpoints.is_empty() {
+            return None;
+        }
+        nes.memory.drain_accesses(&mut self.accesses);
+        for &(kind, addr, value) in &self.accesses {
+            if !self.watchpoints.iter().any(|w| w.matches(addr, kind)) {
+                continue;
+            }
+            if kind == Access::Write
+                && let Some(StopReason::Watchpoint { addr: last_addr, kind: Access::Write, value: last_value, .. }) =
+                    self.pending_hits.back_mut()
+                && *last_addr == addr
+            {
+                *last_value = value;
+                continue;
+            }
+            self.pending_hits.push_back(StopReason::Watchpoint { addr, kind, value, pc });
+        }
+        self.pending_hits.pop_front()
+    }
+
+    // Run until the PC lands on a breakpoint (checked before executing the
+    // instruction there), an instruction touches a watchpoint, or
+    // `max_instructions` have executed. The instruction at the starting PC
+    // always runs, so resuming from a breakpoint works.
+    pub fn run(&mut self, nes: &mut Nes, max_instructions: u64) -> StopReason {
+        if let Some(hit) = self.pending_hits.pop_front() {
+            return hit;
+        }
+        for executed in 0..max_instructions {
+            if let Some(reason) = self.stop_before(nes, executed) {
+                return reason;
+            }
+            if let Some(hit) = self.step(nes) {
+                return hit;
+            }
+        }
+        StopReason::MaxInstructions
+    }
+
+    // Run until the current subroutine or interrupt handler returns. With an
+    // empty call stack this is a single step.
+    pub fn step_out(&mut self, nes: &mut Nes, max_instructions: u64) -> StopReason {
+        if let Some(hit) = self.pending_hits.pop_front() {
+            return hit;
+        }
+        let depth = self.call_stack.depth();
+        for executed in 0..max_instructions {
+            if let Some(reason) = self.stop_before(nes, executed) {
+                return reason;
+            }
+            if let Some(hit) = self.step(nes) {
+                return hit;
+            }
+            if self.call_stack.depth() < depth || depth == 0 {
+                return StopReason::StepOut;
+            }
+        }
+        StopReason::MaxInstructions
+    }
+
+    // Checked before each instruction of a run. Like breakpoints, an
+    // unknown opcode at the starting PC is let through, so resuming skips it.
+    fn stop_before(&self, nes: &Nes, executed: u64) -> Option<StopReason> {
+        let pc = nes.cpu.pc;
+        if nes.cpu.is_halted() {
+            return Some(StopReason::Jammed(pc));
+        }
+        if executed == 0 {
+            return None;
+        }
+        if self.breakpoints.contains(&pc) {
+            return Some(StopReason::Breakpoint(pc));
+        }
+        let opcode = nes.memory.peek(pc);
+        if opcodes::lookup(opcode).is_none() {
+            return Some(StopReason::Error(CpuError::UnknownOpcode { opcode, pc }));
+        }
+        None
+    }
+}
+
+impl WatchKind {
+    pub fn parse(text: &str) -> Result<Self, String> {
+        match text.to_ascii_lowercase().as_str() {
+            "r" | "read" => Ok(WatchKind::Read),
+            "w" | "write" => Ok(WatchKind::Write),
+            "rw" | "both" => Ok(WatchKind::ReadWrite),
+            _ => Err(format!("unknown watchpoint kind '{}' (expected r, w or rw)", text)),
+        }
+    }
+}
+
+impl fmt::Display for WatchKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            WatchKind::Read => "r",
+            WatchKind::Write => "w",
+            WatchKind::ReadWrite => "rw",
+        })
+    }
+}
+
+impl Watchpoint {
+    pub fn matches(&self, addr: u16, access: Access) -> bool {
+        let kind = matches!(
+            (self.kind, access),
+            (WatchKind::ReadWrite, _) | (WatchKind::Read, Access::Read) | (WatchKind::Write, Access::Write)
+        );
+        kind && (self.start..=self.end).contains(&addr)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "${:04X}", self.start)?;
+        if self.end != self.start {
+            write!(f, "..${:04X}", self.end)?;
+        }
+        write!(f, " {}", self.kind)
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+
+    fn console(source: &str) -> Nes {
+        Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap()
+    }
+
+    // Three trips round an INX loop at $C002, then a spin at $C009
+    const LOOP: &str = "
+            ldx #$00
+    loop:   inx
+            cpx #$03
+            bne loop
+            lda #$AA
+    spin:   jmp spin";
+
+    #[test]
+    fn breakpoint_stops_before_the_instruction_runs() {
+        let mut nes = console(LOOP);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0xC002);
+
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::Breakpoint(0xC002));
+        assert_eq!(nes.cpu.pc, 0xC002);
+        assert_eq!(nes.cpu.x, 0x00, "INX ran before the stop");
+        assert_eq!(nes.cpu.cycles, 7 + 2); // Reset, LDX
+    }
+
+    // Each resume runs the instruction under the breakpoint and goes round
+    // once more; after the last trip the run carries on past the loop
+    #[test]
+    fn resuming_from_a_breakpoint_makes_progress() {
+        let mut nes = console(LOOP);
+        let mut debugger = Debugger::new();
+        debugger.add_breakpoint(0xC002);
+
+        for x in 0..3 {
+            assert_eq!(debugger.run(&mut nes, 100), StopReason::Breakpoint(0xC002));
+            assert_eq!(nes.cpu.x, x, "X on hit {}", x + 1);
+        }
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::MaxInstructions);
+        assert_eq!((nes.cpu.x, nes.cpu.a, nes.cpu.pc), (3, 0xAA, 0xC009));
+
+        // Removed, it no longer stops anything
+        assert!(debugger.remove_breakpoint(0xC002));
+        assert!(!debugger.remove_breakpoint(0xC002));
+        let mut nes = console(LOOP);
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::MaxInstructions);
+    }
+
+    #[test]
+    fn max_instructions_counts_instructions() {
+        let mut nes = console(LOOP);
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run(&mut nes, 4), StopReason::MaxInstructions);
+        assert_eq!((nes.cpu.pc, nes.cpu.x), (0xC002, 1)); // LDX, INX, CPX, BNE back round
+    }
+
+    #[test]
+    fn jam_stops_the_run() {
+        let mut nes = console("lda #$01\n .byte $02\n lda #$02");
+        let mut debugger = Debugger::new();
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::Jammed(0xC002));
+        assert_eq!(nes.cpu.a, 0x01);
+        // Resuming doesn't get past it
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::Jammed(0xC002));
+    }
+
+    // LXA ($AB) has no handler. The run stops in front of it; resuming lets
+    // Nes skip it like a one-byte NOP.
+    #[test]
+    fn unknown_opcode_stops_the_run() {
+        let mut nes = console("lda #$01\n .byte $AB\n lda #$02\n spin: jmp spin");
+        let mut debugger = Debugger::new();
+        let error = CpuError::UnknownOpcode { opcode: 0xAB, pc: 0xC002 };
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::Error(error));
+        assert_eq!((nes.cpu.pc, nes.cpu.a), (0xC002, 0x01));
+
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::MaxInstructions);
+        assert_eq!(nes.cpu.a, 0x02);
+    }
+
+    fn watch_hit(addr: u16, kind: Access, value: u8, pc: u16) -> StopReason {
+        StopReason::Watchpoint { addr, kind, value, pc }
+    }
+
+    #[test]
+    fn write_watchpoint_catches_indexed_sta() {
+        let mut nes = console("ldx #$02\n lda #$5A\n sta $F8,x\n spin: jmp spin");
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x00FA..=0x00FA, WatchKind::Write);
+        assert_eq!(debugger.run(&mut nes, 100), watch_hit(0x00FA, Access::Write, 0x5A, 0xC004));
+        // The stop comes after the instruction, so the write has landed
+        assert_eq!(nes.memory.peek(0x00FA), 0x5A);
+        assert_eq!(nes.cpu.pc, 0xC006);
+    }
+
+    // The read and the write of an RMW are separate hits. The second is
+    // queued and returned by the next run without executing anything.
+    #[test]
+    fn inc_reports_its_read_then_its_write() {
+        let mut nes = console("lda #$41\n sta $FA\n inc $FA\n lda #$00\n spin: jmp spin");
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x00FA..=0x00FA, WatchKind::ReadWrite);
+
+        assert_eq!(debugger.run(&mut nes, 100), watch_hit(0x00FA, Access::Write, 0x41, 0xC002)); // STA
+        assert_eq!(debugger.run(&mut nes, 100), watch_hit(0x00FA, Access::Read, 0x41, 0xC004));
+        let cycles = nes.cpu.cycles;
+        assert_eq!(debugger.run(&mut nes, 100), watch_hit(0x00FA, Access::Write, 0x42, 0xC004));
+        assert_eq!((nes.cpu.cycles, nes.cpu.a), (cycles, 0x41), "the queued hit ran something");
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::MaxInstructions);
+    }
+
+    #[test]
+    fn watch_kinds_only_see_their_own_accesses() {
+        let source = "lda $FA\n sta $FA\n spin: jmp spin"; // Read at $C000, write at $C002
+        let cases = [
+            (WatchKind::Read, vec![watch_hit(0x00FA, Access::Read, 0x00, 0xC000)]),
+            (WatchKind::Write, vec![watch_hit(0x00FA, Access::Write, 0x00, 0xC002)]),
+            (
+                WatchKind::ReadWrite,
+                vec![watch_hit(0x00FA, Access::Read, 0x00, 0xC000), watch_hit(0x00FA, Access::Write, 0x00, 0xC002)],
+            ),
+        ];
+        for (kind, expected) in cases {
+            let mut nes = console(source);
+            let mut debugger = Debugger::new();
+            debugger.add_watchpoint(0x00FA..=0x00FA, kind);
+            for hit in expected {
+                assert_eq!(debugger.run(&mut nes, 100), hit, "{kind}");
+            }
+            assert_eq!(debugger.run(&mut nes, 100), StopReason::MaxInstructions, "{kind}");
+        }
+    }
+
+    // A range covers its ends and nothing outside them
+    #[test]
+    fn range_watchpoints_match_inclusively() {
+        let source = "
+            lda #$01
+            sta $FF
+            sta $100
+            sta $120
+            sta $13F
+            sta $140
+    spin:   jmp spin";
+        let mut nes = console(source);
+        let mut debugger = Debugger::new();
+        debugger.add_watchpoint(0x0100..=0x013F, WatchKind::Write);
+        for (addr, pc) in [(0x0100, 0xC004), (0x0120, 0xC007), (0x013F, 0xC00A)] {
+            assert_eq!(debugger.run(&mut nes, 100), watch_hit(addr, Access::Write, 0x01, pc));
+        }
+        assert_eq!(debugger.run(&mut nes, 100), StopReason::MaxInstructions);
+
+        let watchpoint = debugger.watchpoints()[0];
+        assert!(watchpoint.matches(0x0100, Access::Write) && watchpoint.matches(0x013F, Access::Write));
+        assert!(!watchpoint.matches(0x0140, Access::Write) && !watchpoint.matches(0x0120, Access::Read));
+        assert!(debugger.remove_watchpoint(0x0100));
+        assert!(debugger.watchpoints().is_empty());
+    }
+}
+
@@ -0,0 +1,196 @@
+use std::fmt;
+
+use crate::mem;
+use crate::opcodes::{self, AddrMode};
+use crate::symbols::Symbols;
+
+// Moved to opcodes so the CPU's nestest trace can use it without std
+pub use crate::opcodes::format_operand;
+
+pub struct DisasmLine {
+    pub addr: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operand: String,
+    pub target: Option<u16>, // Address named by the operand, if it has one
+    pub label: Option<String>,
+    pub comment: Option<String>,
+}
+
+impl DisasmLine {
+    // Address of the instruction that follows this one
+    pub fn next_addr(&self) -> u16 {
+        self.addr.wrapping_add(self.bytes.len() as u16)
+    }
+
+    // Substitute labels for address operands and annotate labelled lines
+    pub fn apply_symbols(&mut self, symbols: &Symbols) {
+        if let Some(symbol) = symbols.lookup(self.addr) {
+            self.label = Some(symbol.name.clone());
+            self.comment = symbol.comment.clone();
+        }
+
+        if let Some(target) = self.target
+            && let Some(symbol) = symbols.lookup(target)
+        {
+            let hex = if self.operand.contains(&format!("${:04X}", target)) {
+                format!("${:04X}", target)
+            } else {
+                format!("${:02X}", target)
+            };
+            self.operand = self.operand.replacen(&hex, &symbol.name, 1);
+        }
+    }
+}
+
+impl fmt::Display for DisasmLine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes: Vec<String> = self.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let mut text = format!("{:04X}  {:<8}  {}", self.addr, bytes.join(" "), self.mnemonic);
+        if !self.operand.is_empty() {
+            text.push(' ');
+            text.push_str(&self.operand);
+        }
+        match (&self.label, &self.comment) {
+            (Some(label), Some(comment)) => write!(f, "{:<32}; {}: {}", text, label, comment),
+            (Some(label), None) => write!(f, "{:<32}; {}", text, label),
+            _ => write!(f, "{}", text),
+        }
+    }
+}
+
+// Decode a single instruction; unknown opcodes become a one-byte `.byte` line
+pub fn disassemble_one(memory: &mem::Memory, addr: u16) -> DisasmLine {
+    let opcode = memory.peek(addr);
+
+    let Some(op) = opcodes::lookup(opcode) else {
+        return DisasmLine {
+            addr,
+            bytes: vec![opcode],
+            mnemonic: ".byte",
+            operand: format!("${:02X}", opcode),
+            target: None,
+            label: None,
+            comment: None,
+        };
+    };
+
+    let bytes: Vec<u8> = (0..op.size())
+        .map(|i| memory.peek(addr.wrapping_add(i)))
+        .collect();
+
+    DisasmLine {
+        addr,
+        mnemonic: op.mnemonic,
+        operand: format_operand(op.mode, addr, &bytes),
+        target: operand_target(op.mode, addr, &bytes),
+        bytes,
+        label: None,
+        comment: None,
+    }
+}
+
+pub fn disassemble(memory: &mem::Memory, start: u16, count: usize) -> Vec<DisasmLine> {
+    let mut lines = Vec::with_capacity(count);
+    let mut addr = start;
+    for _ in 0..count {
+        let line = disassemble_one(memory, addr);
+        addr = line.next_addr();
+        lines.push(line);
+    }
+    lines
+}
+
+pub fn disassemble_with_symbols(
+    memory: &mem::Memory,
+    start: u16,
+    count: usize,
+    symbols: &Symbols,
+) -> Vec<DisasmLine> {
+    let mut lines = disassemble(memory, start, count);
+    for line in &mut lines {
+        line.apply_symbols(symbols);
+    }
+    lines
+}
+
+// The address an operand refers to (not the effective address, which
+// depends on register contents)
+fn operand_target(mode: AddrMode, addr: u16, bytes: &[u8]) -> Option<u16> {
+    match mode {
+        AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate => None,
+        AddrMode::ZeroPage
+        | AddrMode::ZeroPageX
+        | AddrMode::ZeroPageY
+        | AddrMode::IndirectX
+        | AddrMode::IndirectY => Some(bytes[1] as u16),
+        AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => {
+            Some(((bytes[2] as u16) << 8) | bytes[1] as u16)
+        }
+        AddrMode::Relative => Some(addr.wrapping_add(2).wrapping_add(bytes[1] as i8 as u16)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn listing(code: &[u8], count: usize) -> Vec<String> {
+        let memory = mem::Memory::with_program(code, 0x8000);
+        disassemble(&memory, 0x8000, count).iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn known_bytes_disassemble_exactly() {
+        let code = [
+            0xA9, 0x10, // LDA #$10
+            0xBD, 0x00, 0x03, // LDA $0300,X
+            0xB1, 0x20, // LDA ($20),Y
+            0x0A, // ASL A
+            0x6C, 0xFC, 0xFF, // JMP ($FFFC)
+            0xD0, 0xFC, // BNE back to the JMP's operand
+            0xAB, // No opcode
+            0xA7, 0x10, // LAX $10
+            0x60, // RTS
+        ];
+        let expected = [
+            "8000  A9 10     LDA #$10",
+            "8002  BD 00 03  LDA $0300,X",
+            "8005  B1 20     LDA ($20),Y",
+            "8007  0A        ASL A",
+            "8008  6C FC FF  JMP ($FFFC)",
+            "800B  D0 FC     BNE $8009",
+            "800D  AB        .byte $AB",
+            "800E  A7 10     LAX $10",
+            "8010  60        RTS",
+        ];
+        assert_eq!(listing(&code, expected.len()), expected);
+    }
+
+    // Data decodes as whatever it happens to look like, and never panics
+    #[test]
+    fn any_bytes_decode() {
+        let code: Vec<u8> = (0..=255).collect();
+        let memory = mem::Memory::with_program(&code, 0x8000);
+        let lines = disassemble(&memory, 0x8000, 200);
+        assert_eq!(lines.len(), 200);
+        assert!(lines.windows(2).all(|pair| pair[0].next_addr() == pair[1].addr));
+    }
+
+    #[test]
+    fn symbols_replace_operands_and_label_lines() {
+        let mut symbols = Symbols::new();
+        symbols.parse_fceux_nl("$8003#loop#Main loop\n$0010#counter#", None).unwrap();
+        let memory = mem::Memory::with_program(&[0xE6, 0x10, 0xEA, 0x4C, 0x03, 0x80], 0x8000);
+        let lines: Vec<String> = disassemble_with_symbols(&memory, 0x8000, 3, &symbols)
+            .iter()
+            .map(|line| line.to_string())
+            .collect();
+        let expected = [
+            "8000  E6 10     INC counter",
+            "8002  EA        NOP",
+            "8003  4C 03 80  JMP loop        ; loop: Main loop",
+        ];
+        assert_eq!(lines, expected);
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::eventstream::{hash_bytes, json_string};
+use crate::nes::Nes;
+
+// Raw memory dumps for offline analysis: one binary file per memory region
+// plus manifest.json listing each file's size and FNV-1a hash, e.g.
+//
+//   {"frame":120,"cycle":3573702,"files":[{"name":"ram.bin","size":2048,
+//    "fnv1a":"..."},...],"unavailable":["vram","palette"]}
+
+// Regions there is nothing to dump for until a PPU exists
+const UNAVAILABLE: &[&str] = &["vram", "palette"];
+
+pub struct Region {
+    pub file: &'static str,
+    pub data: Vec<u8>,
+}
+
+// Work RAM, save RAM and OAM, plus the whole CPU address space as the CPU
+// would read it (without side effects) if `cpu_space` is set
+pub fn regions(nes: &Nes, cpu_space: bool) -> Vec<Region> {
+    let mut regions = vec![
+        Region {
+            file: "ram.bin",
+            data: nes.memory.ram().to_vec(),
+        },
+        Region {
+            file: "sram.bin",
+            data: nes.memory.cartridge_ram().to_vec(),
+        },
+        Region {
+            file: "oam.bin",
+            data: nes.memory.oam().to_vec(),
+        },
+    ];
+    if cpu_space {
+        regions.push(Region {
+            file: "cpu.bin",
+            data: (0..=0xFFFF).map(|addr| nes.memory.peek(addr)).collect(),
+        });
+    }
+    regions
+}
+
+// Writes the regions and the manifest into `dir`, creating it if needed;
+// returns the manifest
+pub fn write_dump(nes: &Nes, dir: &Path, cpu_space: bool) -> io::Result<String> {
+    fs::create_dir_all(dir)?;
+    let mut files = Vec::new();
+    for region in regions(nes, cpu_space) {
+        fs::write(dir.join(region.file), &region.data)?;
+        files.push(format!(
+            "{{\"name\":{},\"size\":{},\"fnv1a\":{}}}",
+            json_string(region.file),
+            region.data.len(),
+            json_string(&format!("{:016x}", hash_bytes(&region.data)))
+        ));
+    }
+    let unavailable: Vec<String> = UNAVAILABLE.iter().map(|name| json_string(name)).collect();
+    let manifest = format!(
+        "{{\"frame\":{},\"cycle\":{},\"files\":[{}],\"unavailable\":[{}]}}\n",
+        nes.ppu_position().frame,
+        nes.cpu.cycles,
+        files.join(","),
+        unavailable.join(",")
+    );
+    fs::write(dir.join("manifest.json"), &manifest)?;
+    Ok(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+
+    #[test]
+    fn dump_files_have_their_sizes_and_contents() {
+        let mut nes = Nes::from_bytes(&testbus::ines_image("lda #$42\n spin: jmp spin", 0).unwrap()).unwrap();
+        nes.poke(0x0123, 0x5A);
+        nes.poke(0x6000, 0xC3);
+        nes.write_oam(5, 0x77);
+
+        let dir = std::env::temp_dir().join(format!("nesemu-{}-dump", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let manifest = write_dump(&nes, &dir, true).unwrap();
+
+        let read = |file: &str| fs::read(dir.join(file)).unwrap();
+        let (ram, sram, oam, cpu) = (read("ram.bin"), read("sram.bin"), read("oam.bin"), read("cpu.bin"));
+        assert_eq!([ram.len(), sram.len(), oam.len(), cpu.len()], [0x800, 0x2000, 0x100, 0x10000]);
+        assert_eq!((ram[0x123], sram[0], oam[5]), (0x5A, 0xC3, 0x77));
+        assert_eq!(ram.iter().filter(|&&b| b != 0).count(), 1);
+        assert_eq!(cpu[0x0923], 0x5A, "RAM mirror");
+        assert_eq!(cpu[0x6000], 0xC3);
+        assert_eq!(&cpu[0xC000..0xC005], [0xA9, 0x42, 0x4C, 0x02, 0xC0]);
+        assert_eq!(&cpu[0xFFFC..0xFFFE], [0x00, 0xC0]);
+
+        // The manifest on disk is the one returned, and its hashes are the files'
+        assert_eq!(fs::read_to_string(dir.join("manifest.json")).unwrap(), manifest);
+        assert!(manifest.starts_with("{\"frame\":0,\"cycle\":7,\"files\":[{\"name\":\"ram.bin\",\"size\":2048,"));
+        assert!(manifest.ends_with("],\"unavailable\":[\"vram\",\"palette\"]}\n"));
+        for (file, data) in [("ram.bin", &ram), ("sram.bin", &sram), ("oam.bin", &oam), ("cpu.bin", &cpu)] {
+            let hash = hash_bytes(data);
+            let entry = format!("{{\"name\":\"{}\",\"size\":{},\"fnv1a\":\"{:016x}\"}}", file, data.len(), hash);
+            assert!(manifest.contains(&entry), "{entry}");
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn cpu_space_is_optional() {
+        let nes = Nes::from_bytes(&testbus::ines_image("", 0).unwrap()).unwrap();
+        let files: Vec<_> = regions(&nes, false).iter().map(|region| region.file).collect();
+        assert_eq!(files, ["ram.bin", "sram.bin", "oam.bin"]);
+        assert_eq!(regions(&nes, true).len(), 4);
+    }
+}
@@ -0,0 +1,136 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+use crate::cpu::CpuError;
+
+// What the Nes facade returns when something goes wrong. Tooling modules
+// keep their plain String errors; those convert into Invalid.
+
+#[derive(Debug)]
+pub enum NesError {
+    Io { path: Option<PathBuf>, source: io::Error },
+    InvalidRom { path: Option<PathBuf>, reason: String },
+    Cpu(CpuError),
+    NotEnabled(&'static str), // An optional feature used before it was turned on
+    Invalid(String),          // Bad argument or input
+}
+
+impl NesError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> Self {
+        NesError::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for NesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            NesError::Io { path: Some(path), source } => write!(f, "{}: {}", path.display(), source),
+            NesError::Io { path: None, source } => write!(f, "{}", source),
+            NesError::InvalidRom { path: Some(path), reason } => {
+                write!(f, "{}: not a valid NES ROM: {}", path.display(), reason)
+            }
+            NesError::InvalidRom { path: None, reason } => write!(f, "not a valid NES ROM: {}", reason),
+            NesError::Cpu(err) => write!(f, "{}", err),
+            NesError::NotEnabled(what) => write!(f, "{} is not enabled", what),
+            NesError::Invalid(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl Error for NesError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            NesError::Io { source, .. } => Some(source),
+            NesError::Cpu(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for NesError {
+    fn from(source: io::Error) -> Self {
+        NesError::Io { path: None, source }
+    }
+}
+
+impl From<CpuError> for NesError {
+    fn from(err: CpuError) -> Self {
+        NesError::Cpu(err)
+    }
+}
+
+impl From<String> for NesError {
+    fn from(message: String) -> Self {
+        NesError::Invalid(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use std::path::Path;
+
+    #[test]
+    fn messages_name_the_path_when_there_is_one() {
+        let not_found = || io::Error::new(io::ErrorKind::NotFound, "no such file");
+        let cases = [
+            (NesError::io("game.nes", not_found()), "game.nes: no such file"),
+            (NesError::from(not_found()), "no such file"),
+            (
+                NesError::InvalidRom { path: Some("game.nes".into()), reason: "bad magic".into() },
+                "game.nes: not a valid NES ROM: bad magic",
+            ),
+            (NesError::InvalidRom { path: None, reason: "bad magic".into() }, "not a valid NES ROM: bad magic"),
+            (
+                NesError::from(CpuError::UnknownOpcode { opcode: 0xAB, pc: 0xC001 }),
+                "unknown opcode $AB at $C001",
+            ),
+            (NesError::NotEnabled("code/data logger"), "code/data logger is not enabled"),
+            (NesError::from("bad watch".to_string()), "bad watch"),
+        ];
+        for (err, message) in cases {
+            assert_eq!(err.to_string(), message);
+        }
+    }
+
+    // Only the wrapped I/O and CPU errors have a source
+    #[test]
+    fn sources_are_the_wrapped_errors() {
+        let io = NesError::io("game.nes", io::Error::other("disk on fire"));
+        assert_eq!(io.source().unwrap().to_string(), "disk on fire");
+        let cpu = NesError::from(CpuError::UnknownOpcode { opcode: 0xEB, pc: 0x8000 });
+        let source = cpu.source().unwrap().downcast_ref::<CpuError>();
+        assert_eq!(source, Some(&CpuError::UnknownOpcode { opcode: 0xEB, pc: 0x8000 }));
+        assert!(NesError::NotEnabled("coverage").source().is_none());
+        assert!(NesError::Invalid("x".into()).source().is_none());
+    }
+
+    // What the facade actually hands back
+    #[test]
+    fn facade_errors() {
+        let missing = Path::new("/nonexistent/nesemu/game.nes");
+        let err = Nes::from_file(missing).err().unwrap();
+        assert!(matches!(&err, NesError::Io { path: Some(path), .. } if path == missing), "{err:?}");
+
+        let err = Nes::from_bytes(&[0; 16]).err().unwrap();
+        assert_eq!(err.to_string(), "not a valid NES ROM: Invalid magic bytes: not a NES ROM");
+
+        // Loading the same bytes from a file adds its path
+        let garbage = std::env::temp_dir().join(format!("nesemu-{}-garbage.nes", std::process::id()));
+        std::fs::write(&garbage, [0; 16]).unwrap();
+        let err = Nes::from_file(&garbage).err().unwrap();
+        std::fs::remove_file(&garbage).unwrap();
+        let expected = format!("{}: not a valid NES ROM: Invalid magic bytes: not a NES ROM", garbage.display());
+        assert_eq!(err.to_string(), expected);
+
+        let nes = Nes::from_bytes(&crate::testbus::ines_image("", 0).unwrap()).unwrap();
+        let err = nes.save_cdl(Path::new("game.cdl")).unwrap_err();
+        assert_eq!(err.to_string(), "code/data logger is not enabled");
+    }
+}
@@ -0,0 +1,174 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+pub use crate::irq::Source as IrqSource;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    Nmi,
+    Irq(IrqSource),
+    Brk,
+    OamDma { page: u8 },
+    RegisterWrite { addr: u16, value: u8 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Event {
+    pub cycle: u64,
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+    pub pc: u16, // Address of the instruction that caused the event
+    pub kind: EventKind,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "CYC:{:<10} F:{:<5} SL:{:<3} DOT:{:<3} PC:{:04X}  ",
+            self.cycle, self.frame, self.scanline, self.dot, self.pc
+        )?;
+        match self.kind {
+            EventKind::Nmi => write!(f, "NMI"),
+            EventKind::Irq(source) => write!(f, "IRQ ({:?})", source),
+            EventKind::Brk => write!(f, "BRK"),
+            EventKind::OamDma { page } => write!(f, "OAM DMA from ${:02X}00", page),
+            EventKind::RegisterWrite { addr, value } => {
+                write!(f, "write ${:02X} -> ${:04X}", value, addr)
+            }
+        }
+    }
+}
+
+pub struct EventLogConfig {
+    pub capacity: usize,
+    // Registers whose writes are logged as RegisterWrite events
+    pub watch_registers: Vec<u16>,
+}
+
+impl Default for EventLogConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 4096,
+            watch_registers: Vec::new(),
+        }
+    }
+}
+
+// Bounded log of interrupts and other timing-relevant events; the oldest
+// entries are dropped once `capacity` is reached
+pub struct EventLog {
+    config: EventLogConfig,
+    events: VecDeque<Event>,
+}
+
+impl EventLog {
+    pub fn new(config: EventLogConfig) -> Self {
+        Self {
+            events: VecDeque::with_capacity(config.capacity),
+            config,
+        }
+    }
+
+    pub fn record(&mut self, event: Event) {
+        if self.config.capacity == 0 {
+            return;
+        }
+        if self.events.len() == self.config.capacity {
+            self.events.pop_front();
+        }
+        self.events.push_back(event);
+    }
+
+    pub fn is_watched(&self, addr: u16) -> bool {
+        self.config.watch_registers.contains(&addr)
+    }
+
+    // Oldest first
+    pub fn events(&self) -> impl Iterator<Item = &Event> {
+        self.events.iter()
+    }
+
+    pub fn filter<'a>(&'a self, pred: impl Fn(&Event) -> bool + 'a) -> impl Iterator<Item = &'a Event> {
+        self.events.iter().filter(move |event| pred(event))
+    }
+
+    pub fn len(&self) -> usize {
+        self.events.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.events.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.events.clear();
+    }
+
+    // Text dump of the newest `count` events
+    pub fn dump(&self, count: usize) -> String {
+        let skip = self.events.len().saturating_sub(count);
+        self.events
+            .iter()
+            .skip(skip)
+            .map(|event| event.to_string())
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    fn event(cycle: u64, kind: EventKind) -> Event {
+        Event { cycle, frame: 0, scanline: 0, dot: 0, pc: 0xC000, kind }
+    }
+
+    // Vblank starts at scanline 241, and the NMI is taken there
+    #[test]
+    fn nmi_is_logged_at_the_start_of_vblank() {
+        let source = "
+            lda #$80
+            sta $2000
+     spin:  jmp spin
+     nmi:   rti
+            * = $FFFA
+            .word nmi";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.enable_event_log(EventLogConfig { watch_registers: vec![0x2000], ..Default::default() });
+        nes.step_frame();
+        nes.step_frame();
+        nes.step();
+
+        let log = nes.event_log().unwrap();
+        let nmis: Vec<_> = log.filter(|event| event.kind == EventKind::Nmi).collect();
+        assert_eq!(nmis.len(), 2);
+        for (frame, nmi) in nmis.iter().enumerate() {
+            assert_eq!((nmi.frame, nmi.scanline), (frame as u64, 241));
+        }
+        let first = log.events().next().unwrap();
+        assert_eq!(first.kind, EventKind::RegisterWrite { addr: 0x2000, value: 0x80 });
+        assert_eq!((first.scanline, first.pc), (0, 0xC002));
+    }
+
+    #[test]
+    fn oldest_events_drop_out_first() {
+        let mut log = EventLog::new(EventLogConfig { capacity: 2, ..Default::default() });
+        log.record(event(10, EventKind::Brk));
+        log.record(event(20, EventKind::Nmi));
+        log.record(event(30, EventKind::OamDma { page: 0x02 }));
+        let cycles: Vec<_> = log.events().map(|event| event.cycle).collect();
+        assert_eq!(cycles, [20, 30]);
+
+        let dump = log.dump(1);
+        assert_eq!(dump, "CYC:30         F:0     SL:0   DOT:0   PC:C000  OAM DMA from $0200");
+
+        let mut off = EventLog::new(EventLogConfig { capacity: 0, ..Default::default() });
+        off.record(event(10, EventKind::Brk));
+        assert!(off.is_empty());
+    }
+}
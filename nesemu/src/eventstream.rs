@@ -0,0 +1,353 @@
+use std::io::{self, Write};
+
+use crate::mem;
+use crate::stats::StatsSummary;
+use crate::testrom;
+
+// Newline-delimited JSON records for external tools. Every record carries
+// `type` and `cycle` (emulated CPU cycles, so timestamps are monotonic and
+// reproducible). Any `io::Write` can be the sink.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateAction {
+    Save,
+    Load,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StreamEvent {
+    // `hash` covers CPU RAM; there is no frame buffer to hash yet
+    Frame { frame: u64, hash: u64 },
+    BlarggStatus { status: u8, message: Option<String> },
+    UnknownOpcode { pc: u16, opcode: u8 },
+    // Host timing, so unlike everything else not reproducible between runs
+    Stats(StatsSummary),
+    SaveState { action: StateAction, path: String },
+    Custom { name: String, fields: Vec<(String, String)> },
+}
+
+pub(crate) fn json_string(text: &str) -> String {
+    let mut out = String::with_capacity(text.len() + 2);
+    out.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl StreamEvent {
+    pub fn to_json(&self, cycle: u64) -> String {
+        let (kind, fields): (&str, Vec<(&str, String)>) = match self {
+            StreamEvent::Frame { frame, hash } => (
+                "frame",
+                vec![("frame", frame.to_string()), ("hash", json_string(&format!("{:016x}", hash)))],
+            ),
+            StreamEvent::BlarggStatus { status, message } => (
+                "blargg_status",
+                vec![
+                    ("status", status.to_string()),
+                    ("message", message.as_deref().map_or("null".to_string(), json_string)),
+                ],
+            ),
+            StreamEvent::UnknownOpcode { pc, opcode } => (
+                "unknown_opcode",
+                vec![("pc", pc.to_string()), ("opcode", opcode.to_string())],
+            ),
+            StreamEvent::Stats(summary) => ("stats", vec![("stats", summary.to_json())]),
+            StreamEvent::SaveState { action, path } => (
+                match action {
+                    StateAction::Save => "state_saved",
+                    StateAction::Load => "state_loaded",
+                },
+                vec![("path", json_string(path))],
+            ),
+            StreamEvent::Custom { name, fields } => {
+                let data: Vec<String> = fields
+                    .iter()
+                    .map(|(key, value)| format!("{}:{}", json_string(key), json_string(value)))
+                    .collect();
+                (
+                    "custom",
+                    vec![("name", json_string(name)), ("data", format!("{{{}}}", data.join(",")))],
+                )
+            }
+        };
+
+        let mut json = format!("{{\"type\":{},\"cycle\":{}", json_string(kind), cycle);
+        for (key, value) in fields {
+            json.push_str(&format!(",{}:{}", json_string(key), value));
+        }
+        json.push('}');
+        json
+    }
+}
+
+// FNV-1a, stable across platforms and runs
+pub fn hash_bytes(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+pub struct EventStream {
+    sink: Box<dyn Write + Send>,
+    error: Option<io::Error>,
+    last_frame: u64,
+    blargg_status: Option<u8>,
+}
+
+impl EventStream {
+    pub fn new(sink: Box<dyn Write + Send>) -> Self {
+        Self {
+            sink,
+            error: None,
+            last_frame: 0,
+            blargg_status: None,
+        }
+    }
+
+    // Writes stop after the first I/O error, which take_error() returns
+    pub fn emit(&mut self, cycle: u64, event: &StreamEvent) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = writeln!(self.sink, "{}", event.to_json(cycle)) {
+            self.error = Some(err);
+        }
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.sink.flush()
+    }
+
+    // Called after each instruction with the writes it made
+    pub fn observe(&mut self, cycle: u64, frame: u64, memory: &mem::Memory, writes: &[(u16, u8)]) {
+        if frame != self.last_frame {
+            self.last_frame = frame;
+            let hash = hash_bytes(memory.ram());
+            self.emit(cycle, &StreamEvent::Frame { frame, hash });
+        }
+
+        // blargg test ROMs report through $6000; see testrom.rs
+        if !writes.iter().any(|&(addr, _)| addr == testrom::STATUS_ADDR) {
+            return;
+        }
+        let Some(status) = testrom::status(memory) else {
+            return;
+        };
+        if self.blargg_status == Some(status) {
+            return;
+        }
+        self.blargg_status = Some(status);
+
+        let message = match status {
+            testrom::STATUS_RUNNING | testrom::STATUS_RESET_REQUIRED => None,
+            _ => Some(testrom::read_message(memory)),
+        };
+        self.emit(cycle, &StreamEvent::BlarggStatus { status, message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    // A sink the test can still read after handing it to the stream
+    #[derive(Clone, Default)]
+    struct SharedSink(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedSink {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum Json {
+        Null,
+        Number(u64),
+        Str(String),
+        Object(Vec<(String, Json)>),
+    }
+
+    impl Json {
+        fn get(&self, key: &str) -> Option<&Json> {
+            match self {
+                Json::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+                _ => None,
+            }
+        }
+    }
+
+    // Just enough of a JSON parser for what the stream writes: objects,
+    // strings with escapes, unsigned numbers and null. Anything else, or
+    // trailing text, is an error.
+    fn parse(text: &str) -> Result<Json, String> {
+        let mut chars = text.chars().peekable();
+        let value = parse_value(&mut chars)?;
+        match chars.next() {
+            None => Ok(value),
+            Some(c) => Err(format!("trailing '{}'", c)),
+        }
+    }
+
+    fn parse_value(chars: &mut std::iter::Peekable<std::str::Chars>) -> Result<Json, String> {
+        match chars.peek() {
+            Some('{') => {
+                chars.next();
+                let mut fields = Vec::new();
+                if chars.peek() == Some(&'}') {
+                    chars.next();
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    let Json::Str(key) = parse_value(chars)? else {
+                        return Err("object key isn't a string".to_string());
+                    };
+                    if chars.next() != Some(':') {
+                        return Err("expected ':'".to_string());
+                    }
+                    fields.push((key, parse_value(chars)?));
+                    match chars.next() {
+                        Some(',') => {}
+                        Some('}') => return Ok(Json::Object(fields)),
+                        other => return Err(format!("expected ',' or '}}', got {:?}", other)),
+                    }
+                }
+            }
+            Some('"') => {
+                chars.next();
+                let mut out = String::new();
+                loop {
+                    match chars.next().ok_or("unterminated string")? {
+                        '"' => return Ok(Json::Str(out)),
+                        '\\' => match chars.next().ok_or("unterminated escape")? {
+                            c @ ('"' | '\\' | '/') => out.push(c),
+                            'n' => out.push('\n'),
+                            'r' => out.push('\r'),
+                            't' => out.push('\t'),
+                            'u' => {
+                                let hex: String = chars.by_ref().take(4).collect();
+                                let code = u32::from_str_radix(&hex, 16).map_err(|_| "bad \\u escape")?;
+                                out.push(char::from_u32(code).ok_or("bad \\u escape")?);
+                            }
+                            c => return Err(format!("bad escape '\\{}'", c)),
+                        },
+                        c if (c as u32) < 0x20 => return Err("raw control character".to_string()),
+                        c => out.push(c),
+                    }
+                }
+            }
+            Some('n') => {
+                let word: String = chars.by_ref().take(4).collect();
+                if word == "null" { Ok(Json::Null) } else { Err(format!("bad literal '{}'", word)) }
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(&c) = chars.peek().filter(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                    chars.next();
+                }
+                digits.parse().map(Json::Number).map_err(|e| e.to_string())
+            }
+            other => Err(format!("unexpected {:?}", other)),
+        }
+    }
+
+    // Signs the blargg status area, reports "running", then passes with a
+    // message, then hits LXA ($AB), which has no handler
+    const PROGRAM: &str = "
+            lda #$DE
+            sta $6001
+            lda #$B0
+            sta $6002
+            lda #$61
+            sta $6003
+            lda #$80
+            sta $6000
+            lda #$6F
+            sta $6004
+            lda #$6B
+            sta $6005
+            lda #$00
+            sta $6006
+            sta $6000
+            .byte $AB
+    spin:   jmp spin";
+
+    #[test]
+    fn stream_parses_and_has_the_records_in_order() {
+        let mut nes = Nes::from_bytes(&testbus::ines_image(PROGRAM, 0).unwrap()).unwrap();
+        let sink = SharedSink::default();
+        nes.set_event_stream(EventStream::new(Box::new(sink.clone())));
+        for _ in 0..3 {
+            nes.run_frame();
+        }
+        nes.emit_event(StreamEvent::Custom {
+            name: "note".to_string(),
+            fields: vec![("text".to_string(), "say \"hi\"\n".to_string())],
+        });
+        let mut stream = nes.take_event_stream().unwrap();
+        stream.flush().unwrap();
+        assert!(stream.take_error().is_none());
+
+        let output = String::from_utf8(sink.0.lock().unwrap().clone()).unwrap();
+        let records: Vec<Json> = output
+            .lines()
+            .map(|line| parse(line).unwrap_or_else(|err| panic!("{}: {}", err, line)))
+            .collect();
+
+        let kinds: Vec<&Json> = records.iter().map(|r| r.get("type").unwrap()).collect();
+        let expected = ["blargg_status", "blargg_status", "unknown_opcode", "frame", "frame", "frame", "custom"];
+        assert_eq!(kinds, expected.map(|kind| Json::Str(kind.to_string())).iter().collect::<Vec<_>>());
+
+        let cycles: Vec<u64> = records
+            .iter()
+            .map(|r| match r.get("cycle") {
+                Some(Json::Number(cycle)) => *cycle,
+                other => panic!("cycle is {:?}", other),
+            })
+            .collect();
+        assert!(cycles.is_sorted(), "{:?}", cycles);
+
+        assert_eq!(records[0].get("status"), Some(&Json::Number(0x80)));
+        assert_eq!(records[0].get("message"), Some(&Json::Null));
+        assert_eq!(records[1].get("status"), Some(&Json::Number(0)));
+        assert_eq!(records[1].get("message"), Some(&Json::Str("ok".to_string())));
+        assert_eq!(records[2].get("opcode"), Some(&Json::Number(0xAB)));
+        for (record, frame) in records[3..6].iter().zip(1..) {
+            assert_eq!(record.get("frame"), Some(&Json::Number(frame)));
+        }
+        let data = records[6].get("data").unwrap();
+        assert_eq!(data.get("text"), Some(&Json::Str("say \"hi\"\n".to_string())));
+    }
+
+    #[test]
+    fn control_characters_are_escaped() {
+        let text = "a\u{1}b\\";
+        assert_eq!(json_string(text), "\"a\\u0001b\\\\\"");
+        assert_eq!(parse(&json_string(text)), Ok(Json::Str(text.to_string())));
+    }
+}
@@ -0,0 +1,359 @@
+// Controller input. The console asks its InputProvider for both ports once
+// per frame, before the frame's first instruction, so every source (manual
+// setters, movies, turbo, remote control) sees the same well-defined point.
+
+use std::fmt;
+use std::ops::BitOr;
+
+use crate::rom::ExpansionDevice;
+
+// In the order the $4016/$4017 shift register reports them, A first
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    A,
+    B,
+    Select,
+    Start,
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+        Button::Up,
+        Button::Down,
+        Button::Left,
+        Button::Right,
+    ];
+
+    pub fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+// One controller's buttons, bit 0 = A, then B, Select, Start, Up, Down,
+// Left, Right, so to_bits() is what the shift register serializes LSB first
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ButtonState(u8);
+
+impl ButtonState {
+    pub const NONE: ButtonState = ButtonState(0);
+    pub const A: ButtonState = ButtonState(0x01);
+    pub const B: ButtonState = ButtonState(0x02);
+    pub const SELECT: ButtonState = ButtonState(0x04);
+    pub const START: ButtonState = ButtonState(0x08);
+    pub const UP: ButtonState = ButtonState(0x10);
+    pub const DOWN: ButtonState = ButtonState(0x20);
+    pub const LEFT: ButtonState = ButtonState(0x40);
+    pub const RIGHT: ButtonState = ButtonState(0x80);
+
+    pub const fn from_bits(bits: u8) -> Self {
+        ButtonState(bits)
+    }
+
+    pub const fn to_bits(self) -> u8 {
+        self.0
+    }
+
+    pub fn press(&mut self, button: Button) {
+        self.0 |= button.bit();
+    }
+
+    pub fn release(&mut self, button: Button) {
+        self.0 &= !button.bit();
+    }
+
+    pub fn is_pressed(self, button: Button) -> bool {
+        self.0 & button.bit() != 0
+    }
+
+    // Buttons held in `self` but not in `other`
+    pub fn without(self, other: ButtonState) -> Self {
+        ButtonState(self.0 & !other.0)
+    }
+}
+
+impl BitOr for ButtonState {
+    type Output = ButtonState;
+
+    fn bitor(self, other: ButtonState) -> ButtonState {
+        ButtonState(self.0 | other.0)
+    }
+}
+
+impl From<Button> for ButtonState {
+    fn from(button: Button) -> Self {
+        ButtonState(button.bit())
+    }
+}
+
+// RLDUTSBA like FM2 movie logs, Right first and '.' for released:
+// Up + Start + A is "...UT..A"
+impl fmt::Display for ButtonState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (button, letter) in Button::ALL.iter().zip(b"ABSTUDLR").rev() {
+            write!(f, "{}", if self.is_pressed(*button) { *letter as char } else { '.' })?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PortDevice {
+    Controller,
+    Zapper,
+}
+
+// What is plugged into the two controller ports. Picked from the ROM header
+// on load; frontends can override it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputConfig {
+    pub ports: [PortDevice; 2],
+    pub four_score: bool, // Four Score adapter between the ports and the pads
+}
+
+impl Default for InputConfig {
+    fn default() -> Self {
+        Self {
+            ports: [PortDevice::Controller; 2],
+            four_score: false,
+        }
+    }
+}
+
+impl InputConfig {
+    // Standard pads unless the header asks for something we know
+    pub fn for_device(device: ExpansionDevice) -> Self {
+        let mut config = Self::default();
+        match device {
+            ExpansionDevice::FourScore => config.four_score = true,
+            ExpansionDevice::Zapper => config.ports[1] = PortDevice::Zapper,
+            ExpansionDevice::TwoZappers => config.ports = [PortDevice::Zapper; 2],
+            _ => {}
+        }
+        config
+    }
+
+    // Command line spelling: standard, zapper, two-zappers or fourscore
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let device = match text {
+            "standard" => ExpansionDevice::StandardControllers,
+            "zapper" => ExpansionDevice::Zapper,
+            "two-zappers" => ExpansionDevice::TwoZappers,
+            "fourscore" => ExpansionDevice::FourScore,
+            _ => return Err(format!("unknown input device '{}'", text)),
+        };
+        Ok(Self::for_device(device))
+    }
+}
+
+pub trait InputProvider: Send {
+    fn poll(&mut self, frame: u64) -> (ButtonState, ButtonState);
+}
+
+// Plays back recorded input, one entry per frame starting at `start_frame`;
+// nothing is held outside the recording
+pub struct MoviePlayback {
+    start_frame: u64,
+    frames: Vec<(ButtonState, ButtonState)>,
+}
+
+impl MoviePlayback {
+    pub fn new(start_frame: u64, frames: Vec<(ButtonState, ButtonState)>) -> Self {
+        Self { start_frame, frames }
+    }
+
+    pub fn is_finished(&self, frame: u64) -> bool {
+        frame >= self.start_frame + self.frames.len() as u64
+    }
+}
+
+impl InputProvider for MoviePlayback {
+    fn poll(&mut self, frame: u64) -> (ButtonState, ButtonState) {
+        frame
+            .checked_sub(self.start_frame)
+            .and_then(|i| self.frames.get(i as usize))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+// Wraps another provider and auto-fires the buttons in `mask`: while held
+// they read as pressed for `rate` frames, then released for `rate` frames
+pub struct Turbo<P: InputProvider> {
+    inner: P,
+    mask: (ButtonState, ButtonState),
+    rate: u64,
+}
+
+impl<P: InputProvider> Turbo<P> {
+    pub fn new(inner: P, mask: (ButtonState, ButtonState), rate: u64) -> Self {
+        Self {
+            inner,
+            mask,
+            rate: rate.max(1),
+        }
+    }
+
+    pub fn into_inner(self) -> P {
+        self.inner
+    }
+}
+
+impl<P: InputProvider> InputProvider for Turbo<P> {
+    fn poll(&mut self, frame: u64) -> (ButtonState, ButtonState) {
+        let (port1, port2) = self.inner.poll(frame);
+        if (frame / self.rate).is_multiple_of(2) {
+            return (port1, port2);
+        }
+        (port1.without(self.mask.0), port2.without(self.mask.1))
+    }
+}
+
+impl<F: FnMut(u64) -> (ButtonState, ButtonState) + Send> InputProvider for F {
+    fn poll(&mut self, frame: u64) -> (ButtonState, ButtonState) {
+        self(frame)
+    }
+}
+
+impl InputProvider for Box<dyn InputProvider> {
+    fn poll(&mut self, frame: u64) -> (ButtonState, ButtonState) {
+        (**self).poll(frame)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+    use std::sync::{Arc, Mutex};
+
+    // Latches the pads and copies port 1's A bit to $10, forever
+    const READ_A: &str = "
+     loop:  lda #1
+            sta $4016
+            lda #0
+            sta $4016
+            lda $4016
+            and #1
+            sta $10
+            jmp loop";
+
+    #[test]
+    fn button_bits_follow_the_shift_register_order() {
+        let bits: Vec<_> = Button::ALL.iter().map(|&button| ButtonState::from(button).to_bits()).collect();
+        assert_eq!(bits, [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80]);
+        let consts = [
+            ButtonState::A,
+            ButtonState::B,
+            ButtonState::SELECT,
+            ButtonState::START,
+            ButtonState::UP,
+            ButtonState::DOWN,
+            ButtonState::LEFT,
+            ButtonState::RIGHT,
+        ];
+        for (button, state) in Button::ALL.into_iter().zip(consts) {
+            assert_eq!(ButtonState::from(button), state, "{button:?}");
+        }
+        assert_eq!(ButtonState::from_bits(0xA5).to_bits(), 0xA5);
+        assert_eq!(ButtonState::default(), ButtonState::NONE);
+    }
+
+    #[test]
+    fn press_release_and_combine() {
+        let mut state = ButtonState::NONE;
+        state.press(Button::Up);
+        state.press(Button::A);
+        state.press(Button::A);
+        assert_eq!(state, ButtonState::UP | ButtonState::A);
+        assert!(state.is_pressed(Button::Up) && !state.is_pressed(Button::Down));
+        state.release(Button::Up);
+        state.release(Button::B); // Not held
+        assert_eq!(state, ButtonState::A);
+
+        let held = ButtonState::A | ButtonState::B | ButtonState::START;
+        assert_eq!(held.without(ButtonState::B | ButtonState::LEFT), ButtonState::A | ButtonState::START);
+    }
+
+    // FM2 order, Right first
+    #[test]
+    fn displays_like_fm2() {
+        assert_eq!(ButtonState::NONE.to_string(), "........");
+        assert_eq!((ButtonState::UP | ButtonState::START | ButtonState::A).to_string(), "...UT..A");
+        assert_eq!(ButtonState::from_bits(0xFF).to_string(), "RLDUTSBA");
+    }
+
+    #[test]
+    fn config_follows_the_expansion_device() {
+        use PortDevice::{Controller, Zapper};
+        let cases = [
+            (ExpansionDevice::Unspecified, [Controller, Controller], false),
+            (ExpansionDevice::StandardControllers, [Controller, Controller], false),
+            (ExpansionDevice::FourScore, [Controller, Controller], true),
+            (ExpansionDevice::FamicomFourPlayers, [Controller, Controller], false),
+            (ExpansionDevice::Zapper, [Controller, Zapper], false),
+            (ExpansionDevice::TwoZappers, [Zapper, Zapper], false),
+            (ExpansionDevice::Other(0x2A), [Controller, Controller], false),
+        ];
+        for (device, ports, four_score) in cases {
+            assert_eq!(InputConfig::for_device(device), InputConfig { ports, four_score }, "{device:?}");
+        }
+
+        assert_eq!(InputConfig::parse("zapper"), Ok(InputConfig::for_device(ExpansionDevice::Zapper)));
+        assert_eq!(InputConfig::parse("standard"), Ok(InputConfig::default()));
+        assert_eq!(InputConfig::parse("Zapper"), Err("unknown input device 'Zapper'".to_string()));
+    }
+
+    // An NES 2.0 header asking for two Zappers
+    #[test]
+    fn console_takes_its_config_from_the_header() {
+        let mut image = testbus::ines_image(READ_A, 0).unwrap();
+        image[7] = 0x08;
+        image[15] = 0x09;
+        let mut nes = Nes::from_bytes(&image).unwrap();
+        assert_eq!(nes.input_config().ports, [PortDevice::Zapper; 2]);
+
+        let four_score = InputConfig::parse("fourscore").unwrap();
+        nes.set_input_config(four_score);
+        assert_eq!(nes.input_config(), four_score);
+
+        let plain = Nes::from_bytes(&testbus::ines_image(READ_A, 0).unwrap()).unwrap();
+        assert_eq!(plain.input_config(), InputConfig::default());
+    }
+
+    // Holds A on odd frames and logs every poll
+    #[test]
+    fn provider_is_polled_once_per_frame() {
+        let polls = Arc::new(Mutex::new(Vec::new()));
+        let log = Arc::clone(&polls);
+        let mut nes = Nes::from_bytes(&testbus::ines_image(READ_A, 0).unwrap()).unwrap();
+        nes.set_input_provider(Box::new(move |frame: u64| {
+            log.lock().unwrap().push(frame);
+            let port1 = if frame % 2 == 1 { ButtonState::A } else { ButtonState::NONE };
+            (port1, ButtonState::NONE)
+        }));
+
+        for frame in 0..4 {
+            nes.run_frame();
+            for _ in 0..20 {
+                nes.step();
+            }
+            assert_eq!(nes.memory.peek(0x0010), (frame + 1) % 2, "early in frame {}", frame + 1);
+        }
+        nes.step_frame();
+        assert_eq!(*polls.lock().unwrap(), [0, 1, 2, 3, 4]);
+
+        // The provider can be taken back without being polled again
+        assert!(nes.take_input_provider().is_some());
+        nes.run_frame();
+        assert_eq!(polls.lock().unwrap().len(), 5);
+    }
+}
@@ -0,0 +1,74 @@
+// The CPU's /IRQ input is wired-OR: any device can pull it, and it stays
+// asserted until every device has let go. Each source has its own flag so
+// acknowledging one (reading $4015 for the frame IRQ, writing a mapper's
+// IRQ register) leaves the others pending.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    ApuFrame,
+    Dmc,
+    Mapper,
+}
+
+impl Source {
+    pub const ALL: [Source; 3] = [Source::ApuFrame, Source::Dmc, Source::Mapper];
+
+    fn bit(self) -> u8 {
+        1 << self as u8
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct IrqLine {
+    sources: u8, // One bit per Source
+}
+
+impl IrqLine {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn assert(&mut self, source: Source) {
+        self.sources |= source.bit();
+    }
+
+    pub fn acknowledge(&mut self, source: Source) {
+        self.sources &= !source.bit();
+    }
+
+    // What the CPU sees: any source asserting
+    pub fn is_asserted(&self) -> bool {
+        self.sources != 0
+    }
+
+    pub fn is_asserted_by(&self, source: Source) -> bool {
+        self.sources & source.bit() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sources_are_asserted_and_acknowledged_independently() {
+        let mut line = IrqLine::new();
+        assert!(!line.is_asserted());
+        line.assert(Source::Mapper);
+        line.assert(Source::ApuFrame);
+        line.assert(Source::Mapper); // Already pulling
+        assert!(line.is_asserted());
+        assert!(line.is_asserted_by(Source::Mapper) && line.is_asserted_by(Source::ApuFrame));
+        assert!(!line.is_asserted_by(Source::Dmc));
+
+        line.acknowledge(Source::Mapper);
+        assert!(line.is_asserted(), "the frame IRQ still holds it");
+        assert!(!line.is_asserted_by(Source::Mapper) && line.is_asserted_by(Source::ApuFrame));
+        line.acknowledge(Source::Dmc); // Not asserting; changes nothing
+        assert!(line.is_asserted_by(Source::ApuFrame));
+
+        line.acknowledge(Source::ApuFrame);
+        assert!(!line.is_asserted());
+        assert_eq!(line, IrqLine::new());
+    }
+}
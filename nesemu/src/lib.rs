@@ -0,0 +1,75 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod asm;
+#[cfg(feature = "std")]
+pub mod bench;
+pub mod bus;
+pub mod cartridge;
+#[cfg(feature = "std")]
+pub mod callstack;
+#[cfg(feature = "capi")]
+pub mod capi;
+#[cfg(feature = "std")]
+pub mod cdl;
+pub mod controller;
+#[cfg(feature = "std")]
+pub mod coverage;
+pub mod cpu;
+#[cfg(feature = "std")]
+pub mod crashdump;
+#[cfg(feature = "std")]
+pub mod debug;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod dump;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod eventlog;
+#[cfg(feature = "std")]
+pub mod eventstream;
+#[cfg(feature = "std")]
+pub mod input;
+pub mod irq;
+pub mod mem;
+#[cfg(feature = "std")]
+pub mod monitor;
+#[cfg(feature = "std")]
+pub mod nes;
+pub mod opcodes;
+#[cfg(feature = "std")]
+pub mod opstats;
+#[cfg(feature = "std")]
+pub mod ppuevents;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "remote")]
+pub mod remote;
+#[cfg(feature = "std")]
+pub mod rom;
+#[cfg(feature = "std")]
+pub mod stats;
+#[cfg(feature = "std")]
+pub mod symbols;
+#[cfg(feature = "std")]
+pub mod testbus;
+#[cfg(feature = "std")]
+pub mod testrom;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod tracecmp;
+#[cfg(feature = "std")]
+pub mod unknownops;
+pub mod verify;
+pub mod vram;
+#[cfg(feature = "std")]
+pub mod watch;
+#[cfg(feature = "std")]
+pub mod watchdog;
@@ -1,44 +1,512 @@
-use std::fs::File;
-use std::io::Result;
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufWriter, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::str::FromStr;
+use std::path::Path;
+use std::sync::Mutex;
+use std::time::Duration;
 
-mod cpu;
-mod rom;
-mod mem;
+use nesemu::bench::Baseline;
+use nesemu::disasm;
+use nesemu::error::NesError;
+use nesemu::eventlog::EventLogConfig;
+use nesemu::eventstream::EventStream;
+use nesemu::input::InputConfig;
+use nesemu::monitor::{Command, Monitor};
+use nesemu::nes::Nes;
+use nesemu::profile::Profiler;
+use nesemu::rom::RESET_VECTOR;
+use nesemu::stats::{self, Stats};
+use nesemu::symbols::Symbols;
+use nesemu::testrom;
+use nesemu::trace::{TraceConfig, TraceLogger};
+use nesemu::tracecmp::{self, CompareConfig};
+use nesemu::watch::WatchList;
+use nesemu::watchdog::WatchdogConfig;
 
-fn main() -> Result<()> {
-    // test for now
-    let rom_file = File::open("/media/lukas/MYFILES/backups_all_win/Documents_win/hackclub_ALL_PROJECTS/emulators/nes_emulator/nesemu/src/Tetris (Europe).nes")?;
+type Result<T> = std::result::Result<T, NesError>;
 
+fn run_monitor(nes: &mut Nes, rom_path: &str) -> Result<()> {
+    let mut monitor = Monitor::new();
+    match monitor.debugger.symbols.load_for_rom(Path::new(rom_path)) {
+        Ok(0) => {}
+        Ok(count) => println!("Loaded {} symbol file(s)", count),
+        Err(err) => println!("warning: {}", err),
+    }
+    let watch_file = WatchList::sidecar_path(Path::new(rom_path));
+    if watch_file.exists()
+        && let Err(err) = monitor.watches.load(&watch_file, &monitor.debugger.symbols)
+    {
+        println!("warning: {}", err);
+    }
+    monitor.watch_file = Some(watch_file);
+    let stdin = io::stdin();
+
+    print!("> ");
+    io::stdout().flush()?;
+    for line in stdin.lock().lines() {
+        let line = line?;
+        if !line.trim().is_empty() {
+            match Command::parse_with_symbols(&line, &monitor.debugger.symbols) {
+                Ok(Command::Quit) => break,
+                Ok(command) => match monitor.execute(nes, command) {
+                    Ok(output) => println!("{}", output),
+                    Err(err) => println!("error: {}", err),
+                },
+                Err(err) => println!("error: {}", err),
+            }
+        }
+        print!("> ");
+        io::stdout().flush()?;
+    }
+    Ok(())
+}
+
+// Value following a `--name value` option
+fn option_value<'a>(args: &'a [String], name: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+fn parse_option<T: FromStr>(args: &[String], name: &str) -> Result<Option<T>>
+where
+    T::Err: std::fmt::Display,
+{
+    option_value(args, name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|e| NesError::Invalid(format!("invalid {} '{}': {}", name, value, e)))
+        })
+        .transpose()
+}
+
+// The ROM at `rom_path`. With --verbose its header goes to stderr, out of
+// the way of anything parsing stdout.
+fn load_rom(args: &[String], rom_path: &str) -> Result<Nes> {
+    let nes = Nes::from_file(Path::new(rom_path))?;
+    if args.iter().any(|arg| arg == "--verbose") {
+        let info = nes.rom_info();
+        eprintln!("PRG-ROM: {} KB", info.prg_rom_size / 1024);
+        eprintln!("CHR-ROM: {} KB", info.chr_rom_size / 1024);
+        eprintln!("Mapper: {}", info.mapper);
+        eprintln!("Has trainer: {}", info.has_trainer);
+        eprintln!("Has battery: {}", info.has_battery);
+        eprintln!("Expansion device: {:?}", info.expansion_device);
+        eprintln!("Reset vector: ${:04X}", nes.memory.peek_u16(RESET_VECTOR));
+    }
+    Ok(nes)
+}
 
+// nesemu compare-trace <rom.nes> <reference.log> [--fields pc,a,x,y,p,sp,cyc]
+fn compare_trace(args: &[String]) -> Result<()> {
+    let (Some(rom_path), Some(log_path)) = (args.get(2), args.get(3)) else {
+        eprintln!("usage: nesemu compare-trace <rom.nes> <reference.log> [--fields pc,a,x,y,p,sp,cyc]");
+        std::process::exit(1);
+    };
 
+    let mut config = CompareConfig::default();
+    if let Some(fields) = option_value(args, "--fields") {
+        config.fields = tracecmp::parse_fields(fields)?;
+    }
 
-    let rom_data: rom::Rom = rom::Rom::parse(rom_file)?;
+    let mut nes = load_rom(args, rom_path)?;
+    let reference = fs::read_to_string(log_path).map_err(|e| NesError::io(log_path, e))?;
 
-    let mut nes_mem = mem::Memory::new(rom_data.prg_rom);
+    match tracecmp::compare(&mut nes, reference.lines(), &config)? {
+        Some(mismatch) => {
+            print!("{}", mismatch);
+            std::process::exit(2);
+        }
+        None => println!("Trace matches ({} lines)", reference.lines().count()),
+    }
+    Ok(())
+}
 
+// nesemu bench <rom.nes> [--seconds N] [--json] [--save-baseline <file>]
+//                        [--baseline <file> [--threshold <percent>]]
+fn bench(args: &[String]) -> Result<()> {
+    let Some(rom_path) = args.get(2) else {
+        eprintln!("usage: nesemu bench <rom.nes> [--seconds N] [--json] [--save-baseline <file>] [--baseline <file> [--threshold <percent>]] [--verbose]");
+        std::process::exit(1);
+    };
+    let seconds: f64 = parse_option(args, "--seconds")?.unwrap_or(5.0);
+    let threshold: f64 = parse_option(args, "--threshold")?.unwrap_or(10.0);
 
-    // now execute
-    let mut cpu = cpu::Cpu::new();
-    cpu.reset(&nes_mem);
+    let mut nes = load_rom(args, rom_path)?;
+    let result = nesemu::bench::run(&mut nes, Duration::from_secs_f64(seconds));
 
+    if args.iter().any(|arg| arg == "--json") {
+        println!("{}", result.to_json());
+    } else {
+        println!("{}", result);
+    }
+    if let Some(path) = option_value(args, "--save-baseline") {
+        fs::write(path, format!("{}\n", result.to_json())).map_err(|e| NesError::io(path, e))?;
+    }
+    if let Some(path) = option_value(args, "--baseline") {
+        let baseline = Baseline::parse(&fs::read_to_string(path).map_err(|e| NesError::io(path, e))?)?;
+        if let Err(err) = baseline.check(result.fps(), threshold) {
+            eprintln!("regression: {}", err);
+            std::process::exit(2);
+        }
+    }
+    Ok(())
+}
 
+// nesemu dump <rom.nes> --out <dir> [--frames N] [--cpu-space]
+fn dump(args: &[String]) -> Result<()> {
+    let (Some(rom_path), Some(out)) = (args.get(2), option_value(args, "--out")) else {
+        eprintln!("usage: nesemu dump <rom.nes> --out <dir> [--frames N] [--cpu-space]");
+        std::process::exit(1);
+    };
+    let frames: u64 = parse_option(args, "--frames")?.unwrap_or(0);
 
-    // Run a few cycles to test
-    for _ in 0..1000
+    let mut nes = load_rom(args, rom_path)?;
+    nes.enable_watchdog(WatchdogConfig::default());
+    let mut stuck = false;
+    for _ in 0..frames {
+        nes.run_frame();
+        if report_if_stuck(&nes, rom_path)? {
+            stuck = true;
+            break;
+        }
+    }
+    // A stuck run still gets its dump: that's the state worth looking at
+    let cpu_space = args.iter().any(|arg| arg == "--cpu-space");
+    let manifest = nesemu::dump::write_dump(&nes, Path::new(out), cpu_space)
+        .map_err(|e| NesError::io(out, e))?;
+    print!("{}", manifest);
+    if stuck {
+        std::process::exit(EXIT_STUCK);
+    }
+    Ok(())
+}
 
-     {
-        cpu.exec_next_instr(&mut nes_mem);
+// Instructions --disasm prints unless --disasm-count says otherwise
+const DISASM_COUNT: usize = 64;
 
-        println!("PC: {:04X}, A: {:02X}, X: {:02X}, Y: {:02X}, P: {:02X}", 
-                 cpu.pc, cpu.a, cpu.x, cpu.y, cpu.status);
-                
+// Listing of the code at the reset vector, with labels from the ROM's
+// symbol files if there are any
+fn print_disasm(nes: &Nes, rom_path: &str, count: usize) -> Result<()> {
+    let mut symbols = Symbols::new();
+    if let Err(err) = symbols.load_for_rom(Path::new(rom_path)) {
+        eprintln!("warning: {}", err);
+    }
+    let reset = nes.memory.peek_u16(RESET_VECTOR);
+    println!("; reset vector ${:04X}", reset);
+    for line in disasm::disassemble_with_symbols(&nes.memory, reset, count, &symbols) {
+        println!("{}", line);
     }
+    Ok(())
+}
 
+// Exit code for headless runs the watchdog stopped
+const EXIT_STUCK: i32 = 3;
 
+// nesemu test-roms [dir]; the directory defaults to $NESEMU_TEST_ROMS
+fn test_roms(args: &[String]) -> Result<()> {
+    let Some(dir) = args.get(2).cloned().or_else(|| env::var(testrom::TEST_ROM_DIR_VAR).ok()) else {
+        eprintln!("usage: nesemu test-roms <dir> (or set {})", testrom::TEST_ROM_DIR_VAR);
+        std::process::exit(1);
+    };
 
-    
+    let results = testrom::run_suites(Path::new(&dir), testrom::SUITES);
+    let mut stuck = false;
+    for result in &results {
+        println!("{}", result);
+        if let Some(Ok(testrom::Outcome::Stuck { dump, .. })) = &result.outcome {
+            let path = Path::new(&dir).join(format!("{}.stuck.txt", result.rom.path));
+            fs::write(&path, dump).map_err(|e| NesError::io(&path, e))?;
+            println!("    report written to {}", path.display());
+            stuck |= !result.as_expected();
+        }
+    }
+    if stuck {
+        std::process::exit(EXIT_STUCK);
+    }
+    if !results.iter().all(testrom::SuiteResult::as_expected) {
+        std::process::exit(2);
+    }
     Ok(())
+}
+
+// `-` for stdout, `fd:N` for a descriptor the caller opened for us (3 and
+// up; 0-2 are ours), else a path. A plain number is a path too.
+fn open_output(target: &str) -> Result<Box<dyn Write + Send>> {
+    if target == "-" {
+        return Ok(Box::new(io::stdout()));
+    }
+    if let Some(fd) = target.strip_prefix("fd:") {
+        return open_fd(fd);
+    }
+    let file = File::create(target).map_err(|e| NesError::io(target, e))?;
+    Ok(Box::new(BufWriter::new(file)))
+}
+
+#[cfg(unix)]
+fn open_fd(fd: &str) -> Result<Box<dyn Write + Send>> {
+    use std::os::fd::{BorrowedFd, RawFd};
+
+    let fd: RawFd = fd
+        .parse()
+        .map_err(|_| NesError::Invalid(format!("invalid file descriptor 'fd:{}'", fd)))?;
+    if fd <= 2 {
+        return Err(NesError::Invalid(format!("fd:{} is stdin, stdout or stderr; use - for stdout", fd)));
+    }
+    // SAFETY: the caller opened the fd for us to write to. We only borrow
+    // it long enough to dup, so closing our copy leaves theirs alone.
+    let borrowed = unsafe { BorrowedFd::borrow_raw(fd) };
+    let owned = borrowed
+        .try_clone_to_owned()
+        .map_err(|e| NesError::io(format!("fd:{}", fd), e))?;
+    Ok(Box::new(BufWriter::new(File::from(owned))))
+}
+
+#[cfg(not(unix))]
+fn open_fd(fd: &str) -> Result<Box<dyn Write + Send>> {
+    Err(NesError::Invalid(format!("fd:{} needs a Unix host", fd)))
+}
+
+// Whether the watchdog gave up on a headless run, leaving a report behind
+// if it did. The caller stops the run and exits with EXIT_STUCK once the
+// save, trace and event stream are flushed.
+fn report_if_stuck(nes: &Nes, rom_path: &str) -> Result<bool> {
+    let Some(reason) = nes.stuck() else {
+        return Ok(false);
+    };
+    let path = format!("{}.crash.txt", rom_path);
+    nes.write_crash_dump(Path::new(&path), &format!("watchdog: {}", reason))?;
+    eprintln!("stuck: {}; report written to {}", reason, path);
+    Ok(true)
+}
+
+// Headless runs end with the unknown opcodes they hit, if any
+fn report_unknown_opcodes(nes: &Nes) {
+    let report = nes.unknown_opcode_report();
+    if !report.is_empty() {
+        eprintln!("unknown opcodes:\n{}", report);
+    }
+}
 
+// Headless run of whole frames, printing the CPU state to `progress` after
+// each one. With `profile`, a time breakdown goes to stderr at the end, as
+// do speed stats (JSON) if they are enabled. True if the watchdog stopped
+// it early.
+fn run_frames(nes: &mut Nes, rom_path: &str, frames: u64, profile: bool, progress: &mut dyn Write) -> Result<bool> {
+    let mut profiler = profile.then(Profiler::new);
+    nes.enable_watchdog(WatchdogConfig::default());
+    let mut stuck = false;
+    for _ in 0..frames {
+        match &mut profiler {
+            Some(profiler) => {
+                profiler.begin_frame();
+                profiler.time("cpu", || nes.run_frame());
+            }
+            None => nes.run_frame(),
+        }
+        if report_if_stuck(nes, rom_path)? {
+            stuck = true;
+            break;
+        }
+
+        let cpu = &nes.cpu;
+        let mut print = || {
+            writeln!(progress, "PC: {:04X}, A: {:02X}, X: {:02X}, Y: {:02X}, P: {:02X}, CYC: {}",
+                     cpu.pc, cpu.a, cpu.x, cpu.y, cpu.status, cpu.cycles)
+        };
+        match &mut profiler {
+            Some(profiler) => {
+                profiler.time("frontend", print)?;
+                profiler.end_frame();
+            }
+            None => print()?,
+        }
+    }
+    if let Some(profiler) = profiler {
+        eprintln!("{}", profiler);
+    }
+    if let Some(summary) = nes.stats().and_then(Stats::summary) {
+        eprintln!("{}", summary.to_json());
+    }
+    Ok(stuck)
+}
+
+// Set by the panic hook so the crash dump can say what went wrong
+static PANIC_MESSAGE: Mutex<Option<String>> = Mutex::new(None);
+
+fn install_panic_hook() {
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        if let Ok(mut message) = PANIC_MESSAGE.lock() {
+            *message = Some(format!("panic: {}", info));
+        }
+        default_hook(info);
+    }));
+}
+
+fn main() {
+    if let Err(err) = run() {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
 }
 
+fn run() -> Result<()> {
+    let args: Vec<String> = env::args().collect();
+    if args.get(1).map(String::as_str) == Some("compare-trace") {
+        return compare_trace(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("test-roms") {
+        return test_roms(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("bench") {
+        return bench(&args);
+    }
+    if args.get(1).map(String::as_str) == Some("dump") {
+        return dump(&args);
+    }
+    let Some(rom_path) = args.get(1) else {
+        eprintln!("usage: nesemu <rom.nes> [--monitor] [--disasm [--disasm-count <n>]] [--input <device>] [--event-log] [--coverage-out <file>] [--events-out <file|-|fd:N>] [--trace <file|-|fd:N> [--trace-range <start>-<end>] [--trace-limit <n>]] [--frames <n> [--profile]] [--stats] [--opcode-stats] [--verbose]");
+        std::process::exit(1);
+    };
+
+    let mut nes = load_rom(&args, rom_path)?;
+
+    if args.iter().any(|arg| arg == "--disasm") {
+        let count = parse_option(&args, "--disasm-count")?.unwrap_or(DISASM_COUNT);
+        return print_disasm(&nes, rom_path, count);
+    }
+
+    if let Some(device) = option_value(&args, "--input") {
+        nes.set_input_config(InputConfig::parse(device)?);
+    }
+
+    if args.iter().any(|arg| arg == "--stats") {
+        nes.enable_stats(stats::DEFAULT_WINDOW);
+    }
+
+    if args.iter().any(|arg| arg == "--event-log") {
+        nes.enable_event_log(EventLogConfig::default());
+    }
+
+    // An event stream on stdout gets it to itself: the progress lines move
+    // to stderr, and the monitor, which needs stdout, is refused
+    let events_on_stdout = option_value(&args, "--events-out") == Some("-");
+    if events_on_stdout && args.iter().any(|arg| arg == "--monitor") {
+        return Err(NesError::Invalid("--events-out - can't share stdout with --monitor".to_string()));
+    }
+    let mut progress: Box<dyn Write> = if events_on_stdout {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    };
+    if let Some(target) = option_value(&args, "--events-out") {
+        nes.set_event_stream(EventStream::new(open_output(target)?));
+    }
+
+    if let Some(target) = option_value(&args, "--trace") {
+        let mut config = TraceConfig {
+            limit: parse_option(&args, "--trace-limit")?,
+            ..TraceConfig::default()
+        };
+        if let Some(range) = option_value(&args, "--trace-range") {
+            config.range = Some(TraceConfig::parse_range(range)?);
+        }
+        nes.set_trace(TraceLogger::with_config(open_output(target)?, config));
+    }
+
+    if args.iter().any(|arg| arg == "--opcode-stats") {
+        nes.enable_opcode_stats();
+    }
+
+    let coverage_out = option_value(&args, "--coverage-out");
+    if coverage_out.is_some() {
+        nes.enable_coverage();
+    }
+
+    #[cfg(feature = "remote")]
+    if let Some(addr) = option_value(&args, "--remote") {
+        let mut server = nesemu::remote::Server::new(Some(nes));
+        if let Some(dir) = option_value(&args, "--remote-roms") {
+            server = server.with_rom_dir(dir);
+        }
+        let (local_addr, handle) = server.spawn(addr)?;
+        println!("Listening on {}", local_addr);
+        let _ = handle.join();
+        return Ok(());
+    }
+
+    install_panic_hook();
+    // Ok(true) when the watchdog stopped the run
+    let run = panic::catch_unwind(AssertUnwindSafe(|| -> Result<bool> {
+        let mut stuck = false;
+        if args.iter().any(|arg| arg == "--monitor") {
+            run_monitor(&mut nes, rom_path)?;
+        } else if let Some(frames) = parse_option(&args, "--frames")? {
+            let profile = args.iter().any(|arg| arg == "--profile");
+            stuck = run_frames(&mut nes, rom_path, frames, profile, &mut progress)?;
+            report_unknown_opcodes(&nes);
+        } else {
+            // Run a few cycles to test
+            nes.enable_watchdog(WatchdogConfig::default());
+            for _ in 0..1000 {
+                nes.step();
+                if report_if_stuck(&nes, rom_path)? {
+                    stuck = true;
+                    break;
+                }
+
+                writeln!(progress, "PC: {:04X}, A: {:02X}, X: {:02X}, Y: {:02X}, P: {:02X}",
+                         nes.cpu.pc, nes.cpu.a, nes.cpu.x, nes.cpu.y, nes.cpu.status)?;
+            }
+            report_unknown_opcodes(&nes);
+        }
+        Ok(stuck)
+    }));
+    let stuck = match run {
+        Ok(result) => result?,
+        Err(payload) => {
+            let reason = PANIC_MESSAGE.lock().ok().and_then(|m| m.clone());
+            let path = format!("{}.crash.txt", rom_path);
+            match nes.write_crash_dump(Path::new(&path), reason.as_deref().unwrap_or("panic")) {
+                Ok(()) => eprintln!("crash dump written to {}", path),
+                Err(err) => eprintln!("failed to write crash dump: {}", err),
+            }
+            panic::resume_unwind(payload);
+        }
+    };
 
+    nes.flush()?;
+
+    if let Some(stats) = nes.opcode_stats() {
+        eprintln!("{}", stats);
+    }
+
+    if let Some(mut trace) = nes.take_trace() {
+        trace.flush()?;
+        if let Some(err) = trace.take_error() {
+            return Err(err.into());
+        }
+    }
+
+    if let Some(mut stream) = nes.take_event_stream() {
+        stream.flush()?;
+        if let Some(err) = stream.take_error() {
+            return Err(err.into());
+        }
+    }
+
+    if let (Some(path), Some(coverage)) = (coverage_out, nes.coverage()) {
+        fs::write(path, coverage.report(&nes.memory)).map_err(|e| NesError::io(path, e))?;
+    }
+
+    // Only now, with everything above written out
+    if stuck {
+        std::process::exit(EXIT_STUCK);
+    }
+    Ok(())
+}
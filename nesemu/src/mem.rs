@@ -1,67 +1,159 @@
-use std::ops::RangeInclusive;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
 
+use crate::cartridge::{Cartridge, PrgRomError};
+use crate::controller::Controller;
+use crate::irq::{self, IrqLine};
+use crate::vram::Vram;
+
+// Bits of $4016/$4017 reads not driven by the controller ports
+const CONTROLLER_OPEN_BUS_BITS: u8 = 0xE0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Clone)]
 pub struct Memory {
     cpu_ram: [u8; 0x0800],       // $0000-$07FF
-    prg_rom: Vec<u8>,           // $8000-$FFFF (external)
-    cartridge_ram: [u8; 0x2000],// $6000-$7FFF (optional save RAM)
+    cartridge: Cartridge,       // $4020-$FFFF
     ppu_registers: [u8; 8],     // $2000-$2007
     apu_io_registers: [u8; 0x18], // $4000-$4017
     oam_dma: u8,                // $4014 (DMA trigger)
+    oam_dma_pending: bool,      // Set by a $4014 write until the CPU takes it
+    oam: [u8; 0x100],           // Sprite attribute memory, via $2003/$2004
+    vram: Vram,                 // PPU memory and address latches, via $2006/$2007
+    irq: IrqLine,               // Shared by every device that can raise an IRQ
+    controllers: [Controller; 2], // Read through $4016/$4017
+    write_log: Option<Vec<(u16, u8)>>, // CPU writes since the last drain, when recording
+    access_log: Option<Vec<(Access, u16, u8)>>, // Every read and write likewise
 }
 
 impl Memory {
-    pub fn new(prg_rom: Vec<u8>) -> Self {
+    pub fn new(cartridge: Cartridge) -> Self {
         Self {
             cpu_ram: [0; 0x0800],
-            prg_rom,
-            cartridge_ram: [0; 0x2000],
+            cartridge,
             ppu_registers: [0; 8],
             apu_io_registers: [0; 0x18],
             oam_dma: 0,
+            oam_dma_pending: false,
+            oam: [0; 0x100],
+            vram: Vram::new(),
+            irq: IrqLine::new(),
+            controllers: [Controller::new(); 2],
+            write_log: None,
+            access_log: None,
         }
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
+    // A blank 32 KiB PRG-ROM with `program` at `load_addr` and the reset
+    // vector pointing at it, for tests and experiments. The bytes can go in
+    // RAM or ROM; any that land where nothing is mapped are dropped.
+    pub fn with_program(program: &[u8], load_addr: u16) -> Self {
+        let cartridge = Cartridge::from_prg(vec![0; 0x8000]).expect("32 KiB PRG-ROM always maps");
+        let mut memory = Memory::new(cartridge);
+        for (i, &byte) in program.iter().enumerate() {
+            memory.poke(load_addr.wrapping_add(i as u16), byte);
+        }
+        let [lo, hi] = load_addr.to_le_bytes();
+        memory.poke(0xFFFC, lo);
+        memory.poke(0xFFFD, hi);
+        memory
+    }
+
+    #[inline]
+    pub fn read(&mut self, addr: u16) -> u8 {
+        let value = self.peek(addr);
         match addr {
-            // CPU internal RAM (mirrored every 0x800 bytes)
-            0x0000..=0x1FFF => {
-                let mirror_addr = addr as usize % 0x0800;
-                self.cpu_ram[mirror_addr]
+            // PPUSTATUS resets the $2005/$2006 toggle; PPUDATA moves on
+            0x2000..=0x3FFF if addr & 7 == 2 => self.vram.read_status(),
+            0x2000..=0x3FFF if addr & 7 == 7 => {
+                let increment = self.vram_increment();
+                self.vram.read_data(&self.cartridge, increment);
+            }
+            0x4016 | 0x4017 => {
+                self.controllers[addr as usize - 0x4016].read();
             }
+            _ => {}
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push((Access::Read, addr, value));
+        }
+        value
+    }
+
+    // What a read would return, without any of its side effects, not even
+    // an access log entry. Debuggers, traces and dumps use this so looking
+    // at the machine never changes it: peeking $2007 shows the byte a read
+    // would return without stepping the VRAM address or refilling the read
+    // buffer, and peeking $2002 leaves the write toggle alone.
+    #[inline]
+    pub fn peek(&self, addr: u16) -> u8 {
+        match addr {
+            // CPU internal RAM (mirrored every 0x800 bytes)
+            0x0000..=0x1FFF => self.cpu_ram[addr as usize & 0x07FF],
             // PPU registers (mirrored every 8 bytes)
             0x2000..=0x3FFF => {
-                let reg = (addr - 0x2000) % 8;
-                self.ppu_registers[reg as usize]
+                let reg = addr & 7;
+                match reg {
+                    4 => self.oam[self.ppu_registers[3] as usize],
+                    7 => self.vram.peek_data(&self.cartridge),
+                    _ => self.ppu_registers[reg as usize],
+                }
             }
             // APU and I/O
             0x4000..=0x4013 | 0x4015 => {
                 self.apu_io_registers[(addr - 0x4000) as usize]
             }
             0x4014 => self.oam_dma,
-            // Cartridge RAM (optional save RAM)
-            0x6000..=0x7FFF => {
-                self.cartridge_ram[(addr - 0x6000) as usize]
-            }
-            // PRG-ROM (no mirroring)
-            0x8000..=0xFFFF => {
-                let prg_addr = (addr - 0x8000) as usize % self.prg_rom.len();
-                self.prg_rom[prg_addr]
+            // Controller ports drive only the low bits; the rest is open bus,
+            // which after the usual LDA $4016 still holds the address's high
+            // byte. A pad drives bit 0.
+            0x4016 | 0x4017 => {
+                let serial = self.controllers[addr as usize - 0x4016].peek();
+                (addr >> 8) as u8 & CONTROLLER_OPEN_BUS_BITS | serial
             }
+            0x4020..=0xFFFF => self.cartridge.cpu_read(addr),
             _ => 0 // Unmapped areas return 0
         }
     }
 
     pub fn write(&mut self, addr: u16, value: u8) {
+        if let Some(log) = &mut self.write_log {
+            log.push((addr, value));
+        }
+        if let Some(log) = &mut self.access_log {
+            log.push((Access::Write, addr, value));
+        }
+
         match addr {
             // CPU internal RAM
-            0x0000..=0x1FFF => {
-                let mirror_addr = addr as usize % 0x0800;
-                self.cpu_ram[mirror_addr] = value;
-            }
+            0x0000..=0x1FFF => self.cpu_ram[addr as usize & 0x07FF] = value,
             // PPU registers
             0x2000..=0x3FFF => {
-                let reg = (addr - 0x2000) % 8;
+                let reg = addr & 7;
                 self.ppu_registers[reg as usize] = value;
+                match reg {
+                    0 => self.vram.write_ctrl(value),
+                    4 => {
+                        // OAMDATA writes go to OAM and advance OAMADDR
+                        let oam_addr = self.ppu_registers[3];
+                        self.store_oam(oam_addr, value);
+                        self.ppu_registers[3] = oam_addr.wrapping_add(1);
+                    }
+                    5 => self.vram.write_scroll(value),
+                    6 => self.vram.write_addr(value),
+                    7 => {
+                        let increment = self.vram_increment();
+                        self.vram.write_data(&mut self.cartridge, value, increment);
+                    }
+                    _ => {}
+                }
             }
             // APU and I/O
             0x4000..=0x4013 | 0x4015 => {
@@ -69,35 +161,509 @@ impl Memory {
             }
             0x4014 => {
                 self.oam_dma = value;
-                // NOTE: OAM DMA logic would be handled by CPU
+                self.copy_page_to_oam(value);
+                // The CPU stalls for the transfer once the instruction ends
+                self.oam_dma_pending = true;
             }
-            // Cartridge SRAM
-            0x6000..=0x7FFF => {
-                self.cartridge_ram[(addr - 0x6000) as usize] = value;
-            }
-            // PRG-ROM is read-only
-            0x8000..=0xFFFF => {
-                // Ignore writes to ROM
+            0x4016 => {
+                for controller in &mut self.controllers {
+                    controller.write_strobe(value);
+                }
             }
+            0x4020..=0xFFFF => self.cartridge.cpu_write(addr, value),
+            _ => {}
+        }
+    }
+
+    // Debugger write: stores straight into the backing array with no side
+    // effects and no write logging. Pokes to $8000-$FFFF patch the loaded
+    // PRG-ROM image (every mirror sees the patch; reloading the ROM undoes it).
+    // Addresses with no backing storage are ignored.
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        match addr {
+            0x0000..=0x1FFF => self.cpu_ram[addr as usize & 0x07FF] = value,
+            0x2000..=0x3FFF => match addr & 7 {
+                4 => self.store_oam(self.ppu_registers[3], value),
+                reg => self.ppu_registers[reg as usize] = value,
+            },
+            0x4000..=0x4013 | 0x4015 => self.apu_io_registers[(addr - 0x4000) as usize] = value,
+            0x4014 => self.oam_dma = value,
+            0x4020..=0xFFFF => self.cartridge.poke(addr, value),
             _ => {}
         }
     }
 
-    pub fn load_prg_rom(&mut self, new_prg: Vec<u8>) {
-        self.prg_rom = new_prg;
+    // The OAM DMA transfer: 256 reads from `page` through the CPU memory
+    // map, each written to OAMDATA. So it fills OAM from OAMADDR on,
+    // wrapping round, and leaves OAMADDR where it was.
+    fn copy_page_to_oam(&mut self, page: u8) {
+        let oam_addr = self.ppu_registers[3];
+        for i in 0..=255u8 {
+            let value = self.read(u16::from_be_bytes([page, i]));
+            self.store_oam(oam_addr.wrapping_add(i), value);
+        }
+    }
+
+    // Bits 2-4 of each sprite's attribute byte don't exist in OAM and
+    // read back as 0
+    fn store_oam(&mut self, index: u8, value: u8) {
+        let value = if index % 4 == 2 { value & 0xE3 } else { value };
+        self.oam[index as usize] = value;
+    }
+
+    pub fn ram(&self) -> &[u8; 0x0800] {
+        &self.cpu_ram
+    }
+
+    pub fn cartridge_ram(&self) -> &[u8; 0x2000] {
+        self.cartridge.prg_ram()
+    }
+
+    pub fn cartridge(&self) -> &Cartridge {
+        &self.cartridge
     }
 
+    pub fn cartridge_mut(&mut self) -> &mut Cartridge {
+        &mut self.cartridge
+    }
+
+    pub fn oam(&self) -> &[u8; 0x100] {
+        &self.oam
+    }
+
+    // Write one OAM byte directly, leaving OAMADDR alone
+    pub fn poke_oam(&mut self, index: u8, value: u8) {
+        self.store_oam(index, value);
+    }
+
+    // PPU memory at `addr` ($0000-$3FFF) through the cartridge's CHR and
+    // mirroring, without touching the $2006/$2007 latches
+    pub fn peek_vram(&self, addr: u16) -> u8 {
+        self.vram.peek(&self.cartridge, addr)
+    }
+
+    // Like a $2007 write minus the latches, so CHR-ROM ignores it
+    pub fn poke_vram(&mut self, addr: u16, value: u8) {
+        self.vram.poke(&mut self.cartridge, addr, value);
+    }
+
+    pub fn peek_palette(&self, index: u8) -> u8 {
+        self.vram.peek_palette(index)
+    }
+
+    pub fn poke_palette(&mut self, index: u8, value: u8) {
+        self.vram.poke_palette(index, value);
+    }
+
+    // The address the next $2007 access goes to
+    pub fn vram_addr(&self) -> u16 {
+        self.vram.addr()
+    }
+
+    // PPUCTRL bit 2 picks whether $2007 steps across or down a nametable
+    fn vram_increment(&self) -> u16 {
+        if self.ppu_registers[0] & 0x04 != 0 { 32 } else { 1 }
+    }
+
+    // The page a $4014 write asked to copy to OAM, once per write
+    pub fn take_oam_dma(&mut self) -> Option<u8> {
+        core::mem::take(&mut self.oam_dma_pending).then_some(self.oam_dma)
+    }
+
+    // PPUCTRL as last written
+    pub fn ppu_ctrl(&self) -> u8 {
+        self.ppu_registers[0]
+    }
+
+    pub fn prg_rom_len(&self) -> usize {
+        self.cartridge.prg_rom_len()
+    }
+
+    // See Cartridge::prg_offset
+    pub fn prg_offset(&self, addr: u16) -> Option<usize> {
+        self.cartridge.prg_offset(addr)
+    }
+
+    pub fn prg_addr(&self, offset: usize) -> Option<u16> {
+        self.cartridge.prg_addr(offset)
+    }
+
+    // Swap in a new PRG-ROM image, keeping the rest of the cartridge; on
+    // error the old one stays
+    pub fn load_prg_rom(&mut self, new_prg: Vec<u8>) -> Result<(), PrgRomError> {
+        self.cartridge.load_prg_rom(new_prg)
+    }
+
+    // Power-on state: everything but the cartridge ROM cleared
     pub fn reset(&mut self) {
         self.cpu_ram = [0; 0x0800];
-        self.cartridge_ram = [0; 0x2000];
+        self.cartridge.reset();
         self.ppu_registers = [0; 8];
         self.apu_io_registers = [0; 0x18];
         self.oam_dma = 0;
+        self.oam_dma_pending = false;
+        self.oam = [0; 0x100];
+        self.vram = Vram::new();
+        self.irq = IrqLine::new();
+        // The buttons are whatever the player holds, not console state
+        for controller in &mut self.controllers {
+            let buttons = controller.buttons();
+            *controller = Controller::new();
+            controller.set_buttons(buttons);
+        }
+    }
+
+    // The Reset button: RAM, save RAM, OAM and VRAM survive, PPUCTRL/PPUMASK
+    // and the $2006 toggle are cleared and $4015 silences every channel
+    pub fn soft_reset(&mut self) {
+        self.ppu_registers[0] = 0;
+        self.ppu_registers[1] = 0;
+        self.vram.soft_reset();
+        self.apu_io_registers[0x15] = 0;
+        self.irq.acknowledge(irq::Source::Dmc); // Cleared by the $4015 write
+    }
+
+    pub fn irq_line(&self) -> &IrqLine {
+        &self.irq
+    }
+
+    // For devices raising and acknowledging their IRQs
+    pub fn irq_line_mut(&mut self) -> &mut IrqLine {
+        &mut self.irq
+    }
+
+    // Buttons held on the pad in `port` (0 or 1), bit 0 = A through bit 7 =
+    // Right. The game sees them from its next strobe.
+    pub fn set_controller_state(&mut self, port: usize, buttons: u8) {
+        self.controllers[port].set_buttons(buttons);
+    }
+
+    pub fn controller(&self, port: usize) -> &Controller {
+        &self.controllers[port]
+    }
+
+    // Start or stop recording writes for observers like the event log
+    pub fn record_writes(&mut self, enabled: bool) {
+        match (enabled, &self.write_log) {
+            (true, None) => self.write_log = Some(Vec::new()),
+            (false, _) => self.write_log = None,
+            _ => {}
+        }
     }
 
-    pub fn read_u16(&self, addr: u16) -> u16 {
+    // Move the recorded writes into `out` (emptied first). The two buffers
+    // are swapped, so once both have grown the per-instruction drain never
+    // allocates.
+    pub fn drain_writes(&mut self, out: &mut Vec<(u16, u8)>) {
+        out.clear();
+        if let Some(log) = &mut self.write_log {
+            core::mem::swap(log, out);
+        }
+    }
+
+    // Start or stop recording every access, reads included, for watchpoints.
+    // Anything that reads memory between instructions is recorded too, so
+    // clear the log right before the instruction of interest.
+    pub fn record_accesses(&mut self, enabled: bool) {
+        match (enabled, &self.access_log) {
+            (true, None) => self.access_log = Some(Vec::new()),
+            (false, _) => self.access_log = None,
+            _ => {}
+        }
+    }
+
+    pub fn clear_accesses(&mut self) {
+        if let Some(log) = &mut self.access_log {
+            log.clear();
+        }
+    }
+
+    // Like drain_writes, for the access log
+    pub fn drain_accesses(&mut self, out: &mut Vec<(Access, u16, u8)>) {
+        out.clear();
+        if let Some(log) = &mut self.access_log {
+            core::mem::swap(log, out);
+        }
+    }
+
+    pub fn peek_u16(&self, addr: u16) -> u16 {
+        let lo = self.peek(addr) as u16;
+        let hi = self.peek(addr.wrapping_add(1)) as u16;
+        (hi << 8) | lo
+    }
+
+    pub fn read_u16(&mut self, addr: u16) -> u16 {
         let lo = self.read(addr) as u16;
         let hi = self.read(addr.wrapping_add(1)) as u16;
         (hi << 8) | lo
     }
+
+    // Pointer fetch for (zp,X) and (zp),Y: the high byte comes from the next
+    // zero page address, so a pointer at $FF wraps to $00 rather than $0100
+    pub fn read_zp_u16(&mut self, ptr: u8) -> u16 {
+        let lo = self.read(ptr as u16) as u16;
+        let hi = self.read(ptr.wrapping_add(1) as u16) as u16;
+        (hi << 8) | lo
+    }
+
+    // First byte of state that differs from `other`, e.g.
+    // "cpu_ram[$10]: $21 vs $22". The write and access logs are not state.
+    pub fn diff(&self, other: &Memory) -> Option<String> {
+        let regions: [(&str, &[u8], &[u8]); 4] = [
+            ("cpu_ram", &self.cpu_ram, &other.cpu_ram),
+            ("ppu_registers", &self.ppu_registers, &other.ppu_registers),
+            ("apu_io_registers", &self.apu_io_registers, &other.apu_io_registers),
+            ("oam", &self.oam, &other.oam),
+        ];
+        for (name, ours, theirs) in regions {
+            if ours.len() != theirs.len() {
+                return Some(format!("{}: {} bytes vs {}", name, ours.len(), theirs.len()));
+            }
+            if let Some(i) = (0..ours.len()).find(|&i| ours[i] != theirs[i]) {
+                return Some(format!("{}[${:X}]: ${:02X} vs ${:02X}", name, i, ours[i], theirs[i]));
+            }
+        }
+        if self.oam_dma != other.oam_dma {
+            return Some(format!("oam_dma: ${:02X} vs ${:02X}", self.oam_dma, other.oam_dma));
+        }
+        if let Some(diff) = self.vram.diff(&other.vram) {
+            return Some(format!("vram.{}", diff));
+        }
+        if let Some(diff) = self.cartridge.diff(&other.cartridge) {
+            return Some(format!("cartridge.{}", diff));
+        }
+        for port in 0..2 {
+            let (ours, theirs) = (&self.controllers[port], &other.controllers[port]);
+            if ours != theirs {
+                return Some(format!("controllers[{}]: {:?} vs {:?}", port, ours, theirs));
+            }
+        }
+        (self.irq != other.irq).then(|| format!("irq: {:?} vs {:?}", self.irq, other.irq))
+    }
+}
+
+impl PartialEq for Memory {
+    fn eq(&self, other: &Memory) -> bool {
+        self.diff(other).is_none()
+    }
+}
+
+impl Eq for Memory {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cartridge::Mirroring;
+    use crate::cpu::Cpu;
+
+    // The copy starts at OAMADDR, so $80 puts the page's second half at
+    // OAM[$00..$7F]
+    #[test]
+    fn oam_dma_wraps_around_from_oamaddr() {
+        let mut memory = Memory::with_program(&[], 0x8000);
+        for i in 0..=255u8 {
+            memory.write(0x0200 + i as u16, i ^ 0x5A);
+        }
+        memory.write(0x2003, 0x80);
+        memory.write(0x4014, 0x02);
+
+        for i in 0..=255u8 {
+            let index = 0x80u8.wrapping_add(i);
+            let expected = if index % 4 == 2 { (i ^ 0x5A) & 0xE3 } else { i ^ 0x5A };
+            assert_eq!(memory.oam()[index as usize], expected, "OAM[${index:02X}]");
+        }
+        assert_eq!(memory.take_oam_dma(), Some(0x02));
+        assert_eq!(memory.take_oam_dma(), None);
+    }
+
+    // ($FF,X) and ($FF),Y pointers take their high byte from $00, not $0100
+    #[test]
+    fn zero_page_pointers_wrap() {
+        let mut memory = Memory::with_program(&[0xA1, 0x80, 0xB1, 0xFF], 0x8000); // LDA ($80,X), LDA ($FF),Y
+        memory.write(0x00FF, 0x34);
+        memory.write(0x0000, 0x02);
+        memory.write(0x0100, 0x05);
+        memory.write(0x0234, 0xAA);
+        memory.write(0x0235, 0xBB);
+        assert_eq!(memory.read_zp_u16(0xFF), 0x0234);
+
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0, 0x7F, 1, 0x24);
+        cpu.exec_next_instr(&mut memory).unwrap();
+        assert_eq!(cpu.a, 0xAA);
+        cpu.exec_next_instr(&mut memory).unwrap();
+        assert_eq!(cpu.a, 0xBB);
+    }
+
+    fn memory_with(mirroring: Mirroring, chr: Vec<u8>) -> Memory {
+        Memory::new(Cartridge::new(vec![0; 0x8000], chr, mirroring, 0).unwrap())
+    }
+
+    fn set_vram_addr(memory: &mut Memory, addr: u16) {
+        memory.read(0x2002);
+        memory.write(0x2006, (addr >> 8) as u8);
+        memory.write(0x2006, addr as u8);
+    }
+
+    // $2007 reads below the palette lag one behind: the first returns the
+    // stale buffer
+    fn read_vram_via_2007(memory: &mut Memory, addr: u16) -> u8 {
+        set_vram_addr(memory, addr);
+        memory.read(0x2007);
+        memory.read(0x2007)
+    }
+
+    #[test]
+    fn ppudata_writes_show_up_in_peek_vram() {
+        // (mirroring, the slot that shares $2000's table, one that doesn't)
+        let cases = [(Mirroring::Horizontal, 0x2400, 0x2800), (Mirroring::Vertical, 0x2800, 0x2400)];
+        for (mirroring, same, other) in cases {
+            let mut memory = memory_with(mirroring, Vec::new());
+            set_vram_addr(&mut memory, 0x2005);
+            memory.write(0x2007, 0xAA);
+            memory.write(0x2007, 0xBB);
+            assert_eq!(memory.vram_addr(), 0x2007);
+
+            assert_eq!(memory.peek_vram(0x2005), 0xAA, "{mirroring:?}");
+            assert_eq!(memory.peek_vram(0x2006), 0xBB, "{mirroring:?}");
+            assert_eq!(memory.peek_vram(same + 5), 0xAA, "{mirroring:?} ${:04X}", same + 5);
+            assert_eq!(memory.peek_vram(other + 5), 0x00, "{mirroring:?} ${:04X}", other + 5);
+            assert_eq!(memory.peek_vram(0x3005), 0xAA, "{mirroring:?} $3005");
+        }
+    }
+
+    #[test]
+    fn poke_vram_shows_up_through_ppudata() {
+        let cases = [(Mirroring::Horizontal, 0x2C10, 0x2810), (Mirroring::Vertical, 0x2C10, 0x2410)];
+        for (mirroring, poked, mirror) in cases {
+            let mut memory = memory_with(mirroring, Vec::new());
+            memory.poke_vram(poked, 0x55);
+            assert_eq!(read_vram_via_2007(&mut memory, poked), 0x55, "{mirroring:?}");
+            assert_eq!(read_vram_via_2007(&mut memory, mirror), 0x55, "{mirroring:?}");
+            // The poke itself left the latches alone
+            assert_eq!(memory.vram_addr(), mirror + 2);
+        }
+    }
+
+    #[test]
+    fn four_screen_tables_are_all_distinct() {
+        let mut memory = memory_with(Mirroring::FourScreen, Vec::new());
+        for slot in 0..4u16 {
+            memory.poke_vram(0x2000 + slot * 0x400, slot as u8 + 1);
+        }
+        for slot in 0..4u16 {
+            assert_eq!(read_vram_via_2007(&mut memory, 0x2000 + slot * 0x400), slot as u8 + 1);
+        }
+    }
+
+    #[test]
+    fn ppuctrl_bit_2_steps_a_row_at_a_time() {
+        let mut memory = memory_with(Mirroring::Vertical, Vec::new());
+        memory.write(0x2000, 0x04);
+        set_vram_addr(&mut memory, 0x2000);
+        for row in 0..3 {
+            memory.write(0x2007, row);
+        }
+        assert_eq!(memory.vram_addr(), 0x2060);
+        assert_eq!([memory.peek_vram(0x2000), memory.peek_vram(0x2020), memory.peek_vram(0x2040)], [0, 1, 2]);
+    }
+
+    #[test]
+    fn palette_is_mirrored_and_unbuffered() {
+        let mut memory = memory_with(Mirroring::Horizontal, Vec::new());
+        set_vram_addr(&mut memory, 0x3F10);
+        memory.write(0x2007, 0x0F);
+        assert_eq!(memory.peek_palette(0x00), 0x0F);
+        assert_eq!(memory.peek_vram(0x3F00), 0x0F);
+        assert_eq!(memory.peek_vram(0x3FE0), 0x0F);
+
+        memory.poke_palette(0x04, 0xFF); // Only 6 bits stick
+        set_vram_addr(&mut memory, 0x3F14);
+        assert_eq!(memory.read(0x2007), 0x3F); // No stale read first
+        assert_eq!(memory.peek_palette(0x14), 0x3F);
+    }
+
+    #[test]
+    fn pattern_tables_come_from_the_cartridge() {
+        let mut memory = memory_with(Mirroring::Horizontal, Vec::new());
+        set_vram_addr(&mut memory, 0x0010);
+        memory.write(0x2007, 0x99);
+        assert_eq!(memory.peek_vram(0x0010), 0x99);
+        assert_eq!(memory.cartridge().ppu_read(0x0010), 0x99);
+
+        let mut memory = memory_with(Mirroring::Horizontal, vec![0x11; 0x2000]);
+        memory.poke_vram(0x0010, 0x99); // CHR-ROM
+        set_vram_addr(&mut memory, 0x0010);
+        memory.write(0x2007, 0x99);
+        assert_eq!(memory.peek_vram(0x0010), 0x11);
+    }
+
+    #[test]
+    fn ppustatus_read_resets_the_write_toggle() {
+        let mut memory = memory_with(Mirroring::Horizontal, Vec::new());
+        memory.write(0x2006, 0x21); // First half only
+        memory.read(0x2002);
+        memory.write(0x2006, 0x23);
+        memory.write(0x2006, 0x45);
+        assert_eq!(memory.vram_addr(), 0x2345);
+
+        // $2005 shares the toggle
+        memory.write(0x2005, 0x00);
+        memory.write(0x2006, 0x3F); // Taken as the low byte
+        assert_eq!(memory.vram_addr(), 0x233F);
+    }
+
+    #[test]
+    fn peeking_ppudata_changes_nothing() {
+        let mut memory = memory_with(Mirroring::Vertical, Vec::new());
+        memory.poke_vram(0x2000, 0x12);
+        memory.poke_vram(0x2001, 0x34);
+        set_vram_addr(&mut memory, 0x2000);
+        let before = memory.clone();
+        assert_eq!(memory.peek(0x2007), memory.peek(0x2007));
+        assert_eq!(memory.diff(&before), None);
+
+        assert_eq!(memory.read(0x2007), 0x00); // Stale buffer
+        assert_eq!(memory.peek(0x2007), 0x12); // What the next read returns
+        assert_eq!(memory.read(0x2007), 0x12);
+        assert!(memory.diff(&before).is_some_and(|d| d.starts_with("vram.")));
+    }
+
+    // Halfway through a $2006 pair, peeking $2002 keeps the toggle where a
+    // read would clear it
+    #[test]
+    fn peeking_ppustatus_leaves_the_toggle() {
+        let mut memory = memory_with(Mirroring::Horizontal, Vec::new());
+        memory.write(0x2006, 0x21);
+        let before = memory.clone();
+        for _ in 0..3 {
+            memory.peek(0x2002);
+        }
+        assert_eq!(memory.diff(&before), None);
+        memory.write(0x2006, 0x08); // Still the second half
+        assert_eq!(memory.vram_addr(), 0x2108);
+
+        memory.write(0x2006, 0x21);
+        let half = memory.clone();
+        memory.read(0x2002);
+        assert!(memory.diff(&half).is_some_and(|d| d.contains("write_toggle")));
+        memory.write(0x2006, 0x23); // The first half again
+        memory.write(0x2006, 0x45);
+        assert_eq!(memory.vram_addr(), 0x2345);
+    }
+
+    // The tool accessors never go near the PPU registers or latches
+    #[test]
+    fn accessors_leave_the_registers_alone() {
+        let mut memory = memory_with(Mirroring::Vertical, Vec::new());
+        memory.write(0x2006, 0x21);
+        let (registers, before) = (memory.ppu_registers, memory.clone());
+        memory.poke_vram(0x2000, 0x12);
+        memory.poke_palette(0x01, 0x20);
+        assert_eq!(memory.peek_vram(0x2000), 0x12);
+        assert_eq!(memory.ppu_registers, registers);
+        assert!(memory.diff(&before).is_some_and(|d| d.contains("nametables")));
+        memory.poke_vram(0x2000, 0x00);
+        memory.poke_palette(0x01, 0x00);
+        assert_eq!(memory.diff(&before), None);
+    }
 }
@@ -0,0 +1,698 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::asm;
+use crate::debug;
+use crate::debugger::{Debugger, StopReason, WatchKind, Watchpoint};
+use crate::disasm;
+use crate::mem::Access;
+use crate::nes::{DOTS_PER_SCANLINE, Nes, Register, SCANLINES_PER_FRAME};
+use crate::stats;
+use crate::symbols::Symbols;
+use crate::watch::{Watch, WatchList};
+
+// Machine-language monitor. Addresses and values are bare hex, as in
+// `m 0000 00ff` or `r a=ff`; assembler operands use the usual `$` syntax.
+// Anywhere an address is expected, a loaded symbol name also works.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CdlAction {
+    Start,
+    Stop,
+    Save(String),
+    Summary,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Memory { start: u16, end: u16 },              // m <start> [end]
+    Disassemble { start: Option<u16> },           // d [addr]
+    Assemble { addr: u16, source: String },       // a <addr> <instruction>
+    Registers,                                    // r
+    SetRegisters(Vec<(Register, u16)>),           // r a=ff x=10 ...
+    Go(Option<u16>),                              // g [addr]
+    Reset { hard: bool },                         // reset [hard]
+    Step(u32),                                    // s [count]
+    StepOut,                                      // so
+    Backtrace,                                    // bt
+    Events(usize),                                // ev [count]
+    Breakpoint(Option<u16>),                      // bp [addr]
+    ClearBreakpoint(u16),                         // bc <addr>
+    Watchpoint(Option<Watchpoint>),               // wp [<addr>[..<end>] [r|w|rw]]
+    ClearWatchpoint(u16),                         // wpc <addr>
+    Save { path: String, start: u16, end: u16 },  // save <file> <start> <end>
+    Load { path: String, addr: u16 },             // load <file> <addr>
+    LoadSymbols(String),                          // sym <file.nl>
+    Cdl(CdlAction),                               // cdl [start|stop|save <file>]
+    Oam(Option<String>),                          // oam [file.ppm]
+    CapturePpuFrame,                              // pe
+    SavePpuCapture(String),                       // pe save <file.ppm>
+    Stats(Option<bool>),                          // stats [on|off]
+    Watches,                                      // w
+    AddWatch(Watch),                              // w <name> = <addr>[..<end>] [format]
+    RemoveWatch(String),                          // wd <name>
+    Help,
+    Quit,
+}
+
+const DISASM_LINES: usize = 16;
+const DUMP_BYTES: u16 = 0x80;
+const EVENT_LINES: usize = 32;
+
+const HELP: &str = "\
+m <start> [end]          hexdump memory
+d [addr]                 disassemble (continues from last address)
+a <addr> <instruction>   assemble one instruction in place
+r                        show registers
+r <reg>=<val> ...        set registers (a, x, y, sp, pc, p)
+g [addr]                 run until a breakpoint
+reset [hard]             press Reset (RAM kept), or power cycle
+s [count]                step instructions
+so                       run until the current subroutine returns
+bt                       show the call stack
+ev [count]               show the newest event log entries
+bp [addr]                add a breakpoint, or list them (alias: break)
+bc <addr>                clear a breakpoint
+wp [<addr>[..<end>] [r|w|rw]]  stop when memory is read/written, or list watchpoints
+wpc <addr>               clear the watchpoints starting at addr
+save <file> <start> <end>  write memory to a file
+load <file> <addr>       read a file into memory
+sym <file.nl>            load an FCEUX label file
+cdl [start|stop]         start/stop the code/data logger, or show its summary
+cdl save <file>          write the .cdl file
+oam [file.ppm]           list sprites, or save their bounding boxes as an image
+pe                       run through the next frame, logging PPU register accesses
+pe save <file.ppm>       save the captured accesses as a 341x262 image
+stats [on|off]           show emulation speed, or turn measuring on/off
+w                        show watches
+w <name> = <addr>[..<end>] [hex|dec|word|bcd]  add or replace a watch
+wd <name>                delete a watch
+q                        quit";
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value '{}'", text))
+}
+
+// Hex address or symbol name
+fn parse_addr(text: &str, symbols: &Symbols) -> Result<u16, String> {
+    parse_hex(text).or_else(|err| symbols.address_of(text).ok_or(err))
+}
+
+fn parse_register(name: &str) -> Result<Register, String> {
+    match name.to_ascii_lowercase().as_str() {
+        "a" => Ok(Register::A),
+        "x" => Ok(Register::X),
+        "y" => Ok(Register::Y),
+        "sp" | "s" => Ok(Register::Sp),
+        "pc" => Ok(Register::Pc),
+        "p" | "status" => Ok(Register::Status),
+        _ => Err(format!("unknown register '{}'", name)),
+    }
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        Self::parse_with_symbols(line, &Symbols::new())
+    }
+
+    pub fn parse_with_symbols(line: &str, symbols: &Symbols) -> Result<Command, String> {
+        let mut words = line.split_whitespace();
+        let Some(name) = words.next() else {
+            return Err("empty command".to_string());
+        };
+        let args: Vec<&str> = words.collect();
+
+        let arg = |i: usize| -> Result<&str, String> {
+            args.get(i).copied().ok_or_else(|| format!("'{}' needs more arguments", name))
+        };
+        let hex_arg = |i: usize| -> Result<u16, String> { parse_addr(arg(i)?, symbols) };
+        let opt_hex_arg = |i: usize| -> Result<Option<u16>, String> {
+            args.get(i).map(|a| parse_addr(a, symbols)).transpose()
+        };
+
+        let command = match name.to_ascii_lowercase().as_str() {
+            "m" => {
+                let start = hex_arg(0)?;
+                let end = opt_hex_arg(1)?
+                    .unwrap_or_else(|| start.saturating_add(DUMP_BYTES - 1));
+                if end < start {
+                    return Err("end address is before start address".to_string());
+                }
+                Command::Memory { start, end }
+            }
+            "d" => Command::Disassemble { start: opt_hex_arg(0)? },
+            "a" => {
+                let addr = hex_arg(0)?;
+                if args.len() < 2 {
+                    return Err("'a' needs an instruction to assemble".to_string());
+                }
+                Command::Assemble { addr, source: args[1..].join(" ") }
+            }
+            "r" if args.is_empty() => Command::Registers,
+            "r" => {
+                let mut assignments = Vec::new();
+                for assignment in &args {
+                    let (reg, value) = assignment
+                        .split_once('=')
+                        .ok_or_else(|| format!("expected <reg>=<value>, got '{}'", assignment))?;
+                    assignments.push((parse_register(reg)?, parse_hex(value)?));
+                }
+                Command::SetRegisters(assignments)
+            }
+            "g" => Command::Go(opt_hex_arg(0)?),
+            "reset" => match args.first().copied() {
+                None => Command::Reset { hard: false },
+                Some("hard") => Command::Reset { hard: true },
+                Some(other) => return Err(format!("unknown reset kind '{}'", other)),
+            },
+            "s" | "t" => {
+                let count = match args.first() {
+                    Some(n) => n.parse::<u32>().map_err(|_| format!("invalid step count '{}'", n))?,
+                    None => 1,
+                };
+                Command::Step(count)
+            }
+            "so" => Command::StepOut,
+            "bt" => Command::Backtrace,
+            "ev" => {
+                let count = match args.first() {
+                    Some(n) => n.parse::<usize>().map_err(|_| format!("invalid event count '{}'", n))?,
+                    None => EVENT_LINES,
+                };
+                Command::Events(count)
+            }
+            "bp" | "break" => Command::Breakpoint(opt_hex_arg(0)?),
+            "bc" => Command::ClearBreakpoint(hex_arg(0)?),
+            "wp" if args.is_empty() => Command::Watchpoint(None),
+            "wp" => {
+                let (start, end) = match arg(0)?.split_once("..") {
+                    Some((start, end)) => (parse_addr(start, symbols)?, parse_addr(end, symbols)?),
+                    None => {
+                        let addr = hex_arg(0)?;
+                        (addr, addr)
+                    }
+                };
+                if end < start {
+                    return Err("end address is before start address".to_string());
+                }
+                let kind = args.get(1).map(|k| WatchKind::parse(k)).transpose()?;
+                let kind = kind.unwrap_or(WatchKind::ReadWrite);
+                Command::Watchpoint(Some(Watchpoint { start, end, kind }))
+            }
+            "wpc" => Command::ClearWatchpoint(hex_arg(0)?),
+            "save" => Command::Save {
+                path: arg(0)?.to_string(),
+                start: hex_arg(1)?,
+                end: hex_arg(2)?,
+            },
+            "load" => Command::Load {
+                path: arg(0)?.to_string(),
+                addr: hex_arg(1)?,
+            },
+            "sym" => Command::LoadSymbols(arg(0)?.to_string()),
+            "cdl" => match args.first().copied() {
+                None => Command::Cdl(CdlAction::Summary),
+                Some("start") => Command::Cdl(CdlAction::Start),
+                Some("stop") => Command::Cdl(CdlAction::Stop),
+                Some("save") => Command::Cdl(CdlAction::Save(arg(1)?.to_string())),
+                Some(other) => return Err(format!("unknown cdl action '{}'", other)),
+            },
+            "oam" => Command::Oam(args.first().map(|path| path.to_string())),
+            "pe" => match args.first().copied() {
+                None => Command::CapturePpuFrame,
+                Some("save") => Command::SavePpuCapture(arg(1)?.to_string()),
+                Some(other) => return Err(format!("unknown pe action '{}'", other)),
+            },
+            "stats" => match args.first().copied() {
+                None => Command::Stats(None),
+                Some("on") => Command::Stats(Some(true)),
+                Some("off") => Command::Stats(Some(false)),
+                Some(other) => return Err(format!("unknown stats action '{}'", other)),
+            },
+            "w" if args.is_empty() => Command::Watches,
+            "w" => Command::AddWatch(Watch::parse(&args.join(" "), symbols)?),
+            "wd" => Command::RemoveWatch(arg(0)?.to_string()),
+            "h" | "help" | "?" => Command::Help,
+            "q" | "quit" | "x" => Command::Quit,
+            other => return Err(format!("unknown command '{}'", other)),
+        };
+        Ok(command)
+    }
+}
+
+pub struct Monitor {
+    pub debugger: Debugger,
+    // Upper bound on instructions for `g`, so a headless session can't hang
+    pub run_limit: u64,
+    pub watches: WatchList,
+    // Where watch changes are saved, normally the ROM's .watch sidecar
+    pub watch_file: Option<PathBuf>,
+    next_disasm: Option<u16>,
+}
+
+impl Monitor {
+    pub fn new() -> Self {
+        Self {
+            debugger: Debugger::new(),
+            run_limit: 10_000_000,
+            watches: WatchList::new(),
+            watch_file: None,
+            next_disasm: None,
+        }
+    }
+
+    fn save_watches(&self) -> Result<(), String> {
+        match &self.watch_file {
+            Some(path) => self.watches.save(path),
+            None => Ok(()),
+        }
+    }
+
+    // Parse and execute one input line, returning the text to display
+    pub fn execute_line(&mut self, nes: &mut Nes, line: &str) -> Result<String, String> {
+        let command = Command::parse_with_symbols(line, &self.debugger.symbols)?;
+        self.execute(nes, command)
+    }
+
+    pub fn execute(&mut self, nes: &mut Nes, command: Command) -> Result<String, String> {
+        match command {
+            Command::Memory { start, end } => Ok(hexdump(nes, start, end)),
+
+            Command::Disassemble { start } => {
+                let start = start.or(self.next_disasm).unwrap_or(nes.cpu.pc);
+                let lines = disasm::disassemble_with_symbols(
+                    &nes.memory,
+                    start,
+                    DISASM_LINES,
+                    &self.debugger.symbols,
+                );
+                self.next_disasm = lines.last().map(|l| l.next_addr());
+                Ok(lines.iter().map(|l| l.to_string()).collect::<Vec<_>>().join("\n"))
+            }
+
+            Command::Assemble { addr, source } => {
+                let bytes = asm::assemble_line(addr, &source)?;
+                for (i, byte) in bytes.iter().enumerate() {
+                    nes.poke(addr.wrapping_add(i as u16), *byte);
+                }
+                Ok(disasm::disassemble_one(&nes.memory, addr).to_string())
+            }
+
+            Command::Registers => Ok(registers(nes)),
+
+            Command::SetRegisters(assignments) => {
+                for (register, value) in assignments {
+                    nes.set_cpu_register(register, value);
+                }
+                Ok(registers(nes))
+            }
+
+            Command::Go(addr) => {
+                if let Some(addr) = addr {
+                    nes.cpu.pc = addr;
+                }
+                let reason = self.debugger.run(nes, self.run_limit);
+                Ok(format!("{}\n{}", self.describe_stop(reason), self.current_line(nes)))
+            }
+
+            Command::StepOut => {
+                let reason = self.debugger.step_out(nes, self.run_limit);
+                Ok(format!("{}\n{}", self.describe_stop(reason), self.current_line(nes)))
+            }
+
+            Command::Backtrace => {
+                let frames = self.debugger.call_stack();
+                if frames.is_empty() {
+                    return Ok("Call stack is empty".to_string());
+                }
+                let lines: Vec<String> = frames
+                    .iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(i, frame)| format!("#{} {}", i, frame))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+
+            Command::Step(count) => {
+                for _ in 0..count {
+                    if let Some(hit) = self.debugger.step(nes) {
+                        return Ok(format!("{}\n{}", self.describe_stop(hit), self.current_line(nes)));
+                    }
+                }
+                Ok(self.current_line(nes))
+            }
+
+            Command::Events(count) => match nes.event_log() {
+                Some(log) if log.is_empty() => Ok("Event log is empty".to_string()),
+                Some(log) => Ok(log.dump(count)),
+                None => Err("event log is not enabled".to_string()),
+            },
+
+            Command::Breakpoint(Some(addr)) => {
+                self.debugger.add_breakpoint(addr);
+                Ok(format!("Breakpoint set at {}", self.describe_addr(addr)))
+            }
+
+            Command::Breakpoint(None) => {
+                let list: Vec<String> = self
+                    .debugger
+                    .breakpoints()
+                    .map(|addr| self.describe_addr(addr))
+                    .collect();
+                if list.is_empty() {
+                    Ok("No breakpoints".to_string())
+                } else {
+                    Ok(list.join("\n"))
+                }
+            }
+
+            Command::ClearBreakpoint(addr) => {
+                if self.debugger.remove_breakpoint(addr) {
+                    Ok(format!("Breakpoint cleared at ${:04X}", addr))
+                } else {
+                    Err(format!("no breakpoint at ${:04X}", addr))
+                }
+            }
+
+            Command::Watchpoint(Some(watchpoint)) => {
+                self.debugger.add_watchpoint(watchpoint.start..=watchpoint.end, watchpoint.kind);
+                Ok(format!("Watchpoint set on {}", watchpoint))
+            }
+
+            Command::Watchpoint(None) => {
+                let list: Vec<String> = self.debugger.watchpoints().iter().map(|w| w.to_string()).collect();
+                if list.is_empty() {
+                    Ok("No watchpoints".to_string())
+                } else {
+                    Ok(list.join("\n"))
+                }
+            }
+
+            Command::ClearWatchpoint(addr) => {
+                if self.debugger.remove_watchpoint(addr) {
+                    Ok(format!("Watchpoint cleared at ${:04X}", addr))
+                } else {
+                    Err(format!("no watchpoint at ${:04X}", addr))
+                }
+            }
+
+            Command::Save { path, start, end } => {
+                if end < start {
+                    return Err("end address is before start address".to_string());
+                }
+                let data: Vec<u8> = (start..=end).map(|addr| nes.memory.peek(addr)).collect();
+                fs::write(&path, &data).map_err(|e| format!("failed to write {}: {}", path, e))?;
+                Ok(format!("Saved ${:04X}-${:04X} ({} bytes) to {}", start, end, data.len(), path))
+            }
+
+            Command::Load { path, addr } => {
+                let data = fs::read(&path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+                for (i, byte) in data.iter().enumerate() {
+                    nes.poke(addr.wrapping_add(i as u16), *byte);
+                }
+                Ok(format!("Loaded {} bytes at ${:04X} from {}", data.len(), addr, path))
+            }
+
+            Command::LoadSymbols(path) => {
+                self.debugger.symbols.load_fceux_nl(Path::new(&path))?;
+                Ok(format!("Loaded symbols from {}", path))
+            }
+
+            Command::Cdl(action) => match action {
+                CdlAction::Start => {
+                    nes.start_cdl();
+                    Ok("Code/data logger started".to_string())
+                }
+                CdlAction::Stop => {
+                    nes.stop_cdl();
+                    Ok("Code/data logger stopped".to_string())
+                }
+                CdlAction::Save(path) => {
+                    nes.save_cdl(Path::new(&path)).map_err(|e| format!("failed to save CDL: {}", e))?;
+                    Ok(format!("Saved CDL to {}", path))
+                }
+                CdlAction::Summary => match nes.cdl() {
+                    Some(cdl) => Ok(cdl.summary().to_string()),
+                    None => Err("code/data logger was never started".to_string()),
+                },
+            },
+
+            Command::Oam(None) => {
+                let lines: Vec<String> = debug::oam_entries(&nes.memory)
+                    .iter()
+                    .map(|entry| entry.to_string())
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+
+            Command::Oam(Some(path)) => {
+                let image = debug::sprite_overlay(&nes.memory);
+                debug::write_ppm(Path::new(&path), debug::SCREEN_WIDTH, debug::SCREEN_HEIGHT, &image)
+                    .map_err(|e| format!("failed to write {}: {}", path, e))?;
+                Ok(format!("Saved sprite overlay to {}", path))
+            }
+
+            Command::CapturePpuFrame => {
+                nes.capture_ppu_frame();
+                let mut steps = 0;
+                while !nes.ppu_capture().is_some_and(|c| c.is_complete()) {
+                    if steps == self.run_limit {
+                        return Err("frame did not finish within the run limit".to_string());
+                    }
+                    self.debugger.step(nes);
+                    steps += 1;
+                }
+                let table = nes.ppu_capture().map(|c| c.table()).unwrap_or_default();
+                Ok(format!("{}{}", table, self.current_line(nes)))
+            }
+
+            Command::SavePpuCapture(path) => {
+                let capture = nes.ppu_capture().ok_or("no PPU frame has been captured")?;
+                let (width, height) = (DOTS_PER_SCANLINE as usize, SCANLINES_PER_FRAME as usize);
+                debug::write_ppm(Path::new(&path), width, height, &capture.grid_image())
+                    .map_err(|e| format!("failed to write {}: {}", path, e))?;
+                Ok(format!("Saved PPU event grid to {}", path))
+            }
+
+            Command::Stats(None) => match nes.stats() {
+                Some(stats) => Ok(stats
+                    .summary()
+                    .map_or("No frames measured yet".to_string(), |summary| summary.to_string())),
+                None => Err("stats are off (stats on)".to_string()),
+            },
+
+            Command::Stats(Some(on)) => {
+                if on {
+                    nes.enable_stats(stats::DEFAULT_WINDOW);
+                    Ok("Stats on".to_string())
+                } else {
+                    nes.disable_stats();
+                    Ok("Stats off".to_string())
+                }
+            }
+
+            Command::Watches => {
+                if self.watches.is_empty() {
+                    return Ok("No watches".to_string());
+                }
+                let width = self.watches.watches().iter().map(|w| w.name.len()).max().unwrap_or(0);
+                let lines: Vec<String> = self
+                    .watches
+                    .evaluate(&nes.memory)
+                    .into_iter()
+                    .map(|(name, value)| format!("{:<width$}  {}", name, value, width = width))
+                    .collect();
+                Ok(lines.join("\n"))
+            }
+
+            Command::AddWatch(watch) => {
+                let text = format!("{} = {}", watch.name, watch.value(&nes.memory));
+                self.watches.add(watch);
+                self.save_watches()?;
+                Ok(text)
+            }
+
+            Command::RemoveWatch(name) => {
+                if !self.watches.remove(&name) {
+                    return Err(format!("no watch named '{}'", name));
+                }
+                self.save_watches()?;
+                Ok(format!("Removed watch {}", name))
+            }
+
+            Command::Reset { hard } => {
+                if hard {
+                    nes.power_cycle();
+                } else {
+                    nes.soft_reset();
+                }
+                Ok(self.current_line(nes))
+            }
+
+            Command::Help => Ok(HELP.to_string()),
+
+            Command::Quit => Ok(String::new()),
+        }
+    }
+
+    fn describe_stop(&self, reason: StopReason) -> String {
+        match reason {
+            StopReason::Breakpoint(addr) => format!("Breakpoint at {}", self.describe_addr(addr)),
+            StopReason::StepOut => "Returned".to_string(),
+            StopReason::MaxInstructions => format!("Stopped after {} instructions", self.run_limit),
+            StopReason::Error(err) => format!("Stopped: {}", err),
+            StopReason::Jammed(addr) => format!("CPU jammed at {}", self.describe_addr(addr)),
+            StopReason::Watchpoint { addr, kind, value, pc } => {
+                let (verb, preposition) = match kind {
+                    Access::Read => ("Read", "from"),
+                    Access::Write => ("Wrote", "to"),
+                };
+                format!(
+                    "{} ${:02X} {} {} at {}",
+                    verb,
+                    value,
+                    preposition,
+                    self.describe_addr(addr),
+                    self.describe_addr(pc)
+                )
+            }
+        }
+    }
+
+    // `$C123 (reset_handler)` when the address has a symbol
+    fn describe_addr(&self, addr: u16) -> String {
+        match self.debugger.symbols.lookup(addr) {
+            Some(symbol) => format!("${:04X} ({})", addr, symbol.name),
+            None => format!("${:04X}", addr),
+        }
+    }
+
+    // Registers plus the instruction about to execute
+    fn current_line(&mut self, nes: &Nes) -> String {
+        self.next_disasm = None;
+        let mut line = disasm::disassemble_one(&nes.memory, nes.cpu.pc);
+        line.apply_symbols(&self.debugger.symbols);
+        format!("{}\n{}", registers(nes), line)
+    }
+}
+
+impl Default for Monitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn registers(nes: &Nes) -> String {
+    let cpu = &nes.cpu;
+    let flags: String = "NV-BDIZC"
+        .chars()
+        .enumerate()
+        .map(|(i, name)| if cpu.status & (0x80 >> i) != 0 { name } else { '.' })
+        .collect();
+    format!(
+        "PC: {:04X}, A: {:02X}, X: {:02X}, Y: {:02X}, SP: {:02X}, P: {:02X} [{}]",
+        cpu.pc, cpu.a, cpu.x, cpu.y, cpu.sp, cpu.status, flags
+    )
+}
+
+pub fn hexdump(nes: &Nes, start: u16, end: u16) -> String {
+    let mut lines = Vec::new();
+    let mut row = start;
+    loop {
+        let row_end = row.saturating_add(15).min(end);
+        let bytes: Vec<u8> = (row..=row_end).map(|addr| nes.memory.peek(addr)).collect();
+        let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+        let ascii: String = bytes
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        lines.push(format!("{:04X}  {:<47}  {}", row, hex.join(" "), ascii));
+
+        if row_end == end {
+            break;
+        }
+        row = row_end + 1;
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+
+    #[test]
+    fn parses_commands_with_and_without_arguments() {
+        assert_eq!(Command::parse("m c000"), Ok(Command::Memory { start: 0xC000, end: 0xC07F }));
+        assert_eq!(Command::parse("m $10 1f"), Ok(Command::Memory { start: 0x10, end: 0x1F }));
+        assert_eq!(Command::parse("d"), Ok(Command::Disassemble { start: None }));
+        assert_eq!(
+            Command::parse("a 0600 lda #$01"),
+            Ok(Command::Assemble { addr: 0x0600, source: "lda #$01".to_string() })
+        );
+        assert_eq!(Command::parse("R"), Ok(Command::Registers));
+        assert_eq!(
+            Command::parse("r a=ff pc=c000"),
+            Ok(Command::SetRegisters(vec![(Register::A, 0xFF), (Register::Pc, 0xC000)]))
+        );
+        assert_eq!(Command::parse("s"), Ok(Command::Step(1)));
+        assert_eq!(Command::parse("t 20"), Ok(Command::Step(20)));
+        assert_eq!(Command::parse("break 8000"), Ok(Command::Breakpoint(Some(0x8000))));
+        assert_eq!(
+            Command::parse("wp 2000..2007 w"),
+            Ok(Command::Watchpoint(Some(Watchpoint { start: 0x2000, end: 0x2007, kind: WatchKind::Write })))
+        );
+        assert_eq!(Command::parse("reset hard"), Ok(Command::Reset { hard: true }));
+        assert_eq!(Command::parse("stats off"), Ok(Command::Stats(Some(false))));
+        assert_eq!(Command::parse("w"), Ok(Command::Watches));
+        assert_eq!(Command::parse("  q  "), Ok(Command::Quit));
+    }
+
+    #[test]
+    fn rejects_bad_hex() {
+        assert_eq!(Command::parse("m xyz"), Err("invalid hex value 'xyz'".to_string()));
+        assert_eq!(Command::parse("bp 10000"), Err("invalid hex value '10000'".to_string()));
+        assert_eq!(Command::parse("r a=zz"), Err("invalid hex value 'zz'".to_string()));
+        assert_eq!(Command::parse("m 20 10"), Err("end address is before start address".to_string()));
+        assert_eq!(Command::parse("s many"), Err("invalid step count 'many'".to_string()));
+    }
+
+    #[test]
+    fn rejects_missing_arguments() {
+        assert_eq!(Command::parse("m"), Err("'m' needs more arguments".to_string()));
+        assert_eq!(Command::parse("bc"), Err("'bc' needs more arguments".to_string()));
+        assert_eq!(Command::parse("save out.bin 0"), Err("'save' needs more arguments".to_string()));
+        assert_eq!(Command::parse("a 0600"), Err("'a' needs an instruction to assemble".to_string()));
+        assert_eq!(Command::parse(""), Err("empty command".to_string()));
+        assert_eq!(Command::parse("frobnicate"), Err("unknown command 'frobnicate'".to_string()));
+    }
+
+    // A session as someone would type it: stop in a loop, look around,
+    // patch an instruction and step over it
+    #[test]
+    fn runs_a_command_script_against_a_console() {
+        let source = "
+            ldx #0
+     loop:  inx
+            stx $10
+            cpx #5
+            bne loop
+     done:  lda #$42
+     spin:  jmp spin";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        let mut monitor = Monitor::new();
+        let mut run = |line: &str| monitor.execute_line(&mut nes, line);
+
+        assert_eq!(run("bp c009"), Ok("Breakpoint set at $C009".to_string()));
+        assert!(run("g").unwrap().starts_with("Breakpoint at $C009\nPC: C009, A: 00, X: 05"));
+        assert!(run("m 10 10").unwrap().starts_with("0010  05"));
+        assert!(run("r a=7f").unwrap().starts_with("PC: C009, A: 7F"));
+        assert!(run("a c009 lda #$55").unwrap().contains("LDA #$55"));
+        assert!(run("s").unwrap().starts_with("PC: C00B, A: 55"));
+        assert_eq!(run("bc c009"), Ok("Breakpoint cleared at $C009".to_string()));
+        assert_eq!(run("bc c009"), Err("no breakpoint at $C009".to_string()));
+        assert_eq!(run("bp"), Ok("No breakpoints".to_string()));
+    }
+}
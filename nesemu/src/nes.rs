@@ -0,0 +1,1183 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+
+use crate::cdl::CodeDataLogger;
+use crate::coverage::Coverage;
+use crate::crashdump;
+use crate::cpu::{self, CpuError, Interrupt};
+use crate::error::NesError;
+use crate::eventlog::{Event, EventKind, EventLog, EventLogConfig, IrqSource};
+use crate::eventstream::{EventStream, StreamEvent};
+use crate::input::{ButtonState, InputConfig, InputProvider, PortDevice};
+use crate::mem;
+use crate::opcodes;
+use crate::opstats::OpcodeStats;
+use crate::ppuevents::{self, PpuCapture};
+use crate::rom;
+use crate::stats::Stats;
+use crate::trace::TraceLogger;
+use crate::unknownops::UnknownOpcodeReport;
+use crate::watchdog::{Stuck, Watchdog, WatchdogConfig};
+
+// Addresses of the most recently executed instructions kept for crash dumps
+pub const HISTORY_LEN: usize = 64;
+
+// NTSC PPU timing: 3 dots per CPU cycle, 341 dots per scanline, 262 scanlines
+pub const DOTS_PER_CPU_CYCLE: u64 = 3;
+pub const DOTS_PER_SCANLINE: u64 = 341;
+pub const SCANLINES_PER_FRAME: u64 = 262;
+pub const DOTS_PER_FRAME: u64 = DOTS_PER_SCANLINE * SCANLINES_PER_FRAME;
+// Vblank (and the NMI) starts at dot 1 of the scanline after the visible 240
+pub const VBLANK_SCANLINE: u64 = 241;
+const VBLANK_DOT: u64 = VBLANK_SCANLINE * DOTS_PER_SCANLINE + 1;
+// ...and ends at dot 1 of the pre-render scanline
+const VBLANK_END_DOT: u64 = (SCANLINES_PER_FRAME - 1) * DOTS_PER_SCANLINE + 1;
+
+const BRK: u8 = 0x00;
+// BRK fetches its vector in cycles 5 and 6; an NMI by then hijacks it
+const BRK_NMI_HIJACK_CYCLES: u64 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    X,
+    Y,
+    Sp,
+    Pc,
+    Status,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuPosition {
+    pub frame: u64,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+// Passed to the frame callback when a frame completes. There is no PPU yet,
+// so this carries timing only, not pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    pub number: u64, // The frame that just completed
+    pub cycles: u64, // CPU cycle count at the point it completed
+}
+
+// What step_frame() did. There are no pixels to hand out until there is a
+// PPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameResult {
+    pub frame: u64,  // The frame whose vblank was just entered
+    pub cycles: u64, // CPU cycles the call consumed
+    pub nmi: bool,   // Vblank raised an NMI; the next step takes it
+}
+
+// Frame callbacks run in the middle of step() with the console borrowed, so
+// they cannot call back into it; hand data out through captured state instead
+pub type FrameCallback = Box<dyn FnMut(&Frame) + Send>;
+
+// Top-level console: owns the CPU and its memory map
+pub struct Nes {
+    pub cpu: cpu::Cpu,
+    pub memory: mem::Memory,
+    rom_info: rom::RomInfo,
+    save_path: Option<PathBuf>, // Where battery-backed PRG-RAM is kept
+    history: [u16; HISTORY_LEN],
+    history_pos: usize, // Next slot to write
+    history_len: usize,
+    event_log: Option<EventLog>,
+    cdl: Option<CodeDataLogger>,
+    coverage: Option<Coverage>,
+    opcode_stats: Option<OpcodeStats>,
+    ppu_capture: Option<PpuCapture>,
+    event_stream: Option<EventStream>,
+    frame_callback: Option<FrameCallback>,
+    input_provider: Option<Box<dyn InputProvider>>,
+    controllers: [ButtonState; 2],
+    input_config: InputConfig,
+    polled_frame: Option<u64>,
+    watchdog: Option<Watchdog>,
+    stats: Option<Stats>,
+    unknown_opcodes: UnknownOpcodeReport,
+    trace: Option<TraceLogger>,
+    writes: Vec<(u16, u8)>, // Reused for each instruction's drained writes
+}
+
+impl Nes {
+    pub fn new(rom: rom::Rom) -> Result<Self, NesError> {
+        let rom_info = rom.info();
+        let cartridge = rom.into_cartridge().map_err(|e| NesError::InvalidRom {
+            path: None,
+            reason: e.to_string(),
+        })?;
+        let mut memory = mem::Memory::new(cartridge);
+        let cpu = cpu::Cpu::power_on(&mut memory);
+
+        Ok(Self {
+            cpu,
+            memory,
+            rom_info,
+            save_path: None,
+            history: [0; HISTORY_LEN],
+            history_pos: 0,
+            history_len: 0,
+            event_log: None,
+            cdl: None,
+            coverage: None,
+            opcode_stats: None,
+            ppu_capture: None,
+            event_stream: None,
+            frame_callback: None,
+            input_provider: None,
+            controllers: [ButtonState::default(); 2],
+            input_config: InputConfig::for_device(rom_info.expansion_device),
+            polled_frame: None,
+            watchdog: None,
+            stats: None,
+            unknown_opcodes: UnknownOpcodeReport::new(),
+            trace: None,
+            writes: Vec::new(),
+        })
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, NesError> {
+        let rom = rom::Rom::from_bytes(data).map_err(|e| NesError::InvalidRom {
+            path: None,
+            reason: e.to_string(),
+        })?;
+        Self::new(rom)
+    }
+
+    // A ROM with a battery gets its PRG-RAM back from <rom>.sav
+    pub fn from_file(path: &Path) -> Result<Self, NesError> {
+        let data = fs::read(path).map_err(|e| NesError::io(path, e))?;
+        let mut nes = Self::from_bytes(&data).map_err(|err| match err {
+            NesError::InvalidRom { reason, .. } => NesError::InvalidRom {
+                path: Some(path.to_path_buf()),
+                reason,
+            },
+            other => other,
+        })?;
+        if nes.memory.cartridge().has_battery() {
+            let save_path = PathBuf::from(format!("{}.sav", path.display()));
+            nes.load_save(&save_path)?;
+            nes.save_path = Some(save_path);
+        }
+        Ok(nes)
+    }
+
+    // No save file yet is a fresh cartridge. One of the wrong size is still
+    // used, cut off or zero-filled to 8 KiB, since it's probably the
+    // player's only copy.
+    fn load_save(&mut self, path: &Path) -> Result<(), NesError> {
+        let data = match fs::read(path) {
+            Ok(data) => data,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(NesError::io(path, e)),
+        };
+        if !self.memory.cartridge_mut().load_prg_ram(&data) {
+            let len = self.memory.cartridge_ram().len();
+            let fix = if data.len() > len { "truncated" } else { "zero-filled" };
+            eprintln!(
+                "warning: {}: {} bytes, expected {}; {}",
+                path.display(),
+                data.len(),
+                len,
+                fix
+            );
+        }
+        Ok(())
+    }
+
+    pub fn save_path(&self) -> Option<&Path> {
+        self.save_path.as_deref()
+    }
+
+    // Write battery-backed PRG-RAM out to the save file. A no-op for
+    // cartridges without a battery or consoles not loaded from a file.
+    pub fn flush(&self) -> Result<(), NesError> {
+        match &self.save_path {
+            Some(path) => fs::write(path, self.memory.cartridge_ram()).map_err(|e| NesError::io(path, e)),
+            None => Ok(()),
+        }
+    }
+
+    // Execute a single instruction
+    pub fn step(&mut self) {
+        let frame = self.ppu_position().frame;
+        if self.polled_frame != Some(frame) {
+            self.polled_frame = Some(frame);
+            if let Some(provider) = &mut self.input_provider {
+                let (port1, port2) = provider.poll(frame);
+                self.controllers = [port1, port2];
+                self.connect_controllers();
+            }
+        }
+
+        self.step_instruction();
+        self.update_nmi_line();
+
+        let completed = self.ppu_position().frame != frame;
+        if let Some(stats) = &mut self.stats
+            && completed
+        {
+            stats.frame_completed(Instant::now());
+            // One summary per full window keeps the stream readable
+            if stats.frames() > 0
+                && stats.frames().is_multiple_of(stats.window() as u64)
+                && let Some(stream) = &mut self.event_stream
+                && let Some(summary) = stats.summary()
+            {
+                stream.emit(self.cpu.cycles, &StreamEvent::Stats(summary));
+            }
+        }
+        if let Some(callback) = &mut self.frame_callback
+            && completed
+        {
+            callback(&Frame {
+                number: frame,
+                cycles: self.cpu.cycles,
+            });
+        }
+    }
+
+    // Run until the current frame completes
+    pub fn run_frame(&mut self) {
+        let frame = self.ppu_position().frame;
+        while self.ppu_position().frame == frame {
+            self.step();
+        }
+    }
+
+    // Run until the PPU next enters vblank, wherever in the frame we start.
+    // Stops on the first instruction boundary at or past that dot, so
+    // back-to-back calls tile the timeline; a call can only come up short
+    // or long by the instruction straddling the boundary.
+    pub fn step_frame(&mut self) -> FrameResult {
+        let start = self.cpu.cycles;
+        let target = next_vblank_dot(start * DOTS_PER_CPU_CYCLE);
+        while self.cpu.cycles * DOTS_PER_CPU_CYCLE < target {
+            self.step();
+        }
+        FrameResult {
+            frame: target / DOTS_PER_FRAME,
+            cycles: self.cpu.cycles - start,
+            nmi: self.cpu.nmi_pending(),
+        }
+    }
+
+    // The console's Reset button: the CPU re-runs the reset vector, memory
+    // keeps its contents (see Memory::soft_reset)
+    pub fn soft_reset(&mut self) {
+        self.memory.soft_reset();
+        self.cpu.reset(&mut self.memory);
+    }
+
+    // Power off and on again. The cycle counter keeps running so frame
+    // numbers and event timestamps stay monotonic across the cycle.
+    pub fn power_cycle(&mut self) {
+        self.memory.reset();
+        let cycles = self.cpu.cycles;
+        self.cpu = cpu::Cpu::power_on(&mut self.memory);
+        self.cpu.cycles += cycles;
+    }
+
+    // There is no audio counterpart: without an APU there are no samples
+    // to hand out
+    pub fn set_frame_callback(&mut self, callback: FrameCallback) {
+        self.frame_callback = Some(callback);
+    }
+
+    pub fn clear_frame_callback(&mut self) {
+        self.frame_callback = None;
+    }
+
+    pub fn rom_info(&self) -> rom::RomInfo {
+        self.rom_info
+    }
+
+    // Addresses of recently executed instructions, oldest first
+    pub fn instruction_history(&self) -> Vec<u16> {
+        let start = (self.history_pos + HISTORY_LEN - self.history_len) % HISTORY_LEN;
+        (0..self.history_len)
+            .map(|i| self.history[(start + i) % HISTORY_LEN])
+            .collect()
+    }
+
+    // Unknown opcodes executed so far, one entry each
+    pub fn unknown_opcode_report(&self) -> &UnknownOpcodeReport {
+        &self.unknown_opcodes
+    }
+
+    // Same CPU and memory state; tooling attached to either side is ignored
+    pub fn state_eq(&self, other: &Nes) -> bool {
+        self.cpu == other.cpu && self.memory == other.memory
+    }
+
+    // First difference between the two consoles, e.g.
+    // "memory.cpu_ram[$10]: $21 vs $22"
+    pub fn state_diff(&self, other: &Nes) -> Option<String> {
+        if let Some(diff) = self.cpu.diff(&other.cpu) {
+            return Some(format!("cpu.{}", diff));
+        }
+        self.memory.diff(&other.memory).map(|diff| format!("memory.{}", diff))
+    }
+
+    // Post-mortem text dump; see crashdump.rs for the format
+    pub fn write_crash_dump(&self, path: &Path, reason: &str) -> Result<(), NesError> {
+        fs::write(path, crashdump::crash_dump(self, reason)).map_err(|e| NesError::io(path, e))
+    }
+
+    // Without a provider, the buttons set here stay held until changed
+    pub fn set_controller(&mut self, port: usize, buttons: ButtonState) {
+        self.controllers[port] = buttons;
+        self.connect_controllers();
+    }
+
+    pub fn controller(&self, port: usize) -> ButtonState {
+        self.controllers[port]
+    }
+
+    // Devices in the controller ports, from the header unless overridden
+    pub fn input_config(&self) -> InputConfig {
+        self.input_config
+    }
+
+    pub fn set_input_config(&mut self, config: InputConfig) {
+        self.input_config = config;
+        self.connect_controllers();
+    }
+
+    // Hand the buttons to the pads in the ports. A Zapper doesn't drive the
+    // serial bit, so its port reads as a pad with nothing pressed.
+    fn connect_controllers(&mut self) {
+        for port in 0..2 {
+            let buttons = match self.input_config.ports[port] {
+                PortDevice::Controller => self.controllers[port].to_bits(),
+                PortDevice::Zapper => 0,
+            };
+            self.memory.set_controller_state(port, buttons);
+        }
+    }
+
+    // Replaces set_controller as the input source, polled once per frame
+    pub fn set_input_provider(&mut self, provider: Box<dyn InputProvider>) {
+        self.input_provider = Some(provider);
+    }
+
+    pub fn take_input_provider(&mut self) -> Option<Box<dyn InputProvider>> {
+        self.input_provider.take()
+    }
+
+    fn step_instruction(&mut self) {
+        let pc = self.cpu.pc;
+        let opcode = self.memory.peek(pc);
+        // A pending interrupt is taken instead of the instruction at PC, and
+        // a jammed CPU runs nothing, so the per-instruction tooling skips
+        // those steps
+        let interrupt = self.cpu.pending_interrupt(&self.memory);
+        let executes = interrupt.is_none() && !self.cpu.is_halted();
+        if executes {
+            self.observe_instruction(pc, opcode);
+        }
+        if executes && opcode == BRK {
+            let level = self.nmi_line_at(self.cpu.cycles + BRK_NMI_HIJACK_CYCLES);
+            self.cpu.set_nmi_line_after_poll(level);
+        }
+
+        let capturing = executes && self.ppu_capture.as_ref().is_some_and(|c| !c.is_complete());
+        let ppu_read = if capturing {
+            ppuevents::register_read(&self.cpu, &self.memory)
+        } else {
+            None
+        };
+
+        // The tooling above reads memory too; watchpoints only want the CPU's
+        // own accesses
+        self.memory.clear_accesses();
+        if let Err(CpuError::UnknownOpcode { opcode, pc }) = self.cpu.exec_next_instr(&mut self.memory) {
+            self.record_unknown_opcode(pc, opcode);
+            // Skipped like a one-byte NOP so the program can carry on
+            self.cpu.pc = pc.wrapping_add(1);
+        }
+
+        let mut writes = std::mem::take(&mut self.writes);
+        self.memory.drain_writes(&mut writes);
+        if self.event_log.is_some() {
+            self.log_events(pc, opcode, interrupt, &writes);
+        }
+        if self.event_stream.is_some() {
+            let frame = self.ppu_position().frame;
+            if let Some(stream) = &mut self.event_stream {
+                stream.observe(self.cpu.cycles, frame, &self.memory, &writes);
+            }
+        }
+        if self.watchdog.is_some() {
+            let frame = self.ppu_position().frame;
+            if let Some(watchdog) = &mut self.watchdog {
+                let known = interrupt.is_some() || opcodes::lookup(opcode).is_some();
+                watchdog.observe(&self.cpu, pc, frame, known, !writes.is_empty());
+            }
+        }
+        if capturing {
+            let position = self.ppu_position();
+            if let Some(capture) = &mut self.ppu_capture {
+                capture.observe(position, pc, ppu_read, &writes);
+                if capture.is_complete() {
+                    self.update_write_recording();
+                }
+            }
+        }
+        self.writes = writes;
+    }
+
+    // The PPU holds /NMI low through vblank while PPUCTRL bit 7 is set, so
+    // setting the bit mid-vblank raises another NMI
+    fn update_nmi_line(&mut self) {
+        self.cpu.set_nmi_line(self.nmi_line_at(self.cpu.cycles));
+    }
+
+    fn nmi_line_at(&self, cycle: u64) -> bool {
+        let dot = cycle * DOTS_PER_CPU_CYCLE % DOTS_PER_FRAME;
+        let vblank = (VBLANK_DOT..VBLANK_END_DOT).contains(&dot);
+        vblank && self.memory.ppu_ctrl() & 0x80 != 0
+    }
+
+    // History, trace and the loggers see each instruction before it runs
+    fn observe_instruction(&mut self, pc: u16, opcode: u8) {
+        self.history[self.history_pos] = pc;
+        self.history_pos = (self.history_pos + 1) % HISTORY_LEN;
+        self.history_len = (self.history_len + 1).min(HISTORY_LEN);
+
+        if let Some(trace) = &mut self.trace {
+            trace.log(&self.cpu, &self.memory);
+        }
+        if let Some(cdl) = &mut self.cdl {
+            cdl.log_instruction(&self.cpu, &self.memory);
+        }
+        if let Some(coverage) = &mut self.coverage {
+            coverage.log_instruction(&self.memory, pc);
+        }
+        if let Some(stats) = &mut self.opcode_stats {
+            stats.record(opcode);
+        }
+    }
+
+    fn record_unknown_opcode(&mut self, pc: u16, opcode: u8) {
+        let mut report = std::mem::take(&mut self.unknown_opcodes);
+        report.record(&self.memory, pc, self.cpu.cycles, || self.instruction_history());
+        self.unknown_opcodes = report;
+        if let Some(stats) = &mut self.opcode_stats {
+            stats.record_unknown(opcode);
+        }
+        if let Some(stream) = &mut self.event_stream {
+            stream.emit(self.cpu.cycles, &StreamEvent::UnknownOpcode { pc, opcode });
+        }
+    }
+
+    // Write recording is only paid for while something is watching
+    fn update_write_recording(&mut self) {
+        let capturing = self.ppu_capture.as_ref().is_some_and(|c| !c.is_complete());
+        let recording =
+            self.event_log.is_some() || self.event_stream.is_some() || self.watchdog.is_some() || capturing;
+        self.memory.record_writes(recording);
+    }
+
+    // Write memory for a debugger without side effects; see Memory::poke
+    // for how ROM addresses are handled
+    pub fn poke(&mut self, addr: u16, value: u8) {
+        self.memory.poke(addr, value);
+    }
+
+    // Write memory exactly as the CPU would, register side effects included
+    pub fn write_bus(&mut self, addr: u16, value: u8) {
+        self.memory.write(addr, value);
+    }
+
+    // Sprite memory without going through $2003/$2004, so OAMADDR is left
+    // alone
+    pub fn read_oam(&self, index: u8) -> u8 {
+        self.memory.oam()[index as usize]
+    }
+
+    pub fn write_oam(&mut self, index: u8, value: u8) {
+        self.memory.poke_oam(index, value);
+    }
+
+    // PPU memory ($0000-$3FFF) as $2007 would reach it, through the
+    // cartridge's CHR and nametable mirroring, but leaving the address,
+    // toggle and read buffer alone
+    pub fn read_vram(&self, addr: u16) -> u8 {
+        self.memory.peek_vram(addr)
+    }
+
+    pub fn write_vram(&mut self, addr: u16, value: u8) {
+        self.memory.poke_vram(addr, value);
+    }
+
+    // Palette entries $00-$1F; $10/$14/$18/$1C are $00/$04/$08/$0C
+    pub fn read_palette(&self, index: u8) -> u8 {
+        self.memory.peek_palette(index)
+    }
+
+    pub fn write_palette(&mut self, index: u8, value: u8) {
+        self.memory.poke_palette(index, value);
+    }
+
+    // 8-bit registers take the low byte of `value`
+    pub fn set_cpu_register(&mut self, register: Register, value: u16) {
+        match register {
+            Register::A => self.cpu.a = value as u8,
+            Register::X => self.cpu.x = value as u8,
+            Register::Y => self.cpu.y = value as u8,
+            Register::Sp => self.cpu.sp = value as u8,
+            Register::Pc => self.cpu.pc = value,
+            Register::Status => self.cpu.status = value as u8,
+        }
+    }
+
+    // Where the PPU would be, derived from the CPU cycle count (the odd-frame
+    // dot skip is not modelled)
+    pub fn ppu_position(&self) -> PpuPosition {
+        let dots = self.cpu.cycles * DOTS_PER_CPU_CYCLE;
+        let scanlines = dots / DOTS_PER_SCANLINE;
+        PpuPosition {
+            frame: scanlines / SCANLINES_PER_FRAME,
+            scanline: (scanlines % SCANLINES_PER_FRAME) as u16,
+            dot: (dots % DOTS_PER_SCANLINE) as u16,
+        }
+    }
+
+    pub fn enable_event_log(&mut self, config: EventLogConfig) {
+        self.event_log = Some(EventLog::new(config));
+        self.update_write_recording();
+    }
+
+    pub fn disable_event_log(&mut self) {
+        self.event_log = None;
+        self.update_write_recording();
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    // Start (or resume) code/data logging; existing results are kept
+    pub fn start_cdl(&mut self) {
+        match &mut self.cdl {
+            Some(cdl) => cdl.logging = true,
+            None => self.cdl = Some(CodeDataLogger::new(self.memory.prg_rom_len())),
+        }
+    }
+
+    pub fn stop_cdl(&mut self) {
+        if let Some(cdl) = &mut self.cdl {
+            cdl.logging = false;
+        }
+    }
+
+    pub fn cdl(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_ref()
+    }
+
+    pub fn save_cdl(&self, path: &Path) -> Result<(), NesError> {
+        match &self.cdl {
+            Some(cdl) => cdl.save(path).map_err(|e| NesError::io(path, e)),
+            None => Err(NesError::NotEnabled("code/data logger")),
+        }
+    }
+
+    pub fn enable_coverage(&mut self) {
+        if self.coverage.is_none() {
+            self.coverage = Some(Coverage::new(self.memory.prg_rom_len()));
+        }
+    }
+
+    // Executed PRG-ROM offsets, if coverage tracking is enabled
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    // Count executed instructions by opcode
+    pub fn enable_opcode_stats(&mut self) {
+        if self.opcode_stats.is_none() {
+            self.opcode_stats = Some(OpcodeStats::new());
+        }
+    }
+
+    pub fn disable_opcode_stats(&mut self) {
+        self.opcode_stats = None;
+    }
+
+    pub fn opcode_stats(&self) -> Option<&OpcodeStats> {
+        self.opcode_stats.as_ref()
+    }
+
+    pub fn set_event_stream(&mut self, stream: EventStream) {
+        self.event_stream = Some(stream);
+        self.update_write_recording();
+    }
+
+    // Detach the stream, e.g. to flush it or check for write errors
+    pub fn take_event_stream(&mut self) -> Option<EventStream> {
+        let stream = self.event_stream.take();
+        self.update_write_recording();
+        stream
+    }
+
+    // Emit a user-defined record, stamped with the current cycle
+    pub fn emit_event(&mut self, event: StreamEvent) {
+        if let Some(stream) = &mut self.event_stream {
+            stream.emit(self.cpu.cycles, &event);
+        }
+    }
+
+    // Watch for a run that will never finish; see watchdog.rs
+    pub fn enable_watchdog(&mut self, config: WatchdogConfig) {
+        self.watchdog = Some(Watchdog::new(config));
+        self.update_write_recording();
+    }
+
+    pub fn disable_watchdog(&mut self) {
+        self.watchdog = None;
+        self.update_write_recording();
+    }
+
+    // Why the watchdog fired, if it has
+    pub fn stuck(&self) -> Option<&Stuck> {
+        self.watchdog.as_ref().and_then(Watchdog::tripped)
+    }
+
+    // Log every instruction (subject to the trace's range and limit)
+    pub fn set_trace(&mut self, trace: TraceLogger) {
+        self.trace = Some(trace);
+    }
+
+    // Detach the trace, e.g. to flush it or check for write errors
+    pub fn take_trace(&mut self) -> Option<TraceLogger> {
+        self.trace.take()
+    }
+
+    // Measure emulation speed over the last `window` frames; see stats.rs
+    pub fn enable_stats(&mut self, window: usize) {
+        self.stats = Some(Stats::new(window));
+    }
+
+    pub fn disable_stats(&mut self) {
+        self.stats = None;
+    }
+
+    pub fn stats(&self) -> Option<&Stats> {
+        self.stats.as_ref()
+    }
+
+    // Record PPU register accesses for the whole of the next frame
+    pub fn capture_ppu_frame(&mut self) {
+        self.ppu_capture = Some(PpuCapture::new(self.ppu_position().frame + 1));
+        self.update_write_recording();
+    }
+
+    // The last (or in-progress) PPU register capture
+    pub fn ppu_capture(&self) -> Option<&PpuCapture> {
+        self.ppu_capture.as_ref()
+    }
+
+    fn log_events(&mut self, pc: u16, opcode: u8, interrupt: Option<Interrupt>, writes: &[(u16, u8)]) {
+        let position = self.ppu_position();
+        let cycle = self.cpu.cycles;
+        let irq_source = IrqSource::ALL
+            .into_iter()
+            .find(|&source| self.memory.irq_line().is_asserted_by(source));
+        let Some(log) = &mut self.event_log else {
+            return;
+        };
+
+        let event = |kind| Event {
+            cycle,
+            frame: position.frame,
+            scanline: position.scanline,
+            dot: position.dot,
+            pc,
+            kind,
+        };
+
+        match interrupt {
+            Some(Interrupt::Nmi) => log.record(event(EventKind::Nmi)),
+            Some(Interrupt::Irq) => {
+                if let Some(source) = irq_source {
+                    log.record(event(EventKind::Irq(source)));
+                }
+            }
+            None if opcode == 0x00 => log.record(event(EventKind::Brk)),
+            None => {}
+        }
+        for &(addr, value) in writes {
+            if addr == 0x4014 {
+                log.record(event(EventKind::OamDma { page: value }));
+            } else if log.is_watched(addr) {
+                log.record(event(EventKind::RegisterWrite { addr, value }));
+            }
+        }
+    }
+}
+
+// First vblank start strictly after `dots`; landing exactly on one counts as
+// having entered it
+fn next_vblank_dot(dots: u64) -> u64 {
+    let frame = if dots < VBLANK_DOT {
+        0
+    } else {
+        (dots - VBLANK_DOT) / DOTS_PER_FRAME + 1
+    };
+    frame * DOTS_PER_FRAME + VBLANK_DOT
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+    use std::sync::{Arc, Mutex};
+
+    // $AB (LXA) has no handler: the console notes it and steps over it
+    #[test]
+    fn unknown_opcodes_are_reported_and_skipped() {
+        let mut nes = Nes::from_bytes(&testbus::ines_image("nop\n .byte $AB\n lda #$42", 0).unwrap()).unwrap();
+        nes.step(); // NOP
+        nes.step(); // $AB
+        assert_eq!(nes.cpu.pc, 0xC002);
+        nes.step(); // LDA #$42
+        assert_eq!(nes.cpu.a, 0x42);
+
+        let entries = nes.unknown_opcode_report().entries();
+        assert_eq!(entries.len(), 1);
+        assert_eq!((entries[0].opcode, entries[0].first_pc, entries[0].count), (0xAB, 0xC001, 1));
+    }
+
+    // A BRK whose poll comes just before vblank starts, so the NMI is
+    // raised while it runs and hijacks its vector fetch
+    #[test]
+    fn nmi_raised_during_brk_hijacks_it() {
+        let source = "
+            lda #$80
+            sta $2000
+            brk
+            nop
+    nmi:    jmp nmi
+    irq:    jmp irq
+            * = $FFFA
+            .word nmi
+            * = $FFFE
+            .word irq";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.step(); // LDA
+        nes.step(); // STA $2000
+        let vblank_cycle = VBLANK_DOT.div_ceil(DOTS_PER_CPU_CYCLE);
+        nes.cpu.cycles = vblank_cycle - 2;
+        nes.step(); // BRK
+        assert_eq!(nes.cpu.pc, 0xC007, "the NMI handler");
+        assert_eq!(nes.memory.peek_u16(0x01FC), 0xC007, "BRK's return address");
+        assert_eq!(nes.memory.peek(0x01FB) & 0x30, 0x30, "B and bit 5 in the pushed P");
+        nes.step();
+        assert_eq!(nes.cpu.sp, 0xFA, "a second NMI");
+
+        // Two cycles earlier the NMI comes after BRK, through $FFFE
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.step();
+        nes.step();
+        nes.cpu.cycles = vblank_cycle - 2 - BRK_NMI_HIJACK_CYCLES;
+        nes.step(); // BRK
+        assert_eq!(nes.cpu.pc, 0xC00A, "the IRQ/BRK handler");
+        nes.step();
+        assert_eq!((nes.cpu.pc, nes.cpu.sp), (0xC007, 0xF7), "the NMI, after BRK");
+    }
+
+    // A fresh directory per test, so tests running in parallel don't share
+    // save files
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("nesemu-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_rom(dir: &Path, flags6: u8) -> PathBuf {
+        let path = dir.join("game.nes");
+        fs::write(&path, testbus::ines_image("", flags6).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn battery_ram_survives_a_reload() {
+        let dir = scratch_dir("battery");
+        let rom = write_rom(&dir, 0x02);
+        let mut nes = Nes::from_file(&rom).unwrap();
+        let save = dir.join("game.nes.sav");
+        assert_eq!(nes.save_path(), Some(save.as_path()));
+        for i in 0..0x2000u16 {
+            nes.write_bus(0x6000 + i, (i * 7) as u8);
+        }
+        nes.flush().unwrap();
+        assert_eq!(fs::read(&save).unwrap().len(), 0x2000);
+
+        let reloaded = Nes::from_file(&rom).unwrap();
+        assert_eq!(reloaded.memory.cartridge_ram(), nes.memory.cartridge_ram());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn wrong_size_saves_are_zero_filled_or_truncated() {
+        let dir = scratch_dir("wrong-size");
+        let rom = write_rom(&dir, 0x02);
+        let save = dir.join("game.nes.sav");
+
+        fs::write(&save, [0xAA; 100]).unwrap();
+        let nes = Nes::from_file(&rom).unwrap();
+        let ram = nes.memory.cartridge_ram();
+        assert!(ram[..100].iter().all(|&b| b == 0xAA));
+        assert!(ram[100..].iter().all(|&b| b == 0));
+
+        let mut long = vec![0x55; 0x2000];
+        long.extend([0xAA; 16]);
+        fs::write(&save, &long).unwrap();
+        let nes = Nes::from_file(&rom).unwrap();
+        assert!(nes.memory.cartridge_ram().iter().all(|&b| b == 0x55));
+
+        // Flushing writes it back at the right size
+        nes.flush().unwrap();
+        assert_eq!(fs::read(&save).unwrap(), vec![0x55; 0x2000]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn carts_without_a_battery_write_no_save() {
+        let dir = scratch_dir("no-battery");
+        let rom = write_rom(&dir, 0);
+        let mut nes = Nes::from_file(&rom).unwrap();
+        assert_eq!(nes.save_path(), None);
+        nes.write_bus(0x6000, 0x42);
+        nes.flush().unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1); // Just the ROM
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    // The facade on a vertically mirrored board: $2C01 is $2401's table
+    #[test]
+    fn vram_facade_round_trips() {
+        let mut nes = Nes::from_bytes(&testbus::ines_image("spin: jmp spin", 0x01).unwrap()).unwrap();
+        nes.write_vram(0x2C01, 0x77);
+        nes.write_vram(0x0100, 0x5A); // CHR-RAM
+        nes.write_palette(0x10, 0x21);
+        assert_eq!(nes.read_vram(0x2401), 0x77);
+        assert_eq!(nes.read_vram(0x2001), 0x00);
+        assert_eq!(nes.read_vram(0x0100), 0x5A);
+        assert_eq!(nes.read_palette(0x00), 0x21);
+        assert_eq!(nes.read_vram(0x3F00), 0x21);
+    }
+
+    // The facade and a program's own $2006/$2007 traffic see the same
+    // memory, here on a vertically mirrored board
+    #[test]
+    fn vram_facade_matches_ppudata() {
+        let source = "
+            bit $2002
+            lda #$24
+            sta $2006
+            lda #$00
+            sta $2006
+            lda #$5A
+            sta $2007
+            lda #$3F
+            sta $2006
+            lda #$00
+            sta $2006
+            lda #$21
+            sta $2007
+            lda $2007
+            sta $10
+     spin:  jmp spin";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0x01).unwrap()).unwrap();
+        nes.write_vram(0x2C01, 0x77); // Same table as $2401
+        nes.write_palette(0x01, 0x30);
+        for _ in 0..16 {
+            nes.step();
+        }
+        assert_eq!(nes.read_vram(0x2400), 0x5A);
+        assert_eq!(nes.read_vram(0x2C00), 0x5A);
+        assert_eq!(nes.read_vram(0x2000), 0x00);
+        assert_eq!(nes.read_vram(0x2401), 0x77);
+        assert_eq!(nes.read_palette(0x00), 0x21);
+        assert_eq!(nes.read_palette(0x10), 0x21);
+        assert_eq!(nes.memory.peek(0x0010), 0x30); // The program read $3F01
+        assert_eq!(nes.memory.vram_addr(), 0x3F02);
+    }
+
+    // poke sets the register byte and nothing else; write_bus goes through
+    // the register's side effects
+    #[test]
+    fn poke_leaves_the_ppu_latches_alone() {
+        let mut nes = Nes::from_bytes(&testbus::ines_image("spin: jmp spin", 0).unwrap()).unwrap();
+        nes.write_bus(0x2006, 0x21); // First half of an address
+        let before = nes.memory.clone();
+        nes.poke(0x2002, 0x80);
+        nes.poke(0x2006, 0x55);
+        nes.poke(0x2007, 0x99);
+        assert_eq!(nes.memory.peek(0x2002), 0x80);
+        assert!(nes.memory.diff(&before).is_some_and(|d| d.starts_with("ppu_registers")));
+        assert_eq!(nes.memory.vram_addr(), 0x0000);
+        assert_eq!(nes.read_vram(0x0000), 0x00);
+
+        // The toggle survived the $2002 poke, so this is the second half
+        nes.write_bus(0x2006, 0x08);
+        assert_eq!(nes.memory.vram_addr(), 0x2108);
+        nes.write_bus(0x2007, 0x99);
+        assert_eq!((nes.read_vram(0x2108), nes.memory.vram_addr()), (0x99, 0x2109));
+    }
+
+    #[test]
+    fn set_cpu_register_touches_only_that_register() {
+        let mut nes = Nes::from_bytes(&testbus::ines_image("spin: jmp spin", 0).unwrap()).unwrap();
+        let memory = nes.memory.clone();
+        let cycles = nes.cpu.cycles;
+        nes.set_cpu_register(Register::A, 0x1234); // Low byte only
+        nes.set_cpu_register(Register::Pc, 0xC123);
+        nes.set_cpu_register(Register::Status, 0xE7);
+        assert_eq!((nes.cpu.a, nes.cpu.pc, nes.cpu.status), (0x34, 0xC123, 0xE7));
+        assert_eq!((nes.cpu.x, nes.cpu.y, nes.cpu.sp, nes.cpu.cycles), (0, 0, 0xFD, cycles));
+        assert_eq!(nes.memory.diff(&memory), None);
+    }
+
+    // A main loop churning RAM and an NMI handler that reads the pad and
+    // writes PRG-RAM and OAM, so input steers everything the diffs look at.
+    // (The real ROMs in the tree sit waiting on $2002 until the PPU exists.)
+    const DETERMINISM_SOURCE: &str = "
+            ldx #$FF
+            txs
+            lda #$80
+            sta $2000
+     main:  inc $00
+            lda $00
+            eor $01
+            ldx $00
+            sta $0300,x
+            jmp main
+     nmi:   pha
+            lda #1
+            sta $4016
+            lda #0
+            sta $4016
+            ldx #8
+     read:  lda $4016
+            lsr a
+            rol $01
+            dex
+            bne read
+            inc $02
+            ldx $02
+            lda $01
+            sta $6000,x
+            adc $0400,x
+            sta $0400,x
+            lda #$03
+            sta $4014
+            pla
+            rti
+            * = $FFFA
+            .word nmi";
+
+    // Same ROM, same input, same frames: the two consoles must match byte
+    // for byte all the way through
+    #[test]
+    fn identical_runs_stay_identical() {
+        let rom = testbus::ines_image(DETERMINISM_SOURCE, 0).unwrap();
+        let mut first = Nes::from_bytes(&rom).unwrap();
+        let mut second = Nes::from_bytes(&rom).unwrap();
+        let script = [
+            ButtonState::NONE,
+            ButtonState::START,
+            ButtonState::LEFT,
+            ButtonState::A,
+            ButtonState::DOWN,
+            ButtonState::RIGHT,
+            ButtonState::B,
+        ];
+
+        for frame in 0..600 {
+            let buttons = script[frame / 15 % script.len()];
+            for nes in [&mut first, &mut second] {
+                nes.set_controller(0, buttons);
+                nes.run_frame();
+            }
+            assert_eq!(first.cpu, second.cpu, "CPU after frame {frame}");
+            assert_eq!(first.memory.diff(&second.memory), None, "after frame {frame}");
+            assert_eq!(first.memory.cartridge().diff(second.memory.cartridge()), None);
+        }
+        assert!(first.state_eq(&second));
+
+        // The program really did run, and the input really did reach it
+        assert!(first.memory.ram()[0x0400..0x0500].iter().any(|&b| b != 0));
+        assert!(first.memory.cartridge_ram()[..0x100].iter().any(|&b| b != 0));
+        assert!(first.memory.oam().iter().any(|&b| b != 0));
+    }
+
+    // Runs a frame and a half at a time, so completions land mid-call, and
+    // checks each frame is reported once, when its last instruction runs
+    #[test]
+    fn frame_callback_fires_once_per_frame() {
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let mut nes = Nes::from_bytes(&testbus::ines_image("spin: jmp spin", 0).unwrap()).unwrap();
+        let seen = Arc::clone(&frames);
+        nes.set_frame_callback(Box::new(move |frame| seen.lock().unwrap().push(*frame)));
+        while nes.cpu.cycles * DOTS_PER_CPU_CYCLE < DOTS_PER_FRAME * 9 / 2 {
+            nes.step();
+        }
+        nes.step_frame(); // To vblank in frame 4, which doesn't complete it
+
+        let completed = std::mem::take(&mut *frames.lock().unwrap());
+        let numbers: Vec<_> = completed.iter().map(|frame| frame.number).collect();
+        assert_eq!(numbers, [0, 1, 2, 3]);
+        for frame in &completed {
+            let end = (frame.number + 1) * DOTS_PER_FRAME;
+            let overshoot = frame.cycles * DOTS_PER_CPU_CYCLE - end;
+            assert!(overshoot < 3 * DOTS_PER_CPU_CYCLE, "frame {} ended {overshoot} dots late", frame.number);
+        }
+
+        nes.clear_frame_callback();
+        nes.run_frame();
+        assert!(frames.lock().unwrap().is_empty());
+    }
+
+    // Back-to-back calls each end on the first instruction boundary past
+    // vblank, so their cycle counts add up to the whole run. The main loop
+    // has 7-cycle instructions and each NMI adds 7 more, to give the
+    // boundary something to straddle.
+    #[test]
+    fn step_frame_tiles_the_timeline() {
+        let source = "
+            lda #$80
+            sta $2000
+     main:  inc $0300,x
+            inx
+            jmp main
+     nmi:   inc $10
+            rti
+            * = $FFFA
+            .word nmi";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        let start = nes.cpu.cycles;
+        let mut total = 0;
+        for frame in 0..20 {
+            let result = nes.step_frame();
+            assert_eq!(result.frame, frame);
+            assert!(result.nmi, "frame {frame}");
+            let vblank = frame * DOTS_PER_FRAME + VBLANK_DOT;
+            let end = nes.cpu.cycles * DOTS_PER_CPU_CYCLE;
+            assert!((vblank..vblank + 7 * DOTS_PER_CPU_CYCLE).contains(&end), "frame {frame} ended at dot {end}");
+            if frame > 0 {
+                let drift = (result.cycles * DOTS_PER_CPU_CYCLE).abs_diff(DOTS_PER_FRAME);
+                assert!(drift < 7 * DOTS_PER_CPU_CYCLE, "frame {frame} is {drift} dots off a whole frame");
+            }
+            total += result.cycles;
+        }
+        assert_eq!(total, nes.cpu.cycles - start);
+        assert_eq!(nes.memory.peek(0x0010), 19, "the last NMI is still pending");
+
+        // Starting mid-frame only runs to the next vblank
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        while nes.cpu.cycles * DOTS_PER_CPU_CYCLE < VBLANK_DOT + DOTS_PER_SCANLINE * 10 {
+            nes.step();
+        }
+        let result = nes.step_frame();
+        assert_eq!(result.frame, 1);
+        assert!(result.cycles * DOTS_PER_CPU_CYCLE < DOTS_PER_FRAME);
+    }
+
+    // Leaves its mark on the registers, RAM, PRG-RAM, OAM and PPUCTRL
+    const RESET_SOURCE: &str = "
+            lda #$80
+            sta $2000
+            lda #$55
+            sta $0200
+            sta $6000
+            lda #$07
+            sta $2003
+            lda #$99
+            sta $2004
+            ldx #$11
+            ldy #$22
+            lda #$33
+            pha
+     spin:  jmp spin";
+
+    fn reset_test_console(flags6: u8) -> Nes {
+        let mut nes = Nes::from_bytes(&testbus::ines_image(RESET_SOURCE, flags6).unwrap()).unwrap();
+        for _ in 0..14 {
+            nes.step();
+        }
+        assert_eq!((nes.cpu.pc, nes.cpu.sp), (0xC01E, 0xFC));
+        nes
+    }
+
+    #[test]
+    fn soft_reset_keeps_memory_and_registers() {
+        let mut nes = reset_test_console(0);
+        let cycles = nes.cpu.cycles;
+        nes.soft_reset();
+        assert_eq!((nes.cpu.pc, nes.cpu.sp, nes.cpu.cycles), (0xC000, 0xF9, cycles + 7));
+        assert_eq!((nes.cpu.a, nes.cpu.x, nes.cpu.y), (0x33, 0x11, 0x22));
+        assert_ne!(nes.cpu.status & 0x04, 0, "I is set");
+        assert_eq!(nes.memory.ppu_ctrl(), 0);
+        assert_eq!([nes.memory.peek(0x0200), nes.memory.peek(0x01FD), nes.memory.peek(0x6000)], [0x55, 0x33, 0x55]);
+        assert_eq!(nes.memory.oam()[7], 0x99);
+    }
+
+    #[test]
+    fn power_cycle_starts_over() {
+        let mut nes = reset_test_console(0);
+        let cycles = nes.cpu.cycles;
+        nes.power_cycle();
+        let fresh = Nes::from_bytes(&testbus::ines_image(RESET_SOURCE, 0).unwrap()).unwrap();
+        assert_eq!(nes.cpu.cycles, cycles + fresh.cpu.cycles, "the clock keeps running");
+        nes.cpu.cycles = fresh.cpu.cycles;
+        assert_eq!(nes.cpu, fresh.cpu);
+        assert_eq!(nes.memory.diff(&fresh.memory), None);
+
+        // Battery-backed PRG-RAM is the one thing that survives
+        let mut nes = reset_test_console(0x02);
+        nes.power_cycle();
+        assert_eq!((nes.memory.peek(0x6000), nes.memory.peek(0x0200)), (0x55, 0x00));
+    }
+
+    // Differences are reported CPU first, then memory, one at a time; each
+    // is fixed in turn until the consoles match
+    #[test]
+    fn state_diff_reports_the_first_difference() {
+        let rom = testbus::ines_image("spin: jmp spin", 0).unwrap();
+        let mut ours = Nes::from_bytes(&rom).unwrap();
+        let mut theirs = Nes::from_bytes(&rom).unwrap();
+        assert_eq!(ours.state_diff(&theirs), None);
+
+        type Change = fn(&mut Nes);
+        let changes: [(&str, Change); 5] = [
+            ("cpu.x: $01 vs $00", |nes| nes.set_cpu_register(Register::X, 0x01)),
+            ("cpu.cycles: 10 vs 7", |nes| nes.cpu.cycles += 3),
+            ("memory.cpu_ram[$10]: $21 vs $00", |nes| nes.poke(0x0010, 0x21)),
+            ("memory.vram.nametables[$5]: $77 vs $00", |nes| nes.write_vram(0x2005, 0x77)),
+            ("memory.cartridge.prg_ram[$1]: $C3 vs $00", |nes| nes.poke(0x6001, 0xC3)),
+        ];
+        for (_, change) in &changes {
+            change(&mut ours);
+        }
+        for (diff, change) in &changes {
+            assert_eq!(ours.state_diff(&theirs).as_deref(), Some(*diff));
+            assert!(!ours.state_eq(&theirs));
+            change(&mut theirs);
+        }
+        assert_eq!(ours.state_diff(&theirs), None);
+        assert!(ours.state_eq(&theirs));
+
+        // The other way round the values swap sides
+        theirs.poke(0x07FF, 0x01);
+        assert_eq!(ours.state_diff(&theirs).as_deref(), Some("memory.cpu_ram[$7FF]: $00 vs $01"));
+    }
+}
@@ -0,0 +1,431 @@
+// Opcode metadata shared by the CPU, the disassembler and the assembler
+
+use alloc::format;
+use alloc::string::{String, ToString};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AddrMode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+impl AddrMode {
+    // Number of operand bytes following the opcode
+    pub fn operand_len(self) -> u16 {
+        match self {
+            AddrMode::Implied | AddrMode::Accumulator => 0,
+            AddrMode::Immediate
+            | AddrMode::ZeroPage
+            | AddrMode::ZeroPageX
+            | AddrMode::ZeroPageY
+            | AddrMode::IndirectX
+            | AddrMode::IndirectY
+            | AddrMode::Relative => 1,
+            AddrMode::Absolute | AddrMode::AbsoluteX | AddrMode::AbsoluteY | AddrMode::Indirect => 2,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Opcode {
+    pub mnemonic: &'static str,
+    pub mode: AddrMode,
+    pub cycles: u8, // Base cycle count, before page-cross/branch penalties
+    pub page_cross: bool, // One more cycle when indexing crosses a page
+    pub official: bool,
+}
+
+impl Opcode {
+    const fn new(mnemonic: &'static str, mode: AddrMode, cycles: u8) -> Self {
+        Self { mnemonic, mode, cycles, page_cross: false, official: true }
+    }
+
+    const fn unofficial(mnemonic: &'static str, mode: AddrMode, cycles: u8) -> Self {
+        Self { mnemonic, mode, cycles, page_cross: false, official: false }
+    }
+
+    // Indexed reads pay for a page cross; stores and read-modify-writes
+    // always spend that cycle, so it is already in their base count
+    const fn page_cross(self) -> Self {
+        Self { page_cross: true, ..self }
+    }
+
+    // Total instruction size in bytes, including the opcode itself
+    pub fn size(&self) -> u16 {
+        1 + self.mode.operand_len()
+    }
+}
+
+// `bytes` holds the whole instruction, opcode first
+pub fn format_operand(mode: AddrMode, addr: u16, bytes: &[u8]) -> String {
+    let byte = || bytes[1];
+    let word = || ((bytes[2] as u16) << 8) | bytes[1] as u16;
+
+    match mode {
+        AddrMode::Implied => String::new(),
+        AddrMode::Accumulator => "A".to_string(),
+        AddrMode::Immediate => format!("#${:02X}", byte()),
+        AddrMode::ZeroPage => format!("${:02X}", byte()),
+        AddrMode::ZeroPageX => format!("${:02X},X", byte()),
+        AddrMode::ZeroPageY => format!("${:02X},Y", byte()),
+        AddrMode::Absolute => format!("${:04X}", word()),
+        AddrMode::AbsoluteX => format!("${:04X},X", word()),
+        AddrMode::AbsoluteY => format!("${:04X},Y", word()),
+        AddrMode::Indirect => format!("(${:04X})", word()),
+        AddrMode::IndirectX => format!("(${:02X},X)", byte()),
+        AddrMode::IndirectY => format!("(${:02X}),Y", byte()),
+        AddrMode::Relative => {
+            // Branch targets are relative to the instruction after the branch
+            let target = addr.wrapping_add(2).wrapping_add(byte() as i8 as u16);
+            format!("${:04X}", target)
+        }
+    }
+}
+
+// Every opcode the CPU implements, indexed by opcode byte
+pub static OPCODES: [Option<Opcode>; 256] = build_table();
+
+pub fn lookup(opcode: u8) -> Option<&'static Opcode> {
+    OPCODES[opcode as usize].as_ref()
+}
+
+const fn build_table() -> [Option<Opcode>; 256] {
+    use AddrMode::*;
+
+    let mut t: [Option<Opcode>; 256] = [None; 256];
+    // LDA
+    t[0xA9] = Some(Opcode::new("LDA", Immediate, 2));
+    t[0xA5] = Some(Opcode::new("LDA", ZeroPage, 3));
+    t[0xB5] = Some(Opcode::new("LDA", ZeroPageX, 4));
+    t[0xAD] = Some(Opcode::new("LDA", Absolute, 4));
+    t[0xBD] = Some(Opcode::new("LDA", AbsoluteX, 4).page_cross());
+    t[0xB9] = Some(Opcode::new("LDA", AbsoluteY, 4).page_cross());
+    t[0xA1] = Some(Opcode::new("LDA", IndirectX, 6));
+    t[0xB1] = Some(Opcode::new("LDA", IndirectY, 5).page_cross());
+    // LDX
+    t[0xA2] = Some(Opcode::new("LDX", Immediate, 2));
+    t[0xA6] = Some(Opcode::new("LDX", ZeroPage, 3));
+    t[0xB6] = Some(Opcode::new("LDX", ZeroPageY, 4));
+    t[0xAE] = Some(Opcode::new("LDX", Absolute, 4));
+    t[0xBE] = Some(Opcode::new("LDX", AbsoluteY, 4).page_cross());
+    // LDY
+    t[0xA0] = Some(Opcode::new("LDY", Immediate, 2));
+    t[0xA4] = Some(Opcode::new("LDY", ZeroPage, 3));
+    t[0xB4] = Some(Opcode::new("LDY", ZeroPageX, 4));
+    t[0xAC] = Some(Opcode::new("LDY", Absolute, 4));
+    t[0xBC] = Some(Opcode::new("LDY", AbsoluteX, 4).page_cross());
+    // STA
+    t[0x85] = Some(Opcode::new("STA", ZeroPage, 3));
+    t[0x95] = Some(Opcode::new("STA", ZeroPageX, 4));
+    t[0x8D] = Some(Opcode::new("STA", Absolute, 4));
+    t[0x9D] = Some(Opcode::new("STA", AbsoluteX, 5));
+    t[0x99] = Some(Opcode::new("STA", AbsoluteY, 5));
+    t[0x81] = Some(Opcode::new("STA", IndirectX, 6));
+    t[0x91] = Some(Opcode::new("STA", IndirectY, 6));
+    // STX
+    t[0x86] = Some(Opcode::new("STX", ZeroPage, 3));
+    t[0x96] = Some(Opcode::new("STX", ZeroPageY, 4));
+    t[0x8E] = Some(Opcode::new("STX", Absolute, 4));
+    // STY
+    t[0x84] = Some(Opcode::new("STY", ZeroPage, 3));
+    t[0x94] = Some(Opcode::new("STY", ZeroPageX, 4));
+    t[0x8C] = Some(Opcode::new("STY", Absolute, 4));
+    // TAX
+    t[0xAA] = Some(Opcode::new("TAX", Implied, 2));
+    // TAY
+    t[0xA8] = Some(Opcode::new("TAY", Implied, 2));
+    // TSX
+    t[0xBA] = Some(Opcode::new("TSX", Implied, 2));
+    // TXA
+    t[0x8A] = Some(Opcode::new("TXA", Implied, 2));
+    // TXS
+    t[0x9A] = Some(Opcode::new("TXS", Implied, 2));
+    // TYA
+    t[0x98] = Some(Opcode::new("TYA", Implied, 2));
+    // PHA
+    t[0x48] = Some(Opcode::new("PHA", Implied, 3));
+    // PHP
+    t[0x08] = Some(Opcode::new("PHP", Implied, 3));
+    // PLA
+    t[0x68] = Some(Opcode::new("PLA", Implied, 4));
+    // PLP
+    t[0x28] = Some(Opcode::new("PLP", Implied, 4));
+    // ADC
+    t[0x69] = Some(Opcode::new("ADC", Immediate, 2));
+    t[0x65] = Some(Opcode::new("ADC", ZeroPage, 3));
+    t[0x75] = Some(Opcode::new("ADC", ZeroPageX, 4));
+    t[0x6D] = Some(Opcode::new("ADC", Absolute, 4));
+    t[0x7D] = Some(Opcode::new("ADC", AbsoluteX, 4).page_cross());
+    t[0x79] = Some(Opcode::new("ADC", AbsoluteY, 4).page_cross());
+    t[0x61] = Some(Opcode::new("ADC", IndirectX, 6));
+    t[0x71] = Some(Opcode::new("ADC", IndirectY, 5).page_cross());
+    // SBC
+    t[0xE9] = Some(Opcode::new("SBC", Immediate, 2));
+    t[0xE5] = Some(Opcode::new("SBC", ZeroPage, 3));
+    t[0xF5] = Some(Opcode::new("SBC", ZeroPageX, 4));
+    t[0xED] = Some(Opcode::new("SBC", Absolute, 4));
+    t[0xFD] = Some(Opcode::new("SBC", AbsoluteX, 4).page_cross());
+    t[0xF9] = Some(Opcode::new("SBC", AbsoluteY, 4).page_cross());
+    t[0xE1] = Some(Opcode::new("SBC", IndirectX, 6));
+    t[0xF1] = Some(Opcode::new("SBC", IndirectY, 5).page_cross());
+    // INC
+    t[0xE6] = Some(Opcode::new("INC", ZeroPage, 5));
+    t[0xF6] = Some(Opcode::new("INC", ZeroPageX, 6));
+    t[0xEE] = Some(Opcode::new("INC", Absolute, 6));
+    t[0xFE] = Some(Opcode::new("INC", AbsoluteX, 7));
+    // INX
+    t[0xE8] = Some(Opcode::new("INX", Implied, 2));
+    // INY
+    t[0xC8] = Some(Opcode::new("INY", Implied, 2));
+    // DEC
+    t[0xC6] = Some(Opcode::new("DEC", ZeroPage, 5));
+    t[0xD6] = Some(Opcode::new("DEC", ZeroPageX, 6));
+    t[0xCE] = Some(Opcode::new("DEC", Absolute, 6));
+    t[0xDE] = Some(Opcode::new("DEC", AbsoluteX, 7));
+    // DEX
+    t[0xCA] = Some(Opcode::new("DEX", Implied, 2));
+    // DEY
+    t[0x88] = Some(Opcode::new("DEY", Implied, 2));
+    // AND
+    t[0x29] = Some(Opcode::new("AND", Immediate, 2));
+    t[0x25] = Some(Opcode::new("AND", ZeroPage, 3));
+    t[0x35] = Some(Opcode::new("AND", ZeroPageX, 4));
+    t[0x2D] = Some(Opcode::new("AND", Absolute, 4));
+    t[0x3D] = Some(Opcode::new("AND", AbsoluteX, 4).page_cross());
+    t[0x39] = Some(Opcode::new("AND", AbsoluteY, 4).page_cross());
+    t[0x21] = Some(Opcode::new("AND", IndirectX, 6));
+    t[0x31] = Some(Opcode::new("AND", IndirectY, 5).page_cross());
+    // ORA
+    t[0x09] = Some(Opcode::new("ORA", Immediate, 2));
+    t[0x05] = Some(Opcode::new("ORA", ZeroPage, 3));
+    t[0x15] = Some(Opcode::new("ORA", ZeroPageX, 4));
+    t[0x0D] = Some(Opcode::new("ORA", Absolute, 4));
+    t[0x1D] = Some(Opcode::new("ORA", AbsoluteX, 4).page_cross());
+    t[0x19] = Some(Opcode::new("ORA", AbsoluteY, 4).page_cross());
+    t[0x01] = Some(Opcode::new("ORA", IndirectX, 6));
+    t[0x11] = Some(Opcode::new("ORA", IndirectY, 5).page_cross());
+    // EOR
+    t[0x49] = Some(Opcode::new("EOR", Immediate, 2));
+    t[0x45] = Some(Opcode::new("EOR", ZeroPage, 3));
+    t[0x55] = Some(Opcode::new("EOR", ZeroPageX, 4));
+    t[0x4D] = Some(Opcode::new("EOR", Absolute, 4));
+    t[0x5D] = Some(Opcode::new("EOR", AbsoluteX, 4).page_cross());
+    t[0x59] = Some(Opcode::new("EOR", AbsoluteY, 4).page_cross());
+    t[0x41] = Some(Opcode::new("EOR", IndirectX, 6));
+    t[0x51] = Some(Opcode::new("EOR", IndirectY, 5).page_cross());
+    // BIT
+    t[0x24] = Some(Opcode::new("BIT", ZeroPage, 3));
+    t[0x2C] = Some(Opcode::new("BIT", Absolute, 4));
+    // ASL
+    t[0x0A] = Some(Opcode::new("ASL", Accumulator, 2));
+    t[0x06] = Some(Opcode::new("ASL", ZeroPage, 5));
+    t[0x16] = Some(Opcode::new("ASL", ZeroPageX, 6));
+    t[0x0E] = Some(Opcode::new("ASL", Absolute, 6));
+    t[0x1E] = Some(Opcode::new("ASL", AbsoluteX, 7));
+    // LSR
+    t[0x4A] = Some(Opcode::new("LSR", Accumulator, 2));
+    t[0x46] = Some(Opcode::new("LSR", ZeroPage, 5));
+    t[0x56] = Some(Opcode::new("LSR", ZeroPageX, 6));
+    t[0x4E] = Some(Opcode::new("LSR", Absolute, 6));
+    t[0x5E] = Some(Opcode::new("LSR", AbsoluteX, 7));
+    // ROL
+    t[0x2A] = Some(Opcode::new("ROL", Accumulator, 2));
+    t[0x26] = Some(Opcode::new("ROL", ZeroPage, 5));
+    t[0x36] = Some(Opcode::new("ROL", ZeroPageX, 6));
+    t[0x2E] = Some(Opcode::new("ROL", Absolute, 6));
+    t[0x3E] = Some(Opcode::new("ROL", AbsoluteX, 7));
+    // ROR
+    t[0x6A] = Some(Opcode::new("ROR", Accumulator, 2));
+    t[0x66] = Some(Opcode::new("ROR", ZeroPage, 5));
+    t[0x76] = Some(Opcode::new("ROR", ZeroPageX, 6));
+    t[0x6E] = Some(Opcode::new("ROR", Absolute, 6));
+    t[0x7E] = Some(Opcode::new("ROR", AbsoluteX, 7));
+    // CMP
+    t[0xC9] = Some(Opcode::new("CMP", Immediate, 2));
+    t[0xC5] = Some(Opcode::new("CMP", ZeroPage, 3));
+    t[0xD5] = Some(Opcode::new("CMP", ZeroPageX, 4));
+    t[0xCD] = Some(Opcode::new("CMP", Absolute, 4));
+    t[0xDD] = Some(Opcode::new("CMP", AbsoluteX, 4).page_cross());
+    t[0xD9] = Some(Opcode::new("CMP", AbsoluteY, 4).page_cross());
+    t[0xC1] = Some(Opcode::new("CMP", IndirectX, 6));
+    t[0xD1] = Some(Opcode::new("CMP", IndirectY, 5).page_cross());
+    // CPX
+    t[0xE0] = Some(Opcode::new("CPX", Immediate, 2));
+    t[0xE4] = Some(Opcode::new("CPX", ZeroPage, 3));
+    t[0xEC] = Some(Opcode::new("CPX", Absolute, 4));
+    // CPY
+    t[0xC0] = Some(Opcode::new("CPY", Immediate, 2));
+    t[0xC4] = Some(Opcode::new("CPY", ZeroPage, 3));
+    t[0xCC] = Some(Opcode::new("CPY", Absolute, 4));
+    // JMP
+    t[0x4C] = Some(Opcode::new("JMP", Absolute, 3));
+    t[0x6C] = Some(Opcode::new("JMP", Indirect, 5));
+    // JSR
+    t[0x20] = Some(Opcode::new("JSR", Absolute, 6));
+    // RTS
+    t[0x60] = Some(Opcode::new("RTS", Implied, 6));
+    // BEQ
+    t[0xF0] = Some(Opcode::new("BEQ", Relative, 2));
+    // BNE
+    t[0xD0] = Some(Opcode::new("BNE", Relative, 2));
+    // BCS
+    t[0xB0] = Some(Opcode::new("BCS", Relative, 2));
+    // BCC
+    t[0x90] = Some(Opcode::new("BCC", Relative, 2));
+    // BMI
+    t[0x30] = Some(Opcode::new("BMI", Relative, 2));
+    // BPL
+    t[0x10] = Some(Opcode::new("BPL", Relative, 2));
+    // BVS
+    t[0x70] = Some(Opcode::new("BVS", Relative, 2));
+    // BVC
+    t[0x50] = Some(Opcode::new("BVC", Relative, 2));
+    // BRK
+    t[0x00] = Some(Opcode::new("BRK", Implied, 7));
+    // RTI
+    t[0x40] = Some(Opcode::new("RTI", Implied, 6));
+    // NOP
+    t[0xEA] = Some(Opcode::new("NOP", Implied, 2));
+    // CLC
+    t[0x18] = Some(Opcode::new("CLC", Implied, 2));
+    // SEC
+    t[0x38] = Some(Opcode::new("SEC", Implied, 2));
+    // CLD
+    t[0xD8] = Some(Opcode::new("CLD", Implied, 2));
+    // SED
+    t[0xF8] = Some(Opcode::new("SED", Implied, 2));
+    // CLI
+    t[0x58] = Some(Opcode::new("CLI", Implied, 2));
+    // SEI
+    t[0x78] = Some(Opcode::new("SEI", Implied, 2));
+    // CLV
+    t[0xB8] = Some(Opcode::new("CLV", Implied, 2));
+    // Unofficial NOPs
+    t[0x1A] = Some(Opcode::unofficial("NOP", Implied, 2));
+    t[0x3A] = Some(Opcode::unofficial("NOP", Implied, 2));
+    t[0x5A] = Some(Opcode::unofficial("NOP", Implied, 2));
+    t[0x7A] = Some(Opcode::unofficial("NOP", Implied, 2));
+    t[0xDA] = Some(Opcode::unofficial("NOP", Implied, 2));
+    t[0xFA] = Some(Opcode::unofficial("NOP", Implied, 2));
+    t[0x80] = Some(Opcode::unofficial("NOP", Immediate, 2));
+    t[0x82] = Some(Opcode::unofficial("NOP", Immediate, 2));
+    t[0x89] = Some(Opcode::unofficial("NOP", Immediate, 2));
+    t[0xC2] = Some(Opcode::unofficial("NOP", Immediate, 2));
+    t[0xE2] = Some(Opcode::unofficial("NOP", Immediate, 2));
+    t[0x04] = Some(Opcode::unofficial("NOP", ZeroPage, 3));
+    t[0x44] = Some(Opcode::unofficial("NOP", ZeroPage, 3));
+    t[0x64] = Some(Opcode::unofficial("NOP", ZeroPage, 3));
+    t[0x14] = Some(Opcode::unofficial("NOP", ZeroPageX, 4));
+    t[0x34] = Some(Opcode::unofficial("NOP", ZeroPageX, 4));
+    t[0x54] = Some(Opcode::unofficial("NOP", ZeroPageX, 4));
+    t[0x74] = Some(Opcode::unofficial("NOP", ZeroPageX, 4));
+    t[0xD4] = Some(Opcode::unofficial("NOP", ZeroPageX, 4));
+    t[0xF4] = Some(Opcode::unofficial("NOP", ZeroPageX, 4));
+    t[0x0C] = Some(Opcode::unofficial("NOP", Absolute, 4));
+    t[0x1C] = Some(Opcode::unofficial("NOP", AbsoluteX, 4).page_cross());
+    t[0x3C] = Some(Opcode::unofficial("NOP", AbsoluteX, 4).page_cross());
+    t[0x5C] = Some(Opcode::unofficial("NOP", AbsoluteX, 4).page_cross());
+    t[0x7C] = Some(Opcode::unofficial("NOP", AbsoluteX, 4).page_cross());
+    t[0xDC] = Some(Opcode::unofficial("NOP", AbsoluteX, 4).page_cross());
+    t[0xFC] = Some(Opcode::unofficial("NOP", AbsoluteX, 4).page_cross());
+    // LAX (LDA + LDX)
+    t[0xA7] = Some(Opcode::unofficial("LAX", ZeroPage, 3));
+    t[0xB7] = Some(Opcode::unofficial("LAX", ZeroPageY, 4));
+    t[0xAF] = Some(Opcode::unofficial("LAX", Absolute, 4));
+    t[0xBF] = Some(Opcode::unofficial("LAX", AbsoluteY, 4).page_cross());
+    t[0xA3] = Some(Opcode::unofficial("LAX", IndirectX, 6));
+    t[0xB3] = Some(Opcode::unofficial("LAX", IndirectY, 5).page_cross());
+    // SAX (store A & X)
+    t[0x87] = Some(Opcode::unofficial("SAX", ZeroPage, 3));
+    t[0x97] = Some(Opcode::unofficial("SAX", ZeroPageY, 4));
+    t[0x8F] = Some(Opcode::unofficial("SAX", Absolute, 4));
+    t[0x83] = Some(Opcode::unofficial("SAX", IndirectX, 6));
+    // SLO (ASL + ORA)
+    t[0x07] = Some(Opcode::unofficial("SLO", ZeroPage, 5));
+    t[0x17] = Some(Opcode::unofficial("SLO", ZeroPageX, 6));
+    t[0x0F] = Some(Opcode::unofficial("SLO", Absolute, 6));
+    t[0x1F] = Some(Opcode::unofficial("SLO", AbsoluteX, 7));
+    t[0x1B] = Some(Opcode::unofficial("SLO", AbsoluteY, 7));
+    t[0x03] = Some(Opcode::unofficial("SLO", IndirectX, 8));
+    t[0x13] = Some(Opcode::unofficial("SLO", IndirectY, 8));
+    // RLA (ROL + AND)
+    t[0x27] = Some(Opcode::unofficial("RLA", ZeroPage, 5));
+    t[0x37] = Some(Opcode::unofficial("RLA", ZeroPageX, 6));
+    t[0x2F] = Some(Opcode::unofficial("RLA", Absolute, 6));
+    t[0x3F] = Some(Opcode::unofficial("RLA", AbsoluteX, 7));
+    t[0x3B] = Some(Opcode::unofficial("RLA", AbsoluteY, 7));
+    t[0x23] = Some(Opcode::unofficial("RLA", IndirectX, 8));
+    t[0x33] = Some(Opcode::unofficial("RLA", IndirectY, 8));
+    // SRE (LSR + EOR)
+    t[0x47] = Some(Opcode::unofficial("SRE", ZeroPage, 5));
+    t[0x57] = Some(Opcode::unofficial("SRE", ZeroPageX, 6));
+    t[0x4F] = Some(Opcode::unofficial("SRE", Absolute, 6));
+    t[0x5F] = Some(Opcode::unofficial("SRE", AbsoluteX, 7));
+    t[0x5B] = Some(Opcode::unofficial("SRE", AbsoluteY, 7));
+    t[0x43] = Some(Opcode::unofficial("SRE", IndirectX, 8));
+    t[0x53] = Some(Opcode::unofficial("SRE", IndirectY, 8));
+    // RRA (ROR + ADC)
+    t[0x67] = Some(Opcode::unofficial("RRA", ZeroPage, 5));
+    t[0x77] = Some(Opcode::unofficial("RRA", ZeroPageX, 6));
+    t[0x6F] = Some(Opcode::unofficial("RRA", Absolute, 6));
+    t[0x7F] = Some(Opcode::unofficial("RRA", AbsoluteX, 7));
+    t[0x7B] = Some(Opcode::unofficial("RRA", AbsoluteY, 7));
+    t[0x63] = Some(Opcode::unofficial("RRA", IndirectX, 8));
+    t[0x73] = Some(Opcode::unofficial("RRA", IndirectY, 8));
+    // DCP (DEC + CMP)
+    t[0xC7] = Some(Opcode::unofficial("DCP", ZeroPage, 5));
+    t[0xD7] = Some(Opcode::unofficial("DCP", ZeroPageX, 6));
+    t[0xCF] = Some(Opcode::unofficial("DCP", Absolute, 6));
+    t[0xDF] = Some(Opcode::unofficial("DCP", AbsoluteX, 7));
+    t[0xDB] = Some(Opcode::unofficial("DCP", AbsoluteY, 7));
+    t[0xC3] = Some(Opcode::unofficial("DCP", IndirectX, 8));
+    t[0xD3] = Some(Opcode::unofficial("DCP", IndirectY, 8));
+    // ISC (INC + SBC, also known as ISB)
+    t[0xE7] = Some(Opcode::unofficial("ISC", ZeroPage, 5));
+    t[0xF7] = Some(Opcode::unofficial("ISC", ZeroPageX, 6));
+    t[0xEF] = Some(Opcode::unofficial("ISC", Absolute, 6));
+    t[0xFF] = Some(Opcode::unofficial("ISC", AbsoluteX, 7));
+    t[0xFB] = Some(Opcode::unofficial("ISC", AbsoluteY, 7));
+    t[0xE3] = Some(Opcode::unofficial("ISC", IndirectX, 8));
+    t[0xF3] = Some(Opcode::unofficial("ISC", IndirectY, 8));
+    // Immediate-only combinations
+    t[0x0B] = Some(Opcode::unofficial("ANC", Immediate, 2));
+    t[0x2B] = Some(Opcode::unofficial("ANC", Immediate, 2));
+    t[0x4B] = Some(Opcode::unofficial("ALR", Immediate, 2));
+    t[0x6B] = Some(Opcode::unofficial("ARR", Immediate, 2));
+    t[0xCB] = Some(Opcode::unofficial("AXS", Immediate, 2));
+    // The unstable ones. The stores AND their value with the high byte of
+    // the base address + 1; XAA depends on the chip, see ops::xaa
+    t[0x9C] = Some(Opcode::unofficial("SHY", AbsoluteX, 5));
+    t[0x9E] = Some(Opcode::unofficial("SHX", AbsoluteY, 5));
+    t[0x9F] = Some(Opcode::unofficial("AHX", AbsoluteY, 5));
+    t[0x93] = Some(Opcode::unofficial("AHX", IndirectY, 6));
+    t[0x9B] = Some(Opcode::unofficial("TAS", AbsoluteY, 5));
+    t[0xBB] = Some(Opcode::unofficial("LAS", AbsoluteY, 4).page_cross());
+    t[0x8B] = Some(Opcode::unofficial("XAA", Immediate, 2));
+    // JAM (also KIL): the CPU stops until reset
+    t[0x02] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x12] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x22] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x32] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x42] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x52] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x62] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x72] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0x92] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0xB2] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0xD2] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t[0xF2] = Some(Opcode::unofficial("JAM", Implied, 2));
+    t
+}
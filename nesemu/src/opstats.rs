@@ -0,0 +1,130 @@
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::opcodes;
+
+// How often each opcode ran, to see what a ROM actually leans on when
+// bringing it up. Unknown opcodes are counted like the rest and also kept
+// in a set of their own.
+//
+//   $A9  LDA Immediate       1234   12.3%
+//   $AB  ???                    2    0.0%
+
+#[derive(Debug, Clone)]
+pub struct OpcodeStats {
+    counts: [u64; 256],
+    unknown: BTreeSet<u8>,
+}
+
+impl OpcodeStats {
+    pub fn new() -> Self {
+        Self {
+            counts: [0; 256],
+            unknown: BTreeSet::new(),
+        }
+    }
+
+    pub fn record(&mut self, opcode: u8) {
+        self.counts[opcode as usize] += 1;
+    }
+
+    // The CPU couldn't run `opcode`; it is still counted by record
+    pub fn record_unknown(&mut self, opcode: u8) {
+        self.unknown.insert(opcode);
+    }
+
+    pub fn count(&self, opcode: u8) -> u64 {
+        self.counts[opcode as usize]
+    }
+
+    pub fn counts(&self) -> &[u64; 256] {
+        &self.counts
+    }
+
+    pub fn unknown(&self) -> impl Iterator<Item = u8> + '_ {
+        self.unknown.iter().copied()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    // Opcodes that ran at least once, most frequent first
+    pub fn by_count(&self) -> Vec<(u8, u64)> {
+        let mut used: Vec<(u8, u64)> = (0..=255u8)
+            .map(|opcode| (opcode, self.count(opcode)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        used.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        used
+    }
+}
+
+impl Default for OpcodeStats {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for OpcodeStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let total = self.total().max(1) as f64;
+        for (opcode, count) in self.by_count() {
+            let name = match opcodes::lookup(opcode) {
+                Some(op) if !self.unknown.contains(&opcode) => format!("{} {:?}", op.mnemonic, op.mode),
+                _ => "???".to_string(),
+            };
+            writeln!(f, "${:02X}  {:<16} {:>10} {:>6.1}%", opcode, name, count, count as f64 / total * 100.0)?;
+        }
+        if !self.unknown.is_empty() {
+            let list: Vec<String> = self.unknown().map(|opcode| format!("${:02X}", opcode)).collect();
+            writeln!(f, "unknown: {}", list.join(" "))?;
+        }
+        write!(f, "{} instructions, {} distinct opcodes", self.total(), self.by_count().len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    #[test]
+    fn counts_what_a_program_ran() {
+        let source = "
+                    ldx #$03
+            loop:   dex
+                    bne loop
+                    .byte $AB
+                    nop
+            spin:   jmp spin";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.enable_opcode_stats();
+        for _ in 0..13 {
+            nes.step();
+        }
+        let stats = nes.opcode_stats().unwrap();
+        let counts = [0xA2, 0xCA, 0xD0, 0xAB, 0xEA, 0x4C].map(|opcode| stats.count(opcode));
+        assert_eq!(counts, [1, 3, 3, 1, 1, 4]);
+        assert_eq!(stats.total(), 13);
+        assert_eq!(stats.unknown().collect::<Vec<_>>(), [0xAB]);
+        assert_eq!(stats.by_count()[..3], [(0x4C, 4), (0xCA, 3), (0xD0, 3)]);
+    }
+
+    #[test]
+    fn report_lists_by_count_with_unknowns_last() {
+        let mut stats = OpcodeStats::new();
+        for _ in 0..3 {
+            stats.record(0xA9);
+        }
+        stats.record(0xAB);
+        stats.record_unknown(0xAB);
+        let expected = "\
+$A9  LDA Immediate             3   75.0%
+$AB  ???                       1   25.0%
+unknown: $AB
+4 instructions, 2 distinct opcodes";
+        assert_eq!(stats.to_string(), expected);
+    }
+}
@@ -0,0 +1,244 @@
+use std::fmt;
+
+use crate::cpu::Cpu;
+use crate::mem;
+use crate::nes::{DOTS_PER_SCANLINE, PpuPosition, SCANLINES_PER_FRAME};
+use crate::opcodes::{self, AddrMode};
+
+// One frame's worth of CPU accesses to the PPU registers ($2000-$2007,
+// mirrors folded) and $4014, stamped with where the PPU was at the time.
+
+const REGISTER_NAMES: [&str; 8] = [
+    "PPUCTRL", "PPUMASK", "PPUSTATUS", "OAMADDR", "OAMDATA", "PPUSCROLL", "PPUADDR", "PPUDATA",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PpuAccess {
+    pub scanline: u16,
+    pub dot: u16,
+    pub pc: u16,
+    pub addr: u16, // $2000-$2007 or $4014
+    pub access: Access,
+    pub value: u8,
+}
+
+impl fmt::Display for PpuAccess {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self.addr {
+            0x4014 => "OAMDMA",
+            addr => REGISTER_NAMES[(addr & 0x07) as usize],
+        };
+        let arrow = match self.access {
+            Access::Read => "<-",
+            Access::Write => "->",
+        };
+        write!(
+            f,
+            "SL:{:<3} DOT:{:<3} PC:{:04X}  ${:02X} {} ${:04X} {}",
+            self.scanline, self.dot, self.pc, self.value, arrow, self.addr, name
+        )
+    }
+}
+
+// Folds $2008-$3FFF onto $2000-$2007; None for anything that isn't a PPU register
+fn ppu_register(addr: u16) -> Option<u16> {
+    match addr {
+        0x2000..=0x3FFF => Some(0x2000 | (addr & 0x07)),
+        0x4014 => Some(0x4014),
+        _ => None,
+    }
+}
+
+// PPU register the instruction at PC is about to read, if any. Called before
+// the instruction executes, like the code/data logger.
+pub fn register_read(cpu: &Cpu, memory: &mem::Memory) -> Option<(u16, u8)> {
+    let op = opcodes::lookup(memory.peek(cpu.pc))?;
+    match (op.mnemonic, op.mode) {
+        ("STA" | "STX" | "STY" | "JMP" | "JSR", _) => None,
+        (_, AddrMode::Implied | AddrMode::Accumulator | AddrMode::Immediate | AddrMode::Relative) => None,
+        (_, mode) => {
+            let addr = cpu.effective_address(memory, mode)?;
+            Some((ppu_register(addr)?, memory.peek(addr)))
+        }
+    }
+}
+
+pub struct PpuCapture {
+    frame: u64, // Frame being captured
+    complete: bool,
+    accesses: Vec<PpuAccess>,
+}
+
+impl PpuCapture {
+    pub fn new(frame: u64) -> Self {
+        Self {
+            frame,
+            complete: false,
+            accesses: Vec::new(),
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    pub fn accesses(&self) -> &[PpuAccess] {
+        &self.accesses
+    }
+
+    // Called after each instruction with its PPU register read (if any) and
+    // the CPU writes it made
+    pub fn observe(
+        &mut self,
+        position: PpuPosition,
+        pc: u16,
+        read: Option<(u16, u8)>,
+        writes: &[(u16, u8)],
+    ) {
+        if self.complete || position.frame < self.frame {
+            return;
+        }
+        if position.frame > self.frame {
+            self.complete = true;
+            return;
+        }
+
+        let access = |addr, access, value| PpuAccess {
+            scanline: position.scanline,
+            dot: position.dot,
+            pc,
+            addr,
+            access,
+            value,
+        };
+
+        if let Some((addr, value)) = read {
+            self.accesses.push(access(addr, Access::Read, value));
+        }
+        for &(addr, value) in writes {
+            if let Some(addr) = ppu_register(addr) {
+                self.accesses.push(access(addr, Access::Write, value));
+            }
+        }
+    }
+
+    pub fn table(&self) -> String {
+        let mut out = format!("Frame {}: {} PPU register accesses\n", self.frame, self.accesses.len());
+        for access in &self.accesses {
+            out.push_str(&format!("{}\n", access));
+        }
+        out
+    }
+
+    // 341x262 RGB image, one pixel per dot, with the visible area shaded and
+    // a small cross per access, coloured by register
+    pub fn grid_image(&self) -> Vec<u8> {
+        const COLORS: [[u8; 3]; 9] = [
+            [255, 64, 64],   // PPUCTRL
+            [255, 160, 0],   // PPUMASK
+            [255, 255, 64],  // PPUSTATUS
+            [64, 255, 64],   // OAMADDR
+            [0, 200, 160],   // OAMDATA
+            [64, 200, 255],  // PPUSCROLL
+            [96, 96, 255],   // PPUADDR
+            [200, 96, 255],  // PPUDATA
+            [255, 255, 255], // OAMDMA
+        ];
+        let (width, height) = (DOTS_PER_SCANLINE as usize, SCANLINES_PER_FRAME as usize);
+
+        let mut image = vec![0; width * height * 3];
+        for y in 0..240 {
+            for x in 1..=256 {
+                let i = (y * width + x) * 3;
+                image[i..i + 3].copy_from_slice(&[40, 40, 40]);
+            }
+        }
+
+        for access in &self.accesses {
+            let color = match access.addr {
+                0x4014 => COLORS[8],
+                addr => COLORS[(addr & 0x07) as usize],
+            };
+            let (x, y) = (access.dot as isize, access.scanline as isize);
+            for (dx, dy) in [(0, 0), (-1, 0), (1, 0), (0, -1), (0, 1)] {
+                let (px, py) = (x + dx, y + dy);
+                if (0..width as isize).contains(&px) && (0..height as isize).contains(&py) {
+                    let i = (py as usize * width + px as usize) * 3;
+                    image[i..i + 3].copy_from_slice(&color);
+                }
+            }
+        }
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::{DOTS_PER_CPU_CYCLE, Nes};
+    use crate::testbus;
+
+    // A split-screen style scroll change: the program spins until $10 is
+    // set, which the test does partway through scanline 100 of frame 1
+    #[test]
+    fn mid_frame_scroll_write_is_logged_at_its_scanline() {
+        let source = "
+     wait:  lda $10
+            beq wait
+            lda #$08
+            sta $2005
+            stx $2005
+     spin:  jmp spin";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.capture_ppu_frame();
+        assert_eq!(nes.ppu_capture().unwrap().frame(), 1);
+        nes.step(); // LDA $10
+        nes.step(); // BEQ, back to wait
+        let scanline_100 = (SCANLINES_PER_FRAME + 100) * DOTS_PER_SCANLINE;
+        nes.cpu.cycles = scanline_100.div_ceil(DOTS_PER_CPU_CYCLE);
+        nes.poke(0x0010, 1);
+        nes.step_frame();
+        nes.step_frame();
+
+        let capture = nes.ppu_capture().unwrap();
+        assert!(capture.is_complete());
+        // LDA, BEQ, LDA # and STA are 11 cycles on from dot 2
+        let expected = [
+            PpuAccess { scanline: 100, dot: 35, pc: 0xC006, addr: 0x2005, access: Access::Write, value: 0x08 },
+            PpuAccess { scanline: 100, dot: 47, pc: 0xC009, addr: 0x2005, access: Access::Write, value: 0x00 },
+        ];
+        assert_eq!(capture.accesses(), expected);
+        assert_eq!(
+            capture.table(),
+            "Frame 1: 2 PPU register accesses\n\
+             SL:100 DOT:35  PC:C006  $08 -> $2005 PPUSCROLL\n\
+             SL:100 DOT:47  PC:C009  $00 -> $2005 PPUSCROLL\n"
+        );
+    }
+
+    // Reads are logged with the value they return; mirrors fold onto $2000-$2007
+    #[test]
+    fn reads_and_mirrored_writes_are_folded() {
+        let mut capture = PpuCapture::new(0);
+        let position = PpuPosition { frame: 0, scanline: 241, dot: 10 };
+        capture.observe(position, 0xC000, Some((0x2002, 0x80)), &[(0x3FFE, 0x3F), (0x0200, 1), (0x4014, 0x02)]);
+        let logged: Vec<_> = capture.accesses().iter().map(|a| (a.addr, a.access, a.value)).collect();
+        let expected = [(0x2002, Access::Read, 0x80), (0x2006, Access::Write, 0x3F), (0x4014, Access::Write, 0x02)];
+        assert_eq!(logged, expected);
+        assert_eq!(capture.accesses()[2].to_string(), "SL:241 DOT:10  PC:C000  $02 -> $4014 OAMDMA");
+
+        capture.observe(PpuPosition { frame: 1, scanline: 0, dot: 0 }, 0xC003, None, &[(0x2000, 0)]);
+        assert!(capture.is_complete());
+        assert_eq!(capture.accesses().len(), 3);
+    }
+}
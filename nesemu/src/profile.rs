@@ -0,0 +1,168 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+// Opt-in self-profiling for frontends: time coarse sections of each frame
+// (emulation, presentation, ...) and whole frames, then print a breakdown.
+// Sampling happens at frame-sized boundaries only, so when the frontend
+// doesn't create a Profiler nothing is measured at all.
+//
+//   cpu            81.2%    1.234s
+//   frontend       18.8%    0.286s
+//   frames: 600  min 2.01ms  avg 2.53ms  max 4.80ms
+
+#[derive(Debug, Clone, Default)]
+pub struct Profiler {
+    sections: Vec<(&'static str, Duration)>, // In first-seen order
+    frames: u64,
+    frame_total: Duration,
+    frame_min: Duration,
+    frame_max: Duration,
+    frame_start: Option<Instant>,
+}
+
+impl Profiler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, section: &'static str, time: Duration) {
+        match self.sections.iter_mut().find(|(name, _)| *name == section) {
+            Some((_, total)) => *total += time,
+            None => self.sections.push((section, time)),
+        }
+    }
+
+    // Run `f`, charging its wall time to `section`
+    pub fn time<T>(&mut self, section: &'static str, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(section, start.elapsed());
+        result
+    }
+
+    pub fn begin_frame(&mut self) {
+        self.frame_start = Some(Instant::now());
+    }
+
+    // Closes the frame opened by begin_frame(); ignored without one
+    pub fn end_frame(&mut self) {
+        if let Some(start) = self.frame_start.take() {
+            self.add_frame(start.elapsed());
+        }
+    }
+
+    pub fn add_frame(&mut self, time: Duration) {
+        if self.frames == 0 || time < self.frame_min {
+            self.frame_min = time;
+        }
+        self.frame_max = self.frame_max.max(time);
+        self.frame_total += time;
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Each section's share of the total section time, in percent
+    pub fn percentages(&self) -> Vec<(&'static str, f64)> {
+        let total: Duration = self.sections.iter().map(|(_, time)| *time).sum();
+        self.sections
+            .iter()
+            .map(|&(name, time)| {
+                let share = if total.is_zero() {
+                    0.0
+                } else {
+                    time.as_secs_f64() / total.as_secs_f64() * 100.0
+                };
+                (name, share)
+            })
+            .collect()
+    }
+
+    pub fn average_frame(&self) -> Option<Duration> {
+        (self.frames > 0).then(|| self.frame_total / self.frames as u32)
+    }
+}
+
+impl fmt::Display for Profiler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for ((name, share), (_, time)) in self.percentages().into_iter().zip(&self.sections) {
+            writeln!(f, "{:<12} {:>6.1}%  {:>8.3}s", name, share, time.as_secs_f64())?;
+        }
+        match self.average_frame() {
+            Some(avg) => write!(
+                f,
+                "frames: {}  min {:.2}ms  avg {:.2}ms  max {:.2}ms",
+                self.frames,
+                self.frame_min.as_secs_f64() * 1000.0,
+                avg.as_secs_f64() * 1000.0,
+                self.frame_max.as_secs_f64() * 1000.0
+            ),
+            None => write!(f, "frames: 0"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // Elevenths and thirds don't come out exact, but the shares still add up
+    #[test]
+    fn percentages_sum_to_100() {
+        let mut profiler = Profiler::new();
+        profiler.add("cpu", ms(400));
+        profiler.add("frontend", ms(100));
+        profiler.add("cpu", ms(100));
+        profiler.add("audio", ms(500));
+
+        let shares = profiler.percentages();
+        let names: Vec<_> = shares.iter().map(|&(name, _)| name).collect();
+        assert_eq!(names, ["cpu", "frontend", "audio"], "first-seen order");
+        let sum: f64 = shares.iter().map(|&(_, share)| share).sum();
+        assert!((sum - 100.0).abs() < 1e-9, "{sum}");
+        assert!((shares[1].1 - 100.0 / 11.0).abs() < 1e-9);
+
+        let mut thirds = Profiler::new();
+        for section in ["a", "b", "c"] {
+            thirds.add(section, ms(7));
+        }
+        let sum: f64 = thirds.percentages().iter().map(|&(_, share)| share).sum();
+        assert!((sum - 100.0).abs() < 1e-9, "{sum}");
+    }
+
+    #[test]
+    fn frame_times_and_report() {
+        let mut profiler = Profiler::new();
+        assert_eq!(profiler.average_frame(), None);
+        assert_eq!(profiler.to_string(), "frames: 0");
+        profiler.end_frame(); // No frame open
+        assert_eq!(profiler.frames(), 0);
+
+        profiler.add("cpu", ms(900));
+        profiler.add("frontend", ms(300));
+        for time in [3, 2, 5, 2] {
+            profiler.add_frame(ms(time));
+        }
+        assert_eq!(profiler.average_frame(), Some(Duration::from_micros(3000)));
+        assert_eq!(
+            profiler.to_string(),
+            "cpu            75.0%     0.900s\n\
+             frontend       25.0%     0.300s\n\
+             frames: 4  min 2.00ms  avg 3.00ms  max 5.00ms"
+        );
+    }
+
+    // Sections with no time recorded yet share nothing
+    #[test]
+    fn zero_time_gives_zero_shares() {
+        let mut profiler = Profiler::new();
+        profiler.add("cpu", Duration::ZERO);
+        assert_eq!(profiler.percentages(), [("cpu", 0.0)]);
+    }
+}
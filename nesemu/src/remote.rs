@@ -0,0 +1,237 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::thread::{self, JoinHandle};
+
+use crate::eventstream::{hash_bytes, json_string};
+use crate::input::ButtonState;
+use crate::nes::Nes;
+
+// Line-based remote control. Each request is one line of whitespace
+// separated words; each response is one line of JSON with an `ok` field.
+//
+//   load <path>              load a ROM from the ROM directory and power
+//                            it on; refused unless one was configured
+//   reset [hard]             press Reset, or power cycle with "hard"
+//   step <frames> [p1] [p2]  run whole frames holding the given buttons
+//                            (hex masks, bit 0 = A ... bit 7 = Right)
+//   read <addr> <len>        read memory (hex address) -> {"data":[...]}
+//   hash                     hash of CPU RAM
+//   framebuffer              not available (no PPU)
+//   save_state / load_state  not available
+//   quit                     close the connection
+//
+// Connections are served one at a time on the emulator's thread, and every
+// command runs to completion before the next is read, so a given command
+// sequence always produces the same results.
+//
+// There is no authentication: anyone who can connect can drive the
+// emulator and read its memory, so bind to a loopback address. `load` only
+// opens files inside the directory given to with_rom_dir, so a client
+// can't use it to probe the rest of the filesystem.
+
+pub struct Server {
+    nes: Option<Nes>,
+    rom_dir: Option<PathBuf>,
+}
+
+impl Server {
+    pub fn new(nes: Option<Nes>) -> Self {
+        Self { nes, rom_dir: None }
+    }
+
+    // Let `load` open ROMs from `dir` (and its subdirectories)
+    pub fn with_rom_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.rom_dir = Some(dir.into());
+        self
+    }
+
+    // Bind and serve on a new thread; returns the bound address so callers
+    // can ask for port 0
+    pub fn spawn(self, addr: impl ToSocketAddrs) -> io::Result<(SocketAddr, JoinHandle<()>)> {
+        let listener = TcpListener::bind(addr)?;
+        let local_addr = listener.local_addr()?;
+        let handle = thread::spawn(move || {
+            let mut server = self;
+            for stream in listener.incoming() {
+                let result = stream.and_then(|stream| server.handle_connection(stream));
+                if let Err(err) = result {
+                    eprintln!("remote: {}", err);
+                }
+            }
+        });
+        Ok((local_addr, handle))
+    }
+
+    fn handle_connection(&mut self, stream: TcpStream) -> io::Result<()> {
+        let mut writer = stream.try_clone()?;
+        for line in BufReader::new(stream).lines() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            if line.trim() == "quit" {
+                break;
+            }
+            let response = match self.execute(&line) {
+                Ok(fields) => format!("{{\"ok\":true{}}}", fields),
+                Err(err) => format!("{{\"ok\":false,\"error\":{}}}", json_string(&err)),
+            };
+            writeln!(writer, "{}", response)?;
+        }
+        Ok(())
+    }
+
+    // Returns the extra response fields, each with a leading comma
+    pub fn execute(&mut self, line: &str) -> Result<String, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let Some(&name) = words.first() else {
+            return Err("empty command".to_string());
+        };
+        let arg = |i: usize| -> Result<&str, String> {
+            words.get(i).copied().ok_or_else(|| format!("'{}' needs more arguments", name))
+        };
+
+        match name {
+            "load" => {
+                let path = self.rom_path(arg(1)?)?;
+                self.nes = Some(Nes::from_file(&path).map_err(|e| e.to_string())?);
+                Ok(String::new())
+            }
+            "reset" => {
+                let nes = self.nes()?;
+                match words.get(1).copied() {
+                    None => nes.soft_reset(),
+                    Some("hard") => nes.power_cycle(),
+                    Some(other) => return Err(format!("unknown reset kind '{}'", other)),
+                }
+                Ok(String::new())
+            }
+            "step" => {
+                let frames: u64 = arg(1)?
+                    .parse()
+                    .map_err(|_| format!("invalid frame count '{}'", words[1]))?;
+                let mut buttons = [ButtonState::default(); 2];
+                for (port, mask) in words.iter().skip(2).take(2).enumerate() {
+                    let bits = u8::from_str_radix(mask.trim_start_matches('$'), 16)
+                        .map_err(|_| format!("invalid button mask '{}'", mask))?;
+                    buttons[port] = ButtonState::from_bits(bits);
+                }
+                let nes = self.nes()?;
+                nes.set_controller(0, buttons[0]);
+                nes.set_controller(1, buttons[1]);
+                for _ in 0..frames {
+                    nes.run_frame();
+                }
+                let frame = nes.ppu_position().frame;
+                Ok(format!(",\"frame\":{},\"cycle\":{}", frame, nes.cpu.cycles))
+            }
+            "read" => {
+                let addr = u16::from_str_radix(arg(1)?.trim_start_matches('$'), 16)
+                    .map_err(|_| format!("invalid address '{}'", words[1]))?;
+                let len: u32 = arg(2)?
+                    .parse()
+                    .map_err(|_| format!("invalid length '{}'", words[2]))?;
+                let nes = self.nes()?;
+                let data: Vec<String> = (0..len.min(0x10000))
+                    .map(|i| nes.memory.peek(addr.wrapping_add(i as u16)).to_string())
+                    .collect();
+                Ok(format!(",\"data\":[{}]", data.join(",")))
+            }
+            "hash" => {
+                let nes = self.nes()?;
+                Ok(format!(",\"hash\":\"{:016x}\"", hash_bytes(nes.memory.ram())))
+            }
+            "framebuffer" => Err("there is no frame buffer (PPU is not emulated)".to_string()),
+            "save_state" | "load_state" => Err("save states are not supported".to_string()),
+            other => Err(format!("unknown command '{}'", other)),
+        }
+    }
+
+    fn nes(&mut self) -> Result<&mut Nes, String> {
+        self.nes.as_mut().ok_or_else(|| "no ROM loaded".to_string())
+    }
+
+    // `name` resolved inside the ROM directory. Both sides are canonicalized
+    // first so neither `..` nor a symlink can lead out of it.
+    fn rom_path(&self, name: &str) -> Result<PathBuf, String> {
+        let dir = self.rom_dir.as_ref().ok_or("load is disabled (no ROM directory configured)")?;
+        let dir = dir.canonicalize().map_err(|e| format!("ROM directory: {}", e))?;
+        let path = dir.join(Path::new(name)).canonicalize().map_err(|e| format!("{}: {}", name, e))?;
+        if !path.starts_with(&dir) {
+            return Err(format!("'{}' is outside the ROM directory", name));
+        }
+        Ok(path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    use crate::testbus;
+
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn connect(addr: SocketAddr) -> Self {
+            let writer = TcpStream::connect(addr).unwrap();
+            let reader = BufReader::new(writer.try_clone().unwrap());
+            Self { reader, writer }
+        }
+
+        fn send(&mut self, line: &str) -> String {
+            writeln!(self.writer, "{}", line).unwrap();
+            let mut response = String::new();
+            self.reader.read_line(&mut response).unwrap();
+            response.trim_end().to_string()
+        }
+    }
+
+    #[test]
+    fn serves_the_protocol_over_tcp() {
+        let dir = std::env::temp_dir().join(format!("nesemu-{}-remote", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("roms")).unwrap();
+        let rom = testbus::ines_image("lda #$42\n sta $10\n spin: jmp spin", 0).unwrap();
+        fs::write(dir.join("roms/game.nes"), &rom).unwrap();
+        fs::write(dir.join("outside.nes"), &rom).unwrap();
+
+        let server = Server::new(None).with_rom_dir(dir.join("roms"));
+        let (addr, _) = server.spawn("127.0.0.1:0").unwrap();
+        let mut client = Client::connect(addr);
+
+        assert_eq!(client.send("hash"), r#"{"ok":false,"error":"no ROM loaded"}"#);
+        assert_eq!(
+            client.send("load ../outside.nes"),
+            r#"{"ok":false,"error":"'../outside.nes' is outside the ROM directory"}"#
+        );
+        assert_eq!(client.send("load game.nes"), r#"{"ok":true}"#);
+        assert!(client.send("step 2 01").starts_with(r#"{"ok":true,"frame":2,"cycle":"#));
+        assert_eq!(client.send("read 10 1"), r#"{"ok":true,"data":[66]}"#);
+        assert_eq!(client.send("read c000 3"), r#"{"ok":true,"data":[169,66,133]}"#);
+        assert!(client.send("hash").starts_with(r#"{"ok":true,"hash":""#));
+        assert_eq!(client.send("read zz 1"), r#"{"ok":false,"error":"invalid address 'zz'"}"#);
+        assert_eq!(client.send("frobnicate"), r#"{"ok":false,"error":"unknown command 'frobnicate'"}"#);
+
+        // quit closes this connection; the server takes the next one
+        writeln!(client.writer, "quit").unwrap();
+        assert_eq!(client.reader.read_line(&mut String::new()).unwrap(), 0);
+        let mut client = Client::connect(addr);
+        assert_eq!(client.send("read 10 1"), r#"{"ok":true,"data":[66]}"#);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn load_is_refused_without_a_rom_directory() {
+        let mut server = Server::new(None);
+        assert_eq!(
+            server.execute("load /etc/passwd"),
+            Err("load is disabled (no ROM directory configured)".to_string())
+        );
+    }
+}
@@ -1,21 +1,72 @@
 use std::{fs::File, io::{Error, ErrorKind, Read, Result}};
 
+use crate::cartridge::{Cartridge, Mirroring, PrgRomError};
+
+pub const NMI_VECTOR: u16 = 0xFFFA;
+pub const RESET_VECTOR: u16 = 0xFFFC;
+pub const IRQ_VECTOR: u16 = 0xFFFE;
+
 pub struct Rom {
     pub prg_rom : Vec<u8>,
     pub chr_rom: Vec<u8>,
+    pub mapper: u8,
+    pub mirroring: Mirroring,
+    pub has_trainer: bool,
+    pub has_battery: bool, // PRG-RAM is kept alive between runs
+    pub expansion_device: ExpansionDevice,
+}
+
+// NES 2.0 byte 15: the input device the game expects to find. iNES 1.0
+// headers have no such field and report Unspecified.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpansionDevice {
+    Unspecified,
+    StandardControllers,
+    FourScore,
+    FamicomFourPlayers,
+    Zapper, // On port 2
+    TwoZappers,
+    Other(u8),
+}
+
+impl ExpansionDevice {
+    pub fn from_byte(byte: u8) -> Self {
+        match byte & 0x3F {
+            0x00 => ExpansionDevice::Unspecified,
+            0x01 => ExpansionDevice::StandardControllers,
+            0x02 => ExpansionDevice::FourScore,
+            0x03 => ExpansionDevice::FamicomFourPlayers,
+            0x08 => ExpansionDevice::Zapper,
+            0x09 => ExpansionDevice::TwoZappers,
+            other => ExpansionDevice::Other(other),
+        }
+    }
+}
+
+// Header summary that outlives the ROM data (for crash dumps and the like)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RomInfo {
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u8,
+    pub has_trainer: bool,
+    pub has_battery: bool,
+    pub expansion_device: ExpansionDevice,
 }
 
 impl Rom {
     pub fn check_magic(magic_bytes: &[u8]) -> bool {
-        return magic_bytes == b"NES\x1A"
+        magic_bytes == b"NES\x1A"
     }
 
     pub fn parse(mut rom_file: File) -> Result<Rom> {
         let mut rom = Vec::new();
         rom_file.read_to_end(&mut rom)?;
+        Self::from_bytes(&rom)
+    }
 
-        println!("Read {} bytes from ROM", rom.len());
-
+    // Parse an iNES image already in memory
+    pub fn from_bytes(rom: &[u8]) -> Result<Rom> {
         // Check minimum length (16-byte header)
         if rom.len() < 16 {
             return Err(Error::new(ErrorKind::InvalidData, "ROM too short to contain NES header"));
@@ -33,15 +84,24 @@ impl Rom {
         let flags6 = rom[6];
         let flags7 = rom[7];
         
+        let has_battery = (flags6 & 0b00000010) != 0; // Battery-backed PRG-RAM?
         let has_trainer = (flags6 & 0b00000100) != 0; // Trainer present?
+        let mirroring = if flags6 & 0b00001000 != 0 {
+            Mirroring::FourScreen
+        } else if flags6 & 0b00000001 != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
+        };
         let mapper_low = flags6 >> 4;
         let mapper_high = flags7 >> 4;
         let mapper = (mapper_high << 4) | mapper_low;
-
-        println!("PRG-ROM size: {} KB", prg_rom_size / 1024);
-        println!("CHR-ROM size: {} KB", chr_rom_size / 1024);
-        println!("Mapper: {}", mapper);
-        println!("Has trainer: {}", has_trainer);
+        let nes2 = flags7 & 0x0C == 0x08;
+        let expansion_device = if nes2 {
+            ExpansionDevice::from_byte(rom[15])
+        } else {
+            ExpansionDevice::Unspecified
+        };
 
         // Calculate where PRG-ROM and CHR-ROM start
         let mut offset = 16; // Skip header
@@ -51,37 +111,160 @@ impl Rom {
             offset += 512; // Trainer is always 512 bytes
         }
 
+        // The header sizes are untrusted; a short file is an error, not a panic
+        if prg_rom_size == 0 {
+            return Err(Error::new(ErrorKind::InvalidData, "ROM has no PRG-ROM"));
+        }
+        let mut section = |size: usize, name: &str| {
+            let data = rom.get(offset..offset + size).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::InvalidData,
+                    format!("ROM truncated: {} needs {} bytes at offset 0x{:X}, file is {} bytes", name, size, offset, rom.len()),
+                )
+            })?;
+            offset += size;
+            Ok::<_, Error>(data.to_vec())
+        };
+
         // Extract PRG-ROM (CPU instructions)
-        let prg_rom = rom[offset..offset + prg_rom_size].to_vec();
-        offset += prg_rom_size;
+        let prg_rom = section(prg_rom_size, "PRG-ROM")?;
 
         // Extract CHR-ROM (Graphics data)
-        let chr_rom = rom[offset..offset + chr_rom_size].to_vec();
-        offset += chr_rom_size;
+        let chr_rom = section(chr_rom_size, "CHR-ROM")?;
+
+        Ok(Rom {
+            prg_rom,
+            chr_rom,
+            mapper,
+            mirroring,
+            has_trainer,
+            has_battery,
+            expansion_device,
+        })
+    }
+
+    // The word at a CPU vector ($FFFA-$FFFF) on power-up. Those addresses
+    // always show the end of the image: a 16 KiB one is mirrored up to
+    // $C000-$FFFF, 32 KiB maps straight, and most mappers start with the
+    // last bank there.
+    pub fn vector(&self, addr: u16) -> u16 {
+        let offset = self.prg_rom.len() - (0x10000 - addr as usize);
+        u16::from_le_bytes([self.prg_rom[offset], self.prg_rom[offset + 1]])
+    }
 
-        println!("PRG-ROM starts at 0x{:X}, ends at 0x{:X}", 16, 16 + prg_rom_size);
-        println!("CHR-ROM starts at 0x{:X}, ends at 0x{:X}", 16 + prg_rom_size, 16 + prg_rom_size + chr_rom_size);
+    // The board the image describes, ready to plug into Memory
+    pub fn into_cartridge(self) -> std::result::Result<Cartridge, PrgRomError> {
+        let mut cartridge = Cartridge::new(self.prg_rom, self.chr_rom, self.mirroring, self.mapper)?;
+        cartridge.set_battery(self.has_battery);
+        Ok(cartridge)
+    }
 
-        println!("\nFirst few PRG-ROM bytes (opcodes):");
-        for &byte in prg_rom.iter().take(16) {
-            print!("{:02X} ", byte);
+    pub fn info(&self) -> RomInfo {
+        RomInfo {
+            prg_rom_size: self.prg_rom.len(),
+            chr_rom_size: self.chr_rom.len(),
+            mapper: self.mapper,
+            has_trainer: self.has_trainer,
+            has_battery: self.has_battery,
+            expansion_device: self.expansion_device,
         }
-        println!();
+    }
+}
 
-        let reset_vector = {
-            let lo = prg_rom[prg_rom.len() - 4] as u16;
-            let hi = prg_rom[prg_rom.len() - 3] as u16;
-            (hi << 8) | lo
-        };
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
 
-        println!("Reset vector: ${:04X}", reset_vector);
+    // xorshift64, so the mutations are the same on every run
+    struct Rng(u64);
 
-        let prg_rom_start = 16;
-        let chr_rom_start = prg_rom_start + prg_rom_size;
-        
-        Ok(Rom {
-            prg_rom: rom[prg_rom_start..chr_rom_start].to_vec(),
-            chr_rom: rom[chr_rom_start..chr_rom_start + chr_rom_size].to_vec(),
-        })
+    impl Rng {
+        fn next(&mut self) -> u64 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+            self.0
+        }
+
+        fn below(&mut self, n: usize) -> usize {
+            (self.next() % n as u64) as usize
+        }
+    }
+
+    // NES 2.0 with a trainer, one bank of each and the Zapper as the
+    // expansion device
+    fn nes2_image() -> Vec<u8> {
+        let mut image = vec![b'N', b'E', b'S', 0x1A, 1, 1, 0x05, 0x08, 0, 0, 0, 0, 0, 0, 0, 0x08];
+        image.resize(16 + 512 + 0x4000 + 0x2000, 0xEA);
+        image
+    }
+
+    fn check_invariants(data: &[u8], rom: &Rom) {
+        assert_eq!(rom.prg_rom.len(), data[4] as usize * 0x4000);
+        assert_eq!(rom.chr_rom.len(), data[5] as usize * 0x2000);
+        assert!(!rom.prg_rom.is_empty());
+        let trainer = if rom.has_trainer { 512 } else { 0 };
+        assert!(16 + trainer + rom.prg_rom.len() + rom.chr_rom.len() <= data.len());
+        // The vectors are always inside the image
+        rom.vector(NMI_VECTOR);
+        rom.vector(IRQ_VECTOR);
+    }
+
+    #[test]
+    fn seeds_parse() {
+        let rom = Rom::from_bytes(&testbus::ines_image("", 0).unwrap()).unwrap();
+        assert_eq!((rom.prg_rom.len(), rom.chr_rom.len(), rom.mapper), (0x4000, 0, 0));
+        assert_eq!(rom.vector(RESET_VECTOR), 0xC000);
+
+        let rom = Rom::from_bytes(&nes2_image()).unwrap();
+        assert!(rom.has_trainer);
+        assert_eq!(rom.mirroring, Mirroring::Vertical);
+        assert_eq!(rom.expansion_device, ExpansionDevice::Zapper);
+    }
+
+    // Mostly header damage, since that's what the parser trusts, plus
+    // truncation and stray bytes further in. Any input must come back as
+    // Ok or Err without panicking.
+    #[test]
+    fn mutated_images_never_panic() {
+        let seeds = [testbus::ines_image("", 0).unwrap(), nes2_image()];
+        let mut rng = Rng(0x9E37_79B9_7F4A_7C15);
+        for _ in 0..5_000 {
+            let mut data = seeds[rng.below(seeds.len())].clone();
+            for _ in 0..1 + rng.below(4) {
+                match rng.below(4) {
+                    0 | 1 => {
+                        let index = rng.below(16);
+                        data[index] = rng.next() as u8;
+                    }
+                    2 => data.truncate(rng.below(data.len() + 1)),
+                    _ if !data.is_empty() => {
+                        let index = rng.below(data.len());
+                        data[index] ^= 1 << rng.below(8);
+                    }
+                    _ => {}
+                }
+                if data.len() < 16 {
+                    break;
+                }
+            }
+
+            if let Ok(rom) = Rom::from_bytes(&data) {
+                check_invariants(&data, &rom);
+                let _ = rom.into_cartridge();
+            }
+        }
+    }
+
+    #[test]
+    fn truncated_and_empty_images_are_errors() {
+        let image = testbus::ines_image("", 0).unwrap();
+        for len in [0, 15, 16, 17, 0x4000, image.len() - 1] {
+            assert!(Rom::from_bytes(&image[..len]).is_err(), "{len} bytes");
+        }
+        let mut no_prg = image.clone();
+        no_prg[4] = 0;
+        assert!(Rom::from_bytes(&no_prg).is_err());
     }
 }
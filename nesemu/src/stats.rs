@@ -0,0 +1,198 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::bench::NTSC_FPS;
+
+// How fast emulation is actually running, over a window of recent frames.
+// Fed the host time between completed frames, so it measures everything the
+// frontend does per frame, not just the CPU.
+
+pub const DEFAULT_WINDOW: usize = 120;
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    times: Vec<Duration>, // Ring buffer of the newest frame times
+    next: usize,          // Slot the next frame time goes in
+    window: usize,
+    frames: u64,
+    last_frame: Option<Instant>,
+}
+
+// Snapshot of the window, cheap to copy into events and reports
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StatsSummary {
+    pub frames: u64, // Frames recorded since stats were enabled
+    pub average: Duration,
+    pub p50: Duration,
+    pub p95: Duration,
+    pub max: Duration,
+}
+
+impl Stats {
+    pub fn new(window: usize) -> Self {
+        let window = window.max(1);
+        Self {
+            times: Vec::with_capacity(window),
+            next: 0,
+            window,
+            frames: 0,
+            last_frame: None,
+        }
+    }
+
+    // A frame just completed; the first call only starts the clock
+    pub fn frame_completed(&mut self, now: Instant) {
+        if let Some(last) = self.last_frame.replace(now) {
+            self.record_frame(now - last);
+        }
+    }
+
+    pub fn record_frame(&mut self, time: Duration) {
+        if self.times.len() < self.window {
+            self.times.push(time);
+        } else {
+            self.times[self.next] = time;
+        }
+        self.next = (self.next + 1) % self.window;
+        self.frames += 1;
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    pub fn window(&self) -> usize {
+        self.window
+    }
+
+    // Nearest-rank percentile of the window, `percent` in 0..=100
+    pub fn percentile(&self, percent: f64) -> Option<Duration> {
+        if self.times.is_empty() {
+            return None;
+        }
+        let mut sorted = self.times.clone();
+        sorted.sort_unstable();
+        let rank = (percent.clamp(0.0, 100.0) / 100.0 * sorted.len() as f64).ceil() as usize;
+        Some(sorted[rank.saturating_sub(1)])
+    }
+
+    pub fn summary(&self) -> Option<StatsSummary> {
+        let total: Duration = self.times.iter().sum();
+        Some(StatsSummary {
+            frames: self.frames,
+            average: total / self.times.len().max(1) as u32,
+            p50: self.percentile(50.0)?,
+            p95: self.percentile(95.0)?,
+            max: self.times.iter().max().copied()?,
+        })
+    }
+}
+
+impl Default for Stats {
+    fn default() -> Self {
+        Self::new(DEFAULT_WINDOW)
+    }
+}
+
+impl StatsSummary {
+    pub fn fps(&self) -> f64 {
+        if self.average.is_zero() {
+            return 0.0;
+        }
+        1.0 / self.average.as_secs_f64()
+    }
+
+    // Speed relative to a real NTSC console
+    pub fn realtime_percent(&self) -> f64 {
+        self.fps() / NTSC_FPS * 100.0
+    }
+
+    pub fn to_json(&self) -> String {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        format!(
+            "{{\"frames\":{},\"fps\":{:.2},\"realtime_percent\":{:.1},\"p50_ms\":{:.3},\"p95_ms\":{:.3},\"max_ms\":{:.3}}}",
+            self.frames,
+            self.fps(),
+            self.realtime_percent(),
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.max)
+        )
+    }
+}
+
+impl fmt::Display for StatsSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let ms = |time: Duration| time.as_secs_f64() * 1000.0;
+        write!(
+            f,
+            "{:.1} fps ({:.0}%)  p50 {:.2}ms  p95 {:.2}ms  max {:.2}ms",
+            self.fps(),
+            self.realtime_percent(),
+            ms(self.p50),
+            ms(self.p95),
+            ms(self.max)
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    // 1-20ms in a scrambled order; nearest rank picks whole samples
+    #[test]
+    fn percentiles_use_the_nearest_rank() {
+        let mut stats = Stats::new(32);
+        assert_eq!(stats.percentile(50.0), None);
+        assert_eq!(stats.summary(), None);
+        for i in 0..20 {
+            stats.record_frame(ms(i * 7 % 20 + 1));
+        }
+        assert_eq!(stats.percentile(50.0), Some(ms(10)));
+        assert_eq!(stats.percentile(95.0), Some(ms(19)));
+        assert_eq!(stats.percentile(96.0), Some(ms(20)));
+        assert_eq!(stats.percentile(0.0), Some(ms(1)));
+        assert_eq!(stats.percentile(150.0), Some(ms(20)));
+
+        let summary = stats.summary().unwrap();
+        assert_eq!((summary.frames, summary.average, summary.max), (20, Duration::from_micros(10500), ms(20)));
+    }
+
+    // The window keeps the newest frames only; the count keeps going
+    #[test]
+    fn old_frames_leave_the_window() {
+        let mut stats = Stats::new(4);
+        for time in [100, 1, 2, 3, 4] {
+            stats.record_frame(ms(time));
+        }
+        let summary = stats.summary().unwrap();
+        assert_eq!((summary.frames, summary.p95, summary.max), (5, ms(4), ms(4)));
+        assert_eq!(Stats::new(0).window(), 1);
+    }
+
+    // Made-up frame boundaries 16ms and 34ms apart
+    #[test]
+    fn frame_completions_time_the_gaps() {
+        let start = Instant::now();
+        let mut stats = Stats::default();
+        stats.frame_completed(start);
+        assert_eq!(stats.frames(), 0, "the first call only starts the clock");
+        stats.frame_completed(start + ms(16));
+        stats.frame_completed(start + ms(50));
+
+        let summary = stats.summary().unwrap();
+        let expected = StatsSummary { frames: 2, average: ms(25), p50: ms(16), p95: ms(34), max: ms(34) };
+        assert_eq!(summary, expected);
+        assert_eq!(summary.fps(), 40.0);
+        assert_eq!(summary.to_string(), "40.0 fps (67%)  p50 16.00ms  p95 34.00ms  max 34.00ms");
+        assert_eq!(
+            summary.to_json(),
+            "{\"frames\":2,\"fps\":40.00,\"realtime_percent\":66.6,\"p50_ms\":16.000,\"p95_ms\":34.000,\"max_ms\":34.000}"
+        );
+    }
+}
@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Label tables for the debugger and disassembler. Loads FCEUX .nl files:
+// one `<rom>.ram.nl` for $0000-$7FFF and one `<rom>.<bank>.nl` per 16 KiB
+// PRG bank (bank number in hex), with lines like `$C123#reset_handler#comment`.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub comment: Option<String>,
+    pub bank: Option<u16>, // None for RAM/register symbols
+    pub addr: u16,
+    pub size: u16,         // Bytes covered; arrays use the `$addr/size` form
+}
+
+pub struct Symbols {
+    by_addr: HashMap<u16, Vec<Symbol>>,
+    by_name: HashMap<String, u16>,
+}
+
+impl Symbols {
+    pub fn new() -> Self {
+        Self {
+            by_addr: HashMap::new(),
+            by_name: HashMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_name.is_empty()
+    }
+
+    pub fn add(&mut self, symbol: Symbol) {
+        self.by_name.insert(symbol.name.clone(), symbol.addr);
+        self.by_addr.entry(symbol.addr).or_default().push(symbol);
+    }
+
+    // Symbol at `addr`, preferring unbanked (RAM) symbols over PRG ones
+    pub fn lookup(&self, addr: u16) -> Option<&Symbol> {
+        let candidates = self.by_addr.get(&addr)?;
+        candidates
+            .iter()
+            .find(|s| s.bank.is_none())
+            .or_else(|| candidates.first())
+    }
+
+    pub fn lookup_in_bank(&self, bank: u16, addr: u16) -> Option<&Symbol> {
+        self.by_addr
+            .get(&addr)?
+            .iter()
+            .find(|s| s.bank.is_none() || s.bank == Some(bank))
+    }
+
+    // Reverse lookup for commands like `bp reset_handler`
+    pub fn address_of(&self, name: &str) -> Option<u16> {
+        self.by_name.get(name).copied()
+    }
+
+    // Parse the contents of one .nl file
+    pub fn parse_fceux_nl(&mut self, text: &str, bank: Option<u16>) -> Result<(), String> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                continue;
+            }
+            let fail = |msg: &str| format!("line {}: {}", index + 1, msg);
+
+            let mut fields = line.splitn(3, '#');
+            let location = fields.next().unwrap_or("");
+            let name = fields.next().ok_or_else(|| fail("missing '#' after address"))?;
+            let comment = fields
+                .next()
+                .map(|c| c.trim_end_matches('#').trim())
+                .filter(|c| !c.is_empty())
+                .map(str::to_string);
+
+            let location = location
+                .strip_prefix('$')
+                .ok_or_else(|| fail("address must start with '$'"))?;
+            let (addr, size) = match location.split_once('/') {
+                Some((addr, size)) => (addr, size),
+                None => (location, "1"),
+            };
+            let addr = u16::from_str_radix(addr, 16).map_err(|_| fail("invalid address"))?;
+            let size = u16::from_str_radix(size, 16).map_err(|_| fail("invalid array size"))?;
+
+            // Unnamed entries only carry a comment
+            if name.is_empty() {
+                continue;
+            }
+
+            self.add(Symbol {
+                name: name.to_string(),
+                comment,
+                bank,
+                addr,
+                size,
+            });
+        }
+        Ok(())
+    }
+
+    // Load a single .nl file, taking the bank from its name
+    pub fn load_fceux_nl(&mut self, path: &Path) -> Result<(), String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+
+        let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("");
+        let bank = match stem.rsplit_once('.') {
+            Some((_, "ram")) | None => None,
+            Some((_, bank)) => u16::from_str_radix(bank, 16).ok(),
+        };
+
+        self.parse_fceux_nl(&text, bank)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // Load every FCEUX label file sitting next to the ROM; returns how many were found
+    pub fn load_for_rom(&mut self, rom_path: &Path) -> Result<usize, String> {
+        let mut loaded = 0;
+        let ram = format!("{}.ram.nl", rom_path.display());
+        let banks = (0..0x100).map(|bank| format!("{}.{:X}.nl", rom_path.display(), bank));
+
+        for file in std::iter::once(ram).chain(banks) {
+            let path = Path::new(&file);
+            if path.exists() {
+                self.load_fceux_nl(path)?;
+                loaded += 1;
+            }
+        }
+        Ok(loaded)
+    }
+}
+
+impl Default for Symbols {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fceux_lines() {
+        let mut symbols = Symbols::new();
+        let text = "$0000#temp#\n\n$0300/20#buffer#32 bytes#\n$0010##just a comment\n$C000#reset#Entry point";
+        symbols.parse_fceux_nl(text, Some(1)).unwrap();
+
+        let buffer = symbols.lookup(0x0300).unwrap();
+        assert_eq!(buffer.name, "buffer");
+        assert_eq!((buffer.size, buffer.comment.as_deref()), (0x20, Some("32 bytes")));
+        let reset = symbols.lookup(0xC000).unwrap();
+        assert_eq!((reset.bank, reset.comment.as_deref()), (Some(1), Some("Entry point")));
+        assert_eq!(symbols.lookup(0x0000).unwrap().comment, None);
+        assert_eq!(symbols.lookup(0x0010), None, "unnamed entries are skipped");
+    }
+
+    #[test]
+    fn bad_lines_name_the_line() {
+        let mut symbols = Symbols::new();
+        let missing_dollar = symbols.parse_fceux_nl("$00#ok#\nC000#reset#", None);
+        assert_eq!(missing_dollar, Err("line 2: address must start with '$'".into()));
+        assert_eq!(symbols.parse_fceux_nl("$C000", None), Err("line 1: missing '#' after address".into()));
+        assert_eq!(symbols.parse_fceux_nl("$G000#x#", None), Err("line 1: invalid address".into()));
+        assert_eq!(symbols.parse_fceux_nl("$0300/zz#x#", None), Err("line 1: invalid array size".into()));
+    }
+
+    // The same address can be a RAM label and a label in each PRG bank
+    #[test]
+    fn lookup_prefers_ram_then_the_bank() {
+        let mut symbols = Symbols::new();
+        symbols.parse_fceux_nl("$8000#bank0_start#", Some(0)).unwrap();
+        symbols.parse_fceux_nl("$8000#bank1_start#", Some(1)).unwrap();
+        symbols.parse_fceux_nl("$6000#save_ram#", None).unwrap();
+        symbols.parse_fceux_nl("$6000#bank2_save#", Some(2)).unwrap();
+
+        assert_eq!(symbols.lookup(0x8000).unwrap().name, "bank0_start");
+        assert_eq!(symbols.lookup_in_bank(1, 0x8000).unwrap().name, "bank1_start");
+        assert_eq!(symbols.lookup_in_bank(3, 0x8000), None);
+        assert_eq!(symbols.lookup(0x6000).unwrap().name, "save_ram");
+        assert_eq!(symbols.lookup_in_bank(2, 0x6000).unwrap().name, "save_ram");
+    }
+
+    #[test]
+    fn reverse_lookup_finds_the_address() {
+        let mut symbols = Symbols::new();
+        assert!(symbols.is_empty());
+        symbols.parse_fceux_nl("$C000#reset#\n$C123#nmi_handler#", Some(0)).unwrap();
+        assert!(!symbols.is_empty());
+        assert_eq!(symbols.address_of("nmi_handler"), Some(0xC123));
+        assert_eq!(symbols.address_of("reset"), Some(0xC000));
+        assert_eq!(symbols.address_of("irq_handler"), None);
+    }
+
+    // File names carry the bank: game.nes.ram.nl and game.nes.<bank>.nl
+    #[test]
+    fn loads_the_files_next_to_the_rom() {
+        let dir = std::env::temp_dir().join(format!("nesemu-{}-symbols", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let rom = dir.join("game.nes");
+        fs::write(dir.join("game.nes.ram.nl"), "$0040#lives#").unwrap();
+        fs::write(dir.join("game.nes.1F.nl"), "$E000#fixed#").unwrap();
+
+        let mut symbols = Symbols::new();
+        assert_eq!(symbols.load_for_rom(&rom), Ok(2));
+        assert_eq!(symbols.lookup(0x0040).unwrap().bank, None);
+        assert_eq!(symbols.lookup(0xE000).unwrap().bank, Some(0x1F));
+
+        fs::write(dir.join("game.nes.2.nl"), "$8000").unwrap();
+        let err = Symbols::new().load_for_rom(&rom).unwrap_err();
+        assert!(err.ends_with("game.nes.2.nl: line 1: missing '#' after address"), "{err}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
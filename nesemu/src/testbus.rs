@@ -0,0 +1,242 @@
+use crate::asm;
+use crate::bus::Bus;
+use crate::cartridge::Cartridge;
+use crate::cpu::Cpu;
+use crate::mem;
+
+// Memory setup for CPU tests and experiments: a 32 KiB PRG image mapped at
+// $8000-$FFFF plus whatever RAM contents the test wants.
+//
+//   let bus = TestBus::builder()
+//       .program_at(0x8000, &[0xA9, 0x01, 0x00])
+//       .reset_vector(0x8000)
+//       .build();
+
+const PRG_SIZE: usize = 0x8000;
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
+
+// The memory map a TestBus builds
+pub type TestBus = mem::Memory;
+
+// 64 KiB of plain RAM and nothing else, for CPU-only programs that don't
+// want the NES memory map in the way: no mirroring, no registers, and the
+// vectors are as writable as the rest.
+#[derive(Clone)]
+pub struct FlatBus {
+    ram: Vec<u8>,
+}
+
+pub struct TestBusBuilder {
+    prg: Vec<u8>,
+    ram: Vec<(u16, Vec<u8>)>,
+}
+
+impl TestBus {
+    pub fn builder() -> TestBusBuilder {
+        TestBusBuilder {
+            prg: vec![0; PRG_SIZE],
+            ram: Vec::new(),
+        }
+    }
+}
+
+impl TestBusBuilder {
+    // Place bytes in PRG space ($8000-$FFFF); panics if they don't fit
+    pub fn program_at(mut self, addr: u16, bytes: &[u8]) -> Self {
+        assert!(
+            addr >= 0x8000 && addr as usize + bytes.len() <= 0x10000,
+            "program at ${:04X} ({} bytes) must lie within $8000-$FFFF",
+            addr,
+            bytes.len()
+        );
+        let offset = (addr - 0x8000) as usize;
+        self.prg[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self
+    }
+
+    pub fn nmi_vector(self, addr: u16) -> Self {
+        self.program_at(NMI_VECTOR, &addr.to_le_bytes())
+    }
+
+    pub fn reset_vector(self, addr: u16) -> Self {
+        self.program_at(RESET_VECTOR, &addr.to_le_bytes())
+    }
+
+    pub fn irq_vector(self, addr: u16) -> Self {
+        self.program_at(IRQ_VECTOR, &addr.to_le_bytes())
+    }
+
+    // Preload RAM or cartridge RAM ($0000-$7FFF)
+    pub fn ram(mut self, addr: u16, bytes: &[u8]) -> Self {
+        self.ram.push((addr, bytes.to_vec()));
+        self
+    }
+
+    pub fn build(self) -> TestBus {
+        let cartridge = Cartridge::from_prg(self.prg).expect("32 KiB PRG-ROM always maps");
+        let mut memory = mem::Memory::new(cartridge);
+        for (addr, bytes) in self.ram {
+            for (i, &byte) in bytes.iter().enumerate() {
+                memory.poke(addr.wrapping_add(i as u16), byte);
+            }
+        }
+        memory
+    }
+}
+
+// Assemble `source` at $8000, point the reset vector at it and run until a
+// BRK is reached or `max_instructions` have executed. The BRK itself is not
+// executed, so the CPU is left exactly where the program stopped.
+pub fn run_program(source: &str, max_instructions: usize) -> Result<(Cpu, TestBus), String> {
+    let program = asm::assemble(0x8000, source)?;
+    let mut builder = TestBus::builder().reset_vector(0x8000);
+    for (addr, bytes) in &program.segments {
+        builder = builder.program_at(*addr, bytes);
+    }
+    let mut bus = builder.build();
+
+    let mut cpu = Cpu::power_on(&mut bus);
+    for _ in 0..max_instructions {
+        if bus.peek(cpu.pc) == 0x00 {
+            break;
+        }
+        cpu.exec_next_instr(&mut bus).map_err(|e| e.to_string())?;
+    }
+    Ok((cpu, bus))
+}
+
+impl FlatBus {
+    pub fn new() -> Self {
+        Self { ram: vec![0; 0x10000] }
+    }
+
+    // `program` at `load_addr` with the reset vector pointing at it
+    pub fn with_program(program: &[u8], load_addr: u16) -> Self {
+        let mut bus = FlatBus::new();
+        bus.load(load_addr, program);
+        bus.load(RESET_VECTOR, &load_addr.to_le_bytes());
+        bus
+    }
+
+    // Copy `bytes` in at `addr`, wrapping at the top of memory
+    pub fn load(&mut self, addr: u16, bytes: &[u8]) {
+        for (i, &byte) in bytes.iter().enumerate() {
+            self.ram[addr.wrapping_add(i as u16) as usize] = byte;
+        }
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+}
+
+impl Default for FlatBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for FlatBus {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.ram[addr as usize] = value;
+    }
+
+    fn peek(&self, addr: u16) -> u8 {
+        self.ram[addr as usize]
+    }
+}
+
+// run_program on a FlatBus, for programs that use memory the NES maps
+// elsewhere (RAM above $0800, self-modifying code in $8000-$FFFF)
+pub fn run_flat_program(source: &str, max_instructions: usize) -> Result<(Cpu, FlatBus), String> {
+    let program = asm::assemble(0x8000, source)?;
+    let mut bus = FlatBus::new();
+    for (addr, bytes) in &program.segments {
+        bus.load(*addr, bytes);
+    }
+    bus.load(RESET_VECTOR, &0x8000u16.to_le_bytes());
+
+    let mut cpu = Cpu::power_on(&mut bus);
+    for _ in 0..max_instructions {
+        if bus.peek(cpu.pc) == 0x00 {
+            break;
+        }
+        cpu.exec_next_instr(&mut bus).map_err(|e| e.to_string())?;
+    }
+    Ok((cpu, bus))
+}
+
+// An iNES image for tests that need a whole Nes: mapper 0, one 16 KiB PRG
+// bank mirrored at $8000 and $C000, CHR-RAM. `source` is assembled at
+// $C000; the reset vector points there unless the source sets its own
+// with `* = $FFFA`.
+pub fn ines_image(source: &str, flags6: u8) -> Result<Vec<u8>, String> {
+    let program = asm::assemble(0xC000, source)?;
+    let mut prg = vec![0; 0x4000];
+    prg[0x3FFC..0x3FFE].copy_from_slice(&0xC000u16.to_le_bytes());
+    for (addr, bytes) in &program.segments {
+        let offset = (addr & 0x3FFF) as usize;
+        if *addr < 0x8000 || offset + bytes.len() > prg.len() {
+            return Err(format!("${:04X} ({} bytes) is outside the PRG bank", addr, bytes.len()));
+        }
+        prg[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    let mut image = vec![b'N', b'E', b'S', 0x1A, 1, 0, flags6, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+    image.extend(prg);
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flat_bus_starts_at_its_reset_vector() {
+        let mut bus = FlatBus::with_program(&[0xA9, 0x42, 0x85, 0x10], 0x0400); // LDA #$42, STA $10
+        let mut cpu = Cpu::power_on(&mut bus);
+        assert_eq!(cpu.pc, 0x0400);
+        cpu.exec_next_instr(&mut bus).unwrap();
+        cpu.exec_next_instr(&mut bus).unwrap();
+        assert_eq!(bus.ram()[0x10], 0x42);
+    }
+
+    // $1234 would be a mirror of $0234 on the NES
+    #[test]
+    fn flat_bus_has_no_mirroring() {
+        let (cpu, bus) = run_flat_program("lda #$42\n sta $1234\n lda #0\n lda $1234\n brk", 10).unwrap();
+        assert_eq!(cpu.a, 0x42);
+        assert_eq!(bus.ram()[0x1234], 0x42);
+        assert_eq!(bus.ram()[0x0234], 0x00);
+    }
+
+    #[test]
+    fn flat_bus_runs_self_modifying_code() {
+        let source = "
+            lda #$E8      ; INX
+            sta patch
+        patch:
+            nop
+            brk";
+        let (cpu, bus) = run_flat_program(source, 10).unwrap();
+        assert_eq!(cpu.x, 1);
+        assert_eq!(bus.ram()[0x8005], 0xE8);
+    }
+
+    // No OAM DMA or IRQ on a plain bus: $4014 is just RAM and costs no stall
+    #[test]
+    fn flat_bus_has_no_devices() {
+        let mut bus = FlatBus::with_program(&[0x8D, 0x14, 0x40], 0x8000); // STA $4014
+        let mut cpu = Cpu::with_state(0x8000, 0xFD, 0x02, 0, 0, 0x20);
+        assert_eq!(cpu.exec_next_instr(&mut bus).unwrap(), 4);
+        assert_eq!(bus.ram()[0x4014], 0x02);
+        assert!(!bus.irq_asserted());
+        assert_eq!(cpu.pending_interrupt(&bus), None);
+    }
+}
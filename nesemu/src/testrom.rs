@@ -0,0 +1,275 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use crate::crashdump;
+use crate::mem;
+use crate::nes::Nes;
+use crate::rom;
+use crate::watchdog::{Stuck, WatchdogConfig};
+
+// Headless runner for test ROMs that report through blargg's $6000 protocol:
+// once $6001-$6003 hold the signature, $6000 is the status ($80 running, $81
+// reset requested, anything below $80 the final result with 0 meaning pass)
+// and a NUL-terminated message starts at $6004.
+//
+// The suite list below says where each ROM lives under the test ROM directory
+// (NESEMU_TEST_ROMS, or the argument to `nesemu test-roms`) and whether it is
+// currently expected to pass. ROMs that are not present are skipped.
+
+// Environment variable naming the directory the suite paths are relative to
+pub const TEST_ROM_DIR_VAR: &str = "NESEMU_TEST_ROMS";
+
+pub(crate) const STATUS_ADDR: u16 = 0x6000;
+pub(crate) const SIGNATURE: [u8; 3] = [0xDE, 0xB0, 0x61];
+pub(crate) const MESSAGE_ADDR: u16 = 0x6004;
+pub(crate) const STATUS_RUNNING: u8 = 0x80;
+pub(crate) const STATUS_RESET_REQUIRED: u8 = 0x81;
+
+// blargg asks for at least 100ms between the reset request and the reset
+const RESET_DELAY_FRAMES: u64 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Expected {
+    Pass,
+    Fail(&'static str), // Known failure and what is missing
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestRom {
+    pub path: &'static str,
+    pub max_frames: u64,
+    pub expected: Expected,
+}
+
+impl TestRom {
+    const fn new(path: &'static str, max_frames: u64, expected: Expected) -> Self {
+        Self {
+            path,
+            max_frames,
+            expected,
+        }
+    }
+}
+
+const NO_VBLANK: Expected = Expected::Fail("no PPU: the $2002 vblank flag is not emulated");
+
+const NO_SPRITE_HIT: Expected = Expected::Fail("no PPU: sprite 0 hit is not emulated; reports on screen only");
+
+const NO_APU: Expected = Expected::Fail("no APU: length counters, frame counter and $4015 are not emulated");
+
+const NO_INTERRUPTS: Expected = Expected::Fail("no APU frame IRQ or DMC IRQ to interrupt with");
+
+// Recorded state of every suite; update it when a ROM starts passing so a
+// later regression shows up as a FAIL line
+pub const SUITES: &[TestRom] = &[
+    TestRom::new("instr_test-v5/official_only.nes", 3600, Expected::Fail("needs mapper 1 (MMC1)")),
+    // Timing tests use the APU frame counter as their time base
+    TestRom::new(
+        "cpu_timing_test6/cpu_timing_test.nes",
+        3600,
+        Expected::Fail("no APU frame counter; reports on screen only, not through $6000"),
+    ),
+    TestRom::new(
+        "instr_timing/instr_timing.nes",
+        3600,
+        Expected::Fail("needs mapper 1 (MMC1), APU frame counter and page-cross penalties"),
+    ),
+    // PPU vblank/NMI timing
+    TestRom::new("ppu_vbl_nmi/rom_singles/01-vbl_basics.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/04-nmi_control.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/05-nmi_timing.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/06-suppression.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes", 1200, NO_VBLANK),
+    TestRom::new("ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes", 1200, NO_VBLANK),
+    // Sprite 0 hit
+    TestRom::new("sprite_hit_tests_2005.10.05/01.basics.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/02.alignment.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/03.corners.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/04.flip.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/05.left_clip.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/06.right_edge.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/07.screen_bottom.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/08.double_height.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/09.timing_basics.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/10.timing_order.nes", 600, NO_SPRITE_HIT),
+    TestRom::new("sprite_hit_tests_2005.10.05/11.edge_timing.nes", 600, NO_SPRITE_HIT),
+    // OAM access; oam_stress also needs OAMADDR corruption during rendering
+    TestRom::new(
+        "oam_read/oam_read.nes",
+        600,
+        Expected::Fail("no PPU: the test framework waits on the $2002 vblank flag"),
+    ),
+    TestRom::new(
+        "oam_stress/oam_stress.nes",
+        1800,
+        Expected::Fail("no PPU: rendering, OAMADDR corruption and OAM DMA timing"),
+    ),
+    // APU as seen from the CPU; apu_reset asks for resets through $6000
+    TestRom::new("apu_test/rom_singles/1-len_ctr.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/2-len_table.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/3-irq_flag.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/4-jitter.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/5-len_timing.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/6-irq_flag_timing.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/7-dmc_basics.nes", 600, NO_APU),
+    TestRom::new("apu_test/rom_singles/8-dmc_rates.nes", 600, NO_APU),
+    TestRom::new("apu_reset/4015_cleared.nes", 600, NO_APU),
+    TestRom::new("apu_reset/4017_timing.nes", 600, NO_APU),
+    TestRom::new("apu_reset/4017_written.nes", 600, NO_APU),
+    TestRom::new("apu_reset/irq_flag_cleared.nes", 600, NO_APU),
+    TestRom::new("apu_reset/len_ctrs_enabled.nes", 600, NO_APU),
+    TestRom::new("apu_reset/works_immediately.nes", 600, NO_APU),
+    // Interrupt polling points and hijacking
+    TestRom::new("cpu_interrupts_v2/rom_singles/1-cli_latency.nes", 900, NO_INTERRUPTS),
+    TestRom::new("cpu_interrupts_v2/rom_singles/2-nmi_and_brk.nes", 900, NO_INTERRUPTS),
+    TestRom::new("cpu_interrupts_v2/rom_singles/3-nmi_and_irq.nes", 900, NO_INTERRUPTS),
+    TestRom::new("cpu_interrupts_v2/rom_singles/4-irq_and_dma.nes", 900, NO_INTERRUPTS),
+    TestRom::new("cpu_interrupts_v2/rom_singles/5-branch_delays_irq.nes", 900, NO_INTERRUPTS),
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed { message: String },
+    Failed { code: u8, message: String },
+    // Still running (or never signed) when the frame budget ran out
+    TimedOut { status: Option<u8>, message: String },
+    // The watchdog gave up; `dump` is a crash dump taken at that point
+    Stuck { reason: Stuck, dump: String },
+}
+
+impl Outcome {
+    pub fn passed(&self) -> bool {
+        matches!(self, Outcome::Passed { .. })
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (summary, message) = match self {
+            Outcome::Passed { message } => ("passed".to_string(), message),
+            Outcome::Failed { code, message } => (format!("failed (code {})", code), message),
+            Outcome::TimedOut { status: Some(status), message } => {
+                (format!("timed out (status ${:02X})", status), message)
+            }
+            Outcome::TimedOut { status: None, message } => ("timed out (no status)".to_string(), message),
+            Outcome::Stuck { reason, .. } => return write!(f, "stuck: {}", reason),
+        };
+        write!(f, "{}", summary)?;
+        for line in message.lines().filter(|line| !line.trim().is_empty()) {
+            write!(f, "\n    {}", line.trim_end())?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn is_signed(memory: &mem::Memory) -> bool {
+    (0..3).all(|i| memory.peek(STATUS_ADDR + 1 + i) == SIGNATURE[i as usize])
+}
+
+pub(crate) fn read_message(memory: &mem::Memory) -> String {
+    let bytes: Vec<u8> = (MESSAGE_ADDR..0x8000)
+        .map(|addr| memory.peek(addr))
+        .take_while(|&b| b != 0)
+        .collect();
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+// Current status byte, if the ROM has signed $6001-$6003 yet
+pub fn status(memory: &mem::Memory) -> Option<u8> {
+    is_signed(memory).then(|| memory.peek(STATUS_ADDR))
+}
+
+// Run for up to `max_frames` frames, checking the status (and the watchdog,
+// if the caller enabled it) after each one
+pub fn run(nes: &mut Nes, max_frames: u64) -> Outcome {
+    let mut reset_at = None;
+    for frame in 0..max_frames {
+        nes.run_frame();
+        if let Some(reason) = nes.stuck() {
+            let reason = reason.clone();
+            let dump = crashdump::crash_dump(nes, &format!("watchdog: {}", reason));
+            return Outcome::Stuck { reason, dump };
+        }
+        match status(&nes.memory) {
+            Some(STATUS_RUNNING) | None => {}
+            Some(STATUS_RESET_REQUIRED) => {
+                let at = *reset_at.get_or_insert(frame + RESET_DELAY_FRAMES);
+                if frame >= at {
+                    reset_at = None;
+                    nes.soft_reset();
+                }
+            }
+            Some(0) => {
+                return Outcome::Passed {
+                    message: read_message(&nes.memory),
+                };
+            }
+            Some(code) => {
+                return Outcome::Failed {
+                    code,
+                    message: read_message(&nes.memory),
+                };
+            }
+        }
+    }
+    Outcome::TimedOut {
+        status: status(&nes.memory),
+        message: read_message(&nes.memory),
+    }
+}
+
+pub fn run_file(path: &Path, max_frames: u64) -> io::Result<Outcome> {
+    let rom = rom::Rom::parse(File::open(path)?)?;
+    let mut nes = Nes::new(rom).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e.to_string()))?;
+    nes.enable_watchdog(WatchdogConfig::default());
+    Ok(run(&mut nes, max_frames))
+}
+
+// Result of one suite entry; `outcome` is None when the ROM isn't present
+pub struct SuiteResult {
+    pub rom: TestRom,
+    pub outcome: Option<io::Result<Outcome>>,
+}
+
+impl SuiteResult {
+    // A pass where a failure is recorded counts too, so the list gets updated
+    pub fn as_expected(&self) -> bool {
+        match &self.outcome {
+            None => true,
+            Some(Ok(outcome)) => outcome.passed() == (self.rom.expected == Expected::Pass),
+            Some(Err(_)) => false,
+        }
+    }
+}
+
+impl fmt::Display for SuiteResult {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mark = if self.as_expected() { "ok  " } else { "FAIL" };
+        write!(f, "{} {}: ", mark, self.rom.path)?;
+        match &self.outcome {
+            None => write!(f, "skipped (not found)")?,
+            Some(Ok(outcome)) => write!(f, "{}", outcome)?,
+            Some(Err(err)) => write!(f, "error: {}", err)?,
+        }
+        if let Expected::Fail(reason) = self.rom.expected {
+            write!(f, "\n    expected to fail: {}", reason)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn run_suites(dir: &Path, roms: &[TestRom]) -> Vec<SuiteResult> {
+    roms.iter()
+        .map(|&rom| {
+            let path = dir.join(rom.path);
+            let outcome = path.exists().then(|| run_file(&path, rom.max_frames));
+            SuiteResult { rom, outcome }
+        })
+        .collect()
+}
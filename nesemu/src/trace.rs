@@ -0,0 +1,193 @@
+use std::io::{self, BufWriter, Write};
+
+use crate::cpu::Cpu;
+use crate::disasm;
+use crate::mem;
+use crate::tracecmp::TraceState;
+
+// Instruction trace, one line per instruction before it executes, in the
+// format compare-trace prints:
+//
+//   8000  E8        INX               PC:8000 A:00 X:00 Y:00 P:24 SP:FD CYC:7
+//
+// Any `io::Write` can be the sink; output is buffered, so call flush() (or
+// into_inner()) before reading it back. Like EventStream, the first write
+// error stops the trace and is kept for take_error().
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TraceConfig {
+    pub range: Option<(u16, u16)>, // Only instructions at these addresses (inclusive)
+    pub limit: Option<u64>,        // Stop after this many lines
+}
+
+impl TraceConfig {
+    // "8000-80FF" (hex, inclusive) for the --trace-range option
+    pub fn parse_range(text: &str) -> Result<(u16, u16), String> {
+        let (start, end) = text.split_once('-').ok_or("expected <start>-<end>")?;
+        let hex = |s: &str| {
+            u16::from_str_radix(s.trim_start_matches('$'), 16).map_err(|_| format!("invalid address '{}'", s))
+        };
+        let (start, end) = (hex(start)?, hex(end)?);
+        if end < start {
+            return Err("end address is before start address".to_string());
+        }
+        Ok((start, end))
+    }
+}
+
+pub fn trace_line(cpu: &Cpu, memory: &mem::Memory) -> String {
+    let line = disasm::disassemble_one(memory, cpu.pc);
+    format!("{:<32}  {}", line.to_string(), TraceState::from_cpu(cpu))
+}
+
+pub struct TraceLogger<W: Write = Box<dyn Write + Send>> {
+    out: BufWriter<W>,
+    config: TraceConfig,
+    lines: u64,
+    error: Option<io::Error>,
+}
+
+impl<W: Write> TraceLogger<W> {
+    pub fn new(out: W) -> Self {
+        Self::with_config(out, TraceConfig::default())
+    }
+
+    pub fn with_config(out: W, config: TraceConfig) -> Self {
+        Self {
+            out: BufWriter::new(out),
+            config,
+            lines: 0,
+            error: None,
+        }
+    }
+
+    // Called with the CPU about to execute the instruction at its PC
+    pub fn log(&mut self, cpu: &Cpu, memory: &mem::Memory) {
+        if self.is_finished() {
+            return;
+        }
+        if let Some((start, end)) = self.config.range
+            && !(start..=end).contains(&cpu.pc)
+        {
+            return;
+        }
+        match writeln!(self.out, "{}", trace_line(cpu, memory)) {
+            Ok(()) => self.lines += 1,
+            Err(err) => self.error = Some(err),
+        }
+    }
+
+    pub fn lines(&self) -> u64 {
+        self.lines
+    }
+
+    // Past the line limit or stopped by an error; nothing more is written
+    pub fn is_finished(&self) -> bool {
+        self.error.is_some() || self.config.limit.is_some_and(|limit| self.lines >= limit)
+    }
+
+    pub fn take_error(&mut self) -> Option<io::Error> {
+        self.error.take()
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+
+    // Flushes and hands back the sink, e.g. the Vec<u8> a test traced into
+    pub fn into_inner(self) -> io::Result<W> {
+        self.out.into_inner().map_err(|err| err.into_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm;
+    use crate::testbus::TestBus;
+
+    // Three trips round an INX loop, then a spin: 11 instructions for the
+    // first 11 steps
+    const LOOP: &str = "
+            ldx #$00
+    loop:   inx
+            cpx #$03
+            bne loop
+    spin:   jmp spin";
+
+    // The trace of `steps` instructions, as lines
+    fn trace(config: TraceConfig, steps: usize) -> (Vec<String>, u64) {
+        let program = asm::assemble(0x8000, LOOP).unwrap();
+        let mut builder = TestBus::builder().reset_vector(0x8000);
+        for (addr, bytes) in &program.segments {
+            builder = builder.program_at(*addr, bytes);
+        }
+        let mut bus = builder.build();
+        let mut cpu = Cpu::power_on(&mut bus);
+
+        let mut logger = TraceLogger::with_config(Vec::new(), config);
+        for _ in 0..steps {
+            logger.log(&cpu, &bus);
+            cpu.exec_next_instr(&mut bus).unwrap();
+        }
+        let lines = logger.lines();
+        let out = String::from_utf8(logger.into_inner().unwrap()).unwrap();
+        (out.lines().map(str::to_string).collect(), lines)
+    }
+
+    #[test]
+    fn unfiltered_trace_has_a_line_per_instruction() {
+        let (lines, count) = trace(TraceConfig::default(), 11);
+        assert_eq!((lines.len(), count), (11, 11));
+        assert!(lines[0].starts_with("8000  A2 00     LDX #$00"), "{}", lines[0]);
+        assert!(lines[10].starts_with("8007  4C 07 80  JMP $8007"), "{}", lines[10]);
+    }
+
+    #[test]
+    fn range_keeps_only_instructions_inside_it() {
+        let config = TraceConfig {
+            range: Some((0x8002, 0x8005)),
+            ..TraceConfig::default()
+        };
+        let (lines, count) = trace(config, 11);
+        // LDX before the loop and the JMP after it are left out
+        assert_eq!((lines.len(), count), (9, 9));
+        for line in &lines {
+            let pc = u16::from_str_radix(&line[..4], 16).unwrap();
+            assert!((0x8002..=0x8005).contains(&pc), "{}", line);
+        }
+    }
+
+    #[test]
+    fn limit_stops_the_trace() {
+        let config = TraceConfig {
+            limit: Some(4),
+            ..TraceConfig::default()
+        };
+        let (lines, count) = trace(config, 11);
+        assert_eq!((lines.len(), count), (4, 4));
+        assert!(lines[3].starts_with("8005  D0 FB     BNE $8002"), "{}", lines[3]);
+    }
+
+    // The limit counts lines written, so filtered-out instructions don't use
+    // it up
+    #[test]
+    fn limit_applies_after_the_range() {
+        let config = TraceConfig {
+            range: Some((0x8005, 0x8005)),
+            limit: Some(2),
+        };
+        let (lines, _) = trace(config, 11);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.starts_with("8005")));
+    }
+
+    #[test]
+    fn parse_range_accepts_hex_and_rejects_backwards_ranges() {
+        assert_eq!(TraceConfig::parse_range("8000-80FF"), Ok((0x8000, 0x80FF)));
+        assert_eq!(TraceConfig::parse_range("$C000-$C000"), Ok((0xC000, 0xC000)));
+        assert!(TraceConfig::parse_range("80FF-8000").is_err());
+        assert!(TraceConfig::parse_range("8000").is_err());
+        assert!(TraceConfig::parse_range("8000-xyz").is_err());
+    }
+}
@@ -0,0 +1,349 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use crate::cpu::Cpu;
+use crate::nes::Nes;
+use crate::trace;
+
+// Lockstep comparison against a known-good trace. Accepts nestest.log lines
+// (`C000  4C F5 C5  JMP $C5F5   A:00 X:00 Y:00 P:24 SP:FD ... CYC:7`) or
+// CSV lines of `PC,A,X,Y,P,SP[,CYC]` in hex (cycles in decimal).
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceField {
+    Pc,
+    A,
+    X,
+    Y,
+    P,
+    Sp,
+    Cycles,
+}
+
+pub const ALL_FIELDS: [TraceField; 7] = [
+    TraceField::Pc,
+    TraceField::A,
+    TraceField::X,
+    TraceField::Y,
+    TraceField::P,
+    TraceField::Sp,
+    TraceField::Cycles,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TraceState {
+    pub pc: u16,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: Option<u64>,
+}
+
+impl TraceState {
+    pub fn from_nes(nes: &Nes) -> Self {
+        Self::from_cpu(&nes.cpu)
+    }
+
+    pub fn from_cpu(cpu: &Cpu) -> Self {
+        Self {
+            pc: cpu.pc,
+            a: cpu.a,
+            x: cpu.x,
+            y: cpu.y,
+            p: cpu.status,
+            sp: cpu.sp,
+            cycles: Some(cpu.cycles),
+        }
+    }
+
+    fn field(&self, field: TraceField) -> Option<u64> {
+        match field {
+            TraceField::Pc => Some(self.pc as u64),
+            TraceField::A => Some(self.a as u64),
+            TraceField::X => Some(self.x as u64),
+            TraceField::Y => Some(self.y as u64),
+            TraceField::P => Some(self.p as u64),
+            TraceField::Sp => Some(self.sp as u64),
+            TraceField::Cycles => self.cycles,
+        }
+    }
+
+    pub fn parse(line: &str) -> Result<TraceState, String> {
+        if line.contains("A:") {
+            Self::parse_nestest(line)
+        } else {
+            Self::parse_csv(line)
+        }
+    }
+
+    fn parse_nestest(line: &str) -> Result<TraceState, String> {
+        let pc = line
+            .get(0..4)
+            .and_then(|pc| u16::from_str_radix(pc, 16).ok())
+            .ok_or("line does not start with a PC")?;
+
+        let value = |key: &str| -> Option<&str> {
+            let start = line.find(key)? + key.len();
+            line[start..].split_whitespace().next()
+        };
+        let hex = |key: &str| -> Result<u8, String> {
+            value(key)
+                .and_then(|v| u8::from_str_radix(v, 16).ok())
+                .ok_or_else(|| format!("missing or invalid {}", key.trim()))
+        };
+
+        Ok(TraceState {
+            pc,
+            a: hex(" A:")?,
+            x: hex(" X:")?,
+            y: hex(" Y:")?,
+            p: hex(" P:")?,
+            sp: hex("SP:")?,
+            cycles: value("CYC:").and_then(|c| c.parse().ok()),
+        })
+    }
+
+    fn parse_csv(line: &str) -> Result<TraceState, String> {
+        let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+        if fields.len() < 6 {
+            return Err(format!("expected at least 6 CSV fields, got {}", fields.len()));
+        }
+        let hex = |i: usize| -> Result<u16, String> {
+            u16::from_str_radix(fields[i], 16).map_err(|_| format!("invalid hex field '{}'", fields[i]))
+        };
+
+        Ok(TraceState {
+            pc: hex(0)?,
+            a: hex(1)? as u8,
+            x: hex(2)? as u8,
+            y: hex(3)? as u8,
+            p: hex(4)? as u8,
+            sp: hex(5)? as u8,
+            cycles: fields.get(6).and_then(|c| c.parse().ok()),
+        })
+    }
+}
+
+impl fmt::Display for TraceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X}",
+            self.pc, self.a, self.x, self.y, self.p, self.sp
+        )?;
+        if let Some(cycles) = self.cycles {
+            write!(f, " CYC:{}", cycles)?;
+        }
+        Ok(())
+    }
+}
+
+pub struct CompareConfig {
+    pub fields: Vec<TraceField>,
+    // Jump to the reference's first PC before comparing (nestest starts at $C000)
+    pub sync_start: bool,
+    pub history_len: usize,
+}
+
+impl Default for CompareConfig {
+    fn default() -> Self {
+        Self {
+            fields: ALL_FIELDS.to_vec(),
+            sync_start: true,
+            history_len: 16,
+        }
+    }
+}
+
+pub struct Mismatch {
+    pub line: usize, // 1-based line number in the reference
+    pub reference_line: String,
+    pub expected: TraceState,
+    pub actual: TraceState,
+    pub differing: Vec<TraceField>,
+    pub history: Vec<String>, // Our trace lines leading up to the mismatch
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Mismatch at reference line {}", self.line)?;
+        writeln!(f, "  reference: {}", self.reference_line)?;
+        writeln!(f, "  emulator:  {}", self.history.last().map_or("", String::as_str))?;
+        writeln!(f, "\nPreceding instructions:")?;
+        for line in &self.history[..self.history.len().saturating_sub(1)] {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "\nField   Expected  Actual")?;
+        for field in ALL_FIELDS {
+            let show = |v: Option<u64>| match (field, v) {
+                (_, None) => "-".to_string(),
+                (TraceField::Cycles, Some(v)) => v.to_string(),
+                (TraceField::Pc, Some(v)) => format!("{:04X}", v),
+                (_, Some(v)) => format!("{:02X}", v),
+            };
+            let row = format!(
+                "{:<7} {:<9} {:<8}",
+                format!("{:?}", field),
+                show(self.expected.field(field)),
+                show(self.actual.field(field))
+            );
+            if self.differing.contains(&field) {
+                writeln!(f, "{}  <--", row)?;
+            } else {
+                writeln!(f, "{}", row.trim_end())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Run `nes` one instruction per reference line, stopping at the first
+// difference. Cycle counts are compared relative to the first line, so a
+// reference that counts from a different origin still matches.
+pub fn compare<'a>(
+    nes: &mut Nes,
+    reference: impl IntoIterator<Item = &'a str>,
+    config: &CompareConfig,
+) -> Result<Option<Mismatch>, String> {
+    let mut history: VecDeque<String> = VecDeque::with_capacity(config.history_len + 1);
+    let mut cycle_offset: Option<i128> = None;
+
+    let lines = reference.into_iter().enumerate().filter(|(_, l)| !l.trim().is_empty());
+    for (index, reference_line) in lines {
+        let expected = TraceState::parse(reference_line)
+            .map_err(|e| format!("reference line {}: {}", index + 1, e))?;
+
+        if index == 0 && config.sync_start {
+            nes.cpu.pc = expected.pc;
+        }
+
+        let actual = TraceState::from_nes(nes);
+        if history.len() > config.history_len {
+            history.pop_front();
+        }
+        history.push_back(trace::trace_line(&nes.cpu, &nes.memory));
+
+        if let (None, Some(ours), Some(theirs)) = (cycle_offset, actual.cycles, expected.cycles) {
+            cycle_offset = Some(theirs as i128 - ours as i128);
+        }
+        let mut adjusted = actual;
+        adjusted.cycles = match (actual.cycles, cycle_offset) {
+            (Some(ours), Some(offset)) => Some((ours as i128 + offset) as u64),
+            _ => None,
+        };
+
+        let differing: Vec<TraceField> = config
+            .fields
+            .iter()
+            .copied()
+            .filter(|&field| match (expected.field(field), adjusted.field(field)) {
+                (Some(e), Some(a)) => e != a,
+                _ => false, // Field missing from the reference
+            })
+            .collect();
+
+        if !differing.is_empty() {
+            return Ok(Some(Mismatch {
+                line: index + 1,
+                reference_line: reference_line.trim_end().to_string(),
+                expected,
+                actual: adjusted,
+                differing,
+                history: history.into_iter().collect(),
+            }));
+        }
+
+        nes.step();
+    }
+    Ok(None)
+}
+
+pub fn parse_fields(list: &str) -> Result<Vec<TraceField>, String> {
+    list.split(',')
+        .map(|name| match name.trim().to_ascii_lowercase().as_str() {
+            "pc" => Ok(TraceField::Pc),
+            "a" => Ok(TraceField::A),
+            "x" => Ok(TraceField::X),
+            "y" => Ok(TraceField::Y),
+            "p" => Ok(TraceField::P),
+            "sp" => Ok(TraceField::Sp),
+            "cyc" | "cycles" => Ok(TraceField::Cycles),
+            other => Err(format!("unknown trace field '{}'", other)),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testbus;
+
+    const PROGRAM: &str = "lda #$42\n ldx #$10\n spin: jmp spin";
+
+    // What PROGRAM does from power-on, with cycles counted from 100
+    const REFERENCE: [&str; 4] = [
+        "C000,00,00,00,24,FD,100",
+        "C002,42,00,00,24,FD,102",
+        "C004,42,10,00,24,FD,104",
+        "C004,42,10,00,24,FD,107",
+    ];
+
+    fn nes() -> Nes {
+        Nes::from_bytes(&testbus::ines_image(PROGRAM, 0).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn a_matching_trace_has_no_mismatch() {
+        let mismatch = compare(&mut nes(), REFERENCE, &CompareConfig::default()).unwrap();
+        assert!(mismatch.is_none());
+    }
+
+    #[test]
+    fn stops_at_the_first_mismatch() {
+        let mut reference = REFERENCE;
+        reference[2] = "C004,42,11,00,24,FD,105";
+        reference[3] = "C004,42,10,00,24,FC,107";
+        let mismatch = compare(&mut nes(), reference, &CompareConfig::default()).unwrap().unwrap();
+        assert_eq!(mismatch.line, 3);
+        assert_eq!(mismatch.differing, [TraceField::X, TraceField::Cycles]);
+        assert_eq!((mismatch.expected.x, mismatch.actual.x), (0x11, 0x10));
+        assert_eq!(mismatch.actual.cycles, Some(104), "in the reference's count");
+        assert_eq!(mismatch.history.len(), 3);
+        assert!(mismatch.history[2].starts_with("C004  4C 04 C0  JMP $C004"), "{}", mismatch.history[2]);
+        let report = mismatch.to_string();
+        assert!(report.contains("\nX       11        10        <--\n"), "{report}");
+        assert!(report.contains("\nCycles  105       104       <--\n"), "{report}");
+        assert!(report.contains("\nA       42        42\n"), "{report}");
+    }
+
+    // Only the configured fields count; here the wrong X and cycles pass
+    // and the SP on line 4 is caught
+    #[test]
+    fn compares_only_the_selected_fields() {
+        let mut reference = REFERENCE;
+        reference[2] = "C004,42,11,00,24,FD,105";
+        reference[3] = "C004,42,10,00,24,FC,107";
+        let config = CompareConfig { fields: parse_fields("pc, A,sp").unwrap(), ..Default::default() };
+        let mismatch = compare(&mut nes(), reference, &config).unwrap().unwrap();
+        assert_eq!((mismatch.line, mismatch.differing.as_slice()), (4, [TraceField::Sp].as_slice()));
+
+        assert_eq!(parse_fields("cyc,P"), Ok(vec![TraceField::Cycles, TraceField::P]));
+        assert_eq!(parse_fields("pc,flags"), Err("unknown trace field 'flags'".to_string()));
+    }
+
+    #[test]
+    fn parses_nestest_and_csv_lines() {
+        let nestest = "C000  4C F5 C5  JMP $C5F5                       A:00 X:01 Y:02 P:24 SP:FD PPU:  0, 21 CYC:7";
+        let expected = TraceState { pc: 0xC000, a: 0, x: 1, y: 2, p: 0x24, sp: 0xFD, cycles: Some(7) };
+        assert_eq!(TraceState::parse(nestest), Ok(expected));
+        assert_eq!(TraceState::parse("C000, 0, 1, 2, 24, FD, 7"), Ok(expected));
+        assert_eq!(TraceState::parse("C000,0,1,2,24,FD"), Ok(TraceState { cycles: None, ..expected }));
+        assert_eq!(TraceState::parse("C000,0,1"), Err("expected at least 6 CSV fields, got 3".to_string()));
+        assert_eq!(TraceState::parse("C000  A:00 X:01"), Err("missing or invalid Y:".to_string()));
+
+        let err = compare(&mut nes(), ["C000,00,00,00,24,FD", "C002,zz,0,0,0,0"], &CompareConfig::default());
+        assert_eq!(err.err(), Some("reference line 2: invalid hex field 'zz'".to_string()));
+    }
+}
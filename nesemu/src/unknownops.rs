@@ -0,0 +1,146 @@
+use std::fmt;
+
+use crate::disasm;
+use crate::mem;
+
+// Every unknown opcode the CPU ran into, one entry per opcode with where it
+// was first seen and what led there. Repeats only bump the count, so a game
+// stuck in garbage doesn't turn this into megabytes.
+
+// Instructions before the first occurrence kept as context
+pub const CONTEXT_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnknownOpcode {
+    pub opcode: u8,
+    pub count: u64,
+    pub first_pc: u16,
+    pub first_cycle: u64,
+    pub disassembly: String,  // Of the bytes at first_pc
+    pub context: Vec<String>, // Disassembled instructions leading up to it, oldest first
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UnknownOpcodeReport {
+    entries: Vec<UnknownOpcode>, // In order of first occurrence
+}
+
+impl UnknownOpcodeReport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // `history` holds recently executed addresses, oldest first, ending with
+    // `pc` itself; only read on an opcode's first occurrence
+    pub fn record(&mut self, memory: &mem::Memory, pc: u16, cycle: u64, history: impl FnOnce() -> Vec<u16>) {
+        let opcode = memory.peek(pc);
+        if let Some(entry) = self.entries.iter_mut().find(|e| e.opcode == opcode) {
+            entry.count += 1;
+            return;
+        }
+
+        let history = history();
+        let before = &history[..history.len().saturating_sub(1)];
+        let context = before[before.len().saturating_sub(CONTEXT_LEN)..]
+            .iter()
+            .map(|&addr| disasm::disassemble_one(memory, addr).to_string())
+            .collect();
+        self.entries.push(UnknownOpcode {
+            opcode,
+            count: 1,
+            first_pc: pc,
+            first_cycle: cycle,
+            disassembly: disasm::disassemble_one(memory, pc).to_string(),
+            context,
+        });
+    }
+
+    pub fn entries(&self) -> &[UnknownOpcode] {
+        &self.entries
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for UnknownOpcodeReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, entry) in self.entries.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            writeln!(
+                f,
+                "opcode ${:02X}: {} time(s), first at ${:04X} (cycle {})",
+                entry.opcode, entry.count, entry.first_pc, entry.first_cycle
+            )?;
+            for line in &entry.context {
+                writeln!(f, "    {}", line)?;
+            }
+            writeln!(f, "  > {}", entry.disassembly)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    // $AB inside a loop that runs three times, then $EB once
+    #[test]
+    fn repeats_only_bump_the_count() {
+        let source = "
+            ldx #3
+     loop:  nop
+            .byte $AB
+            dex
+            bne loop
+            .byte $EB
+     spin:  jmp spin";
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        for _ in 0..16 {
+            nes.step();
+        }
+
+        let report = nes.unknown_opcode_report();
+        let summary: Vec<_> = report.entries().iter().map(|e| (e.opcode, e.count, e.first_pc)).collect();
+        assert_eq!(summary, [(0xAB, 3, 0xC003), (0xEB, 1, 0xC007)]);
+        // The second one's context is cut to the last CONTEXT_LEN instructions
+        let expected = "\
+opcode $AB: 3 time(s), first at $C003 (cycle 11)
+    C000  A2 03     LDX #$03
+    C002  EA        NOP
+  > C003  AB        .byte $AB
+
+opcode $EB: 1 time(s), first at $C007 (cycle 29)
+    C002  EA        NOP
+    C003  AB        .byte $AB
+    C004  CA        DEX
+    C005  D0 FB     BNE $C002
+    C002  EA        NOP
+    C003  AB        .byte $AB
+    C004  CA        DEX
+    C005  D0 FB     BNE $C002
+  > C007  EB        .byte $EB
+";
+        assert_eq!(report.to_string(), expected);
+    }
+
+    // The history is only gathered the first time
+    #[test]
+    fn repeats_skip_the_history() {
+        let memory = mem::Memory::with_program(&[0xAB], 0x8000);
+        let mut report = UnknownOpcodeReport::new();
+        assert!(report.is_empty());
+        report.record(&memory, 0x8000, 100, Vec::new);
+        for cycle in [200, 300] {
+            report.record(&memory, 0x8000, cycle, || panic!("history read again"));
+        }
+        let entry = &report.entries()[0];
+        assert_eq!((entry.count, entry.first_cycle, entry.context.len()), (3, 100, 0));
+    }
+}
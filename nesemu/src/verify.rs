@@ -0,0 +1,105 @@
+use alloc::format;
+use alloc::string::String;
+
+use crate::cpu::CpuState;
+use crate::opcodes::AddrMode;
+
+// Reference model for the ALU instructions, used by Cpu::enable_verify to
+// cross-check every result. It works from the definitions, in plain
+// integer arithmetic (signed range for V, unsigned for C), rather than the
+// bit tricks the CPU uses, so the two are unlikely to share a bug. Only
+// results that land in registers are covered; shifts on memory are not.
+
+const C: u8 = 0b0000_0001;
+const Z: u8 = 0b0000_0010;
+const V: u8 = 0b0100_0000;
+const N: u8 = 0b1000_0000;
+
+// A and P after the instruction, or None when the model doesn't cover it
+pub fn expected(mnemonic: &str, mode: AddrMode, before: &CpuState, operand: u8) -> Option<(u8, u8)> {
+    let a = before.a;
+    let p = before.status;
+    let carry = p & C != 0;
+    let shift_operand = (mode == AddrMode::Accumulator).then_some(a);
+    match mnemonic {
+        "ADC" => Some(add(a, operand, carry, p)),
+        "SBC" => Some(subtract(a, operand, carry, p)),
+        "AND" => Some(logic(a & operand, p)),
+        "ORA" => Some(logic(a | operand, p)),
+        "EOR" => Some(logic(a ^ operand, p)),
+        "CMP" => Some((a, compare(a, operand, p))),
+        "CPX" => Some((a, compare(before.x, operand, p))),
+        "CPY" => Some((a, compare(before.y, operand, p))),
+        "BIT" => {
+            let p = with(p, Z, a & operand == 0);
+            let p = with(p, N, operand >= 0x80);
+            Some((a, with(p, V, operand & 0x40 != 0)))
+        }
+        "ASL" => shift_operand.map(|v| shift(v as u16 * 2, v >= 0x80, p)),
+        "LSR" => shift_operand.map(|v| shift(v as u16 / 2, v % 2 == 1, p)),
+        "ROL" => shift_operand.map(|v| shift(v as u16 * 2 + carry as u16, v >= 0x80, p)),
+        "ROR" => shift_operand.map(|v| shift(v as u16 / 2 + carry as u16 * 0x80, v % 2 == 1, p)),
+        _ => None,
+    }
+}
+
+// Compare the CPU's A and P with the model's, describing any mismatch
+pub fn check(
+    mnemonic: &str,
+    mode: AddrMode,
+    before: &CpuState,
+    operand: u8,
+    after: &CpuState,
+) -> Result<(), String> {
+    let Some((a, p)) = expected(mnemonic, mode, before, operand) else {
+        return Ok(());
+    };
+    if (a, p) == (after.a, after.status) {
+        return Ok(());
+    }
+    Err(format!(
+        "{} at ${:04X} with A=${:02X} X=${:02X} Y=${:02X} P=${:02X} operand=${:02X}: expected A=${:02X} P=${:02X}, got A=${:02X} P=${:02X}",
+        mnemonic, before.pc, before.a, before.x, before.y, before.status, operand, a, p, after.a, after.status
+    ))
+}
+
+fn with(p: u8, flag: u8, set: bool) -> u8 {
+    if set { p | flag } else { p & !flag }
+}
+
+fn zero_and_negative(p: u8, result: u8) -> u8 {
+    with(with(p, Z, result == 0), N, result >= 0x80)
+}
+
+fn add(a: u8, m: u8, carry: bool, p: u8) -> (u8, u8) {
+    let unsigned = a as u16 + m as u16 + carry as u16;
+    let signed = a as i8 as i16 + m as i8 as i16 + carry as i16;
+    let result = unsigned as u8;
+    let p = with(p, C, unsigned > 0xFF);
+    let p = with(p, V, !(-128..=127).contains(&signed));
+    (result, zero_and_negative(p, result))
+}
+
+fn subtract(a: u8, m: u8, carry: bool, p: u8) -> (u8, u8) {
+    let borrow = !carry as i16;
+    let unsigned = a as i16 - m as i16 - borrow;
+    let signed = a as i8 as i16 - m as i8 as i16 - borrow;
+    let result = unsigned as u8;
+    let p = with(p, C, unsigned >= 0);
+    let p = with(p, V, !(-128..=127).contains(&signed));
+    (result, zero_and_negative(p, result))
+}
+
+fn logic(result: u8, p: u8) -> (u8, u8) {
+    (result, zero_and_negative(p, result))
+}
+
+fn compare(register: u8, m: u8, p: u8) -> u8 {
+    let p = with(p, C, register >= m);
+    zero_and_negative(p, register.wrapping_sub(m))
+}
+
+fn shift(wide: u16, carry_out: bool, p: u8) -> (u8, u8) {
+    let result = wide as u8;
+    (result, zero_and_negative(with(p, C, carry_out), result))
+}
@@ -0,0 +1,186 @@
+use alloc::format;
+use alloc::string::String;
+
+use crate::cartridge::{Cartridge, Mirroring};
+
+// The PPU's address space as the CPU reaches it through $2006/$2007:
+//
+//   $0000-$1FFF  pattern tables, on the cartridge (CHR-ROM or CHR-RAM)
+//   $2000-$2FFF  four nametable slots over 2 KiB of console RAM, wired up
+//                by the cartridge's mirroring; $3000-$3EFF repeats them
+//   $3F00-$3FFF  32 bytes of palette RAM, repeated; $3F10/$14/$18/$1C are
+//                the same bytes as $3F00/$04/$08/$0C
+//
+// Nothing renders from it yet. The address latches follow the PPU's own
+// v/t/x/w registers so $2000/$2005/$2006 writes combine the way games
+// expect.
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Vram {
+    nametables: [u8; 0x1000], // 2 KiB in the console; four-screen boards add the rest
+    palette: [u8; 0x20],
+    addr: u16,          // v: where the next $2007 access goes
+    temp_addr: u16,     // t: built up by $2000/$2005/$2006 writes
+    fine_x: u8,         // Fine X scroll from the first $2005 write
+    write_toggle: bool, // w: set when the next $2005/$2006 write is the second half
+    read_buffer: u8,    // $2007 reads below the palette return the previous read
+}
+
+impl Vram {
+    pub fn new() -> Self {
+        Self {
+            nametables: [0; 0x1000],
+            palette: [0; 0x20],
+            addr: 0,
+            temp_addr: 0,
+            fine_x: 0,
+            write_toggle: false,
+            read_buffer: 0,
+        }
+    }
+
+    // A PPU address, $0000-$3FFF (higher ones wrap), without side effects
+    pub fn peek(&self, cartridge: &Cartridge, addr: u16) -> u8 {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => cartridge.ppu_read(addr),
+            addr @ 0x2000..=0x3EFF => self.nametables[nametable_index(cartridge.mirroring(), addr)],
+            addr => self.palette[palette_index(addr as u8)],
+        }
+    }
+
+    // CHR-ROM ignores writes, as it would a $2007 write
+    pub fn poke(&mut self, cartridge: &mut Cartridge, addr: u16, value: u8) {
+        match addr & 0x3FFF {
+            addr @ 0x0000..=0x1FFF => cartridge.ppu_write(addr, value),
+            addr @ 0x2000..=0x3EFF => self.nametables[nametable_index(cartridge.mirroring(), addr)] = value,
+            addr => self.poke_palette(addr as u8, value),
+        }
+    }
+
+    // Palette entries by index, $00-$1F with the sprite backdrop mirrors
+    pub fn peek_palette(&self, index: u8) -> u8 {
+        self.palette[palette_index(index)]
+    }
+
+    // Palette RAM is 6 bits wide
+    pub fn poke_palette(&mut self, index: u8, value: u8) {
+        self.palette[palette_index(index)] = value & 0x3F;
+    }
+
+    // The address the next $2007 access uses
+    pub fn addr(&self) -> u16 {
+        self.addr
+    }
+
+    // $2000: the base nametable bits go into t
+    pub fn write_ctrl(&mut self, value: u8) {
+        self.temp_addr = (self.temp_addr & !0x0C00) | ((value as u16 & 0x03) << 10);
+    }
+
+    // $2002 reads reset the toggle shared by $2005 and $2006
+    pub fn read_status(&mut self) {
+        self.write_toggle = false;
+    }
+
+    // $2005: X scroll, then Y scroll
+    pub fn write_scroll(&mut self, value: u8) {
+        let value = value as u16;
+        if self.write_toggle {
+            self.temp_addr = (self.temp_addr & !0x73E0) | ((value & 0x07) << 12) | ((value & 0xF8) << 2);
+        } else {
+            self.temp_addr = (self.temp_addr & !0x001F) | (value >> 3);
+            self.fine_x = value as u8 & 0x07;
+        }
+        self.write_toggle = !self.write_toggle;
+    }
+
+    // $2006: high byte, then low byte, which also makes it the address
+    pub fn write_addr(&mut self, value: u8) {
+        let value = value as u16;
+        if self.write_toggle {
+            self.temp_addr = (self.temp_addr & 0xFF00) | value;
+            self.addr = self.temp_addr;
+        } else {
+            self.temp_addr = (self.temp_addr & 0x00FF) | ((value & 0x3F) << 8);
+        }
+        self.write_toggle = !self.write_toggle;
+    }
+
+    // What a $2007 read returns. Below the palette that's the buffered
+    // byte from the last read; palette reads come straight through.
+    pub fn peek_data(&self, cartridge: &Cartridge) -> u8 {
+        if self.addr & 0x3FFF >= 0x3F00 {
+            self.peek(cartridge, self.addr)
+        } else {
+            self.read_buffer
+        }
+    }
+
+    // The side effects of a $2007 read: refill the buffer and step the
+    // address. A palette read still fills the buffer, from the nametable
+    // byte "underneath" it.
+    pub fn read_data(&mut self, cartridge: &Cartridge, increment: u16) {
+        let addr = self.addr & 0x3FFF;
+        let buffered = if addr >= 0x3F00 { addr - 0x1000 } else { addr };
+        self.read_buffer = self.peek(cartridge, buffered);
+        self.addr = self.addr.wrapping_add(increment) & 0x7FFF;
+    }
+
+    pub fn write_data(&mut self, cartridge: &mut Cartridge, value: u8, increment: u16) {
+        self.poke(cartridge, self.addr, value);
+        self.addr = self.addr.wrapping_add(increment) & 0x7FFF;
+    }
+
+    // The Reset button clears the toggle and the read buffer; the memory
+    // and the address survive
+    pub fn soft_reset(&mut self) {
+        self.write_toggle = false;
+        self.read_buffer = 0;
+    }
+
+    // First difference from `other`, like Memory::diff
+    pub fn diff(&self, other: &Vram) -> Option<String> {
+        if let Some(i) = (0..self.nametables.len()).find(|&i| self.nametables[i] != other.nametables[i]) {
+            let (ours, theirs) = (self.nametables[i], other.nametables[i]);
+            return Some(format!("nametables[${:X}]: ${:02X} vs ${:02X}", i, ours, theirs));
+        }
+        if let Some(i) = (0..self.palette.len()).find(|&i| self.palette[i] != other.palette[i]) {
+            return Some(format!("palette[${:X}]: ${:02X} vs ${:02X}", i, self.palette[i], other.palette[i]));
+        }
+        let latches = [
+            ("addr", self.addr, other.addr),
+            ("temp_addr", self.temp_addr, other.temp_addr),
+            ("fine_x", self.fine_x as u16, other.fine_x as u16),
+            ("write_toggle", self.write_toggle as u16, other.write_toggle as u16),
+            ("read_buffer", self.read_buffer as u16, other.read_buffer as u16),
+        ];
+        latches
+            .iter()
+            .find(|(_, ours, theirs)| ours != theirs)
+            .map(|(name, ours, theirs)| format!("{}: ${:X} vs ${:X}", name, ours, theirs))
+    }
+}
+
+impl Default for Vram {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Slot 0-3 of $2000-$2FFF onto the 1 KiB tables the board actually has
+fn nametable_index(mirroring: Mirroring, addr: u16) -> usize {
+    let addr = (addr - 0x2000) as usize & 0x0FFF;
+    let (slot, offset) = (addr / 0x400, addr % 0x400);
+    let table = match mirroring {
+        Mirroring::Horizontal => slot / 2,
+        Mirroring::Vertical => slot % 2,
+        Mirroring::FourScreen => slot,
+    };
+    table * 0x400 + offset
+}
+
+// The sprite palettes' entry 0 is the background palettes' entry 0
+fn palette_index(index: u8) -> usize {
+    let index = index as usize & 0x1F;
+    if index & 0x13 == 0x10 { index & 0x0F } else { index }
+}
@@ -0,0 +1,277 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::mem;
+use crate::symbols::Symbols;
+
+// Named memory watches. Each line of a watch file (`<rom>.watch` next to the
+// ROM) is one entry in the same syntax the monitor's `w` command takes:
+//
+//   lives = $0075
+//   score = $0700..$0702 bcd
+//   player_x = $0086 dec
+//
+// Formats: hex (default), dec, word (16-bit little-endian), bcd (two digits
+// per byte, most significant byte first). Blank lines and `;` comments are
+// ignored.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchFormat {
+    Hex,
+    Decimal,
+    Word,
+    Bcd,
+}
+
+impl WatchFormat {
+    fn parse(name: &str) -> Result<Self, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "hex" => Ok(WatchFormat::Hex),
+            "dec" => Ok(WatchFormat::Decimal),
+            "word" => Ok(WatchFormat::Word),
+            "bcd" => Ok(WatchFormat::Bcd),
+            other => Err(format!("unknown watch format '{}'", other)),
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            WatchFormat::Hex => "hex",
+            WatchFormat::Decimal => "dec",
+            WatchFormat::Word => "word",
+            WatchFormat::Bcd => "bcd",
+        }
+    }
+
+    // Render `bytes` (in address order)
+    pub fn format(self, bytes: &[u8]) -> String {
+        match self {
+            WatchFormat::Hex => {
+                let hex: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                format!("${}", hex.join(" "))
+            }
+            WatchFormat::Decimal => {
+                let value = bytes.iter().rev().fold(0u64, |acc, &b| (acc << 8) | b as u64);
+                value.to_string()
+            }
+            WatchFormat::Word => {
+                let value = bytes.iter().take(2).rev().fold(0u16, |acc, &b| (acc << 8) | b as u16);
+                format!("${:04X} ({})", value, value)
+            }
+            WatchFormat::Bcd => bytes
+                .iter()
+                .map(|b| {
+                    let digit = |d: u8| if d < 10 { (b'0' + d) as char } else { '?' };
+                    format!("{}{}", digit(b >> 4), digit(b & 0x0F))
+                })
+                .collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watch {
+    pub name: String,
+    pub addr: u16,
+    pub len: u16,
+    pub format: WatchFormat,
+}
+
+impl Watch {
+    // Parse `name = addr[..end] [format]`; addresses may be symbol names
+    pub fn parse(text: &str, symbols: &Symbols) -> Result<Watch, String> {
+        let (name, spec) = text
+            .split_once('=')
+            .ok_or("expected <name> = <addr>[..<end>] [format]")?;
+        let name = name.trim();
+        if name.is_empty() || name.contains(char::is_whitespace) {
+            return Err(format!("invalid watch name '{}'", name));
+        }
+
+        let mut words = spec.split_whitespace();
+        let location = words.next().ok_or("missing address")?;
+        let format = words.next().map(WatchFormat::parse).transpose()?.unwrap_or(WatchFormat::Hex);
+        if let Some(extra) = words.next() {
+            return Err(format!("unexpected '{}'", extra));
+        }
+
+        let addr = |text: &str| -> Result<u16, String> {
+            u16::from_str_radix(text.trim_start_matches('$'), 16)
+                .ok()
+                .or_else(|| symbols.address_of(text))
+                .ok_or_else(|| format!("invalid address '{}'", text))
+        };
+        let (addr, len) = match location.split_once("..") {
+            Some((start, end)) => {
+                let (start, end) = (addr(start)?, addr(end)?);
+                if end < start {
+                    return Err("end address is before start address".to_string());
+                }
+                (start, end - start + 1)
+            }
+            None if format == WatchFormat::Word => (addr(location)?, 2),
+            None => (addr(location)?, 1),
+        };
+
+        Ok(Watch {
+            name: name.to_string(),
+            addr,
+            len,
+            format,
+        })
+    }
+
+    pub fn read(&self, memory: &mem::Memory) -> Vec<u8> {
+        (0..self.len).map(|i| memory.peek(self.addr.wrapping_add(i))).collect()
+    }
+
+    pub fn value(&self, memory: &mem::Memory) -> String {
+        self.format.format(&self.read(memory))
+    }
+}
+
+// The watch file syntax, so a saved list loads back unchanged
+impl fmt::Display for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} = ${:04X}", self.name, self.addr)?;
+        let implied_len = if self.format == WatchFormat::Word { 2 } else { 1 };
+        if self.len != implied_len {
+            write!(f, "..${:04X}", self.addr.wrapping_add(self.len - 1))?;
+        }
+        if self.format != WatchFormat::Hex {
+            write!(f, " {}", self.format.name())?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+pub struct WatchList {
+    watches: Vec<Watch>,
+}
+
+impl WatchList {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        &self.watches
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watches.is_empty()
+    }
+
+    // Adding a name that already exists replaces that watch in place
+    pub fn add(&mut self, watch: Watch) {
+        match self.watches.iter_mut().find(|w| w.name == watch.name) {
+            Some(existing) => *existing = watch,
+            None => self.watches.push(watch),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|w| w.name != name);
+        self.watches.len() != before
+    }
+
+    // `name = value` for every watch
+    pub fn evaluate(&self, memory: &mem::Memory) -> Vec<(String, String)> {
+        self.watches
+            .iter()
+            .map(|w| (w.name.clone(), w.value(memory)))
+            .collect()
+    }
+
+    pub fn parse(&mut self, text: &str, symbols: &Symbols) -> Result<(), String> {
+        for (index, line) in text.lines().enumerate() {
+            let line = line.split(';').next().unwrap_or("").trim();
+            if !line.is_empty() {
+                let watch = Watch::parse(line, symbols).map_err(|e| format!("line {}: {}", index + 1, e))?;
+                self.add(watch);
+            }
+        }
+        Ok(())
+    }
+
+    pub fn load(&mut self, path: &Path, symbols: &Symbols) -> Result<(), String> {
+        let text = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+        self.parse(&text, symbols)
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        let text: String = self.watches.iter().map(|w| format!("{}\n", w)).collect();
+        fs::write(path, text).map_err(|e| format!("failed to write {}: {}", path.display(), e))
+    }
+
+    pub fn sidecar_path(rom_path: &Path) -> PathBuf {
+        PathBuf::from(format!("{}.watch", rom_path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hex_shows_every_byte() {
+        assert_eq!(WatchFormat::Hex.format(&[0x0A]), "$0A");
+        assert_eq!(WatchFormat::Hex.format(&[0x0A, 0xFF, 0x00]), "$0A FF 00");
+    }
+
+    #[test]
+    fn decimal_is_little_endian() {
+        assert_eq!(WatchFormat::Decimal.format(&[200]), "200");
+        assert_eq!(WatchFormat::Decimal.format(&[0x34, 0x12]), "4660");
+        assert_eq!(WatchFormat::Decimal.format(&[0x00, 0x00, 0x01]), "65536");
+    }
+
+    #[test]
+    fn word_is_the_first_two_bytes() {
+        assert_eq!(WatchFormat::Word.format(&[0x34, 0x12]), "$1234 (4660)");
+        assert_eq!(WatchFormat::Word.format(&[0xFF, 0xFF, 0x99]), "$FFFF (65535)");
+    }
+
+    #[test]
+    fn bcd_reads_most_significant_byte_first() {
+        assert_eq!(WatchFormat::Bcd.format(&[0x42]), "42");
+        assert_eq!(WatchFormat::Bcd.format(&[0x01, 0x23, 0x45]), "012345");
+        assert_eq!(WatchFormat::Bcd.format(&[0x09, 0x9A]), "099?"); // Not a decimal digit
+    }
+
+    #[test]
+    fn parse_and_display_round_trip() {
+        let mut symbols = Symbols::new();
+        symbols.parse_fceux_nl("$0075#lives#", None).unwrap();
+        let cases = [
+            ("lives = lives", Watch { name: "lives".into(), addr: 0x75, len: 1, format: WatchFormat::Hex }),
+            ("score = $0700..$0702 BCD", Watch { name: "score".into(), addr: 0x700, len: 3, format: WatchFormat::Bcd }),
+            ("x = $86 word", Watch { name: "x".into(), addr: 0x86, len: 2, format: WatchFormat::Word }),
+        ];
+        for (text, watch) in cases {
+            assert_eq!(Watch::parse(text, &symbols), Ok(watch.clone()), "{text}");
+            assert_eq!(Watch::parse(&watch.to_string(), &symbols), Ok(watch), "{text}");
+        }
+        assert!(Watch::parse("score = $0702..$0700", &symbols).is_err());
+        assert!(Watch::parse("score = $0700 octal", &symbols).is_err());
+        assert!(Watch::parse("lives $0075", &symbols).is_err());
+    }
+
+    #[test]
+    fn list_evaluates_against_memory() {
+        let mut memory = mem::Memory::with_program(&[], 0x8000);
+        for (addr, value) in [(0x0075, 0x03), (0x0700, 0x00), (0x0701, 0x12), (0x0702, 0x50)] {
+            memory.poke(addr, value);
+        }
+        let mut list = WatchList::new();
+        list.parse("lives = $0075 dec ; comment\n\nscore = $0700..$0702 bcd\nlives = $0075", &Symbols::new())
+            .unwrap();
+        let values = list.evaluate(&memory);
+        let expected = [("lives".to_string(), "$03".to_string()), ("score".to_string(), "001250".to_string())];
+        assert_eq!(values, expected);
+    }
+}
@@ -0,0 +1,224 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::cpu::Cpu;
+
+// Detects headless runs that will never finish: a game waiting on hardware
+// that isn't emulated, or code that has run off into garbage. Fed once per
+// instruction; once tripped it stays tripped.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WatchdogConfig {
+    pub max_cycles_per_frame: u64, // Cycles without a frame completing
+    pub max_repeats: u32,          // Identical CPU states with no memory writes in between
+    pub max_unknown_opcodes: u32,
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> Self {
+        Self {
+            max_cycles_per_frame: 300_000, // About ten NTSC frames
+            // A 3-cycle JMP-to-self NMI idle loop repeats about 9,930 times
+            // a frame before the NMI's pushes reset the count; this leaves
+            // it room while still catching a real spin within three frames
+            max_repeats: 30_000,
+            max_unknown_opcodes: 64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Stuck {
+    NoFrame { cycles: u64 },
+    Spinning { pc: u16, repeats: u32 },
+    UnknownOpcodes { count: u32, last_pc: u16 },
+    Jammed { pc: u16 },
+}
+
+impl fmt::Display for Stuck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stuck::NoFrame { cycles } => write!(f, "no frame completed in {} cycles", cycles),
+            Stuck::Spinning { pc, repeats } => write!(
+                f,
+                "CPU state at ${:04X} repeated {} times without a memory write",
+                pc, repeats
+            ),
+            Stuck::UnknownOpcodes { count, last_pc } => {
+                write!(f, "{} unknown opcodes executed (last at ${:04X})", count, last_pc)
+            }
+            Stuck::Jammed { pc } => write!(f, "CPU jammed by the opcode at ${:04X}", pc),
+        }
+    }
+}
+
+// Registers that identify a spin loop; cycles are left out on purpose
+type Snapshot = (u16, u8, u8, u8, u8, u8);
+
+// Loops that touch this many distinct states without writing are counting,
+// not spinning; forget them rather than grow without bound
+const MAX_TRACKED_STATES: usize = 4096;
+
+pub struct Watchdog {
+    config: WatchdogConfig,
+    frame: u64,
+    frame_start: u64,
+    seen: HashMap<Snapshot, u32>,
+    unknown_opcodes: u32,
+    tripped: Option<Stuck>,
+}
+
+impl Watchdog {
+    pub fn new(config: WatchdogConfig) -> Self {
+        Self {
+            config,
+            frame: 0,
+            frame_start: 0,
+            seen: HashMap::new(),
+            unknown_opcodes: 0,
+            tripped: None,
+        }
+    }
+
+    pub fn tripped(&self) -> Option<&Stuck> {
+        self.tripped.as_ref()
+    }
+
+    // Called after each instruction with the address it ran from, whether
+    // its opcode was known and whether it wrote memory
+    pub fn observe(&mut self, cpu: &Cpu, pc: u16, frame: u64, known_opcode: bool, wrote: bool) -> Option<&Stuck> {
+        if self.tripped.is_some() {
+            return self.tripped.as_ref();
+        }
+        if cpu.is_halted() {
+            self.tripped = Some(Stuck::Jammed { pc: cpu.pc });
+            return self.tripped.as_ref();
+        }
+
+        if frame != self.frame {
+            self.frame = frame;
+            self.frame_start = cpu.cycles;
+        } else if cpu.cycles - self.frame_start > self.config.max_cycles_per_frame {
+            self.tripped = Some(Stuck::NoFrame {
+                cycles: cpu.cycles - self.frame_start,
+            });
+        }
+
+        if !known_opcode {
+            self.unknown_opcodes += 1;
+            if self.unknown_opcodes > self.config.max_unknown_opcodes {
+                self.tripped = Some(Stuck::UnknownOpcodes {
+                    count: self.unknown_opcodes,
+                    last_pc: pc,
+                });
+            }
+        }
+
+        if wrote || self.seen.len() >= MAX_TRACKED_STATES {
+            self.seen.clear();
+        }
+        let repeats = self.seen.entry((cpu.pc, cpu.a, cpu.x, cpu.y, cpu.sp, cpu.status)).or_insert(0);
+        *repeats += 1;
+        if *repeats > self.config.max_repeats {
+            self.tripped = Some(Stuck::Spinning {
+                pc: cpu.pc,
+                repeats: *repeats,
+            });
+        }
+
+        self.tripped.as_ref()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nes::Nes;
+    use crate::testbus;
+
+    fn console(source: &str, config: WatchdogConfig) -> Nes {
+        let mut nes = Nes::from_bytes(&testbus::ines_image(source, 0).unwrap()).unwrap();
+        nes.enable_watchdog(config);
+        nes
+    }
+
+    // Steps until the watchdog trips, or `max_steps` run out
+    fn run(nes: &mut Nes, max_steps: u32) -> Option<Stuck> {
+        for _ in 0..max_steps {
+            nes.step();
+            if let Some(stuck) = nes.stuck() {
+                return Some(stuck.clone());
+            }
+        }
+        None
+    }
+
+    #[test]
+    fn jmp_to_self_without_nmi_is_spinning() {
+        let mut nes = console("spin: jmp spin", WatchdogConfig::default());
+        let stuck = run(&mut nes, 100_000);
+        assert_eq!(stuck, Some(Stuck::Spinning { pc: 0xC000, repeats: 30_001 }));
+    }
+
+    // Frames keep coming whatever the CPU does, so NoFrame is driven
+    // directly, with a write every instruction to keep the spin check quiet
+    #[test]
+    fn no_frame_within_the_limit() {
+        let config = WatchdogConfig {
+            max_cycles_per_frame: 1_000,
+            ..WatchdogConfig::default()
+        };
+        let mut watchdog = Watchdog::new(config);
+        let mut cpu = Cpu::with_state(0xC000, 0xFD, 0, 0, 0, 0x24);
+        cpu.cycles = 500;
+        assert_eq!(watchdog.observe(&cpu, cpu.pc, 1, true, true), None);
+        for _ in 0..250 {
+            cpu.cycles += 4;
+            assert_eq!(watchdog.observe(&cpu, cpu.pc, 1, true, true), None);
+        }
+        cpu.cycles += 4;
+        assert_eq!(watchdog.observe(&cpu, cpu.pc, 1, true, true), Some(&Stuck::NoFrame { cycles: 1_004 }));
+        // Tripped for good, even once a frame turns up
+        assert_eq!(watchdog.observe(&cpu, cpu.pc, 2, true, true), Some(&Stuck::NoFrame { cycles: 1_004 }));
+    }
+
+    // $AB has no handler; Nes skips it like a NOP, so the loop runs on
+    // until the budget is used up
+    #[test]
+    fn unknown_opcodes_past_the_budget() {
+        let config = WatchdogConfig {
+            max_unknown_opcodes: 8,
+            ..WatchdogConfig::default()
+        };
+        let mut nes = console("loop: .byte $AB\n inc $10\n jmp loop", config);
+        let stuck = run(&mut nes, 1_000);
+        assert_eq!(stuck, Some(Stuck::UnknownOpcodes { count: 9, last_pc: 0xC000 }));
+    }
+
+    #[test]
+    fn jam_trips_at_once() {
+        let mut nes = console("nop\n .byte $02", WatchdogConfig::default());
+        assert_eq!(run(&mut nes, 3), Some(Stuck::Jammed { pc: 0xC001 }));
+    }
+
+    // The usual main loop of a game that does everything in its NMI
+    // handler. Each NMI pushes onto the stack, which resets the repeat count.
+    #[test]
+    fn nmi_idle_loop_is_not_stuck() {
+        let mut nes = console(
+            "
+            lda #$80
+            sta $2000
+      idle: jmp idle
+      nmi:  rti
+            * = $FFFA
+            .word nmi",
+            WatchdogConfig::default(),
+        );
+        for _ in 0..20 {
+            nes.run_frame();
+        }
+        assert_eq!(nes.stuck(), None);
+        assert!(nes.ppu_position().frame >= 20);
+    }
+}